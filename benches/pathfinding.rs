@@ -0,0 +1,33 @@
+use autonomy::navigation::{Cell, NavGrid, PathFinder};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const SIZE: u32 = 256;
+
+/// A large open map crossed by walls every 16 cells, each with a gap at
+/// alternating ends so the corner-to-corner path has to snake through.
+fn walled_grid() -> NavGrid {
+    let mut grid = NavGrid::new(SIZE, SIZE, 1.0, [0.0, 0.0]);
+    for (i, x) in (8..SIZE).step_by(16).enumerate() {
+        let gap = if i % 2 == 0 { SIZE - 4..SIZE } else { 0..4 };
+        for z in (0..SIZE).filter(|z| !gap.contains(z)) {
+            grid.set_walkable(Cell::new(x, z), false);
+        }
+    }
+    grid
+}
+
+fn find_cells(c: &mut Criterion) {
+    let open = NavGrid::new(SIZE, SIZE, 1.0, [0.0, 0.0]);
+    let walled = walled_grid();
+    let mut finder = PathFinder::new();
+    let (start, goal) = (Cell::new(0, 0), Cell::new(SIZE - 1, SIZE - 1));
+    c.bench_function("find_cells open 256x256", |b| {
+        b.iter(|| finder.find_cells(black_box(&open), start, goal))
+    });
+    c.bench_function("find_cells walled 256x256", |b| {
+        b.iter(|| finder.find_cells(black_box(&walled), start, goal))
+    });
+}
+
+criterion_group!(benches, find_cells);
+criterion_main!(benches);
@@ -1,10 +1,11 @@
-use autonomy::{Autonomy, ScreenTargets};
+use autonomy::{Autonomy, ScreenTargets, DEFAULT_SAMPLE_COUNT, SUPPORTED_SAMPLE_COUNTS};
+use autonomy::text::TextStyle;
+use autonomy::timestep::GameSpeed;
 use futures::executor::LocalPool;
 use winit::{
     event,
     event_loop::{ControlFlow, EventLoop},
 };
-use env_logger;
 use log::info;
 
 use std::sync::Arc;
@@ -15,8 +16,6 @@ fn main() {
 }
 
 pub const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8Unorm;
-pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
-use profiling;
 
 #[profiling::function]
 pub fn main_loop() {
@@ -64,23 +63,20 @@ pub fn main_loop() {
             present_mode: wgpu::PresentMode::Mailbox,
         };
         let mut swap_chain = device.create_swap_chain(&surface, &sc_desc);
-        let mut depth_target = Arc::new(device
-            .create_texture(&wgpu::TextureDescriptor {
-                label: Some("Depth"),
-                size: extent,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: DEPTH_FORMAT,
-                usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
-            })
-            .create_view(&wgpu::TextureViewDescriptor::default()));
-
 
         let mut last_time = time::Instant::now();
         let mut needs_reload = false;
-
-        let app = Autonomy::new(&device, &queue, COLOR_FORMAT);
+        let mut loaded = false;
+        let mut fps = 0.0;
+
+        let mut app = Autonomy::new(&device, &queue, COLOR_FORMAT, extent);
+        // AUTONOMY_MSAA picks the sample count, e.g. 1 to turn MSAA off.
+        let sample_count = std::env::var("AUTONOMY_MSAA")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|count| SUPPORTED_SAMPLE_COUNTS.contains(count))
+            .unwrap_or(DEFAULT_SAMPLE_COUNT);
+        app.set_sample_count(&device, sample_count);
 
         event_loop.run(move |event, _, control_flow| {
             let _ = window;
@@ -106,69 +102,81 @@ pub fn main_loop() {
                         present_mode: wgpu::PresentMode::Mailbox,
                     };
                     swap_chain = device.create_swap_chain(&surface, &sc_desc);
-                    depth_target = Arc::new(device
-                        .create_texture(&wgpu::TextureDescriptor {
-                            label: Some("Depth"),
-                            size: extent,
-                            mip_level_count: 1,
-                            sample_count: 1,
-                            dimension: wgpu::TextureDimension::D2,
-                            format: DEPTH_FORMAT,
-                            usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
-                        })
-                        .create_view(&wgpu::TextureViewDescriptor::default()));
-                    // app.resize(&device, extent);
+                    app.resize(&device, extent);
                 }
-                event::Event::WindowEvent { event, .. } => match event {
-                    event::WindowEvent::Focused(false) => {
-                        needs_reload = true;
+                event::Event::WindowEvent { event, .. } => {
+                    match event {
+                        event::WindowEvent::Focused(false) => {
+                            needs_reload = true;
+                        }
+                        event::WindowEvent::Focused(true) if needs_reload => {
+                            info!("Reloading shaders");
+                            // app.reload(&device);
+                            needs_reload = false;
+                        }
+                        event::WindowEvent::CloseRequested => {
+                            *control_flow = ControlFlow::Exit;
+                        }
+                        _ => {}
+                    }
+                    app.handle_event(&event);
+                }
+                event::Event::MainEventsCleared => {
+                    let _spawner = task_pool.spawner();
+                    let duration = time::Instant::now() - last_time;
+                    last_time += duration;
+                    let delta = duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1.0e-9;
+
+                    // Smoothed so the counter is readable.
+                    if delta > 0.0 {
+                        fps = if fps == 0.0 { 1.0 / delta } else { fps * 0.95 + 0.05 / delta };
                     }
-                    event::WindowEvent::Focused(true) if needs_reload => {
-                        info!("Reloading shaders");
-                        // app.reload(&device);
-                        needs_reload = false;
+                    app.text_mut().queue_screen(&format!("{:.0} fps", fps), [8.0, 8.0], &TextStyle::default());
+
+                    let mut time_of_day = *app.time_of_day();
+                    let mut hours = time_of_day.hours();
+                    let mut speed = app.timestep().speed();
+                    let ui = app.ui_mut();
+                    ui.begin_frame();
+                    let panel_x = ui.screen_size()[0] - 228.0;
+                    ui.panel("Environment", [panel_x, 8.0], 220.0, |ui| {
+                        ui.slider("Time of day", &mut hours, 0.0..=24.0);
+                        ui.checkbox("Pause clock", &mut time_of_day.paused);
+                        ui.horizontal(|ui| {
+                            for option in GameSpeed::ALL {
+                                if ui.button(&option.to_string()) {
+                                    speed = option;
+                                }
+                            }
+                        });
+                    });
+                    time_of_day.set_hours(hours);
+                    if time_of_day != *app.time_of_day() {
+                        app.set_time_of_day(time_of_day);
                     }
-                    event::WindowEvent::CloseRequested => {
+                    app.timestep_mut().set_speed(speed);
+
+                    app.update(&device, &queue, delta);
+                    if app.input().pressed("quit") {
                         *control_flow = ControlFlow::Exit;
                     }
-                    event::WindowEvent::KeyboardInput { input, .. } => {
-                        // if !app.on_key(input) {
-                        //     *control_flow = ControlFlow::Exit;
-                        // }
-                    }
-                    // event::WindowEvent::MouseWheel { delta, .. } => app.on_mouse_wheel(delta),
-                    event::WindowEvent::CursorMoved { position, .. } => {
-                        // app.on_cursor_move(position.into())
+                    let progress = app.loading_progress();
+                    if !progress.is_done() {
+                        window.set_title(&format!("autonomy - loading {:.0}%", progress.ratio() * 100.0));
+                    } else if !loaded {
+                        window.set_title("autonomy");
+                        loaded = true;
                     }
-                    event::WindowEvent::MouseInput { state, button, .. } => {
-                        // app.on_mouse_button(state, button)
+
+                    if let Ok(frame) = swap_chain.get_current_frame() {
+                        let frame = Arc::new(frame);
+                        let targets = Arc::new(ScreenTargets {
+                            extent,
+                            color: frame,
+                        });
+                        let render_command_buffer = task_pool.run_until(app.draw(&device, targets));
+                        queue.submit(render_command_buffer);
                     }
-                    _ => {}
-                },
-                event::Event::MainEventsCleared => {
-                    let _spawner = task_pool.spawner();
-                    let duration = time::Instant::now() - last_time;
-                    last_time += duration;
-                    let _delta = duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1.0e-9;
-
-                    // let update_command_buffers = app.update(&device, delta, &spawner);
-                    // if !update_command_buffers.is_empty() {
-                    //     queue.submit(update_command_buffers);
-                    // }
-
-                    match swap_chain.get_current_frame() {
-                        Ok(frame) => {
-                            let frame = Arc::new(frame);
-                            let targets = Arc::new(ScreenTargets {
-                                extent,
-                                color: frame.clone(),
-                                depth: depth_target.clone(),
-                            });
-                            let render_command_buffer = task_pool.run_until(app.draw(&device, targets));
-                            queue.submit(render_command_buffer);
-                        }
-                        Err(_) => {}
-                    };
 
                     profiling::finish_frame!();
                 }
@@ -0,0 +1,40 @@
+[[group(0), binding(0)]]
+var source: texture_2d<f32>;
+[[group(0), binding(1)]]
+var source_sampler: sampler;
+
+// Keeps the parts of the scene brighter than the threshold, with a soft knee
+// so the cut-off doesn't show up as hard edges.
+[[stage(fragment)]]
+fn fs_bright(in: FullscreenOutput) -> [[location(0)]] vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv).rgb;
+    let luma = luminance(color) * post.exposure;
+    let knee = post.bloom_threshold * 0.5 + 0.0001;
+    let soft = clamp(luma - post.bloom_threshold + knee, 0.0, 2.0 * knee);
+    let weight = max(soft * soft / (4.0 * knee), luma - post.bloom_threshold) / max(luma, 0.0001);
+    return vec4<f32>(color * weight, 1.0);
+}
+
+// 9-tap gaussian, using bilinear filtering to fetch two taps at once.
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    let size = textureDimensions(source);
+    let texel_step = direction / vec2<f32>(f32(size.x), f32(size.y));
+    let near = texel_step * 1.3846153846;
+    let far = texel_step * 3.2307692308;
+    var color: vec3<f32> = textureSample(source, source_sampler, uv).rgb * 0.2270270270;
+    color = color + textureSample(source, source_sampler, uv + near).rgb * 0.3162162162;
+    color = color + textureSample(source, source_sampler, uv - near).rgb * 0.3162162162;
+    color = color + textureSample(source, source_sampler, uv + far).rgb * 0.0702702703;
+    color = color + textureSample(source, source_sampler, uv - far).rgb * 0.0702702703;
+    return vec4<f32>(color, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_blur_horizontal(in: FullscreenOutput) -> [[location(0)]] vec4<f32> {
+    return blur(in.uv, vec2<f32>(1.0, 0.0));
+}
+
+[[stage(fragment)]]
+fn fs_blur_vertical(in: FullscreenOutput) -> [[location(0)]] vec4<f32> {
+    return blur(in.uv, vec2<f32>(0.0, 1.0));
+}
@@ -0,0 +1,47 @@
+[[group(0), binding(0)]]
+var source: texture_2d<f32>;
+[[group(0), binding(1)]]
+var source_sampler: sampler;
+
+// The compact FXAA variant: estimate the edge direction from the corner
+// lumas and blur along it.
+[[stage(fragment)]]
+fn fs_main(in: FullscreenOutput) -> [[location(0)]] vec4<f32> {
+    let reduce_min = 1.0 / 128.0;
+    let reduce_mul = 1.0 / 8.0;
+    let span_max = 8.0;
+
+    let size = textureDimensions(source);
+    let texel = vec2<f32>(1.0 / f32(size.x), 1.0 / f32(size.y));
+
+    let rgb_m = textureSample(source, source_sampler, in.uv).rgb;
+    let luma_nw = luminance(textureSample(source, source_sampler, in.uv + vec2<f32>(-1.0, -1.0) * texel).rgb);
+    let luma_ne = luminance(textureSample(source, source_sampler, in.uv + vec2<f32>(1.0, -1.0) * texel).rgb);
+    let luma_sw = luminance(textureSample(source, source_sampler, in.uv + vec2<f32>(-1.0, 1.0) * texel).rgb);
+    let luma_se = luminance(textureSample(source, source_sampler, in.uv + vec2<f32>(1.0, 1.0) * texel).rgb);
+    let luma_m = luminance(rgb_m);
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    let direction = vec2<f32>(
+        (luma_sw + luma_se) - (luma_nw + luma_ne),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let direction_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul, reduce_min);
+    let inverse_min = 1.0 / (min(abs(direction.x), abs(direction.y)) + direction_reduce);
+    let span = clamp(direction * inverse_min, vec2<f32>(-span_max, -span_max), vec2<f32>(span_max, span_max)) * texel;
+
+    let rgb_a = 0.5 * (
+        textureSample(source, source_sampler, in.uv + span * (1.0 / 3.0 - 0.5)).rgb +
+        textureSample(source, source_sampler, in.uv + span * (2.0 / 3.0 - 0.5)).rgb);
+    let rgb_b = rgb_a * 0.5 + 0.25 * (
+        textureSample(source, source_sampler, in.uv - span * 0.5).rgb +
+        textureSample(source, source_sampler, in.uv + span * 0.5).rgb);
+
+    let luma_b = luminance(rgb_b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return vec4<f32>(rgb_a, 1.0);
+    }
+    return vec4<f32>(rgb_b, 1.0);
+}
@@ -0,0 +1,108 @@
+// Shared by every scene shader, prepended to their source at build time.
+
+[[block]]
+struct Uniforms {
+    view_proj: mat4x4<f32>;
+    view: mat4x4<f32>;
+    inv_view_proj: mat4x4<f32>;
+    // xyz: world-space eye position.
+    camera_position: vec4<f32>;
+    light_view_proj: [[stride(64)]] array<mat4x4<f32>, 4>;
+    // View-space far distance of each cascade.
+    cascade_splits: vec4<f32>;
+    // xyz: direction towards the sun, w: cascade count.
+    sun_direction: vec4<f32>;
+    // rgb: sun colour, a: ambient intensity.
+    sun_color: vec4<f32>;
+    fog_color: vec4<f32>;
+    // x: mode (0 off, 1 linear, 2 exponential), y: start or density, z: end.
+    fog_params: vec4<f32>;
+    // x: density at the base height, y: base height, z: falloff per unit of height.
+    height_fog: vec4<f32>;
+    // Fragments with dot(clip_plane, position) < 0 are discarded.
+    clip_plane: vec4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> uniforms: Uniforms;
+[[group(0), binding(1)]]
+var shadow_map: texture_depth_2d_array;
+[[group(0), binding(2)]]
+var shadow_sampler: sampler_comparison;
+
+fn shadow_cascade_sample(cascade: i32, world_position: vec3<f32>) -> f32 {
+    let light_clip = uniforms.light_view_proj[cascade] * vec4<f32>(world_position, 1.0);
+    let ndc = light_clip.xyz / light_clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 || ndc.z > 1.0) {
+        return 1.0;
+    }
+    let texel = 1.0 / f32(textureDimensions(shadow_map).x);
+    // 3x3 PCF on top of the hardware 2x2 comparison filtering.
+    var lit: f32 = 0.0;
+    for (var y: i32 = -1; y <= 1; y = y + 1) {
+        for (var x: i32 = -1; x <= 1; x = x + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit = lit + textureSampleCompare(shadow_map, shadow_sampler, uv + offset, cascade, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Fraction of sunlight reaching `world_position`, 1.0 when fully lit.
+fn shadow_factor(world_position: vec3<f32>) -> f32 {
+    let view_depth = -(uniforms.view * vec4<f32>(world_position, 1.0)).z;
+    let cascade_count = i32(uniforms.sun_direction.w);
+    var cascade: i32 = 0;
+    loop {
+        if (cascade >= cascade_count) {
+            return 1.0;
+        }
+        if (view_depth <= uniforms.cascade_splits[cascade]) {
+            break;
+        }
+        cascade = cascade + 1;
+    }
+    return shadow_cascade_sample(cascade, world_position);
+}
+
+// Lambert diffuse from the sun plus a flat ambient term.
+fn sun_lighting(normal: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let n_dot_l = max(dot(normalize(normal), normalize(uniforms.sun_direction.xyz)), 0.0);
+    let direct = uniforms.sun_color.rgb * n_dot_l * shadow_factor(world_position);
+    return direct + vec3<f32>(uniforms.sun_color.a);
+}
+
+// Blends `color` towards the fog colour by distance from the camera and by
+// the fog accumulated through the height fog layer along the view ray.
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let to_point = world_position - uniforms.camera_position.xyz;
+    let view_distance = length(to_point);
+
+    var fog: f32 = 0.0;
+    let mode = uniforms.fog_params.x;
+    if (mode > 1.5) {
+        fog = 1.0 - exp(-uniforms.fog_params.y * view_distance);
+    } else {
+        if (mode > 0.5) {
+            let start = uniforms.fog_params.y;
+            let end = uniforms.fog_params.z;
+            fog = clamp((view_distance - start) / max(end - start, 0.0001), 0.0, 1.0);
+        }
+    }
+
+    let density = uniforms.height_fog.x;
+    if (density > 0.0) {
+        // Density falls off exponentially with height, integrated analytically.
+        let falloff = uniforms.height_fog.z;
+        let eye_height = uniforms.camera_position.y - uniforms.height_fog.y;
+        let rise = to_point.y * falloff;
+        var integral: f32 = density * exp(-falloff * eye_height) * view_distance;
+        if (abs(rise) > 0.0001) {
+            integral = integral * (1.0 - exp(-rise)) / rise;
+        }
+        fog = 1.0 - (1.0 - fog) * exp(-integral);
+    }
+
+    return mix(color, uniforms.fog_color.rgb, vec3<f32>(fog, fog, fog));
+}
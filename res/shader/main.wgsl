@@ -1,11 +1,4 @@
-[[block]]
-struct Uniforms {
-    view_proj: mat4x4<f32>;
-};
-
-[[group(0), binding(0)]]
-var<uniform> uniforms: Uniforms;[[stage(vertex)]]
-
+[[stage(vertex)]]
 fn vs_main([[builtin(vertex_index)]] in_vertex_index: u32) -> [[builtin(position)]] vec4<f32> {
     let x = f32(i32(in_vertex_index) - 1);
     let y = f32(i32(in_vertex_index & 1u) * 2 - 1);
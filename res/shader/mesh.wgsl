@@ -0,0 +1,109 @@
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] normal: vec3<f32>;
+    [[location(2)]] tex_coords: vec2<f32>;
+};
+
+struct InstanceInput {
+    [[location(5)]] model_0: vec4<f32>;
+    [[location(6)]] model_1: vec4<f32>;
+    [[location(7)]] model_2: vec4<f32>;
+    [[location(8)]] model_3: vec4<f32>;
+    [[location(9)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] normal: vec3<f32>;
+    [[location(1)]] color: vec4<f32>;
+    [[location(2)]] world_position: vec3<f32>;
+    [[location(3)]] tex_coords: vec2<f32>;
+};
+
+[[block]]
+struct Material {
+    // x: metallic, y: roughness.
+    factors: vec4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> material: Material;
+[[group(1), binding(1)]]
+var base_color_texture: texture_2d<f32>;
+[[group(1), binding(2)]]
+var normal_texture: texture_2d<f32>;
+[[group(1), binding(3)]]
+var metallic_roughness_texture: texture_2d<f32>;
+[[group(1), binding(4)]]
+var material_sampler: sampler;
+
+[[stage(vertex)]]
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let transform = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let world_position = transform * vec4<f32>(model.position, 1.0);
+    var out: VertexOutput;
+    // Only valid for uniform scale, which is all instances use for now.
+    out.normal = (transform * vec4<f32>(model.normal, 0.0)).xyz;
+    out.color = instance.color;
+    out.world_position = world_position.xyz;
+    out.tex_coords = model.tex_coords;
+    out.clip_position = uniforms.view_proj * world_position;
+    return out;
+}
+
+[[stage(vertex)]]
+fn vs_shadow(model: VertexInput, instance: InstanceInput) -> [[builtin(position)]] vec4<f32> {
+    let transform = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    return uniforms.view_proj * transform * vec4<f32>(model.position, 1.0);
+}
+
+// Applies the normal map without stored tangents, building the tangent frame
+// from screen-space derivatives of the position and texture coordinates.
+fn mapped_normal(normal: vec3<f32>, world_position: vec3<f32>, tex_coords: vec2<f32>) -> vec3<f32> {
+    let sampled = textureSample(normal_texture, material_sampler, tex_coords).xyz * 2.0 - 1.0;
+    let dp1 = dpdx(world_position);
+    let dp2 = dpdy(world_position);
+    let duv1 = dpdx(tex_coords);
+    let duv2 = dpdy(tex_coords);
+    let dp2_perp = cross(dp2, normal);
+    let dp1_perp = cross(normal, dp1);
+    let tangent = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    // glTF texture coordinates run downwards, which flips the bitangent.
+    let bitangent = -(dp2_perp * duv1.y + dp1_perp * duv2.y);
+    let scale = max(dot(tangent, tangent), dot(bitangent, bitangent));
+    // Meshes without texture coordinates have no frame to map into.
+    if (scale < 0.0000000001) {
+        return normal;
+    }
+    let frame = mat3x3<f32>(tangent * inverseSqrt(scale), bitangent * inverseSqrt(scale), normal);
+    return normalize(frame * sampled);
+}
+
+// Blinn-Phong highlight of the sun, sharper as `roughness` goes to 0.
+fn sun_specular(normal: vec3<f32>, world_position: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let to_sun = normalize(uniforms.sun_direction.xyz);
+    let to_eye = normalize(uniforms.camera_position.xyz - world_position);
+    let halfway = normalize(to_sun + to_eye);
+    let alpha = max(roughness * roughness, 0.01);
+    let shininess = 2.0 / (alpha * alpha) - 2.0;
+    let highlight = pow(max(dot(normal, halfway), 0.0), shininess) * (shininess + 8.0) / 8.0;
+    return uniforms.sun_color.rgb * highlight * max(dot(normal, to_sun), 0.0) * shadow_factor(world_position);
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // Sampled before the clip plane test, which makes control flow non-uniform.
+    let base_color = textureSample(base_color_texture, material_sampler, in.tex_coords) * in.color;
+    let packed = textureSample(metallic_roughness_texture, material_sampler, in.tex_coords);
+    let normal = mapped_normal(normalize(in.normal), in.world_position, in.tex_coords);
+    if (dot(uniforms.clip_plane, vec4<f32>(in.world_position, 1.0)) < 0.0) {
+        discard;
+    }
+    let metallic = material.factors.x * packed.b;
+    let roughness = material.factors.y * packed.g;
+    // Dielectrics reflect about 4% at normal incidence, metals their base colour.
+    let reflectance = mix(vec3<f32>(0.04), base_color.rgb, vec3<f32>(metallic));
+    let diffuse = base_color.rgb * (1.0 - metallic) * sun_lighting(normal, in.world_position);
+    let specular = reflectance * (sun_specular(normal, in.world_position, roughness) + vec3<f32>(uniforms.sun_color.a));
+    return vec4<f32>(apply_fog(diffuse + specular, in.world_position), base_color.a);
+}
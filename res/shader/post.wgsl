@@ -0,0 +1,38 @@
+// Shared by the post-processing shaders, prepended to their source at build time.
+
+[[block]]
+struct PostUniforms {
+    exposure: f32;
+    bloom_threshold: f32;
+    // Zero while bloom is disabled.
+    bloom_intensity: f32;
+    // Toggles are 1.0 when enabled.
+    tonemapping: f32;
+    color_grading: f32;
+    // The output target encodes to sRGB itself, so undo our gamma encoding.
+    output_srgb: f32;
+    lut_size: f32;
+    padding: f32;
+};
+
+[[group(0), binding(2)]]
+var<uniform> post: PostUniforms;
+
+struct FullscreenOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+// A single triangle covering the screen, no vertex buffer needed.
+[[stage(vertex)]]
+fn vs_fullscreen([[builtin(vertex_index)]] index: u32) -> FullscreenOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: FullscreenOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
@@ -0,0 +1,70 @@
+[[block]]
+struct Joints {
+    matrices: [[stride(64)]] array<mat4x4<f32>>;
+};
+
+[[group(1), binding(0)]]
+var<storage> joints: [[access(read)]] Joints;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] normal: vec3<f32>;
+    [[location(2)]] tex_coords: vec2<f32>;
+    [[location(3)]] joint_indices: vec4<u32>;
+    [[location(4)]] joint_weights: vec4<f32>;
+};
+
+struct InstanceInput {
+    [[location(5)]] model_0: vec4<f32>;
+    [[location(6)]] model_1: vec4<f32>;
+    [[location(7)]] model_2: vec4<f32>;
+    [[location(8)]] model_3: vec4<f32>;
+    [[location(9)]] color: vec4<f32>;
+    [[location(10)]] joint_offset: u32;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] normal: vec3<f32>;
+    [[location(1)]] color: vec4<f32>;
+    [[location(2)]] world_position: vec3<f32>;
+};
+
+// Blends `v` by the four joints influencing the vertex.
+fn skin(v: vec4<f32>, offset: u32, indices: vec4<u32>, weights: vec4<f32>) -> vec4<f32> {
+    return weights.x * (joints.matrices[offset + indices.x] * v)
+        + weights.y * (joints.matrices[offset + indices.y] * v)
+        + weights.z * (joints.matrices[offset + indices.z] * v)
+        + weights.w * (joints.matrices[offset + indices.w] * v);
+}
+
+[[stage(vertex)]]
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let skinned_position = skin(vec4<f32>(model.position, 1.0), instance.joint_offset, model.joint_indices, model.joint_weights);
+    let skinned_normal = skin(vec4<f32>(model.normal, 0.0), instance.joint_offset, model.joint_indices, model.joint_weights);
+
+    let transform = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let world_position = transform * skinned_position;
+    var out: VertexOutput;
+    out.normal = (transform * skinned_normal).xyz;
+    out.color = instance.color;
+    out.world_position = world_position.xyz;
+    out.clip_position = uniforms.view_proj * world_position;
+    return out;
+}
+
+[[stage(vertex)]]
+fn vs_shadow(model: VertexInput, instance: InstanceInput) -> [[builtin(position)]] vec4<f32> {
+    let skinned_position = skin(vec4<f32>(model.position, 1.0), instance.joint_offset, model.joint_indices, model.joint_weights);
+    let transform = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    return uniforms.view_proj * transform * skinned_position;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    if (dot(uniforms.clip_plane, vec4<f32>(in.world_position, 1.0)) < 0.0) {
+        discard;
+    }
+    let light = sun_lighting(in.normal, in.world_position);
+    return vec4<f32>(apply_fog(in.color.rgb * light, in.world_position), in.color.a);
+}
@@ -0,0 +1,122 @@
+[[block]]
+struct Sky {
+    // rgb: Rayleigh scattering coefficients, a: Rayleigh scale height.
+    rayleigh: vec4<f32>;
+    // x: Mie scattering coefficient, y: Mie scale height, z: Mie anisotropy.
+    mie: vec4<f32>;
+    // x: sun intensity, y: cosine of the sun disc radius, z: sun disc brightness.
+    sun: vec4<f32>;
+    // rgb: ground albedo below the horizon.
+    ground: vec4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> sky: Sky;
+
+struct SkyOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] ndc: vec2<f32>;
+};
+
+// Full-screen triangle at the far plane, so the sky ends up behind everything.
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> SkyOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: SkyOutput;
+    out.ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    out.position = vec4<f32>(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+// Distances along `direction` to the near and far intersections with a
+// sphere of `radius` around the planet centre.
+fn ray_sphere(origin: vec3<f32>, direction: vec3<f32>, radius: f32) -> vec2<f32> {
+    let b = dot(direction, origin);
+    let c = dot(origin, origin) - radius * radius;
+    let discriminant = b * b - c;
+    if (discriminant < 0.0) {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let root = sqrt(discriminant);
+    return vec2<f32>(-b - root, -b + root);
+}
+
+// Single scattering of sunlight through a Rayleigh and Mie atmosphere,
+// ray marched from an eye just above the ground.
+fn atmosphere(direction: vec3<f32>, sun_direction: vec3<f32>) -> vec3<f32> {
+    let planet_radius = 6371000.0;
+    let atmosphere_radius = 6471000.0;
+    let origin = vec3<f32>(0.0, planet_radius + 1000.0, 0.0);
+    let primary_steps = 16;
+    let light_steps = 8;
+
+    let rayleigh_coefficients = sky.rayleigh.rgb;
+    let mie_coefficient = sky.mie.x;
+    let mie_coefficients = vec3<f32>(mie_coefficient, mie_coefficient, mie_coefficient);
+
+    let step_size = ray_sphere(origin, direction, atmosphere_radius).y / f32(primary_steps);
+    var travelled: f32 = 0.0;
+    var optical_rayleigh: f32 = 0.0;
+    var optical_mie: f32 = 0.0;
+    var total_rayleigh: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+    var total_mie: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+
+    for (var i: i32 = 0; i < primary_steps; i = i + 1) {
+        let position = origin + direction * (travelled + step_size * 0.5);
+        let height = length(position) - planet_radius;
+        let density_rayleigh = exp(-height / sky.rayleigh.a) * step_size;
+        let density_mie = exp(-height / sky.mie.y) * step_size;
+        optical_rayleigh = optical_rayleigh + density_rayleigh;
+        optical_mie = optical_mie + density_mie;
+
+        // Optical depth from this sample towards the sun.
+        let light_step_size = ray_sphere(position, sun_direction, atmosphere_radius).y / f32(light_steps);
+        var light_distance: f32 = 0.0;
+        var light_rayleigh: f32 = 0.0;
+        var light_mie: f32 = 0.0;
+        for (var j: i32 = 0; j < light_steps; j = j + 1) {
+            let light_position = position + sun_direction * (light_distance + light_step_size * 0.5);
+            let light_height = length(light_position) - planet_radius;
+            light_rayleigh = light_rayleigh + exp(-light_height / sky.rayleigh.a) * light_step_size;
+            light_mie = light_mie + exp(-light_height / sky.mie.y) * light_step_size;
+            light_distance = light_distance + light_step_size;
+        }
+
+        let attenuation = exp(-(mie_coefficients * (optical_mie + light_mie) + rayleigh_coefficients * (optical_rayleigh + light_rayleigh)));
+        total_rayleigh = total_rayleigh + attenuation * density_rayleigh;
+        total_mie = total_mie + attenuation * density_mie;
+        travelled = travelled + step_size;
+    }
+
+    let pi = 3.14159265;
+    let mu = dot(direction, sun_direction);
+    let g = sky.mie.z;
+    let phase_rayleigh = 3.0 / (16.0 * pi) * (1.0 + mu * mu);
+    let phase_mie = 3.0 / (8.0 * pi) * ((1.0 - g * g) * (1.0 + mu * mu)) / (pow(1.0 + g * g - 2.0 * mu * g, 1.5) * (2.0 + g * g));
+
+    var color: vec3<f32> = sky.sun.x * (total_rayleigh * rayleigh_coefficients * phase_rayleigh + total_mie * mie_coefficients * phase_mie);
+
+    // The sun disc itself, dimmed by the air it shines through.
+    let transmittance = exp(-(mie_coefficients * optical_mie + rayleigh_coefficients * optical_rayleigh));
+    let disc = clamp((mu - sky.sun.y) / 0.00002, 0.0, 1.0);
+    color = color + transmittance * (sky.sun.x * sky.sun.z * disc);
+    return color;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: SkyOutput) -> [[location(0)]] vec4<f32> {
+    let near = uniforms.inv_view_proj * vec4<f32>(in.ndc, 0.0, 1.0);
+    let far = uniforms.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = normalize(far.xyz / far.w - near.xyz / near.w);
+    let sun_direction = normalize(uniforms.sun_direction.xyz);
+
+    // March just above the horizon for rays pointing down, then fade to the ground.
+    let view_direction = normalize(vec3<f32>(direction.x, max(direction.y, 0.0), direction.z));
+    var color: vec3<f32> = atmosphere(view_direction, sun_direction);
+    if (direction.y < 0.0) {
+        let ground = sky.ground.rgb * (max(sun_direction.y, 0.0) * sky.sun.x * 0.05);
+        let blend = clamp(-direction.y * 10.0, 0.0, 1.0);
+        color = mix(color, ground, vec3<f32>(blend, blend, blend));
+    }
+    return vec4<f32>(color, 1.0);
+}
@@ -1,29 +1,31 @@
-[[block]]
-struct Uniforms {
-    view_proj: mat4x4<f32>;
-};
-
-[[group(0), binding(0)]]
-var<uniform> uniforms: Uniforms;[[stage(vertex)]]
-
 struct VertexInput {
     [[location(0)]] position: vec3<f32>;
     [[location(1)]] tex_coords: vec2<f32>;
+    [[location(2)]] normal: vec3<f32>;
 };
 
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
     [[location(0)]] tex_coords: vec2<f32>;
+    [[location(1)]] world_position: vec3<f32>;
+    [[location(2)]] normal: vec3<f32>;
 };
 
 [[stage(vertex)]]
 fn vs_main(model: VertexInput) -> VertexOutput {
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
+    out.world_position = model.position;
+    out.normal = model.normal;
     out.clip_position = uniforms.view_proj * vec4<f32>(model.position, 1.0);
     return out;
 }
 
+[[stage(vertex)]]
+fn vs_shadow(model: VertexInput) -> [[builtin(position)]] vec4<f32> {
+    return uniforms.view_proj * vec4<f32>(model.position, 1.0);
+}
+
 [[group(1), binding(0)]]
 var t_diffuse: texture_2d<f32>;
 [[group(1), binding(1)]]
@@ -31,7 +33,11 @@ var s_diffuse: sampler;
 
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    //return vec4<f32>(0.0, 0.7, 0.0, 1.0);
-    //return vec4<f32>(in.color, 1.0);
+    // Sampled first, implicit derivatives need uniform control flow.
+    let albedo = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    if (dot(uniforms.clip_plane, vec4<f32>(in.world_position, 1.0)) < 0.0) {
+        discard;
+    }
+    let light = sun_lighting(in.normal, in.world_position);
+    return vec4<f32>(apply_fog(albedo.rgb * light, in.world_position), albedo.a);
 }
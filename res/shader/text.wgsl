@@ -0,0 +1,67 @@
+[[block]]
+struct Text {
+    // xy: screen size in pixels, z: 1.0 if the screen target is sRGB.
+    screen: vec4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> text: Text;
+// Glyph coverage in the red channel.
+[[group(1), binding(1)]]
+var atlas: texture_2d<f32>;
+[[group(1), binding(2)]]
+var atlas_sampler: sampler;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] tex_coords: vec2<f32>;
+    [[location(2)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] tex_coords: vec2<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+// Positions are in pixels from the top left corner.
+[[stage(vertex)]]
+fn vs_screen(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.color = model.color;
+    out.clip_position = vec4<f32>(
+        model.position.x / text.screen.x * 2.0 - 1.0,
+        1.0 - model.position.y / text.screen.y * 2.0,
+        0.0,
+        1.0,
+    );
+    return out;
+}
+
+[[stage(vertex)]]
+fn vs_world(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.color = model.color;
+    out.clip_position = uniforms.view_proj * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+// The screen target comes after tone mapping, so colours are gamma encoded
+// unless the hardware does it.
+[[stage(fragment)]]
+fn fs_screen(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let coverage = textureSample(atlas, atlas_sampler, in.tex_coords).r;
+    var color: vec3<f32> = in.color.rgb;
+    if (text.screen.z < 0.5) {
+        color = pow(color, vec3<f32>(1.0 / 2.2, 1.0 / 2.2, 1.0 / 2.2));
+    }
+    return vec4<f32>(color, in.color.a * coverage);
+}
+
+[[stage(fragment)]]
+fn fs_world(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let coverage = textureSample(atlas, atlas_sampler, in.tex_coords).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
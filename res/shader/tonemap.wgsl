@@ -0,0 +1,44 @@
+[[group(0), binding(0)]]
+var hdr: texture_2d<f32>;
+[[group(0), binding(1)]]
+var linear_sampler: sampler;
+[[group(0), binding(3)]]
+var bloom: texture_2d<f32>;
+[[group(0), binding(4)]]
+var lut: texture_3d<f32>;
+
+// Narkowicz's fit of the ACES filmic curve.
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let numerator = x * (x * 2.51 + vec3<f32>(0.03, 0.03, 0.03));
+    let denominator = x * (x * 2.43 + vec3<f32>(0.59, 0.59, 0.59)) + vec3<f32>(0.14, 0.14, 0.14);
+    return clamp(numerator / denominator, vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(1.0, 1.0, 1.0));
+}
+
+[[stage(fragment)]]
+fn fs_main(in: FullscreenOutput) -> [[location(0)]] vec4<f32> {
+    var color: vec3<f32> = textureSample(hdr, linear_sampler, in.uv).rgb;
+    if (post.bloom_intensity > 0.0) {
+        color = color + textureSample(bloom, linear_sampler, in.uv).rgb * post.bloom_intensity;
+    }
+    color = color * post.exposure;
+
+    if (post.tonemapping > 0.5) {
+        color = aces(color);
+    } else {
+        color = clamp(color, vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(1.0, 1.0, 1.0));
+    }
+
+    // Grading LUTs are authored against gamma encoded colours.
+    let gamma = 1.0 / 2.2;
+    color = pow(color, vec3<f32>(gamma, gamma, gamma));
+    if (post.color_grading > 0.5) {
+        // Sample texel centres so the edges of the LUT map to 0 and 1.
+        let scale = (post.lut_size - 1.0) / post.lut_size;
+        let offset = 0.5 / post.lut_size;
+        color = textureSample(lut, linear_sampler, color * scale + vec3<f32>(offset, offset, offset)).rgb;
+    }
+    if (post.output_srgb > 0.5) {
+        color = pow(color, vec3<f32>(2.2, 2.2, 2.2));
+    }
+    return vec4<f32>(color, 1.0);
+}
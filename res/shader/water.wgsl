@@ -0,0 +1,95 @@
+[[block]]
+struct Water {
+    // a: distance over which light is absorbed.
+    shallow_color: vec4<f32>;
+    // a: depth below which the shore foams.
+    deep_color: vec4<f32>;
+    // x: normal map tiles per world unit, y: scroll speed, z: normal strength, w: time.
+    waves: vec4<f32>;
+    // rgb: reflected sky colour when there is no reflection texture, a: 1.0 with reflections.
+    sky_color: vec4<f32>;
+    // x: sea level.
+    surface: vec4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> water: Water;
+// xyz: tangent space normal, w: foam pattern.
+[[group(1), binding(1)]]
+var normal_map: texture_2d<f32>;
+[[group(1), binding(2)]]
+var water_sampler: sampler;
+// Depth of the opaque scene, without multisampling.
+[[group(1), binding(3)]]
+var scene_depth: texture_depth_2d;
+[[group(1), binding(4)]]
+var reflection: texture_2d<f32>;
+
+struct VertexInput {
+    [[location(0)]] position: vec2<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] world_position: vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(model: VertexInput) -> VertexOutput {
+    let world_position = vec3<f32>(model.position.x, water.surface.x, model.position.y);
+    var out: VertexOutput;
+    out.world_position = world_position;
+    out.clip_position = uniforms.view_proj * vec4<f32>(world_position, 1.0);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // Two copies of the normal map scrolling against each other.
+    let uv = in.world_position.xz * water.waves.x;
+    let scroll = water.waves.w * water.waves.y;
+    let sample_a = textureSample(normal_map, water_sampler, uv + vec2<f32>(scroll, scroll * 0.6));
+    let sample_b = textureSample(normal_map, water_sampler, uv * 1.7 + vec2<f32>(-scroll * 0.8, scroll));
+    let foam_pattern = textureSample(normal_map, water_sampler, uv * 3.0 + vec2<f32>(scroll * 0.3, -scroll * 0.2)).w;
+    let tangent_normal = (sample_a.xyz + sample_b.xyz) - vec3<f32>(1.0, 1.0, 1.0);
+    let normal = normalize(vec3<f32>(tangent_normal.x * water.waves.z, tangent_normal.z, tangent_normal.y * water.waves.z));
+
+    // Reconstruct the opaque surface under this pixel from the depth pre-pass.
+    let size = textureDimensions(scene_depth);
+    let screen_uv = vec2<f32>(in.clip_position.x / f32(size.x), in.clip_position.y / f32(size.y));
+    let depth = textureLoad(scene_depth, vec2<i32>(i32(in.clip_position.x), i32(in.clip_position.y)), 0);
+    let ndc = vec4<f32>(screen_uv.x * 2.0 - 1.0, 1.0 - screen_uv.y * 2.0, depth, 1.0);
+    let floor_clip = uniforms.inv_view_proj * ndc;
+    let floor_position = floor_clip.xyz / floor_clip.w;
+    let thickness = length(floor_position - in.world_position);
+    let water_depth = water.surface.x - floor_position.y;
+
+    let absorption = 1.0 - exp(-thickness / water.shallow_color.a);
+    let body = mix(water.shallow_color.rgb, water.deep_color.rgb, vec3<f32>(absorption, absorption, absorption));
+    let lit_body = body * sun_lighting(vec3<f32>(0.0, 1.0, 0.0), in.world_position);
+
+    let view_direction = normalize(uniforms.camera_position.xyz - in.world_position);
+    let fresnel = 0.02 + 0.98 * pow(1.0 - max(dot(normal, view_direction), 0.0), 5.0);
+    var reflected: vec3<f32> = water.sky_color.rgb;
+    if (water.sky_color.a > 0.5) {
+        // The reflection is rendered mirrored horizontally to keep its winding.
+        let distorted = vec2<f32>(1.0 - screen_uv.x, screen_uv.y) + normal.xz * 0.03;
+        reflected = textureSample(reflection, water_sampler, clamp(distorted, vec2<f32>(0.001, 0.001), vec2<f32>(0.999, 0.999))).rgb;
+    }
+
+    let sun_direction = normalize(uniforms.sun_direction.xyz);
+    let half_vector = normalize(sun_direction + view_direction);
+    let specular = pow(max(dot(normal, half_vector), 0.0), 256.0) * shadow_factor(in.world_position);
+
+    var color: vec3<f32> = mix(lit_body, reflected, vec3<f32>(fresnel, fresnel, fresnel)) + uniforms.sun_color.rgb * specular * 4.0;
+    var alpha: f32 = clamp(absorption * 1.5 + fresnel, 0.0, 1.0);
+
+    // Foam along the shore, broken up by the foam pattern.
+    let shore = clamp(1.0 - water_depth / water.deep_color.a, 0.0, 1.0);
+    let foam = clamp(shore * 2.0 - (1.0 - foam_pattern), 0.0, 1.0);
+    let foam_color = vec3<f32>(0.9, 0.95, 1.0) * sun_lighting(vec3<f32>(0.0, 1.0, 0.0), in.world_position);
+    color = mix(color, foam_color, vec3<f32>(foam, foam, foam));
+    alpha = max(alpha, foam);
+
+    return vec4<f32>(apply_fog(color, in.world_position), alpha);
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Matrix4, Quaternion, Vector3, VectorSpace};
+
+/// Local transform of a joint, kept decomposed so poses can be blended.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct JointTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl JointTransform {
+    pub const IDENTITY: Self = Self {
+        translation: Vector3::new(0.0, 0.0, 0.0),
+        rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        scale: Vector3::new(1.0, 1.0, 1.0),
+    };
+
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// Interpolates towards `other`, lerping translation and scale and slerping rotation.
+    pub fn blend(&self, other: &Self, t: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: slerp(self.rotation, other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+}
+
+impl Default for JointTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Shortest-path slerp; cgmath does not flip the sign of opposite hemispheres.
+pub(crate) fn slerp(a: Quaternion<f32>, b: Quaternion<f32>, t: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    a.slerp(b, t).normalize()
+}
+
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    /// Local transform when no clip animates the joint.
+    pub rest: JointTransform,
+    pub inverse_bind: Matrix4<f32>,
+}
+
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    /// Transform of everything above the root joints, applied before them.
+    pub root_transform: Matrix4<f32>,
+    node_to_joint: HashMap<usize, usize>,
+}
+
+impl Skeleton {
+    /// `nodes[i]` is the scene node driving joint `i`; clips target nodes, not joints.
+    pub fn new(joints: Vec<Joint>, nodes: &[usize], root_transform: Matrix4<f32>) -> Self {
+        let node_to_joint = nodes.iter().enumerate().map(|(joint, &node)| (node, joint)).collect();
+        Self { joints, root_transform, node_to_joint }
+    }
+
+    pub fn joint_for_node(&self, node: usize) -> Option<usize> {
+        self.node_to_joint.get(&node).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+}
+
+pub enum Keyframes {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+pub struct Channel {
+    /// Scene node animated by this channel.
+    pub node: usize,
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+    pub interpolation: Interpolation,
+}
+
+impl Channel {
+    /// Returns the keyframe pair surrounding `time` and the blend factor between them.
+    fn locate(&self, time: f32) -> (usize, usize, f32) {
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return (0, 0, 0.0);
+        }
+        if next == self.times.len() {
+            return (next - 1, next - 1, 0.0);
+        }
+        let prev = next - 1;
+        let factor = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => (time - self.times[prev]) / (self.times[next] - self.times[prev]),
+        };
+        (prev, next, factor)
+    }
+
+    fn apply(&self, time: f32, transform: &mut JointTransform) {
+        if self.times.is_empty() {
+            return;
+        }
+        let (a, b, t) = self.locate(time);
+        match &self.keyframes {
+            Keyframes::Translation(values) => transform.translation = values[a].lerp(values[b], t),
+            Keyframes::Rotation(values) => transform.rotation = slerp(values[a], values[b], t),
+            Keyframes::Scale(values) => transform.scale = values[a].lerp(values[b], t),
+        }
+    }
+}
+
+pub struct Clip {
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl Clip {
+    pub fn new(name: &str, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self { name: name.to_string(), duration, channels }
+    }
+
+    /// Samples the clip at `time` on top of the skeleton's rest pose.
+    pub fn sample(&self, skeleton: &Skeleton, time: f32, looping: bool) -> Pose {
+        let time = if looping && self.duration > 0.0 {
+            time.rem_euclid(self.duration)
+        } else {
+            time.clamp(0.0, self.duration)
+        };
+        let mut pose = Pose::rest(skeleton);
+        for channel in &self.channels {
+            if let Some(joint) = skeleton.joint_for_node(channel.node) {
+                channel.apply(time, &mut pose.locals[joint]);
+            }
+        }
+        pose
+    }
+}
+
+/// Local transforms for every joint of a skeleton.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pose {
+    pub locals: Vec<JointTransform>,
+}
+
+impl Pose {
+    pub fn rest(skeleton: &Skeleton) -> Self {
+        Self { locals: skeleton.joints.iter().map(|joint| joint.rest).collect() }
+    }
+
+    /// Per-joint blend from `self` (at `t = 0`) to `other` (at `t = 1`).
+    pub fn blend(&self, other: &Pose, t: f32) -> Pose {
+        Pose {
+            locals: self
+                .locals
+                .iter()
+                .zip(&other.locals)
+                .map(|(a, b)| a.blend(b, t))
+                .collect(),
+        }
+    }
+
+    /// Model-space transform of every joint.
+    pub fn global_matrices(&self, skeleton: &Skeleton) -> Vec<Matrix4<f32>> {
+        // Joints are not guaranteed to be listed parent first, so resolve lazily.
+        fn resolve(joint: usize, pose: &Pose, skeleton: &Skeleton, globals: &mut Vec<Option<Matrix4<f32>>>) -> Matrix4<f32> {
+            if let Some(global) = globals[joint] {
+                return global;
+            }
+            let parent = match skeleton.joints[joint].parent {
+                Some(parent) => resolve(parent, pose, skeleton, globals),
+                None => skeleton.root_transform,
+            };
+            let global = parent * pose.locals[joint].to_matrix();
+            globals[joint] = Some(global);
+            global
+        }
+
+        let mut globals = vec![None; skeleton.len()];
+        (0..skeleton.len())
+            .map(|joint| resolve(joint, self, skeleton, &mut globals))
+            .collect()
+    }
+
+    /// Matrices uploaded to the joint buffer: global transform times inverse bind.
+    pub fn skinning_matrices(&self, skeleton: &Skeleton) -> Vec<Matrix4<f32>> {
+        self.global_matrices(skeleton)
+            .into_iter()
+            .zip(&skeleton.joints)
+            .map(|(global, joint)| global * joint.inverse_bind)
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct PlayingClip {
+    clip: usize,
+    time: f32,
+    looping: bool,
+}
+
+/// Plays clips on one skeleton, cross-fading when switching between them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationPlayer {
+    current: PlayingClip,
+    previous: Option<PlayingClip>,
+    fade_elapsed: f32,
+    fade_duration: f32,
+    pub speed: f32,
+}
+
+impl AnimationPlayer {
+    pub fn new(clip: usize, looping: bool) -> Self {
+        Self {
+            current: PlayingClip { clip, time: 0.0, looping },
+            previous: None,
+            fade_elapsed: 0.0,
+            fade_duration: 0.0,
+            speed: 1.0,
+        }
+    }
+
+    /// Switches to `clip`, blending out of the current one over `fade_duration` seconds.
+    pub fn play(&mut self, clip: usize, looping: bool, fade_duration: f32) {
+        if self.current.clip == clip {
+            return;
+        }
+        self.previous = if fade_duration > 0.0 { Some(self.current) } else { None };
+        self.current = PlayingClip { clip, time: 0.0, looping };
+        self.fade_elapsed = 0.0;
+        self.fade_duration = fade_duration;
+    }
+
+    pub fn current_clip(&self) -> usize {
+        self.current.clip
+    }
+
+    pub fn advance(&mut self, delta: f32) {
+        let delta = delta * self.speed;
+        self.current.time += delta;
+        if let Some(previous) = self.previous.as_mut() {
+            previous.time += delta;
+            self.fade_elapsed += delta;
+            if self.fade_elapsed >= self.fade_duration {
+                self.previous = None;
+            }
+        }
+    }
+
+    pub fn pose(&self, skeleton: &Skeleton, clips: &[Clip]) -> Pose {
+        let sample = |playing: &PlayingClip| clips[playing.clip].sample(skeleton, playing.time, playing.looping);
+        let current = sample(&self.current);
+        match &self.previous {
+            Some(previous) => sample(previous).blend(&current, self.fade_elapsed / self.fade_duration),
+            None => current,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, Rotation, Rotation3, SquareMatrix};
+
+    fn joint(parent: Option<usize>, translation: Vector3<f32>) -> Joint {
+        Joint {
+            name: String::new(),
+            parent,
+            rest: JointTransform { translation, ..JointTransform::IDENTITY },
+            inverse_bind: Matrix4::identity(),
+        }
+    }
+
+    fn translation_channel(node: usize, times: Vec<f32>, xs: &[f32], interpolation: Interpolation) -> Channel {
+        let values = xs.iter().map(|&x| Vector3::new(x, 0.0, 0.0)).collect();
+        Channel { node, times, keyframes: Keyframes::Translation(values), interpolation }
+    }
+
+    fn one_joint() -> Skeleton {
+        Skeleton::new(vec![joint(None, Vector3::new(0.0, 0.0, 0.0))], &[0], Matrix4::identity())
+    }
+
+    #[test]
+    fn locate_finds_the_surrounding_keyframes() {
+        let channel = translation_channel(0, vec![0.0, 1.0, 3.0], &[0.0, 1.0, 3.0], Interpolation::Linear);
+        assert_eq!(channel.locate(-1.0), (0, 0, 0.0));
+        assert_eq!(channel.locate(1.0), (1, 2, 0.0));
+        assert_eq!(channel.locate(2.0), (1, 2, 0.5));
+        assert_eq!(channel.locate(3.0), (2, 2, 0.0));
+        assert_eq!(channel.locate(5.0), (2, 2, 0.0));
+    }
+
+    #[test]
+    fn step_holds_until_the_next_keyframe() {
+        let skeleton = one_joint();
+        let step = Clip::new("step", vec![translation_channel(0, vec![0.0, 2.0], &[0.0, 4.0], Interpolation::Step)]);
+        let linear = Clip::new("linear", vec![translation_channel(0, vec![0.0, 2.0], &[0.0, 4.0], Interpolation::Linear)]);
+        assert_eq!(step.sample(&skeleton, 1.5, false).locals[0].translation.x, 0.0);
+        assert_eq!(step.sample(&skeleton, 2.0, false).locals[0].translation.x, 4.0);
+        assert_eq!(linear.sample(&skeleton, 1.5, false).locals[0].translation.x, 3.0);
+    }
+
+    #[test]
+    fn looping_clips_wrap_and_others_clamp() {
+        let skeleton = one_joint();
+        let clip = Clip::new("walk", vec![translation_channel(0, vec![0.0, 2.0], &[0.0, 4.0], Interpolation::Linear)]);
+        assert_eq!(clip.duration, 2.0);
+        assert_eq!(clip.sample(&skeleton, 2.5, true).locals[0].translation.x, 1.0);
+        assert_eq!(clip.sample(&skeleton, -0.5, true).locals[0].translation.x, 3.0);
+        assert_eq!(clip.sample(&skeleton, 2.5, false).locals[0].translation.x, 4.0);
+    }
+
+    #[test]
+    fn slerp_takes_the_shortest_path() {
+        let a = Quaternion::from_angle_y(Deg(0.0));
+        let b = -Quaternion::from_angle_y(Deg(90.0));
+        let half = slerp(a, b, 0.5);
+        let rotated = half.rotate_vector(Vector3::new(0.0, 0.0, 1.0));
+        let expected = Quaternion::from_angle_y(Deg(45.0)).rotate_vector(Vector3::new(0.0, 0.0, 1.0));
+        assert!((rotated - expected).magnitude() < 1e-5, "{:?}", rotated);
+        assert!((half.magnitude() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn cross_fades_reach_the_new_clip() {
+        let skeleton = one_joint();
+        let clips = [
+            Clip::new("idle", vec![translation_channel(0, vec![0.0, 1.0], &[0.0, 0.0], Interpolation::Linear)]),
+            Clip::new("run", vec![translation_channel(0, vec![0.0, 1.0], &[2.0, 2.0], Interpolation::Linear)]),
+        ];
+        let mut player = AnimationPlayer::new(0, true);
+        player.play(1, true, 0.5);
+        assert_eq!(player.pose(&skeleton, &clips).locals[0].translation.x, 0.0);
+        player.advance(0.25);
+        assert_eq!(player.pose(&skeleton, &clips).locals[0].translation.x, 1.0);
+        player.advance(0.25);
+        assert_eq!(player.current_clip(), 1);
+        assert_eq!(player.pose(&skeleton, &clips).locals[0].translation.x, 2.0);
+        player.advance(0.25);
+        assert_eq!(player.pose(&skeleton, &clips).locals[0].translation.x, 2.0);
+    }
+
+    #[test]
+    fn global_matrices_apply_parents_first() {
+        // The child is listed before its parent.
+        let joints = vec![joint(Some(1), Vector3::new(0.0, 1.0, 0.0)), joint(None, Vector3::new(1.0, 0.0, 0.0))];
+        let root = Matrix4::from_translation(Vector3::new(0.0, 0.0, 5.0));
+        let skeleton = Skeleton::new(joints, &[10, 11], root);
+        let globals = Pose::rest(&skeleton).global_matrices(&skeleton);
+        assert_eq!(globals[1].w.truncate(), Vector3::new(1.0, 0.0, 5.0));
+        assert_eq!(globals[0].w.truncate(), Vector3::new(1.0, 1.0, 5.0));
+        assert_eq!(skeleton.joint_for_node(11), Some(1));
+    }
+}
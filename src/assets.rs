@@ -0,0 +1,180 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use log::{error, info};
+
+use crate::helpers::{self, ColorSpace};
+
+/// Refers to a texture requested from an `AssetLoader`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(usize);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub loaded: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl LoadProgress {
+    pub fn is_done(&self) -> bool {
+        self.loaded + self.failed == self.total
+    }
+
+    /// Fraction of requests that finished, in `0.0..=1.0`.
+    pub fn ratio(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            (self.loaded + self.failed) as f32 / self.total as f32
+        }
+    }
+}
+
+enum Slot {
+    Pending,
+    Ready(wgpu::Texture),
+    Failed,
+}
+
+struct Job {
+    handle: TextureHandle,
+    path: String,
+    color_space: ColorSpace,
+}
+
+struct Decoded {
+    job: Job,
+    image: image::ImageResult<image::RgbaImage>,
+}
+
+/// Decodes textures on a pool of worker threads and uploads them on the
+/// thread calling `poll`, which must be the one owning the device and queue.
+pub struct AssetLoader {
+    jobs: Option<Sender<Job>>,
+    /// Lets already decoded images skip the workers.
+    decoded_tx: Sender<Decoded>,
+    decoded: Receiver<Decoded>,
+    workers: Vec<thread::JoinHandle<()>>,
+    slots: Vec<Slot>,
+    progress: LoadProgress,
+}
+
+impl AssetLoader {
+    pub fn new(num_workers: usize) -> Self {
+        let (job_tx, job_rx) = channel::<Job>();
+        let (decoded_tx, decoded_rx) = channel();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+
+        let workers = (0..num_workers.max(1))
+            .map(|i| {
+                let job_rx = job_rx.clone();
+                let decoded_tx = decoded_tx.clone();
+                thread::Builder::new()
+                    .name(format!("asset-worker-{}", i))
+                    .spawn(move || loop {
+                        let job = match job_rx.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            Err(_) => break,
+                        };
+                        let image = helpers::decode_image(&job.path);
+                        if decoded_tx.send(Decoded { job, image }).is_err() {
+                            break;
+                        }
+                    })
+                    .expect("failed to spawn asset worker")
+            })
+            .collect();
+
+        Self {
+            jobs: Some(job_tx),
+            decoded_tx,
+            decoded: decoded_rx,
+            workers,
+            slots: Vec::new(),
+            progress: LoadProgress::default(),
+        }
+    }
+
+    /// Queues a texture from `res/textures` for loading and returns immediately.
+    pub fn load_texture(&mut self, path: &str, color_space: ColorSpace) -> TextureHandle {
+        let handle = TextureHandle(self.slots.len());
+        self.slots.push(Slot::Pending);
+        self.progress.total += 1;
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send(Job { handle, path: path.to_string(), color_space })
+            .expect("asset workers are gone");
+        handle
+    }
+
+    /// Queues an image that is already decoded, such as one embedded in a
+    /// model, to be uploaded by the next `poll` like any other texture.
+    pub fn add_image(&mut self, label: &str, image: image::RgbaImage, color_space: ColorSpace) -> TextureHandle {
+        let handle = TextureHandle(self.slots.len());
+        self.slots.push(Slot::Pending);
+        self.progress.total += 1;
+        let job = Job { handle, path: label.to_string(), color_space };
+        self.decoded_tx
+            .send(Decoded { job, image: Ok(image) })
+            .expect("the loader holds its own receiver");
+        handle
+    }
+
+    /// Uploads every texture decoded since the last call. Returns the handles
+    /// that became ready so callers can swap them in.
+    pub fn poll(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<TextureHandle> {
+        let mut ready = Vec::new();
+        while let Ok(Decoded { job, image }) = self.decoded.try_recv() {
+            match image {
+                Ok(image) => {
+                    info!("Loaded texture {}", job.path);
+                    let texture = helpers::upload_texture(&job.path, &image, job.color_space, device, queue);
+                    self.slots[job.handle.0] = Slot::Ready(texture);
+                    self.progress.loaded += 1;
+                    ready.push(job.handle);
+                }
+                Err(e) => {
+                    error!("Failed to load texture {}: {}", job.path, e);
+                    self.slots[job.handle.0] = Slot::Failed;
+                    self.progress.failed += 1;
+                }
+            }
+        }
+        ready
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> Option<&wgpu::Texture> {
+        match &self.slots[handle.0] {
+            Slot::Ready(texture) => Some(texture),
+            Slot::Pending | Slot::Failed => None,
+        }
+    }
+
+    pub fn is_failed(&self, handle: TextureHandle) -> bool {
+        matches!(self.slots[handle.0], Slot::Failed)
+    }
+
+    pub fn progress(&self) -> LoadProgress {
+        self.progress
+    }
+}
+
+impl Default for AssetLoader {
+    fn default() -> Self {
+        let num_workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(2);
+        Self::new(num_workers)
+    }
+}
+
+impl Drop for AssetLoader {
+    fn drop(&mut self) {
+        // Closing the job channel makes every worker fall out of its loop.
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -6,6 +6,37 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+/// Half-line from `origin` along the normalised `direction`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: cgmath::Point3<f32>,
+    pub direction: cgmath::Vector3<f32>,
+}
+
+impl Ray {
+    pub fn at(&self, distance: f32) -> cgmath::Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// Distance along the ray to where it enters the sphere, or zero when it
+    /// starts inside.
+    pub fn sphere_distance(&self, center: cgmath::Point3<f32>, radius: f32) -> Option<f32> {
+        use cgmath::InnerSpace;
+        let offset = self.origin - center;
+        let b = offset.dot(self.direction);
+        let c = offset.magnitude2() - radius * radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let far = -b + discriminant.sqrt();
+        if far < 0.0 {
+            return None;
+        }
+        Some((-b - discriminant.sqrt()).max(0.0))
+    }
+}
+
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
@@ -17,11 +48,62 @@ pub struct Camera {
 }
 
 impl Camera {
+    pub fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
     pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
+        let view = self.build_view_matrix();
         let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
 
-        return OPENGL_TO_WGPU_MATRIX * proj * view;
+        OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+
+    /// World-space corners of the slice of the view frustum between the
+    /// view-space distances `near` and `far`, near plane first.
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [cgmath::Point3<f32>; 8] {
+        use cgmath::InnerSpace;
+        let forward = (self.target - self.eye).normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+        let tan_half_fovy = (self.fovy.to_radians() / 2.0).tan();
+
+        let mut corners = [self.eye; 8];
+        for (i, &distance) in [near, far].iter().enumerate() {
+            let half_height = distance * tan_half_fovy;
+            let half_width = half_height * self.aspect;
+            let center = self.eye + forward * distance;
+            corners[i * 4] = center - right * half_width - up * half_height;
+            corners[i * 4 + 1] = center + right * half_width - up * half_height;
+            corners[i * 4 + 2] = center + right * half_width + up * half_height;
+            corners[i * 4 + 3] = center - right * half_width + up * half_height;
+        }
+        corners
     }
-}
 
+    /// Ray from the eye through a point in pixels from the top left of a
+    /// `screen_size` window.
+    pub fn screen_ray(&self, position: [f32; 2], screen_size: [f32; 2]) -> Ray {
+        use cgmath::{EuclideanSpace, InnerSpace, SquareMatrix};
+        let x = position[0] / screen_size[0].max(1.0) * 2.0 - 1.0;
+        let y = 1.0 - position[1] / screen_size[1].max(1.0) * 2.0;
+        let inverse = self.build_view_projection_matrix().invert().expect("view projection is invertible");
+        let unproject = |z: f32| {
+            let point = inverse * cgmath::Vector4::new(x, y, z, 1.0);
+            cgmath::Point3::from_vec(point.truncate() / point.w)
+        };
+        let (near, far) = (unproject(0.0), unproject(1.0));
+        Ray { origin: near, direction: (far - near).normalize() }
+    }
+
+    /// Pixel position of a world point in a `screen_size` window, or `None`
+    /// when it is behind the camera.
+    pub fn world_to_screen(&self, point: cgmath::Point3<f32>, screen_size: [f32; 2]) -> Option<[f32; 2]> {
+        let clip = self.build_view_projection_matrix() * point.to_homogeneous();
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+        Some([(x + 1.0) / 2.0 * screen_size[0], (1.0 - y) / 2.0 * screen_size[1]])
+    }
+}
@@ -0,0 +1,470 @@
+//! Immediate-mode debug shapes. Anything may call these during a frame; the
+//! shapes are collected globally and drawn once by `DebugRenderer`, then
+//! cleared. Without the `debug_draw` feature every call compiles to nothing.
+
+use std::sync::Mutex;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+const ENABLED: bool = cfg!(feature = "debug_draw");
+/// Segments used to approximate each circle of a sphere.
+const CIRCLE_SEGMENTS: usize = 24;
+
+/// Whether a shape is hidden behind scene geometry or drawn on top of it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Depth {
+    Test,
+    Overlay,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+struct Label {
+    position: Point3<f32>,
+    text: String,
+    color: [f32; 4],
+    height: f32,
+    depth: Depth,
+}
+
+struct Frame {
+    tested: Vec<DebugVertex>,
+    overlay: Vec<DebugVertex>,
+    labels: Vec<Label>,
+}
+
+impl Frame {
+    const fn new() -> Self {
+        Self { tested: Vec::new(), overlay: Vec::new(), labels: Vec::new() }
+    }
+
+    fn vertices(&mut self, depth: Depth) -> &mut Vec<DebugVertex> {
+        match depth {
+            Depth::Test => &mut self.tested,
+            Depth::Overlay => &mut self.overlay,
+        }
+    }
+}
+
+static FRAME: Mutex<Frame> = Mutex::new(Frame::new());
+
+fn with_frame(f: impl FnOnce(&mut Frame)) {
+    // A panic while recording only loses debug shapes, so ignore poisoning.
+    let mut frame = FRAME.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(&mut frame);
+}
+
+pub fn line(start: Point3<f32>, end: Point3<f32>, color: [f32; 4], depth: Depth) {
+    if ENABLED {
+        with_frame(|frame| {
+            frame.vertices(depth).extend_from_slice(&[
+                DebugVertex { position: start.into(), color },
+                DebugVertex { position: end.into(), color },
+            ]);
+        });
+    }
+}
+
+/// Axis-aligned box between the corners `min` and `max`.
+pub fn aabb(min: Point3<f32>, max: Point3<f32>, color: [f32; 4], depth: Depth) {
+    if !ENABLED {
+        return;
+    }
+    let corner = |i: usize| {
+        Point3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        )
+    };
+    // Each edge joins two corners that differ in exactly one axis.
+    for i in 0..8 {
+        for axis in [1, 2, 4] {
+            if i & axis == 0 {
+                line(corner(i), corner(i | axis), color, depth);
+            }
+        }
+    }
+}
+
+/// Circle around `normal`, which must not be zero.
+pub fn circle(center: Point3<f32>, normal: Vector3<f32>, radius: f32, color: [f32; 4], depth: Depth) {
+    if !ENABLED {
+        return;
+    }
+    let normal = normal.normalize();
+    let reference = if normal.y.abs() > 0.99 { Vector3::unit_x() } else { Vector3::unit_y() };
+    let u = normal.cross(reference).normalize() * radius;
+    let v = normal.cross(u);
+    let point = |i: usize| {
+        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+        center + u * angle.cos() + v * angle.sin()
+    };
+    for i in 0..CIRCLE_SEGMENTS {
+        line(point(i), point(i + 1), color, depth);
+    }
+}
+
+/// Wireframe sphere drawn as three great circles.
+pub fn sphere(center: Point3<f32>, radius: f32, color: [f32; 4], depth: Depth) {
+    circle(center, Vector3::unit_x(), radius, color, depth);
+    circle(center, Vector3::unit_y(), radius, color, depth);
+    circle(center, Vector3::unit_z(), radius, color, depth);
+}
+
+/// Line with a head at `end` sized relative to its length.
+pub fn arrow(start: Point3<f32>, end: Point3<f32>, color: [f32; 4], depth: Depth) {
+    if !ENABLED {
+        return;
+    }
+    line(start, end, color, depth);
+    let shaft = end - start;
+    let length = shaft.magnitude();
+    if length <= f32::EPSILON {
+        return;
+    }
+    let direction = shaft / length;
+    let reference = if direction.y.abs() > 0.99 { Vector3::unit_x() } else { Vector3::unit_y() };
+    let side = direction.cross(reference).normalize();
+    let up = side.cross(direction);
+    let head = length * 0.2;
+    for offset in [side, -side, up, -up] {
+        line(end, end - direction * head + offset * head * 0.5, color, depth);
+    }
+}
+
+/// Square grid on the horizontal plane through `center`, with `cells` cells
+/// of `cell_size` per side.
+pub fn grid(center: Point3<f32>, cells: u32, cell_size: f32, color: [f32; 4], depth: Depth) {
+    if !ENABLED {
+        return;
+    }
+    let half = cells as f32 * cell_size / 2.0;
+    for i in 0..=cells {
+        let offset = i as f32 * cell_size - half;
+        line(center + Vector3::new(offset, 0.0, -half), center + Vector3::new(offset, 0.0, half), color, depth);
+        line(center + Vector3::new(-half, 0.0, offset), center + Vector3::new(half, 0.0, offset), color, depth);
+    }
+}
+
+/// Text facing the camera, centred above `position`. Letters are `height`
+/// world units tall and drawn with a segment font, so only letters, digits
+/// and a little punctuation show up.
+pub fn text(position: Point3<f32>, text: &str, color: [f32; 4], height: f32, depth: Depth) {
+    if ENABLED {
+        with_frame(|frame| {
+            frame.labels.push(Label { position, text: text.to_owned(), color, height, depth });
+        });
+    }
+}
+
+// Sixteen-segment font. Glyphs are one unit wide and two tall.
+const A1: u16 = 1 << 0;
+const A2: u16 = 1 << 1;
+const B: u16 = 1 << 2;
+const C: u16 = 1 << 3;
+const D2: u16 = 1 << 4;
+const D1: u16 = 1 << 5;
+const E: u16 = 1 << 6;
+const F: u16 = 1 << 7;
+const G1: u16 = 1 << 8;
+const G2: u16 = 1 << 9;
+const H: u16 = 1 << 10;
+const I: u16 = 1 << 11;
+const J: u16 = 1 << 12;
+const K: u16 = 1 << 13;
+const L: u16 = 1 << 14;
+const M: u16 = 1 << 15;
+
+/// End points of each segment, indexed by bit.
+const SEGMENTS: [([f32; 2], [f32; 2]); 16] = [
+    ([0.0, 2.0], [0.5, 2.0]),
+    ([0.5, 2.0], [1.0, 2.0]),
+    ([1.0, 2.0], [1.0, 1.0]),
+    ([1.0, 1.0], [1.0, 0.0]),
+    ([1.0, 0.0], [0.5, 0.0]),
+    ([0.5, 0.0], [0.0, 0.0]),
+    ([0.0, 0.0], [0.0, 1.0]),
+    ([0.0, 1.0], [0.0, 2.0]),
+    ([0.0, 1.0], [0.5, 1.0]),
+    ([0.5, 1.0], [1.0, 1.0]),
+    ([0.0, 2.0], [0.5, 1.0]),
+    ([0.5, 2.0], [0.5, 1.0]),
+    ([1.0, 2.0], [0.5, 1.0]),
+    ([0.5, 1.0], [0.0, 0.0]),
+    ([0.5, 1.0], [0.5, 0.0]),
+    ([0.5, 1.0], [1.0, 0.0]),
+];
+
+fn glyph(c: char) -> u16 {
+    const O: u16 = A1 | A2 | B | C | D1 | D2 | E | F;
+    const P: u16 = A1 | A2 | B | E | F | G1 | G2;
+    match c.to_ascii_uppercase() {
+        '0' => O | J | K,
+        '1' => B | C | J,
+        '2' => A1 | A2 | B | G1 | G2 | E | D1 | D2,
+        '3' => A1 | A2 | B | C | D1 | D2 | G2,
+        '4' => F | G1 | G2 | B | C,
+        '5' | 'S' => A1 | A2 | F | G1 | G2 | C | D1 | D2,
+        '6' => O & !B | G1 | G2,
+        '7' => A1 | A2 | B | C,
+        '8' => O | G1 | G2,
+        '9' => O & !E | G1 | G2,
+        'A' => A1 | A2 | B | C | E | F | G1 | G2,
+        'B' => A1 | A2 | B | C | D1 | D2 | I | L | G2,
+        'C' => A1 | A2 | F | E | D1 | D2,
+        'D' => A1 | A2 | B | C | D1 | D2 | I | L,
+        'E' => A1 | A2 | F | E | D1 | D2 | G1,
+        'F' => A1 | A2 | F | E | G1,
+        'G' => A1 | A2 | F | E | D1 | D2 | C | G2,
+        'H' => F | E | B | C | G1 | G2,
+        'I' => A1 | A2 | I | L | D1 | D2,
+        'J' => B | C | D1 | D2 | E,
+        'K' => F | E | G1 | J | M,
+        'L' => F | E | D1 | D2,
+        'M' => F | E | B | C | H | J,
+        'N' => F | E | B | C | H | M,
+        'O' => O,
+        'P' => P,
+        'Q' => O | M,
+        'R' => P | M,
+        'T' => A1 | A2 | I | L,
+        'U' => F | E | D1 | D2 | C | B,
+        'V' => F | E | K | J,
+        'W' => F | E | B | C | K | M,
+        'X' => H | J | K | M,
+        'Y' => H | J | L,
+        'Z' => A1 | A2 | J | K | D1 | D2,
+        '-' => G1 | G2,
+        '+' => G1 | G2 | I | L,
+        '=' => G1 | G2 | D1 | D2,
+        '_' => D1 | D2,
+        '.' | ',' => D1,
+        '/' => J | K,
+        '\\' => H | M,
+        '(' | '<' => J | M,
+        ')' | '>' => H | K,
+        '*' => G1 | G2 | H | I | J | K | L | M,
+        _ => 0,
+    }
+}
+
+#[cfg_attr(not(feature = "debug_draw"), allow(dead_code))]
+/// Expands the recorded labels into segments facing the camera.
+fn push_label(frame: &mut Frame, label: &Label, right: Vector3<f32>, up: Vector3<f32>) {
+    let scale = label.height / 2.0;
+    let advance = 1.5;
+    let width = label.text.chars().count() as f32 * advance - 0.5;
+    let origin = label.position - right * (width * scale / 2.0);
+    let vertices = frame.vertices(label.depth);
+    for (i, c) in label.text.chars().enumerate() {
+        let mask = glyph(c);
+        let x = i as f32 * advance;
+        for (bit, (start, end)) in SEGMENTS.iter().enumerate() {
+            if mask & (1 << bit) != 0 {
+                for point in [start, end] {
+                    let position = origin + (right * (x + point[0]) + up * point[1]) * scale;
+                    vertices.push(DebugVertex { position: position.into(), color: label.color });
+                }
+            }
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "debug_draw"), allow(dead_code))]
+/// Everything recorded since the last call, as line list vertices: the
+/// depth tested ones first, then `overlay` more drawn on top. Labels face
+/// along the camera's `right` and `up` vectors.
+fn take_frame(right: Vector3<f32>, up: Vector3<f32>) -> (Vec<DebugVertex>, u32) {
+    let mut frame = FRAME.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let labels = std::mem::take(&mut frame.labels);
+    for label in &labels {
+        push_label(&mut frame, label, right, up);
+    }
+    let mut vertices = std::mem::take(&mut frame.tested);
+    let overlay = frame.overlay.len() as u32;
+    vertices.append(&mut frame.overlay);
+    (vertices, overlay)
+}
+
+#[cfg(feature = "debug_draw")]
+pub use self::renderer::DebugRenderer;
+
+#[cfg(feature = "debug_draw")]
+mod renderer {
+    use std::borrow::Cow;
+    use std::sync::Arc;
+
+    use cgmath::InnerSpace;
+    use crate::SceneTargets;
+    use crate::camera::Camera;
+    use super::DebugVertex;
+
+    impl DebugVertex {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+        fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+            wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Vertex,
+                attributes: &Self::ATTRIBUTES,
+            }
+        }
+    }
+
+    /// Draws the shapes recorded through `debug_draw` from one dynamic
+    /// vertex buffer, with and without depth testing.
+    pub struct DebugRenderer {
+        shader: wgpu::ShaderModule,
+        pipeline_layout: wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        tested_pipeline: wgpu::RenderPipeline,
+        overlay_pipeline: wgpu::RenderPipeline,
+        vertex_buffer: wgpu::Buffer,
+        capacity: usize,
+        tested_count: u32,
+        overlay_count: u32,
+    }
+
+    impl DebugRenderer {
+        pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat, sample_count: u32, uniforms_bgl: &wgpu::BindGroupLayout) -> Self {
+            let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some("debug draw shader"),
+                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!("../res/shader/globals.wgsl"), include_str!("../res/shader/debug.wgsl")))),
+                flags: wgpu::ShaderFlags::all(),
+            });
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[uniforms_bgl],
+                push_constant_ranges: &[],
+            });
+            let (tested_pipeline, overlay_pipeline) = Self::create_pipelines(device, &pipeline_layout, &shader, color_format, sample_count);
+            let capacity = 1024;
+            Self {
+                shader,
+                pipeline_layout,
+                color_format,
+                tested_pipeline,
+                overlay_pipeline,
+                vertex_buffer: Self::create_buffer(device, capacity),
+                capacity,
+                tested_count: 0,
+                overlay_count: 0,
+            }
+        }
+
+        fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Debug Vertex Buffer"),
+                size: (capacity * std::mem::size_of::<DebugVertex>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+                mapped_at_creation: false,
+            })
+        }
+
+        fn create_pipelines(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, color_format: wgpu::TextureFormat, sample_count: u32) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+            let create = |depth_compare| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("debug draw pipeline"),
+                    layout: Some(layout),
+                    vertex: wgpu::VertexState {
+                        module: shader,
+                        entry_point: "vs_main",
+                        buffers: &[DebugVertex::desc()],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: shader,
+                        entry_point: "fs_main",
+                        targets: &[wgpu::ColorTargetState {
+                            format: color_format,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrite::ALL,
+                        }],
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::LineList,
+                        ..Default::default()
+                    },
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        depth_write_enabled: false,
+                        depth_compare,
+                        ..crate::depth_stencil_state()
+                    }),
+                    multisample: wgpu::MultisampleState {
+                        count: sample_count,
+                        ..Default::default()
+                    },
+                })
+            };
+            (create(wgpu::CompareFunction::LessEqual), create(wgpu::CompareFunction::Always))
+        }
+
+        /// Rebuilds the pipelines for targets with `sample_count` samples.
+        pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+            let (tested, overlay) = Self::create_pipelines(device, &self.pipeline_layout, &self.shader, self.color_format, sample_count);
+            self.tested_pipeline = tested;
+            self.overlay_pipeline = overlay;
+        }
+
+        /// Uploads and clears everything recorded this frame. Labels are
+        /// turned to face `camera`.
+        pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, camera: &Camera) {
+            let forward = (camera.target - camera.eye).normalize();
+            let right = forward.cross(camera.up).normalize();
+            let up = right.cross(forward);
+            let (vertices, overlay) = super::take_frame(right, up);
+            if vertices.len() > self.capacity {
+                self.capacity = vertices.len().next_power_of_two();
+                self.vertex_buffer = Self::create_buffer(device, self.capacity);
+            }
+            if !vertices.is_empty() {
+                queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+            }
+            self.tested_count = vertices.len() as u32 - overlay;
+            self.overlay_count = overlay;
+        }
+
+        pub async fn draw(&self, device: &wgpu::Device, targets: Arc<SceneTargets>, uniforms_bg: &wgpu::BindGroup) -> wgpu::CommandBuffer {
+            let mut encoder =
+                device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("debug draw") });
+            {
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("debug draw"),
+                    color_attachments: &[wgpu::RenderPassColorAttachment {
+                        view: &targets.color,
+                        resolve_target: targets.resolve.as_deref(),
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    }],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &targets.depth,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                pass.set_bind_group(0, uniforms_bg, &[]);
+                pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                if self.tested_count > 0 {
+                    pass.set_pipeline(&self.tested_pipeline);
+                    pass.draw(0..self.tested_count, 0..1);
+                }
+                if self.overlay_count > 0 {
+                    let start = self.tested_count;
+                    pass.set_pipeline(&self.overlay_pipeline);
+                    pass.draw(start..start + self.overlay_count, 0..1);
+                }
+            }
+            encoder.finish()
+        }
+    }
+}
@@ -0,0 +1,796 @@
+//! Entities and components for game objects. Components of each type live in
+//! a packed sparse set; queries walk the smallest set involved. Everything is
+//! ordered by insertion so the same sequence of calls always visits entities
+//! in the same order, which the simulation relies on for determinism.
+//!
+//! ```text
+//! world.query::<(&mut Position, &Velocity)>(|_, (position, velocity)| {
+//!     *position += velocity.0 * tick.delta;
+//! });
+//! ```
+
+use std::any::{Any, TypeId};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Handle to a game object. Stale handles to despawned entities are detected
+/// by the generation, so they never alias a newer entity in the same slot.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+impl Entity {
+    /// Rebuilds a handle written out with `Display`, e.g. in a saved order.
+    pub fn from_raw(index: u32, generation: u32) -> Self {
+        Self { index, generation }
+    }
+
+    pub fn index(self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
+
+impl fmt::Display for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+/// Components of one type, packed densely with a lookup from entity index.
+/// Only public because queries borrow it.
+pub struct Storage<T> {
+    sparse: Vec<Option<u32>>,
+    entities: Vec<Entity>,
+    components: Vec<T>,
+    added: Vec<u64>,
+    changed: Vec<u64>,
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Self { sparse: Vec::new(), entities: Vec::new(), components: Vec::new(), added: Vec::new(), changed: Vec::new() }
+    }
+}
+
+impl<T> Storage<T> {
+    fn dense(&self, entity: Entity) -> Option<usize> {
+        let dense = (*self.sparse.get(entity.index as usize)?)? as usize;
+        (self.entities[dense] == entity).then_some(dense)
+    }
+
+    fn insert(&mut self, entity: Entity, component: T, tick: u64) {
+        if let Some(dense) = self.dense(entity) {
+            self.components[dense] = component;
+            self.changed[dense] = tick;
+            return;
+        }
+        let index = entity.index as usize;
+        if self.sparse.len() <= index {
+            self.sparse.resize(index + 1, None);
+        }
+        self.sparse[index] = Some(self.entities.len() as u32);
+        self.entities.push(entity);
+        self.components.push(component);
+        self.added.push(tick);
+        self.changed.push(tick);
+    }
+
+    fn remove(&mut self, entity: Entity) -> Option<T> {
+        let dense = self.dense(entity)?;
+        self.sparse[entity.index as usize] = None;
+        self.entities.swap_remove(dense);
+        self.added.swap_remove(dense);
+        self.changed.swap_remove(dense);
+        let component = self.components.swap_remove(dense);
+        if let Some(moved) = self.entities.get(dense) {
+            self.sparse[moved.index as usize] = Some(dense as u32);
+        }
+        Some(component)
+    }
+}
+
+/// Type-erased storage so the world can despawn without knowing types.
+trait AnyStorage {
+    fn remove_entity(&mut self, entity: Entity);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> AnyStorage for Storage<T> {
+    fn remove_entity(&mut self, entity: Entity) {
+        self.remove(entity);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+type Command = Box<dyn FnOnce(&mut World)>;
+
+/// All entities, their components and global resources.
+pub struct World {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+    storages: HashMap<TypeId, RefCell<Box<dyn AnyStorage>>>,
+    resources: HashMap<TypeId, RefCell<Box<dyn Any>>>,
+    commands: RefCell<Vec<Command>>,
+    /// Stamp for changes made now. Advances after every system run.
+    tick: u64,
+    /// `Added` and `Changed` match changes made after this tick.
+    since: Cell<u64>,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self {
+            generations: Vec::new(),
+            alive: Vec::new(),
+            free: Vec::new(),
+            storages: HashMap::new(),
+            resources: HashMap::new(),
+            commands: RefCell::new(Vec::new()),
+            tick: 1,
+            since: Cell::new(0),
+        }
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                self.alive[index as usize] = true;
+                Entity { index, generation: self.generations[index as usize] }
+            }
+            None => {
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity { index: self.generations.len() as u32 - 1, generation: 0 }
+            }
+        }
+    }
+
+    /// Spawns an entity with a tuple of components.
+    pub fn spawn_with(&mut self, bundle: impl Bundle) -> Entity {
+        let entity = self.spawn();
+        bundle.insert_into(self, entity);
+        entity
+    }
+
+    /// Removes the entity and all its components. Returns false if it was
+    /// already gone.
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+        for storage in self.storages.values() {
+            storage.borrow_mut().remove_entity(entity);
+        }
+        let index = entity.index as usize;
+        self.alive[index] = false;
+        self.generations[index] += 1;
+        self.free.push(entity.index);
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let index = entity.index as usize;
+        self.alive.get(index).copied().unwrap_or(false) && self.generations[index] == entity.generation
+    }
+
+    /// Every living entity, in slot order.
+    pub fn entities(&self) -> Vec<Entity> {
+        self.alive
+            .iter()
+            .enumerate()
+            .filter(|(_, alive)| **alive)
+            .map(|(index, _)| Entity { index: index as u32, generation: self.generations[index] })
+            .collect()
+    }
+
+    /// Adds or replaces a component. Panics if the entity is dead.
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) {
+        assert!(self.is_alive(entity), "inserting a component on dead {:?}", entity);
+        let tick = self.tick;
+        self.storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| RefCell::new(Box::new(Storage::<T>::default())))
+            .get_mut()
+            .as_any_mut()
+            .downcast_mut::<Storage<T>>()
+            .expect("storage matches its type id")
+            .insert(entity, component, tick);
+    }
+
+    pub fn remove<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        self.storages.get_mut(&TypeId::of::<T>())?.get_mut().as_any_mut().downcast_mut::<Storage<T>>()?.remove(entity)
+    }
+
+    pub fn has<T: 'static>(&self, entity: Entity) -> bool {
+        self.storage::<T>().is_some_and(|storage| storage.dense(entity).is_some())
+    }
+
+    pub fn get<T: 'static>(&self, entity: Entity) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.storage::<T>()?, |storage| storage.dense(entity).map(|dense| &storage.components[dense])).ok()
+    }
+
+    /// Mutable access, which counts as a change whether or not anything is
+    /// written.
+    pub fn get_mut<T: 'static>(&self, entity: Entity) -> Option<RefMut<'_, T>> {
+        let tick = self.tick;
+        RefMut::filter_map(self.storage_mut::<T>()?, |storage| {
+            let dense = storage.dense(entity)?;
+            storage.changed[dense] = tick;
+            Some(&mut storage.components[dense])
+        })
+        .ok()
+    }
+
+    fn storage<T: 'static>(&self) -> Option<Ref<'_, Storage<T>>> {
+        let cell = self.storages.get(&TypeId::of::<T>())?;
+        Some(Ref::map(cell.borrow(), |storage| storage.as_any().downcast_ref::<Storage<T>>().expect("storage matches its type id")))
+    }
+
+    fn storage_mut<T: 'static>(&self) -> Option<RefMut<'_, Storage<T>>> {
+        let cell = self.storages.get(&TypeId::of::<T>())?;
+        Some(RefMut::map(cell.borrow_mut(), |storage| {
+            storage.as_any_mut().downcast_mut::<Storage<T>>().expect("storage matches its type id")
+        }))
+    }
+
+    /// Calls `f` for every entity matching `Q`, in a deterministic order.
+    /// Component types may appear in several nested queries as long as no
+    /// type is borrowed mutably twice.
+    pub fn query<Q: Query>(&self, mut f: impl FnMut(Entity, Q::Item<'_>)) {
+        let since = self.since.get();
+        let mut state = match Q::borrow(self) {
+            Some(state) => state,
+            None => return,
+        };
+        let entities = Q::candidates(&state).map_or_else(|| self.entities(), <[Entity]>::to_vec);
+        for entity in entities {
+            if Q::matches(&state, entity, since) {
+                f(entity, Q::fetch(&mut state, entity, self.tick));
+            }
+        }
+    }
+
+    /// Runs `f` on one entity's components if it matches `Q`.
+    pub fn query_one<Q: Query, R>(&self, entity: Entity, f: impl FnOnce(Q::Item<'_>) -> R) -> Option<R> {
+        let mut state = Q::borrow(self)?;
+        if !self.is_alive(entity) || !Q::matches(&state, entity, self.since.get()) {
+            return None;
+        }
+        Some(f(Q::fetch(&mut state, entity, self.tick)))
+    }
+
+    /// Entities matching `Q`, without borrowing their components.
+    pub fn matching<Q: Query>(&self) -> Vec<Entity> {
+        let mut entities = Vec::new();
+        if let Some(state) = Q::borrow(self) {
+            let since = self.since.get();
+            let candidates = Q::candidates(&state).map_or_else(|| self.entities(), <[Entity]>::to_vec);
+            entities.extend(candidates.into_iter().filter(|&entity| Q::matches(&state, entity, since)));
+        }
+        entities
+    }
+
+    /// Queues a structural change, e.g. spawning from inside a query, to be
+    /// applied after the current system or by `apply_commands`.
+    pub fn defer(&self, command: impl FnOnce(&mut World) + 'static) {
+        self.commands.borrow_mut().push(Box::new(command));
+    }
+
+    pub fn apply_commands(&mut self) {
+        loop {
+            let commands = std::mem::take(self.commands.get_mut());
+            if commands.is_empty() {
+                break;
+            }
+            for command in commands {
+                command(self);
+            }
+        }
+    }
+
+    pub fn insert_resource<R: 'static>(&mut self, resource: R) {
+        self.resources.insert(TypeId::of::<R>(), RefCell::new(Box::new(resource)));
+    }
+
+    pub fn remove_resource<R: 'static>(&mut self) -> Option<R> {
+        let resource = self.resources.remove(&TypeId::of::<R>())?.into_inner();
+        resource.downcast().ok().map(|resource| *resource)
+    }
+
+    pub fn resource<R: 'static>(&self) -> Option<Ref<'_, R>> {
+        let cell = self.resources.get(&TypeId::of::<R>())?;
+        Some(Ref::map(cell.borrow(), |resource| resource.downcast_ref().expect("resource matches its type id")))
+    }
+
+    pub fn resource_mut<R: 'static>(&self) -> Option<RefMut<'_, R>> {
+        let cell = self.resources.get(&TypeId::of::<R>())?;
+        Some(RefMut::map(cell.borrow_mut(), |resource| resource.downcast_mut().expect("resource matches its type id")))
+    }
+
+    /// The tick changes are currently stamped with.
+    pub fn change_tick(&self) -> u64 {
+        self.tick
+    }
+}
+
+/// Several components inserted together by `World::spawn_with`.
+pub trait Bundle {
+    fn insert_into(self, world: &mut World, entity: Entity);
+}
+
+macro_rules! impl_bundle {
+    ($($component:ident),*) => {
+        impl<$($component: 'static),*> Bundle for ($($component,)*) {
+            #[allow(non_snake_case)]
+            fn insert_into(self, world: &mut World, entity: Entity) {
+                let ($($component,)*) = self;
+                $(world.insert(entity, $component);)*
+            }
+        }
+    };
+}
+
+impl_bundle!(A);
+impl_bundle!(A, B);
+impl_bundle!(A, B, C);
+impl_bundle!(A, B, C, D);
+impl_bundle!(A, B, C, D, E);
+impl_bundle!(A, B, C, D, E, F);
+impl_bundle!(A, B, C, D, E, F, G);
+impl_bundle!(A, B, C, D, E, F, G, H);
+
+/// What a query asks for: component references, optional components and
+/// filters, or tuples of those.
+pub trait Query {
+    type State<'w>;
+    type Item<'s>;
+
+    /// `None` if nothing can match, e.g. a required component was never
+    /// inserted.
+    fn borrow(world: &World) -> Option<Self::State<'_>>;
+    /// A list containing every match, if this part of the query narrows it.
+    fn candidates<'a>(state: &'a Self::State<'_>) -> Option<&'a [Entity]>;
+    /// Whether `entity` matches, with `since` the tick change filters compare to.
+    fn matches(state: &Self::State<'_>, entity: Entity, since: u64) -> bool;
+    /// Only called on matching entities. `tick` stamps mutable access.
+    fn fetch<'s>(state: &'s mut Self::State<'_>, entity: Entity, tick: u64) -> Self::Item<'s>;
+}
+
+impl<T: 'static> Query for &T {
+    type State<'w> = Ref<'w, Storage<T>>;
+    type Item<'s> = &'s T;
+
+    fn borrow(world: &World) -> Option<Self::State<'_>> {
+        world.storage::<T>()
+    }
+
+    fn candidates<'a>(state: &'a Self::State<'_>) -> Option<&'a [Entity]> {
+        Some(&state.entities)
+    }
+
+    fn matches(state: &Self::State<'_>, entity: Entity, _since: u64) -> bool {
+        state.dense(entity).is_some()
+    }
+
+    fn fetch<'s>(state: &'s mut Self::State<'_>, entity: Entity, _tick: u64) -> Self::Item<'s> {
+        let dense = state.dense(entity).expect("fetched a matching entity");
+        &state.components[dense]
+    }
+}
+
+/// Mutable access marks the component changed.
+impl<T: 'static> Query for &mut T {
+    type State<'w> = RefMut<'w, Storage<T>>;
+    type Item<'s> = &'s mut T;
+
+    fn borrow(world: &World) -> Option<Self::State<'_>> {
+        world.storage_mut::<T>()
+    }
+
+    fn candidates<'a>(state: &'a Self::State<'_>) -> Option<&'a [Entity]> {
+        Some(&state.entities)
+    }
+
+    fn matches(state: &Self::State<'_>, entity: Entity, _since: u64) -> bool {
+        state.dense(entity).is_some()
+    }
+
+    fn fetch<'s>(state: &'s mut Self::State<'_>, entity: Entity, tick: u64) -> Self::Item<'s> {
+        let dense = state.dense(entity).expect("fetched a matching entity");
+        state.changed[dense] = tick;
+        &mut state.components[dense]
+    }
+}
+
+impl<T: 'static> Query for Option<&T> {
+    type State<'w> = Option<Ref<'w, Storage<T>>>;
+    type Item<'s> = Option<&'s T>;
+
+    fn borrow(world: &World) -> Option<Self::State<'_>> {
+        Some(world.storage::<T>())
+    }
+
+    fn candidates<'a>(_state: &'a Self::State<'_>) -> Option<&'a [Entity]> {
+        None
+    }
+
+    fn matches(_state: &Self::State<'_>, _entity: Entity, _since: u64) -> bool {
+        true
+    }
+
+    fn fetch<'s>(state: &'s mut Self::State<'_>, entity: Entity, _tick: u64) -> Self::Item<'s> {
+        let storage = state.as_ref()?;
+        storage.dense(entity).map(|dense| &storage.components[dense])
+    }
+}
+
+/// Filter for entities that have a `T`, without borrowing it.
+pub struct With<T>(PhantomData<T>);
+
+/// Filter for entities without a `T`.
+pub struct Without<T>(PhantomData<T>);
+
+/// Filter for `T`s inserted since the running system last ran, or outside
+/// systems, during the last schedule run.
+pub struct Added<T>(PhantomData<T>);
+
+/// Filter for `T`s inserted or mutably accessed since the running system
+/// last ran, or outside systems, during the last schedule run.
+pub struct Changed<T>(PhantomData<T>);
+
+macro_rules! impl_filter {
+    ($filter:ident, |$storage:ident, $dense:ident, $since:ident| $matches:expr) => {
+        impl<T: 'static> Query for $filter<T> {
+            type State<'w> = Ref<'w, Storage<T>>;
+            type Item<'s> = ();
+
+            fn borrow(world: &World) -> Option<Self::State<'_>> {
+                world.storage::<T>()
+            }
+
+            fn candidates<'a>(state: &'a Self::State<'_>) -> Option<&'a [Entity]> {
+                Some(&state.entities)
+            }
+
+            fn matches($storage: &Self::State<'_>, entity: Entity, $since: u64) -> bool {
+                $storage.dense(entity).is_some_and(|$dense| $matches)
+            }
+
+            fn fetch<'s>(_state: &'s mut Self::State<'_>, _entity: Entity, _tick: u64) -> Self::Item<'s> {}
+        }
+    };
+}
+
+impl_filter!(With, |_storage, _dense, _since| true);
+impl_filter!(Added, |storage, dense, since| storage.added[dense] > since);
+impl_filter!(Changed, |storage, dense, since| storage.changed[dense] > since);
+
+impl<T: 'static> Query for Without<T> {
+    type State<'w> = Option<Ref<'w, Storage<T>>>;
+    type Item<'s> = ();
+
+    fn borrow(world: &World) -> Option<Self::State<'_>> {
+        Some(world.storage::<T>())
+    }
+
+    fn candidates<'a>(_state: &'a Self::State<'_>) -> Option<&'a [Entity]> {
+        None
+    }
+
+    fn matches(state: &Self::State<'_>, entity: Entity, _since: u64) -> bool {
+        state.as_ref().is_none_or(|storage| storage.dense(entity).is_none())
+    }
+
+    fn fetch<'s>(_state: &'s mut Self::State<'_>, _entity: Entity, _tick: u64) -> Self::Item<'s> {}
+}
+
+macro_rules! impl_query_tuple {
+    ($($query:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<$($query: Query),*> Query for ($($query,)*) {
+            type State<'w> = ($($query::State<'w>,)*);
+            type Item<'s> = ($($query::Item<'s>,)*);
+
+            fn borrow(world: &World) -> Option<Self::State<'_>> {
+                Some(($($query::borrow(world)?,)*))
+            }
+
+            /// The shortest list any part narrows the query to.
+            fn candidates<'a>(state: &'a Self::State<'_>) -> Option<&'a [Entity]> {
+                let ($($query,)*) = state;
+                let mut shortest: Option<&'a [Entity]> = None;
+                $(
+                    if let Some(candidates) = $query::candidates($query) {
+                        if shortest.is_none_or(|shortest| candidates.len() < shortest.len()) {
+                            shortest = Some(candidates);
+                        }
+                    }
+                )*
+                shortest
+            }
+
+            fn matches(state: &Self::State<'_>, entity: Entity, since: u64) -> bool {
+                let ($($query,)*) = state;
+                $($query::matches($query, entity, since))&&*
+            }
+
+            fn fetch<'s>(state: &'s mut Self::State<'_>, entity: Entity, tick: u64) -> Self::Item<'s> {
+                let ($($query,)*) = state;
+                ($($query::fetch($query, entity, tick),)*)
+            }
+        }
+    };
+}
+
+impl_query_tuple!(A);
+impl_query_tuple!(A, B);
+impl_query_tuple!(A, B, C);
+impl_query_tuple!(A, B, C, D);
+impl_query_tuple!(A, B, C, D, E);
+impl_query_tuple!(A, B, C, D, E, F);
+
+type SystemFn = Box<dyn FnMut(&mut World)>;
+
+struct System {
+    name: &'static str,
+    run: SystemFn,
+    /// Change tick after this system last ran.
+    last_run: u64,
+}
+
+/// Systems run one after another in the order they were added, once per
+/// simulation tick.
+#[derive(Default)]
+pub struct Schedule {
+    systems: Vec<System>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a system. `name` is for profiling and debugging.
+    pub fn add_system(&mut self, name: &'static str, system: impl FnMut(&mut World) + 'static) {
+        self.systems.push(System { name, run: Box::new(system), last_run: 0 });
+    }
+
+    pub fn system_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.systems.iter().map(|system| system.name)
+    }
+
+    /// Runs every system once. Each sees the changes made since it last ran,
+    /// and deferred commands are applied between systems.
+    pub fn run(&mut self, world: &mut World) {
+        let start = world.tick;
+        for system in &mut self.systems {
+            profiling::scope!(system.name);
+            world.since.set(system.last_run);
+            (system.run)(world);
+            world.apply_commands();
+            system.last_run = world.tick;
+            world.tick += 1;
+        }
+        // Code outside the schedule sees what this run changed.
+        world.since.set(start.saturating_sub(1));
+    }
+}
+
+/// Change detection for code that runs outside the schedule, e.g. once per
+/// frame. Like a system, it sees the changes made since its last run.
+#[derive(Clone, Debug, Default)]
+pub struct ChangeTracker {
+    last_run: u64,
+}
+
+impl ChangeTracker {
+    pub fn run<R>(&mut self, world: &mut World, f: impl FnOnce(&mut World) -> R) -> R {
+        let since = world.since.replace(self.last_run);
+        let result = f(world);
+        world.apply_commands();
+        world.since.set(since);
+        self.last_run = world.tick;
+        world.tick += 1;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Position(f32);
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Velocity(f32);
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Frozen;
+
+    /// Entities a system saw, per run.
+    #[derive(Default)]
+    struct Seen(Vec<Vec<Entity>>);
+
+    fn seen(world: &World) -> Vec<Vec<Entity>> {
+        world.resource::<Seen>().unwrap().0.clone()
+    }
+
+    #[test]
+    fn despawned_slots_are_reused_with_a_new_generation() {
+        let mut world = World::new();
+        let first = world.spawn_with((Position(1.0),));
+        assert!(world.despawn(first));
+        assert!(!world.despawn(first));
+        let second = world.spawn();
+        assert_eq!(second.index(), first.index());
+        assert_eq!(second.generation(), first.generation() + 1);
+        assert!(!world.is_alive(first));
+        assert!(world.is_alive(second));
+        assert!(world.get::<Position>(first).is_none());
+        assert!(!world.has::<Position>(second));
+        assert_eq!(world.entities(), vec![second]);
+        assert_eq!(Entity::from_raw(second.index(), second.generation()), second);
+        assert_eq!(second.to_string(), format!("{}v1", second.index()));
+    }
+
+    #[test]
+    #[should_panic(expected = "dead")]
+    fn inserting_on_a_stale_handle_panics() {
+        let mut world = World::new();
+        let entity = world.spawn();
+        world.despawn(entity);
+        world.spawn();
+        world.insert(entity, Position(0.0));
+    }
+
+    #[test]
+    fn components_can_be_inserted_replaced_and_removed() {
+        let mut world = World::new();
+        let a = world.spawn_with((Position(1.0), Velocity(2.0)));
+        let b = world.spawn_with((Position(3.0),));
+        world.insert(a, Position(5.0));
+        assert_eq!(*world.get::<Position>(a).unwrap(), Position(5.0));
+        world.get_mut::<Position>(b).unwrap().0 += 1.0;
+        assert_eq!(*world.get::<Position>(b).unwrap(), Position(4.0));
+        assert_eq!(world.remove::<Position>(a), Some(Position(5.0)));
+        assert_eq!(world.remove::<Position>(a), None);
+        assert!(world.has::<Velocity>(a));
+        // Removing `a` moved `b` within the packed storage.
+        assert_eq!(*world.get::<Position>(b).unwrap(), Position(4.0));
+    }
+
+    #[test]
+    fn tuple_queries_mix_access_and_filters() {
+        let mut world = World::new();
+        let moving = world.spawn_with((Position(0.0), Velocity(1.0)));
+        let frozen = world.spawn_with((Position(0.0), Velocity(1.0), Frozen));
+        let still = world.spawn_with((Position(5.0),));
+
+        world.query::<(&mut Position, &Velocity, Without<Frozen>)>(|_, (position, velocity, ())| position.0 += velocity.0);
+        assert_eq!(*world.get::<Position>(moving).unwrap(), Position(1.0));
+        assert_eq!(*world.get::<Position>(frozen).unwrap(), Position(0.0));
+
+        let mut visited = Vec::new();
+        world.query::<(&Position, Option<&Velocity>)>(|entity, (position, velocity)| visited.push((entity, position.0, velocity.copied())));
+        assert_eq!(visited, vec![(moving, 1.0, Some(Velocity(1.0))), (frozen, 0.0, Some(Velocity(1.0))), (still, 5.0, None)]);
+
+        assert_eq!(world.matching::<(With<Position>, With<Frozen>)>(), vec![frozen]);
+        assert_eq!(world.matching::<Without<Velocity>>(), vec![still]);
+        assert_eq!(world.query_one::<&Velocity, _>(still, |velocity| velocity.0), None);
+        assert_eq!(world.query_one::<&Velocity, _>(moving, |velocity| velocity.0), Some(1.0));
+    }
+
+    #[test]
+    fn systems_see_changes_made_since_they_last_ran() {
+        let mut world = World::new();
+        world.insert_resource(Seen::default());
+        let a = world.spawn_with((Position(0.0), Velocity(1.0)));
+        let b = world.spawn_with((Position(0.0), Velocity(0.0)));
+
+        let mut schedule = Schedule::new();
+        schedule.add_system("report", |world: &mut World| {
+            let changed = world.matching::<Changed<Position>>();
+            world.resource_mut::<Seen>().unwrap().0.push(changed);
+            // Writing here must not show up in this system's next run.
+            world.query::<(&mut Position, &Velocity)>(|_, (position, velocity)| position.0 += velocity.0);
+        });
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+        world.get_mut::<Position>(b).unwrap().0 = 9.0;
+        schedule.run(&mut world);
+        assert_eq!(seen(&world), vec![vec![a, b], vec![], vec![b]]);
+
+        // Outside the schedule, the last run's changes show.
+        assert_eq!(world.matching::<Changed<Position>>(), vec![a, b]);
+        assert!(world.matching::<Added<Position>>().is_empty());
+    }
+
+    #[test]
+    fn systems_see_what_earlier_and_later_systems_did() {
+        let mut world = World::new();
+        world.insert_resource(Seen::default());
+        let mut schedule = Schedule::new();
+        schedule.add_system("spawn", |world: &mut World| {
+            world.spawn_with((Position(0.0),));
+        });
+        schedule.add_system("added", |world: &mut World| {
+            let added = world.matching::<Added<Position>>();
+            world.resource_mut::<Seen>().unwrap().0.push(added);
+        });
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+        let entities = world.entities();
+        assert_eq!(seen(&world), vec![vec![entities[0]], vec![entities[1]]]);
+        assert_eq!(schedule.system_names().collect::<Vec<_>>(), vec!["spawn", "added"]);
+    }
+
+    #[test]
+    fn deferred_commands_apply_after_the_system() {
+        let mut world = World::new();
+        world.spawn_with((Position(0.0),));
+        world.spawn_with((Position(1.0),));
+        let mut schedule = Schedule::new();
+        schedule.add_system("split", |world: &mut World| {
+            world.query::<&Position>(|_, position| {
+                let position = *position;
+                world.defer(move |world| {
+                    let child = world.spawn_with((position, Frozen));
+                    // Commands queued by commands run in the same pass.
+                    world.defer(move |world| world.insert(child, Velocity(position.0)));
+                });
+            });
+            assert!(world.matching::<With<Frozen>>().is_empty());
+        });
+        schedule.run(&mut world);
+        assert_eq!(world.matching::<(With<Frozen>, With<Velocity>)>().len(), 2);
+
+        world.defer(|world| world.insert_resource(Seen::default()));
+        assert!(world.resource::<Seen>().is_none());
+        world.apply_commands();
+        assert!(world.resource::<Seen>().is_some());
+    }
+
+    #[test]
+    fn change_trackers_see_changes_since_their_last_run() {
+        let mut world = World::new();
+        let mut tracker = ChangeTracker::default();
+        let a = world.spawn_with((Position(0.0),));
+        assert_eq!(tracker.run(&mut world, |world| world.matching::<Added<Position>>()), vec![a]);
+        assert!(tracker.run(&mut world, |world| world.matching::<Changed<Position>>()).is_empty());
+
+        let mut schedule = Schedule::new();
+        schedule.add_system("move", |world: &mut World| world.query::<&mut Position>(|_, position| position.0 += 1.0));
+        schedule.run(&mut world);
+        let b = world.spawn_with((Position(0.0),));
+        assert_eq!(tracker.run(&mut world, |world| world.matching::<Changed<Position>>()), vec![a, b]);
+
+        // Its own writes don't come back, and it leaves the schedule's view alone.
+        tracker.run(&mut world, |world| world.get_mut::<Position>(a).map(|mut position| position.0 = 5.0));
+        assert!(tracker.run(&mut world, |world| world.matching::<Changed<Position>>()).is_empty());
+        assert_eq!(world.matching::<Changed<Position>>(), vec![a, b]);
+    }
+}
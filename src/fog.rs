@@ -0,0 +1,51 @@
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FogMode {
+    Off,
+    /// No fog before `start`, fully fogged from `end` on.
+    Linear { start: f32, end: f32 },
+    Exponential { density: f32 },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FogColor {
+    Fixed([f32; 3]),
+    /// Matches the sky at the horizon in the direction the camera faces, so
+    /// distant geometry dissolves into the background.
+    Sky,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FogSettings {
+    pub mode: FogMode,
+    pub color: FogColor,
+    /// Height fog density at `height_base`, zero to disable it.
+    pub height_density: f32,
+    pub height_base: f32,
+    /// How quickly height fog thins out going up.
+    pub height_falloff: f32,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        // Fully fogged just before the far plane.
+        Self {
+            mode: FogMode::Linear { start: 30.0, end: 95.0 },
+            color: FogColor::Sky,
+            height_density: 0.02,
+            height_base: 0.0,
+            height_falloff: 0.5,
+        }
+    }
+}
+
+impl FogSettings {
+    /// Packs the settings as the `fog_params` and `height_fog` uniforms.
+    pub(crate) fn params(&self) -> ([f32; 4], [f32; 4]) {
+        let mode = match self.mode {
+            FogMode::Off => [0.0, 0.0, 0.0, 0.0],
+            FogMode::Linear { start, end } => [1.0, start, end, 0.0],
+            FogMode::Exponential { density } => [2.0, density, 0.0, 0.0],
+        };
+        (mode, [self.height_density, self.height_base, self.height_falloff, 0.0])
+    }
+}
@@ -0,0 +1,559 @@
+//! Controllers feed the same actions and axes as keyboard and mouse. A
+//! backend reports raw pad events, `Gamepads` cleans them up (dead zones,
+//! several pads, hot-plug) and turns them into `InputEvent`s.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+
+use winit::event::MouseButton;
+
+use crate::input::{Button, InputEvent};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftBumper,
+    RightBumper,
+    /// Pressed while the trigger axis is past `GamepadSettings::trigger_threshold`.
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl GamepadButton {
+    pub const ALL: [GamepadButton; 16] = [
+        GamepadButton::South,
+        GamepadButton::East,
+        GamepadButton::West,
+        GamepadButton::North,
+        GamepadButton::LeftBumper,
+        GamepadButton::RightBumper,
+        GamepadButton::LeftTrigger,
+        GamepadButton::RightTrigger,
+        GamepadButton::Select,
+        GamepadButton::Start,
+        GamepadButton::LeftStick,
+        GamepadButton::RightStick,
+        GamepadButton::DPadUp,
+        GamepadButton::DPadDown,
+        GamepadButton::DPadLeft,
+        GamepadButton::DPadRight,
+    ];
+
+    /// Name in bindings files.
+    pub fn name(self) -> &'static str {
+        match self {
+            GamepadButton::South => "PadSouth",
+            GamepadButton::East => "PadEast",
+            GamepadButton::West => "PadWest",
+            GamepadButton::North => "PadNorth",
+            GamepadButton::LeftBumper => "PadLeftBumper",
+            GamepadButton::RightBumper => "PadRightBumper",
+            GamepadButton::LeftTrigger => "PadLeftTrigger",
+            GamepadButton::RightTrigger => "PadRightTrigger",
+            GamepadButton::Select => "PadSelect",
+            GamepadButton::Start => "PadStart",
+            GamepadButton::LeftStick => "PadLeftStick",
+            GamepadButton::RightStick => "PadRightStick",
+            GamepadButton::DPadUp => "PadUp",
+            GamepadButton::DPadDown => "PadDown",
+            GamepadButton::DPadLeft => "PadLeft",
+            GamepadButton::DPadRight => "PadRight",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|button| button.name() == name)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    /// Right is positive.
+    LeftStickX,
+    /// Up is positive.
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    /// 0 released to 1 fully pulled.
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl GamepadAxis {
+    pub const ALL: [GamepadAxis; 6] = [
+        GamepadAxis::LeftStickX,
+        GamepadAxis::LeftStickY,
+        GamepadAxis::RightStickX,
+        GamepadAxis::RightStickY,
+        GamepadAxis::LeftTrigger,
+        GamepadAxis::RightTrigger,
+    ];
+
+    /// Name in bindings files.
+    pub fn name(self) -> &'static str {
+        match self {
+            GamepadAxis::LeftStickX => "PadLeftX",
+            GamepadAxis::LeftStickY => "PadLeftY",
+            GamepadAxis::RightStickX => "PadRightX",
+            GamepadAxis::RightStickY => "PadRightY",
+            GamepadAxis::LeftTrigger => "PadLeftTriggerAxis",
+            GamepadAxis::RightTrigger => "PadRightTriggerAxis",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|axis| axis.name() == name)
+    }
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GamepadId(pub usize);
+
+/// Raw controller input as reported by a backend.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GamepadEvent {
+    Connected(GamepadId),
+    Disconnected(GamepadId),
+    /// Triggers should be reported as axes; their buttons are derived.
+    Button { id: GamepadId, button: GamepadButton, pressed: bool },
+    Axis { id: GamepadId, axis: GamepadAxis, value: f32 },
+}
+
+/// Source of controller events, e.g. gilrs or a scripted fake.
+pub trait GamepadBackend {
+    fn poll(&mut self) -> Option<GamepadEvent>;
+}
+
+/// Backend replaying events pushed by hand, for tests and builds without
+/// controller support.
+#[derive(Clone, Debug, Default)]
+pub struct FakeGamepad {
+    events: VecDeque<GamepadEvent>,
+}
+
+impl FakeGamepad {
+    pub fn push(&mut self, event: GamepadEvent) {
+        self.events.push_back(event);
+    }
+}
+
+impl GamepadBackend for FakeGamepad {
+    fn poll(&mut self) -> Option<GamepadEvent> {
+        self.events.pop_front()
+    }
+}
+
+/// Lets the caller keep a handle on a backend `Gamepads` owns, e.g. to push
+/// more events into a `FakeGamepad`.
+impl<B: GamepadBackend> GamepadBackend for Rc<RefCell<B>> {
+    fn poll(&mut self) -> Option<GamepadEvent> {
+        self.borrow_mut().poll()
+    }
+}
+
+#[cfg(feature = "gamepad")]
+pub use self::gilrs_backend::GilrsBackend;
+
+#[cfg(feature = "gamepad")]
+mod gilrs_backend {
+    use std::collections::VecDeque;
+
+    use gilrs::{Axis, Button, EventType, Gilrs};
+
+    use super::{GamepadAxis, GamepadBackend, GamepadButton, GamepadEvent, GamepadId};
+
+    pub struct GilrsBackend {
+        gilrs: Gilrs,
+        /// Pads already plugged in at startup, reported before anything else.
+        connected: VecDeque<GamepadEvent>,
+    }
+
+    impl GilrsBackend {
+        /// The error is boxed as it can carry a whole fallback `Gilrs`.
+        pub fn new() -> Result<Self, Box<gilrs::Error>> {
+            let gilrs = Gilrs::new().map_err(Box::new)?;
+            let connected = gilrs.gamepads().map(|(id, _)| GamepadEvent::Connected(GamepadId(id.into()))).collect();
+            Ok(Self { gilrs, connected })
+        }
+    }
+
+    impl GamepadBackend for GilrsBackend {
+        fn poll(&mut self) -> Option<GamepadEvent> {
+            if let Some(event) = self.connected.pop_front() {
+                return Some(event);
+            }
+            while let Some(event) = self.gilrs.next_event() {
+                let id = GamepadId(event.id.into());
+                let event = match event.event {
+                    EventType::Connected => Some(GamepadEvent::Connected(id)),
+                    EventType::Disconnected => Some(GamepadEvent::Disconnected(id)),
+                    EventType::ButtonPressed(button, _) => button_of(button).map(|button| GamepadEvent::Button { id, button, pressed: true }),
+                    EventType::ButtonReleased(button, _) => button_of(button).map(|button| GamepadEvent::Button { id, button, pressed: false }),
+                    EventType::ButtonChanged(Button::LeftTrigger2, value, _) => Some(GamepadEvent::Axis { id, axis: GamepadAxis::LeftTrigger, value }),
+                    EventType::ButtonChanged(Button::RightTrigger2, value, _) => Some(GamepadEvent::Axis { id, axis: GamepadAxis::RightTrigger, value }),
+                    EventType::AxisChanged(axis, value, _) => axis_of(axis).map(|axis| GamepadEvent::Axis { id, axis, value }),
+                    _ => None,
+                };
+                if event.is_some() {
+                    return event;
+                }
+            }
+            None
+        }
+    }
+
+    /// gilrs calls the bumpers triggers and the triggers `Trigger2`, whose
+    /// analog values arrive as axes instead.
+    fn button_of(button: Button) -> Option<GamepadButton> {
+        Some(match button {
+            Button::South => GamepadButton::South,
+            Button::East => GamepadButton::East,
+            Button::West => GamepadButton::West,
+            Button::North => GamepadButton::North,
+            Button::LeftTrigger => GamepadButton::LeftBumper,
+            Button::RightTrigger => GamepadButton::RightBumper,
+            Button::Select => GamepadButton::Select,
+            Button::Start => GamepadButton::Start,
+            Button::LeftThumb => GamepadButton::LeftStick,
+            Button::RightThumb => GamepadButton::RightStick,
+            Button::DPadUp => GamepadButton::DPadUp,
+            Button::DPadDown => GamepadButton::DPadDown,
+            Button::DPadLeft => GamepadButton::DPadLeft,
+            Button::DPadRight => GamepadButton::DPadRight,
+            _ => return None,
+        })
+    }
+
+    fn axis_of(axis: Axis) -> Option<GamepadAxis> {
+        Some(match axis {
+            Axis::LeftStickX => GamepadAxis::LeftStickX,
+            Axis::LeftStickY => GamepadAxis::LeftStickY,
+            Axis::RightStickX => GamepadAxis::RightStickX,
+            Axis::RightStickY => GamepadAxis::RightStickY,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GamepadSettings {
+    /// Stick deflection ignored around the centre, as a fraction of full tilt.
+    pub stick_dead_zone: f32,
+    /// Trigger travel ignored before it starts to register.
+    pub trigger_dead_zone: f32,
+    /// Trigger travel at which the trigger counts as a pressed button.
+    pub trigger_threshold: f32,
+    /// Steer a mouse cursor with the left stick, with `cursor_click` as the
+    /// left button, so selection and the UI work without a mouse.
+    pub virtual_cursor: bool,
+    /// Pixels per second at full tilt.
+    pub cursor_speed: f32,
+    pub cursor_click: GamepadButton,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            stick_dead_zone: 0.2,
+            trigger_dead_zone: 0.05,
+            trigger_threshold: 0.5,
+            virtual_cursor: true,
+            cursor_speed: 900.0,
+            cursor_click: GamepadButton::South,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct PadState {
+    buttons: HashSet<GamepadButton>,
+    /// Raw values, before dead zones.
+    axes: [f32; 6],
+}
+
+/// Every connected controller, merged as if they were one.
+pub struct Gamepads {
+    backend: Box<dyn GamepadBackend>,
+    settings: GamepadSettings,
+    pads: HashMap<GamepadId, PadState>,
+    /// Merged buttons and axes last reported to input.
+    buttons: HashSet<GamepadButton>,
+    axes: [f32; 6],
+    cursor: Option<[f32; 2]>,
+}
+
+impl Default for Gamepads {
+    /// Reads real controllers with the `gamepad` feature, otherwise none.
+    fn default() -> Self {
+        #[cfg(feature = "gamepad")]
+        match GilrsBackend::new() {
+            Ok(backend) => return Self::new(Box::new(backend)),
+            Err(e) => log::error!("Gamepads unavailable: {}", e),
+        }
+        Self::new(Box::new(FakeGamepad::default()))
+    }
+}
+
+impl Gamepads {
+    pub fn new(backend: Box<dyn GamepadBackend>) -> Self {
+        Self {
+            backend,
+            settings: GamepadSettings::default(),
+            pads: HashMap::new(),
+            buttons: HashSet::new(),
+            axes: [0.0; 6],
+            cursor: None,
+        }
+    }
+
+    pub fn settings(&self) -> &GamepadSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: GamepadSettings) {
+        self.settings = settings;
+    }
+
+    /// Where the stick left the cursor, until the mouse moves it again.
+    pub fn virtual_cursor(&self) -> Option<[f32; 2]> {
+        self.cursor
+    }
+
+    pub fn connected(&self) -> impl Iterator<Item = GamepadId> + '_ {
+        self.pads.keys().copied()
+    }
+
+    /// Drains the backend and returns the input it amounts to this frame.
+    /// `cursor` is where the mouse cursor is, which the virtual cursor takes
+    /// over from, and `screen_size` is in pixels.
+    pub fn update(&mut self, delta: f32, cursor: Option<[f32; 2]>, screen_size: [f32; 2]) -> Vec<InputEvent> {
+        while let Some(event) = self.backend.poll() {
+            match event {
+                GamepadEvent::Connected(id) => {
+                    log::info!("Gamepad {} connected", id.0);
+                    self.pads.entry(id).or_default();
+                }
+                GamepadEvent::Disconnected(id) => {
+                    log::info!("Gamepad {} disconnected", id.0);
+                    self.pads.remove(&id);
+                }
+                GamepadEvent::Button { id, button, pressed } => {
+                    let pad = self.pads.entry(id).or_default();
+                    if pressed {
+                        pad.buttons.insert(button);
+                    } else {
+                        pad.buttons.remove(&button);
+                    }
+                }
+                GamepadEvent::Axis { id, axis, value } => {
+                    self.pads.entry(id).or_default().axes[axis.index()] = value;
+                }
+            }
+        }
+
+        let mut events = Vec::new();
+        let (buttons, axes) = self.merged();
+        let virtual_cursor = self.settings.virtual_cursor;
+        let click = self.settings.cursor_click;
+        for &button in self.buttons.symmetric_difference(&buttons) {
+            let pressed = buttons.contains(&button);
+            let button = match button {
+                button if virtual_cursor && button == click => Button::Mouse(MouseButton::Left),
+                button => Button::Gamepad(button),
+            };
+            events.push(InputEvent::Button { button, pressed });
+        }
+        for axis in GamepadAxis::ALL {
+            let value = axes[axis.index()];
+            let steers_cursor = virtual_cursor && matches!(axis, GamepadAxis::LeftStickX | GamepadAxis::LeftStickY);
+            if value != self.axes[axis.index()] && !steers_cursor {
+                events.push(InputEvent::GamepadAxis { axis, value });
+            }
+        }
+
+        // A real mouse moving takes the cursor back.
+        if cursor != self.cursor {
+            self.cursor = None;
+        }
+        if virtual_cursor {
+            let (x, y) = (axes[GamepadAxis::LeftStickX.index()], axes[GamepadAxis::LeftStickY.index()]);
+            if x != 0.0 || y != 0.0 {
+                let start = self.cursor.or(cursor).unwrap_or([screen_size[0] / 2.0, screen_size[1] / 2.0]);
+                let speed = self.settings.cursor_speed * delta;
+                let position = [
+                    (start[0] + x * speed).clamp(0.0, screen_size[0] - 1.0),
+                    (start[1] - y * speed).clamp(0.0, screen_size[1] - 1.0),
+                ];
+                self.cursor = Some(position);
+                events.push(InputEvent::CursorMoved(position));
+            }
+        }
+
+        self.buttons = buttons;
+        self.axes = axes;
+        events
+    }
+
+    /// Buttons held on any pad and, per axis, the value furthest from rest.
+    fn merged(&self) -> (HashSet<GamepadButton>, [f32; 6]) {
+        let mut buttons = HashSet::new();
+        let mut axes = [0.0f32; 6];
+        for pad in self.pads.values() {
+            let mut values = pad.axes;
+            for (x, y) in [(GamepadAxis::LeftStickX, GamepadAxis::LeftStickY), (GamepadAxis::RightStickX, GamepadAxis::RightStickY)] {
+                let scale = dead_zone_scale((values[x.index()].powi(2) + values[y.index()].powi(2)).sqrt(), self.settings.stick_dead_zone);
+                values[x.index()] *= scale;
+                values[y.index()] *= scale;
+            }
+            for (axis, button) in [(GamepadAxis::LeftTrigger, GamepadButton::LeftTrigger), (GamepadAxis::RightTrigger, GamepadButton::RightTrigger)] {
+                let value = values[axis.index()].max(0.0);
+                values[axis.index()] = value * dead_zone_scale(value, self.settings.trigger_dead_zone);
+                if value >= self.settings.trigger_threshold {
+                    buttons.insert(button);
+                }
+            }
+            for (merged, value) in axes.iter_mut().zip(values) {
+                if value.abs() > merged.abs() {
+                    *merged = value;
+                }
+            }
+            buttons.extend(pad.buttons.iter().copied());
+        }
+        (buttons, axes)
+    }
+}
+
+/// Factor mapping a deflection of `magnitude` to zero inside the dead zone
+/// and rescaling the rest so it still reaches full tilt.
+fn dead_zone_scale(magnitude: f32, dead_zone: f32) -> f32 {
+    if magnitude <= dead_zone || magnitude == 0.0 {
+        0.0
+    } else {
+        ((magnitude - dead_zone) / (1.0 - dead_zone)).min(1.0) / magnitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: [f32; 2] = [800.0, 600.0];
+    const PAD: GamepadId = GamepadId(0);
+
+    fn gamepads(virtual_cursor: bool) -> (Gamepads, Rc<RefCell<FakeGamepad>>) {
+        let backend = Rc::new(RefCell::new(FakeGamepad::default()));
+        let mut gamepads = Gamepads::new(Box::new(backend.clone()));
+        gamepads.set_settings(GamepadSettings { virtual_cursor, ..GamepadSettings::default() });
+        backend.borrow_mut().push(GamepadEvent::Connected(PAD));
+        (gamepads, backend)
+    }
+
+    fn update(gamepads: &mut Gamepads, backend: &Rc<RefCell<FakeGamepad>>, events: &[GamepadEvent]) -> Vec<InputEvent> {
+        for &event in events {
+            backend.borrow_mut().push(event);
+        }
+        gamepads.update(1.0 / 60.0, None, SCREEN)
+    }
+
+    fn axis(id: GamepadId, axis: GamepadAxis, value: f32) -> GamepadEvent {
+        GamepadEvent::Axis { id, axis, value }
+    }
+
+    fn button(id: GamepadId, button: GamepadButton, pressed: bool) -> GamepadEvent {
+        GamepadEvent::Button { id, button, pressed }
+    }
+
+    fn pad_button(button: GamepadButton, pressed: bool) -> InputEvent {
+        InputEvent::Button { button: Button::Gamepad(button), pressed }
+    }
+
+    #[test]
+    fn dead_zone_rescales_stick_deflection() {
+        assert_eq!(dead_zone_scale(0.0, 0.2), 0.0);
+        assert_eq!(dead_zone_scale(0.2, 0.2), 0.0);
+        assert_eq!(dead_zone_scale(1.0, 0.2), 1.0);
+        assert!((0.6 * dead_zone_scale(0.6, 0.2) - 0.5).abs() < 1e-6);
+
+        let (mut gamepads, backend) = gamepads(false);
+        assert!(update(&mut gamepads, &backend, &[axis(PAD, GamepadAxis::RightStickX, 0.2)]).is_empty());
+        let events = update(&mut gamepads, &backend, &[axis(PAD, GamepadAxis::RightStickX, 1.0)]);
+        assert_eq!(events, vec![InputEvent::GamepadAxis { axis: GamepadAxis::RightStickX, value: 1.0 }]);
+    }
+
+    #[test]
+    fn triggers_press_past_the_threshold() {
+        let (mut gamepads, backend) = gamepads(false);
+        let events = update(&mut gamepads, &backend, &[axis(PAD, GamepadAxis::LeftTrigger, 0.4)]);
+        assert!(!events.iter().any(|event| matches!(event, InputEvent::Button { .. })));
+        let events = update(&mut gamepads, &backend, &[axis(PAD, GamepadAxis::LeftTrigger, 0.6), axis(PAD, GamepadAxis::RightTrigger, 1.0)]);
+        assert!(events.contains(&pad_button(GamepadButton::LeftTrigger, true)));
+        assert!(events.contains(&pad_button(GamepadButton::RightTrigger, true)));
+        let events = update(&mut gamepads, &backend, &[axis(PAD, GamepadAxis::LeftTrigger, 0.0)]);
+        assert!(events.contains(&pad_button(GamepadButton::LeftTrigger, false)));
+        assert!(events.contains(&InputEvent::GamepadAxis { axis: GamepadAxis::LeftTrigger, value: 0.0 }));
+    }
+
+    #[test]
+    fn unplugging_releases_held_buttons() {
+        let (mut gamepads, backend) = gamepads(false);
+        let events = update(&mut gamepads, &backend, &[button(PAD, GamepadButton::East, true), axis(PAD, GamepadAxis::RightStickY, 1.0)]);
+        assert!(events.contains(&pad_button(GamepadButton::East, true)));
+        let events = update(&mut gamepads, &backend, &[GamepadEvent::Disconnected(PAD)]);
+        assert!(events.contains(&pad_button(GamepadButton::East, false)));
+        assert!(events.contains(&InputEvent::GamepadAxis { axis: GamepadAxis::RightStickY, value: 0.0 }));
+        assert_eq!(gamepads.connected().count(), 0);
+    }
+
+    #[test]
+    fn pads_merge_into_one() {
+        let (mut gamepads, backend) = gamepads(false);
+        let other = GamepadId(1);
+        let events = update(&mut gamepads, &backend, &[
+            GamepadEvent::Connected(other),
+            button(PAD, GamepadButton::North, true),
+            button(other, GamepadButton::North, true),
+            axis(PAD, GamepadAxis::RightStickX, 0.6),
+            axis(other, GamepadAxis::RightStickX, -1.0),
+        ]);
+        assert_eq!(events.iter().filter(|&&event| event == pad_button(GamepadButton::North, true)).count(), 1);
+        assert!(events.contains(&InputEvent::GamepadAxis { axis: GamepadAxis::RightStickX, value: -1.0 }));
+
+        assert!(update(&mut gamepads, &backend, &[button(PAD, GamepadButton::North, false)]).is_empty());
+        let events = update(&mut gamepads, &backend, &[button(other, GamepadButton::North, false)]);
+        assert_eq!(events, vec![pad_button(GamepadButton::North, false)]);
+    }
+
+    #[test]
+    fn virtual_cursor_clicks_and_stays_on_screen() {
+        let (mut gamepads, backend) = gamepads(true);
+        let events = update(&mut gamepads, &backend, &[button(PAD, GamepadButton::South, true)]);
+        assert_eq!(events, vec![InputEvent::Button { button: Button::Mouse(MouseButton::Left), pressed: true }]);
+
+        backend.borrow_mut().push(axis(PAD, GamepadAxis::LeftStickX, 1.0));
+        backend.borrow_mut().push(axis(PAD, GamepadAxis::LeftStickY, 1.0));
+        let events = gamepads.update(10.0, Some([700.0, 300.0]), SCREEN);
+        assert_eq!(events, vec![InputEvent::CursorMoved([799.0, 0.0])]);
+        assert_eq!(gamepads.virtual_cursor(), Some([799.0, 0.0]));
+
+        backend.borrow_mut().push(axis(PAD, GamepadAxis::LeftStickX, -1.0));
+        backend.borrow_mut().push(axis(PAD, GamepadAxis::LeftStickY, -1.0));
+        let events = gamepads.update(10.0, Some([799.0, 0.0]), SCREEN);
+        assert_eq!(events, vec![InputEvent::CursorMoved([0.0, 599.0])]);
+    }
+}
@@ -0,0 +1,220 @@
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use crate::camera::Ray;
+
+/// Square grid of terrain heights centred on the world origin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heightmap {
+    /// Samples per side.
+    resolution: u32,
+    cell_size: f32,
+    /// Everything below this height is under water.
+    sea_level: f32,
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn new(resolution: u32, cell_size: f32, sea_level: f32, heights: Vec<f32>) -> Self {
+        assert!(resolution >= 2, "heightmap needs at least 2x2 samples");
+        assert_eq!(heights.len(), (resolution * resolution) as usize, "heights don't match the resolution");
+        Self { resolution, cell_size, sea_level, heights }
+    }
+
+    pub fn flat(resolution: u32, cell_size: f32, height: f32) -> Self {
+        Self::new(resolution, cell_size, f32::NEG_INFINITY, vec![height; (resolution * resolution) as usize])
+    }
+
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn sea_level(&self) -> f32 {
+        self.sea_level
+    }
+
+    /// World-space side length.
+    pub fn size(&self) -> f32 {
+        (self.resolution - 1) as f32 * self.cell_size
+    }
+
+    pub fn heights(&self) -> &[f32] {
+        &self.heights
+    }
+
+    /// Height of sample `(x, z)`, clamped to the grid.
+    pub fn sample(&self, x: i32, z: i32) -> f32 {
+        let last = self.resolution as i32 - 1;
+        let (x, z) = (x.clamp(0, last), z.clamp(0, last));
+        self.heights[(z * self.resolution as i32 + x) as usize]
+    }
+
+    pub fn set_sample(&mut self, x: u32, z: u32, height: f32) {
+        self.heights[(z * self.resolution + x) as usize] = height;
+    }
+
+    pub fn sample_position(&self, x: u32, z: u32) -> Point3<f32> {
+        let half = self.size() / 2.0;
+        Point3::new(
+            x as f32 * self.cell_size - half,
+            self.sample(x as i32, z as i32),
+            z as f32 * self.cell_size - half,
+        )
+    }
+
+    /// Fractional sample coordinates of a world position.
+    pub fn grid_coordinates(&self, x: f32, z: f32) -> (f32, f32) {
+        let half = self.size() / 2.0;
+        ((x + half) / self.cell_size, (z + half) / self.cell_size)
+    }
+
+    /// Bilinearly interpolated height at a world position.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let (gx, gz) = self.grid_coordinates(x, z);
+        let (x0, z0) = (gx.floor(), gz.floor());
+        let (tx, tz) = (gx - x0, gz - z0);
+        let (x0, z0) = (x0 as i32, z0 as i32);
+        let top = self.sample(x0, z0) * (1.0 - tx) + self.sample(x0 + 1, z0) * tx;
+        let bottom = self.sample(x0, z0 + 1) * (1.0 - tx) + self.sample(x0 + 1, z0 + 1) * tx;
+        top * (1.0 - tz) + bottom * tz
+    }
+
+    /// Surface normal at sample `(x, z)` from central differences.
+    pub fn sample_normal(&self, x: u32, z: u32) -> Vector3<f32> {
+        let (x, z) = (x as i32, z as i32);
+        let dx = self.sample(x + 1, z) - self.sample(x - 1, z);
+        let dz = self.sample(x, z + 1) - self.sample(x, z - 1);
+        Vector3::new(-dx, 2.0 * self.cell_size, -dz).normalize()
+    }
+
+    pub fn is_under_water(&self, x: u32, z: u32) -> bool {
+        self.sample(x as i32, z as i32) < self.sea_level
+    }
+
+    /// Distance along `ray` to where it first hits the ground, e.g. under the
+    /// cursor. Marches half a cell at a time, so it can step over ridges
+    /// thinner than that. Beyond the edges the ground continues at the edge
+    /// heights, as `height_at` clamps.
+    pub fn raycast(&self, ray: &Ray) -> Option<f32> {
+        let step = self.cell_size / 2.0;
+        let max_distance = self.size() * 2.0 + (ray.origin - Point3::new(0.0, 0.0, 0.0)).magnitude();
+        let above = |distance: f32| {
+            let point = ray.at(distance);
+            point.y - self.height_at(point.x, point.z)
+        };
+        let mut previous = 0.0;
+        let mut distance = 0.0;
+        while distance < max_distance {
+            distance += step;
+            if above(distance) <= 0.0 {
+                // Refine between the last point above ground and this one.
+                let (mut low, mut high) = (previous, distance);
+                for _ in 0..16 {
+                    let middle = (low + high) / 2.0;
+                    if above(middle) > 0.0 { low = middle } else { high = middle }
+                }
+                return Some(high);
+            }
+            previous = distance;
+        }
+        None
+    }
+}
+
+/// Procedural island: rolling fractal hills around a flat plateau at the
+/// origin, sinking below the sea towards the edges.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TerrainGenerator {
+    pub seed: u32,
+    /// Samples per side.
+    pub resolution: u32,
+    pub cell_size: f32,
+    /// Peak height of the hills.
+    pub height_scale: f32,
+    /// Hills per world unit of the first noise octave.
+    pub frequency: f32,
+    pub octaves: u32,
+    pub sea_level: f32,
+    /// Radius of the flat area around the origin.
+    pub plateau_radius: f32,
+}
+
+impl Default for TerrainGenerator {
+    fn default() -> Self {
+        Self {
+            seed: 7,
+            resolution: 129,
+            cell_size: 0.5,
+            height_scale: 4.0,
+            frequency: 0.06,
+            octaves: 5,
+            sea_level: -0.3,
+            plateau_radius: 3.0,
+        }
+    }
+}
+
+impl TerrainGenerator {
+    pub fn generate(&self) -> Heightmap {
+        let mut heightmap = Heightmap::flat(self.resolution, self.cell_size, 0.0);
+        heightmap.sea_level = self.sea_level;
+        let half = heightmap.size() / 2.0;
+        for z in 0..self.resolution {
+            for x in 0..self.resolution {
+                let position = heightmap.sample_position(x, z);
+                // Offset so the lowest hills dip into inland lakes.
+                let hills = (self.fractal_noise(position.x, position.z) - 0.3) * self.height_scale;
+                let distance = (position.x * position.x + position.z * position.z).sqrt();
+                // Blend from the plateau into the hills, then drop into the sea.
+                let hilliness = smoothstep(self.plateau_radius, self.plateau_radius * 3.0, distance);
+                let coast = smoothstep(half * 0.6, half * 0.95, distance);
+                let height = hills * hilliness - coast * (self.height_scale + 1.0);
+                heightmap.set_sample(x, z, height);
+            }
+        }
+        heightmap
+    }
+
+    fn fractal_noise(&self, x: f32, z: f32) -> f32 {
+        let mut sum = 0.0;
+        let mut amplitude = 0.5;
+        let mut frequency = self.frequency;
+        for octave in 0..self.octaves {
+            sum += amplitude * value_noise(self.seed.wrapping_add(octave), x * frequency, z * frequency);
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        sum
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn hash(seed: u32, x: i32, z: i32) -> f32 {
+    let mut h = seed
+        .wrapping_mul(0x9E37_79B9)
+        ^ (x as u32).wrapping_mul(0x85EB_CA6B)
+        ^ (z as u32).wrapping_mul(0xC2B2_AE35);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7FEB_352D);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846C_A68B);
+    h ^= h >> 16;
+    h as f32 / u32::MAX as f32
+}
+
+/// Smoothly interpolated lattice noise in `0.0..1.0`.
+fn value_noise(seed: u32, x: f32, z: f32) -> f32 {
+    let (x0, z0) = (x.floor(), z.floor());
+    let (tx, tz) = (smoothstep(0.0, 1.0, x - x0), smoothstep(0.0, 1.0, z - z0));
+    let (x0, z0) = (x0 as i32, z0 as i32);
+    let top = hash(seed, x0, z0) * (1.0 - tx) + hash(seed, x0 + 1, z0) * tx;
+    let bottom = hash(seed, x0, z0 + 1) * (1.0 - tx) + hash(seed, x0 + 1, z0 + 1) * tx;
+    top * (1.0 - tz) + bottom * tz
+}
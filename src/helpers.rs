@@ -1,11 +1,34 @@
-pub fn load_texture(path: String, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
-    let f = std::fs::File::open("./res/textures/".to_string() + &path).expect("failed to open file");
+/// How the texel data of a texture is interpreted when sampled.
+///
+/// Albedo/diffuse textures are authored in sRGB and must be decoded to linear
+/// by the sampler, while data textures (normal maps, roughness, splat weights)
+/// already store linear values and must be uploaded as-is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+/// Reads and decodes an image from `res/textures`. This does not touch the GPU,
+/// so it is safe to call from worker threads.
+pub fn decode_image(path: &str) -> image::ImageResult<image::RgbaImage> {
+    let f = std::fs::File::open("./res/textures/".to_string() + path)?;
     let reader = std::io::BufReader::new(f);
-    let diffuse_image = image::load(reader, image::ImageFormat::Png).expect("failed to read file");
-    let diffuse_rgba = diffuse_image.to_rgba8();
+    let diffuse_image = image::load(reader, image::ImageFormat::Png)?;
+    Ok(diffuse_image.to_rgba8())
+}
 
-    use image::GenericImageView;
-    let dimensions = diffuse_image.dimensions();
+pub fn upload_texture(label: &str, image: &image::RgbaImage, color_space: ColorSpace, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+    let dimensions = image.dimensions();
 
     let texture_size = wgpu::Extent3d {
         width: dimensions.0,
@@ -18,9 +41,9 @@ pub fn load_texture(path: String, device: &wgpu::Device, queue: &wgpu::Queue) ->
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format: color_space.texture_format(),
             usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-            label: Some("diffuse_texture"),
+            label: Some(label),
         }
     );
 
@@ -32,7 +55,7 @@ pub fn load_texture(path: String, device: &wgpu::Device, queue: &wgpu::Queue) ->
             origin: wgpu::Origin3d::ZERO,
         },
         // The actual pixel data
-        &diffuse_rgba,
+        image,
         // The layout of the texture
         wgpu::ImageDataLayout {
             offset: 0,
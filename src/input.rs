@@ -0,0 +1,643 @@
+//! Maps raw window input onto named actions and axes, so game code asks
+//! whether "select" was pressed rather than which mouse button went down.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use winit::event::{ElementState, ModifiersState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+use crate::gamepad::{GamepadAxis, GamepadButton};
+
+/// Pixels of a pixel-precise scroll that count as one wheel line.
+const PIXELS_PER_LINE: f32 = 20.0;
+
+#[derive(Debug)]
+pub enum InputError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::Io(e) => write!(f, "{}", e),
+            InputError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+impl From<std::io::Error> for InputError {
+    fn from(e: std::io::Error) -> Self {
+        InputError::Io(e)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+/// A button pressed while some modifiers are held, e.g. `Ctrl+Z`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Chord {
+    pub button: Button,
+    pub modifiers: ModifiersState,
+}
+
+impl Chord {
+    pub fn new(button: Button) -> Self {
+        Self { button, modifiers: ModifiersState::empty() }
+    }
+
+    pub fn with(mut self, modifiers: ModifiersState) -> Self {
+        self.modifiers |= modifiers;
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AxisSource {
+    /// +1 while `positive` is held, -1 while `negative` is.
+    Buttons { positive: Button, negative: Button },
+    /// Lines scrolled this frame, positive away from the user.
+    Wheel,
+    /// Pixels the cursor moved this frame.
+    MouseX,
+    MouseY,
+    /// A stick or trigger on any connected gamepad, after dead zones.
+    Gamepad { axis: GamepadAxis, inverted: bool },
+}
+
+/// Which chords trigger each action and which sources feed each axis.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bindings {
+    actions: Vec<(String, Vec<Chord>)>,
+    axes: Vec<(String, Vec<AxisSource>)>,
+}
+
+impl Bindings {
+    /// Loads a bindings file from `res/input`.
+    pub fn load(path: &str) -> Result<Self, InputError> {
+        Self::parse(&std::fs::read_to_string(Path::new("./res/input").join(path))?)
+    }
+
+    /// Parses the format described in `res/input/bindings.cfg`.
+    pub fn parse(source: &str) -> Result<Self, InputError> {
+        let mut bindings = Self::default();
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: String| InputError::Parse { line: index + 1, message };
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (declaration, sources) = line.split_once('=').ok_or_else(|| error("expected `=`".to_owned()))?;
+            let mut words = declaration.split_whitespace();
+            let (kind, name) = match (words.next(), words.next(), words.next()) {
+                (Some(kind), Some(name), None) => (kind, name),
+                _ => return Err(error("expected `action <name>` or `axis <name>`".to_owned())),
+            };
+            for source in sources.split('|').map(str::trim) {
+                match kind {
+                    "action" => bindings.bind(name, parse_chord(source).map_err(error)?),
+                    "axis" => bindings.bind_axis(name, parse_axis_source(source).map_err(error)?),
+                    _ => return Err(error(format!("unknown binding kind `{}`", kind))),
+                }
+            }
+        }
+        Ok(bindings)
+    }
+
+    /// Adds `chord` as another way to trigger `action`.
+    pub fn bind(&mut self, action: &str, chord: Chord) {
+        match self.actions.iter_mut().find(|(name, _)| name == action) {
+            Some((_, chords)) => chords.push(chord),
+            None => self.actions.push((action.to_owned(), vec![chord])),
+        }
+    }
+
+    pub fn bind_axis(&mut self, axis: &str, source: AxisSource) {
+        match self.axes.iter_mut().find(|(name, _)| name == axis) {
+            Some((_, sources)) => sources.push(source),
+            None => self.axes.push((axis.to_owned(), vec![source])),
+        }
+    }
+
+    /// Removes every chord bound to `action`, e.g. before rebinding it.
+    pub fn unbind(&mut self, action: &str) {
+        self.actions.retain(|(name, _)| name != action);
+    }
+
+    pub fn unbind_axis(&mut self, axis: &str) {
+        self.axes.retain(|(name, _)| name != axis);
+    }
+
+    pub fn chords(&self, action: &str) -> &[Chord] {
+        self.actions.iter().find(|(name, _)| name == action).map_or(&[], |(_, chords)| chords)
+    }
+
+    pub fn axis_sources(&self, axis: &str) -> &[AxisSource] {
+        self.axes.iter().find(|(name, _)| name == axis).map_or(&[], |(_, sources)| sources)
+    }
+}
+
+/// Writes the bindings back out in the format `parse` reads.
+impl fmt::Display for Bindings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, chords) in &self.actions {
+            let chords: Vec<String> = chords.iter().map(chord_name).collect();
+            writeln!(f, "action {} = {}", name, chords.join(" | "))?;
+        }
+        for (name, sources) in &self.axes {
+            let sources: Vec<String> = sources
+                .iter()
+                .map(|source| match source {
+                    AxisSource::Buttons { positive, negative } => format!("{} / {}", button_name(*positive), button_name(*negative)),
+                    AxisSource::Wheel => "Wheel".to_owned(),
+                    AxisSource::MouseX => "MouseX".to_owned(),
+                    AxisSource::MouseY => "MouseY".to_owned(),
+                    AxisSource::Gamepad { axis, inverted } => format!("{}{}", if *inverted { "-" } else { "" }, axis.name()),
+                })
+                .collect();
+            writeln!(f, "axis {} = {}", name, sources.join(" | "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The parts of a window event input mapping cares about. Converting to this
+/// first lets bindings be exercised with synthetic events.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    Button { button: Button, pressed: bool },
+    Modifiers(ModifiersState),
+    CursorMoved([f32; 2]),
+    /// Wheel lines, positive away from the user.
+    Wheel(f32),
+    /// The window lost focus, so held buttons will never report a release.
+    FocusLost,
+    /// Merged value of a gamepad axis, reported when it changes.
+    GamepadAxis { axis: GamepadAxis, value: f32 },
+}
+
+impl InputEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        match event {
+            WindowEvent::KeyboardInput { input, .. } => input.virtual_keycode.map(|key| InputEvent::Button {
+                button: Button::Key(key),
+                pressed: input.state == ElementState::Pressed,
+            }),
+            WindowEvent::MouseInput { state, button, .. } => Some(InputEvent::Button {
+                button: Button::Mouse(*button),
+                pressed: *state == ElementState::Pressed,
+            }),
+            WindowEvent::ModifiersChanged(modifiers) => Some(InputEvent::Modifiers(*modifiers)),
+            WindowEvent::CursorMoved { position, .. } => Some(InputEvent::CursorMoved([position.x as f32, position.y as f32])),
+            WindowEvent::MouseWheel { delta, .. } => Some(InputEvent::Wheel(match delta {
+                MouseScrollDelta::LineDelta(_, y) => *y,
+                MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+            })),
+            WindowEvent::Focused(false) => Some(InputEvent::FocusLost),
+            _ => None,
+        }
+    }
+}
+
+/// Edges and motion gathered between two frames.
+#[derive(Clone, Debug, Default)]
+struct FrameInput {
+    pressed: HashSet<usize>,
+    released: HashSet<usize>,
+    wheel: f32,
+    motion: [f32; 2],
+}
+
+/// Per-frame action and axis state, fed window events and advanced by
+/// `begin_frame`.
+pub struct Input {
+    bindings: Bindings,
+    action_indices: HashMap<String, usize>,
+    axis_indices: HashMap<String, usize>,
+    modifiers: ModifiersState,
+    cursor: Option<[f32; 2]>,
+    gamepad_axes: [f32; 6],
+    down: HashSet<Button>,
+    /// Actions each held button triggered when it went down, released with it.
+    triggered: HashMap<Button, Vec<usize>>,
+    pending: FrameInput,
+    frame: FrameInput,
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Self {
+        let mut input = Self {
+            bindings: Bindings::default(),
+            action_indices: HashMap::new(),
+            axis_indices: HashMap::new(),
+            modifiers: ModifiersState::empty(),
+            cursor: None,
+            gamepad_axes: [0.0; 6],
+            down: HashSet::new(),
+            triggered: HashMap::new(),
+            pending: FrameInput::default(),
+            frame: FrameInput::default(),
+        };
+        input.set_bindings(bindings);
+        input
+    }
+
+    pub fn bindings(&self) -> &Bindings {
+        &self.bindings
+    }
+
+    /// Swaps in new bindings. Held actions are released so nothing stays
+    /// stuck under a name that no longer exists.
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.action_indices = bindings.actions.iter().enumerate().map(|(i, (name, _))| (name.clone(), i)).collect();
+        self.axis_indices = bindings.axes.iter().enumerate().map(|(i, (name, _))| (name.clone(), i)).collect();
+        self.bindings = bindings;
+        self.triggered.clear();
+        self.pending = FrameInput::default();
+        self.frame = FrameInput::default();
+    }
+
+    /// Feeds a window event. While `captured`, e.g. by the UI, only releases
+    /// and cursor tracking get through so world actions neither start nor
+    /// stick.
+    pub fn handle_event(&mut self, event: &WindowEvent, captured: bool) {
+        if let Some(event) = InputEvent::from_window_event(event) {
+            self.handle(event, captured);
+        }
+    }
+
+    pub fn handle(&mut self, event: InputEvent, captured: bool) {
+        match event {
+            InputEvent::Button { button, pressed: true } => {
+                // Ignore key repeat and buttons pressed over the UI.
+                if captured || !self.down.insert(button) {
+                    return;
+                }
+                let actions = self.resolve(button);
+                for &action in &actions {
+                    if !self.is_held(action) {
+                        self.pending.pressed.insert(action);
+                    }
+                }
+                self.triggered.insert(button, actions);
+            }
+            InputEvent::Button { button, pressed: false } => {
+                self.down.remove(&button);
+                self.release(button);
+            }
+            InputEvent::Modifiers(modifiers) => self.modifiers = modifiers,
+            InputEvent::CursorMoved(position) => {
+                if let (Some(previous), false) = (self.cursor, captured) {
+                    self.pending.motion[0] += position[0] - previous[0];
+                    self.pending.motion[1] += position[1] - previous[1];
+                }
+                self.cursor = Some(position);
+            }
+            InputEvent::Wheel(lines) => {
+                if !captured {
+                    self.pending.wheel += lines;
+                }
+            }
+            InputEvent::FocusLost => {
+                for button in self.down.drain().collect::<Vec<_>>() {
+                    self.release(button);
+                }
+                self.modifiers = ModifiersState::empty();
+            }
+            InputEvent::GamepadAxis { axis, value } => self.gamepad_axes[axis.index()] = value,
+        }
+    }
+
+    /// Actions `button` triggers with the current modifiers: those whose
+    /// chords need the most modifiers among the ones satisfied.
+    fn resolve(&self, button: Button) -> Vec<usize> {
+        let mut best = None;
+        let mut actions = Vec::new();
+        for (index, (_, chords)) in self.bindings.actions.iter().enumerate() {
+            for chord in chords {
+                if chord.button != button || !self.modifiers.contains(chord.modifiers) {
+                    continue;
+                }
+                let specificity = chord.modifiers.bits().count_ones();
+                if best.is_none_or(|best| specificity > best) {
+                    best = Some(specificity);
+                    actions.clear();
+                }
+                if best == Some(specificity) && !actions.contains(&index) {
+                    actions.push(index);
+                }
+            }
+        }
+        actions
+    }
+
+    fn release(&mut self, button: Button) {
+        for action in self.triggered.remove(&button).unwrap_or_default() {
+            if !self.is_held(action) {
+                self.pending.released.insert(action);
+            }
+        }
+    }
+
+    fn is_held(&self, action: usize) -> bool {
+        self.triggered.values().any(|actions| actions.contains(&action))
+    }
+
+    /// Makes the events received since the last call this frame's input.
+    pub fn begin_frame(&mut self) {
+        self.frame = std::mem::take(&mut self.pending);
+    }
+
+    /// True on the frame `action` went down.
+    pub fn pressed(&self, action: &str) -> bool {
+        self.action_indices.get(action).is_some_and(|index| self.frame.pressed.contains(index))
+    }
+
+    /// True while any chord bound to `action` is down.
+    pub fn held(&self, action: &str) -> bool {
+        self.action_indices.get(action).is_some_and(|&index| self.is_held(index))
+    }
+
+    /// True on the frame `action` was let go.
+    pub fn released(&self, action: &str) -> bool {
+        self.action_indices.get(action).is_some_and(|index| self.frame.released.contains(index))
+    }
+
+    /// Sum of every source bound to `axis` this frame. Button pairs are
+    /// clamped to -1..1 while wheel and mouse sources aren't.
+    pub fn axis(&self, axis: &str) -> f32 {
+        let sources = match self.axis_indices.get(axis) {
+            Some(&index) => &self.bindings.axes[index].1,
+            None => return 0.0,
+        };
+        let mut buttons = 0.0f32;
+        let mut value = 0.0;
+        for source in sources {
+            match source {
+                AxisSource::Buttons { positive, negative } => {
+                    buttons += self.down.contains(positive) as i32 as f32 - self.down.contains(negative) as i32 as f32;
+                }
+                AxisSource::Wheel => value += self.frame.wheel,
+                AxisSource::MouseX => value += self.frame.motion[0],
+                AxisSource::MouseY => value += self.frame.motion[1],
+                AxisSource::Gamepad { axis, inverted } => {
+                    let axis = self.gamepad_axes[axis.index()];
+                    value += if *inverted { -axis } else { axis };
+                }
+            }
+        }
+        value + buttons.clamp(-1.0, 1.0)
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    /// Last known cursor position in pixels from the top left of the window.
+    pub fn cursor(&self) -> Option<[f32; 2]> {
+        self.cursor
+    }
+
+    pub fn is_down(&self, button: Button) -> bool {
+        self.down.contains(&button)
+    }
+}
+
+/// The bindings shipped in `res/input/bindings.cfg`.
+impl Default for Input {
+    fn default() -> Self {
+        Self::new(Bindings::parse(include_str!("../res/input/bindings.cfg")).expect("default bindings are valid"))
+    }
+}
+
+fn parse_chord(source: &str) -> Result<Chord, String> {
+    let mut parts: Vec<&str> = source.split('+').map(str::trim).collect();
+    let button = parse_button(parts.pop().unwrap_or_default())?;
+    let mut chord = Chord::new(button);
+    for part in parts {
+        chord = chord.with(match part {
+            "Ctrl" => ModifiersState::CTRL,
+            "Shift" => ModifiersState::SHIFT,
+            "Alt" => ModifiersState::ALT,
+            "Logo" => ModifiersState::LOGO,
+            _ => return Err(format!("unknown modifier `{}`", part)),
+        });
+    }
+    Ok(chord)
+}
+
+fn parse_axis_source(source: &str) -> Result<AxisSource, String> {
+    match source {
+        "Wheel" => return Ok(AxisSource::Wheel),
+        "MouseX" => return Ok(AxisSource::MouseX),
+        "MouseY" => return Ok(AxisSource::MouseY),
+        _ => {}
+    }
+    // A leading `-` flips a gamepad axis, e.g. so stick up pans forward.
+    let inverted = source.starts_with('-');
+    if let Some(axis) = GamepadAxis::from_name(source.trim_start_matches('-')) {
+        return Ok(AxisSource::Gamepad { axis, inverted });
+    }
+    let (positive, negative) = source
+        .split_once('/')
+        .ok_or_else(|| format!("expected `<positive> / <negative>`, `Wheel`, `MouseX`, `MouseY` or a gamepad axis, found `{}`", source))?;
+    Ok(AxisSource::Buttons { positive: parse_button(positive.trim())?, negative: parse_button(negative.trim())? })
+}
+
+fn parse_button(name: &str) -> Result<Button, String> {
+    let mouse = match name {
+        "MouseLeft" => Some(MouseButton::Left),
+        "MouseRight" => Some(MouseButton::Right),
+        "MouseMiddle" => Some(MouseButton::Middle),
+        _ => name.strip_prefix("Mouse").and_then(|n| n.parse().ok()).map(MouseButton::Other),
+    };
+    mouse
+        .map(Button::Mouse)
+        .or_else(|| GamepadButton::from_name(name).map(Button::Gamepad))
+        .or_else(|| key_code(name).map(Button::Key))
+        .ok_or_else(|| format!("unknown key or button `{}`", name))
+}
+
+fn button_name(button: Button) -> String {
+    match button {
+        Button::Key(key) => key_name(key).map_or_else(|| format!("{:?}", key), str::to_owned),
+        Button::Mouse(MouseButton::Left) => "MouseLeft".to_owned(),
+        Button::Mouse(MouseButton::Right) => "MouseRight".to_owned(),
+        Button::Mouse(MouseButton::Middle) => "MouseMiddle".to_owned(),
+        Button::Mouse(MouseButton::Other(n)) => format!("Mouse{}", n),
+        Button::Gamepad(button) => button.name().to_owned(),
+    }
+}
+
+fn chord_name(chord: &Chord) -> String {
+    let mut name = String::new();
+    for (modifier, prefix) in [
+        (ModifiersState::CTRL, "Ctrl+"),
+        (ModifiersState::SHIFT, "Shift+"),
+        (ModifiersState::ALT, "Alt+"),
+        (ModifiersState::LOGO, "Logo+"),
+    ] {
+        if chord.modifiers.contains(modifier) {
+            name.push_str(prefix);
+        }
+    }
+    name + &button_name(chord.button)
+}
+
+/// Maps between key names in bindings files and winit key codes. The names
+/// are the `VirtualKeyCode` variant names.
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        fn key_code(name: &str) -> Option<VirtualKeyCode> {
+            match name {
+                $(stringify!($key) => Some(VirtualKeyCode::$key),)*
+                _ => None,
+            }
+        }
+
+        fn key_name(key: VirtualKeyCode) -> Option<&'static str> {
+            match key {
+                $(VirtualKeyCode::$key => Some(stringify!($key)),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+key_names!(
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Snapshot, Scroll, Pause, Insert, Home, Delete, End, PageDown, PageUp,
+    Left, Up, Right, Down, Back, Return, Space, Tab, Capital,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    NumpadAdd, NumpadDivide, NumpadDecimal, NumpadEnter, NumpadMultiply, NumpadSubtract,
+    Apostrophe, Backslash, Comma, Equals, Grave, LBracket, Minus, Period, RBracket, Semicolon, Slash,
+    LAlt, LControl, LShift, LWin, RAlt, RControl, RShift, RWin,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: VirtualKeyCode, pressed: bool) -> InputEvent {
+        InputEvent::Button { button: Button::Key(key), pressed }
+    }
+
+    fn parse_error_line(source: &str) -> usize {
+        match Bindings::parse(source) {
+            Err(InputError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bindings_round_trip_through_display() {
+        let bindings = Bindings::parse(include_str!("../res/input/bindings.cfg")).unwrap();
+        assert_eq!(Bindings::parse(&bindings.to_string()).unwrap(), bindings);
+
+        let source = "action undo = Ctrl+Z | Mouse4\naxis pan = D / A | -PadLeftY | Wheel\n";
+        let bindings = Bindings::parse(source).unwrap();
+        assert_eq!(bindings.to_string(), source);
+        assert_eq!(bindings.chords("undo"), &[Chord::new(Button::Key(VirtualKeyCode::Z)).with(ModifiersState::CTRL), Chord::new(Button::Mouse(MouseButton::Other(4)))]);
+    }
+
+    #[test]
+    fn parse_errors_report_their_line() {
+        assert_eq!(parse_error_line("# comment\naction quit Escape\n"), 2);
+        assert_eq!(parse_error_line("action quit = Escape\n\naction undo = Hyper+Z\n"), 3);
+        assert_eq!(parse_error_line("button quit = Escape\n"), 1);
+        assert_eq!(parse_error_line("axis pan = D\n"), 1);
+        assert_eq!(parse_error_line("action quit = NoSuchKey\n"), 1);
+    }
+
+    #[test]
+    fn the_most_specific_chord_wins() {
+        let mut input = Input::new(Bindings::parse("action group.select.1 = Key1\naction group.set.1 = Ctrl+Key1\naction group.add.1 = Shift+Key1\n").unwrap());
+        input.handle(InputEvent::Modifiers(ModifiersState::SHIFT), false);
+        input.handle(key(VirtualKeyCode::Key1, true), false);
+        input.begin_frame();
+        assert!(input.pressed("group.add.1"));
+        assert!(!input.pressed("group.select.1"));
+        assert!(!input.pressed("group.set.1"));
+        input.handle(key(VirtualKeyCode::Key1, false), false);
+
+        input.handle(InputEvent::Modifiers(ModifiersState::CTRL), false);
+        input.handle(key(VirtualKeyCode::Key1, true), false);
+        input.begin_frame();
+        assert!(input.pressed("group.set.1"));
+        assert!(!input.pressed("group.select.1"));
+        input.handle(key(VirtualKeyCode::Key1, false), false);
+
+        input.handle(InputEvent::Modifiers(ModifiersState::empty()), false);
+        input.handle(key(VirtualKeyCode::Key1, true), false);
+        input.begin_frame();
+        assert!(input.pressed("group.select.1"));
+    }
+
+    #[test]
+    fn edges_last_one_frame() {
+        let mut input = Input::new(Bindings::parse("action jump = Space | MouseRight").unwrap());
+        input.handle(key(VirtualKeyCode::Space, true), false);
+        assert!(!input.pressed("jump"), "edges wait for begin_frame");
+        input.begin_frame();
+        assert!(input.pressed("jump") && input.held("jump"));
+        input.begin_frame();
+        assert!(!input.pressed("jump") && input.held("jump"));
+
+        // A second chord of the same action neither presses nor releases it.
+        input.handle(InputEvent::Button { button: Button::Mouse(MouseButton::Right), pressed: true }, false);
+        input.handle(key(VirtualKeyCode::Space, false), false);
+        input.begin_frame();
+        assert!(!input.pressed("jump") && !input.released("jump") && input.held("jump"));
+
+        input.handle(InputEvent::Button { button: Button::Mouse(MouseButton::Right), pressed: false }, false);
+        input.begin_frame();
+        assert!(input.released("jump") && !input.held("jump"));
+        input.begin_frame();
+        assert!(!input.released("jump"));
+    }
+
+    #[test]
+    fn captured_presses_are_ignored() {
+        let mut input = Input::new(Bindings::parse("action select = MouseLeft\naxis zoom = Wheel").unwrap());
+        input.handle(InputEvent::Button { button: Button::Mouse(MouseButton::Left), pressed: true }, true);
+        input.handle(InputEvent::Wheel(2.0), true);
+        input.begin_frame();
+        assert!(!input.pressed("select") && !input.held("select"));
+        assert_eq!(input.axis("zoom"), 0.0);
+
+        // Releasing over the UI still lets go of an action started outside it.
+        input.handle(InputEvent::Button { button: Button::Mouse(MouseButton::Left), pressed: false }, true);
+        input.handle(InputEvent::Button { button: Button::Mouse(MouseButton::Left), pressed: true }, false);
+        input.handle(InputEvent::Button { button: Button::Mouse(MouseButton::Left), pressed: false }, true);
+        input.begin_frame();
+        assert!(input.pressed("select") && input.released("select") && !input.held("select"));
+    }
+
+    #[test]
+    fn focus_loss_releases_everything() {
+        let mut input = Input::new(Bindings::parse("action fire = Ctrl+F\naxis pan = D / A").unwrap());
+        input.handle(InputEvent::Modifiers(ModifiersState::CTRL), false);
+        input.handle(key(VirtualKeyCode::F, true), false);
+        input.handle(key(VirtualKeyCode::D, true), false);
+        input.begin_frame();
+        assert!(input.held("fire"));
+        assert_eq!(input.axis("pan"), 1.0);
+
+        input.handle(InputEvent::FocusLost, false);
+        input.begin_frame();
+        assert!(input.released("fire") && !input.held("fire"));
+        assert_eq!(input.axis("pan"), 0.0);
+        assert_eq!(input.modifiers(), ModifiersState::empty());
+        assert!(!input.is_down(Button::Key(VirtualKeyCode::F)));
+    }
+}
@@ -4,90 +4,204 @@ use std::sync::Arc;
 use bytemuck::{Pod, Zeroable};
 use wgpu::{BindGroup, BindGroupLayout, CommandBuffer, util::DeviceExt};
 
+pub mod animation;
+pub mod assets;
 pub mod camera;
+pub mod debug_draw;
+pub mod ecs;
+pub mod fog;
+pub mod gamepad;
 pub(crate) mod helpers;
+pub mod heightmap;
+pub mod input;
+pub mod mesh;
+pub mod navigation;
+pub mod orders;
+pub mod model;
+pub mod post;
+pub mod scene;
+pub mod selection;
+pub mod shadow;
+pub mod skinned;
+pub mod sky;
 pub mod terrain;
+pub mod text;
+pub mod texture_array;
+pub mod time_of_day;
+pub mod timestep;
+pub mod ui;
+pub mod water;
+pub use self::helpers::ColorSpace;
 use self::terrain::Terrain;
+use self::heightmap::TerrainGenerator;
 use self::camera::Camera;
+use self::assets::{AssetLoader, LoadProgress, TextureHandle};
+use self::mesh::{Instance, InstanceBuffer, Mesh, MeshBatch, MeshRenderer};
+use self::model::{Model, ModelData};
+use self::animation::AnimationPlayer;
+use self::skinned::{SkinnedInstance, SkinnedInstanceBuffer, SkinnedMesh, SkinnedMeshRenderer};
+use self::post::{PostProcess, PostSettings, HDR_FORMAT};
+use self::scene::{GlobalTransform, MeshId, MeshInstance, Transform, TransformPropagation};
+use self::selection::{Selectable, Selected, Selection};
+use self::navigation::{FlowFields, Footprint, NavGrid, NavSettings, Path};
+use self::orders::{Command, Construction, Order, OrderInput, Orders};
+use self::sky::{Sky, SkySettings};
+use self::fog::{FogColor, FogSettings};
+use self::ecs::{Schedule, With, World};
+use self::gamepad::Gamepads;
+use self::input::{Bindings, Input};
+use self::time_of_day::TimeOfDay;
+use self::timestep::{FixedTimestep, GameSpeed, Interpolated, Tick};
+use self::ui::{DrawCommand, Theme, Ui};
+use self::water::{Water, WaterSettings};
+use self::text::TextRenderer;
+use self::shadow::{Cascade, ShadowMaps, ShadowSettings, MAX_CASCADES};
+
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// Depth state shared by every pipeline that renders into `SceneTargets::depth`.
+pub(crate) fn depth_stencil_state() -> wgpu::DepthStencilState {
+    wgpu::DepthStencilState {
+        format: DEPTH_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::Less,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    }
+}
 
 pub struct ScreenTargets {
     pub extent: wgpu::Extent3d,
     pub color: Arc<wgpu::SwapChainFrame>,
+}
+
+/// What scene passes render into. Post-processing reads the HDR colour target
+/// and resolves it into the swap chain.
+pub struct SceneTargets {
+    pub extent: wgpu::Extent3d,
+    /// Multisampled when MSAA is enabled, otherwise the HDR target itself.
+    pub color: Arc<wgpu::TextureView>,
+    /// The HDR target `color` resolves into when multisampled.
+    pub resolve: Option<Arc<wgpu::TextureView>>,
     pub depth: Arc<wgpu::TextureView>,
+    pub sample_count: u32,
 }
 
-pub async fn clear_screen(
-    device: &wgpu::Device,
-    targets: Arc<ScreenTargets>,
-    color: wgpu::Color,
-) -> wgpu::CommandBuffer {
-    let mut encoder =
-        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-    {
-        let _ = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: None,
-            color_attachments: &[wgpu::RenderPassColorAttachment {
-                view: &targets.color.output.view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(color),
-                    store: true,
-                },
-            }],
-            depth_stencil_attachment: None,
-        });
+impl SceneTargets {
+    fn new(device: &wgpu::Device, extent: wgpu::Extent3d, sample_count: u32, hdr: Arc<wgpu::TextureView>) -> Self {
+        let size = wgpu::Extent3d {
+            width: extent.width.max(1),
+            height: extent.height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let depth = Arc::new(device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Depth"),
+                size,
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: DEPTH_FORMAT,
+                usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default()));
+        let (color, resolve) = if sample_count > 1 {
+            let msaa = device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Multisampled Colour"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: HDR_FORMAT,
+                    usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default());
+            (Arc::new(msaa), Some(hdr))
+        } else {
+            (hdr, None)
+        };
+        Self { extent, color, resolve, depth, sample_count }
     }
-    encoder.finish()
 }
 
+/// Sample counts the scene targets can use. wgpu can't report per-format
+/// limits yet, so only the 1x and 4x WebGPU guarantees for every format,
+/// including the `Rgba16Float` HDR target, are offered.
+pub const SUPPORTED_SAMPLE_COUNTS: &[u32] = &[1, 4];
+
+pub const DEFAULT_SAMPLE_COUNT: u32 = 4;
+
+/// Multiples of the camera's distance to its target panned per second.
+const CAMERA_PAN_SPEED: f32 = 1.0;
+/// Factor the camera distance shrinks by per wheel line.
+const CAMERA_ZOOM_STEP: f32 = 1.15;
+const CAMERA_MIN_DISTANCE: f32 = 0.5;
+const CAMERA_MAX_DISTANCE: f32 = 60.0;
+
 pub struct Triangle {
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
     render_pipeline: wgpu::RenderPipeline,
 }
 
 impl Triangle {
-    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat, uniforms_bgl: &BindGroupLayout) -> Self {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat, sample_count: u32, uniforms_bgl: &BindGroupLayout) -> Self {
         let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
             label: None,
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../res/shader/main.wgsl"))),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!("../res/shader/globals.wgsl"), include_str!("../res/shader/main.wgsl")))),
             flags: wgpu::ShaderFlags::all(),
         });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: None,
-        bind_group_layouts: &[uniforms_bgl],
-        push_constant_ranges: &[],
-    });
-
-    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: None,
-        layout: Some(&pipeline_layout),
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vs_main",
-            buffers: &[],
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
-            targets: &[color_format.into()],
-        }),
-        primitive: wgpu::PrimitiveState::default(),
-        depth_stencil: None,
-        multisample: wgpu::MultisampleState::default(),
+            label: None,
+            bind_group_layouts: &[uniforms_bgl],
+            push_constant_ranges: &[],
         });
 
-        Self { render_pipeline }
+        let render_pipeline = Self::create_render_pipeline(device, &pipeline_layout, &shader, color_format, sample_count);
+
+        Self { shader, pipeline_layout, color_format, render_pipeline }
     }
 
-    pub async fn draw(&self, device: &wgpu::Device, targets: Arc<ScreenTargets>, uniforms_bg: &BindGroup) -> CommandBuffer {
+    fn create_render_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, color_format: wgpu::TextureFormat, sample_count: u32) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[color_format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Rebuilds the pipeline for targets with `sample_count` samples.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.render_pipeline = Self::create_render_pipeline(device, &self.pipeline_layout, &self.shader, self.color_format, sample_count);
+    }
+
+    pub async fn draw(&self, device: &wgpu::Device, targets: Arc<SceneTargets>, uniforms_bg: &BindGroup) -> CommandBuffer {
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
         {
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[wgpu::RenderPassColorAttachment {
-                    view: &targets.color.output.view,
-                    resolve_target: None,
+                    view: &targets.color,
+                    resolve_target: targets.resolve.as_deref(),
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Load,
                         store: true,
@@ -111,6 +225,19 @@ struct Uniforms {
     // We can't use cgmath with bytemuck directly so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
+    camera_position: [f32; 4],
+    light_view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    cascade_splits: [f32; 4],
+    // w holds the cascade count
+    sun_direction: [f32; 4],
+    // a holds the ambient intensity
+    sun_color: [f32; 4],
+    fog_color: [f32; 4],
+    fog_params: [f32; 4],
+    height_fog: [f32; 4],
+    clip_plane: [f32; 4],
 }
 
 unsafe impl Zeroable for Uniforms{}
@@ -121,31 +248,152 @@ impl Uniforms {
         use cgmath::SquareMatrix;
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            view: cgmath::Matrix4::identity().into(),
+            inv_view_proj: cgmath::Matrix4::identity().into(),
+            camera_position: [0.0, 0.0, 0.0, 1.0],
+            light_view_proj: shadow::identity_cascades(),
+            cascade_splits: [0.0; 4],
+            sun_direction: [0.0, 1.0, 0.0, 0.0],
+            sun_color: [1.0, 1.0, 1.0, 0.3],
+            fog_color: [0.0; 4],
+            fog_params: [0.0; 4],
+            height_fog: [0.0; 4],
+            clip_plane: [0.0, 0.0, 0.0, 1.0],
         }
     }
 
     fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into();
+        use cgmath::SquareMatrix;
+        let view_proj = camera.build_view_projection_matrix();
+        self.view_proj = view_proj.into();
+        self.view = camera.build_view_matrix().into();
+        self.inv_view_proj = view_proj.invert().unwrap_or_else(cgmath::Matrix4::identity).into();
+        self.camera_position = camera.eye.to_homogeneous().into();
+    }
+
+    fn update_sun(&mut self, direction: cgmath::Vector3<f32>, color: [f32; 3], ambient: f32, cascades: &[Cascade]) {
+        use cgmath::InnerSpace;
+        let direction = direction.normalize();
+        self.sun_direction = [direction.x, direction.y, direction.z, cascades.len() as f32];
+        self.sun_color = [color[0], color[1], color[2], ambient];
+        for (i, cascade) in cascades.iter().enumerate() {
+            self.light_view_proj[i] = cascade.view_proj.into();
+            self.cascade_splits[i] = cascade.split_depth;
+        }
+    }
+
+    fn update_fog(&mut self, fog: &FogSettings, color: [f32; 3]) {
+        let (params, height) = fog.params();
+        self.fog_color = [color[0], color[1], color[2], 1.0];
+        self.fog_params = params;
+        self.height_fog = height;
+    }
+
+    /// Uniforms for rendering the scene mirrored about the water surface. The
+    /// image is also flipped horizontally to keep the triangle winding, and
+    /// everything below the surface is clipped.
+    fn reflected(&self, camera: &Camera, sea_level: f32) -> Self {
+        use cgmath::{SquareMatrix, Transform};
+        let mirror = water::reflection_matrix(sea_level);
+        let flip = cgmath::Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0);
+        let view_proj = flip * camera.build_view_projection_matrix() * mirror;
+        Self {
+            view_proj: view_proj.into(),
+            view: (camera.build_view_matrix() * mirror).into(),
+            inv_view_proj: view_proj.invert().unwrap_or_else(cgmath::Matrix4::identity).into(),
+            camera_position: mirror.transform_point(camera.eye).to_homogeneous().into(),
+            clip_plane: [0.0, 1.0, 0.0, -sea_level],
+            ..*self
+        }
     }
 }
 
+fn create_uniform_bind_group(device: &wgpu::Device, layout: &BindGroupLayout, uniform_buffer: &wgpu::Buffer, shadows: &ShadowMaps) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(shadows.view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(shadows.sampler()),
+            },
+        ],
+        label: Some("uniform_bind_group"),
+    })
+}
 
 pub struct Autonomy {
     camera: Camera,
     triangle: Triangle,
     uniforms: Uniforms,
     uniform_buffer: wgpu::Buffer,
+    uniform_bind_group_layout: BindGroupLayout,
     uniform_bind_group: BindGroup,
+    shadows: ShadowMaps,
+    /// Simulation clock; rendering blends between its last two ticks.
+    timestep: FixedTimestep,
+    /// Game objects, updated by `schedule` once per tick.
+    world: World,
+    schedule: Schedule,
+    transform_propagation: TransformPropagation,
+    selection: Selection,
+    order_input: OrderInput,
+    time_of_day: Interpolated<TimeOfDay>,
+    sun_direction: cgmath::Vector3<f32>,
+    sun_color: [f32; 3],
+    ambient: f32,
+    post: PostProcess,
+    scene: Arc<SceneTargets>,
+    sky: Sky,
+    fog: FogSettings,
     terrain: Terrain,
+    water: Water,
+    text: TextRenderer,
+    ui: Ui,
+    input: Input,
+    gamepads: Gamepads,
+    #[cfg(feature = "debug_draw")]
+    debug_renderer: debug_draw::DebugRenderer,
+    reflection_uniform_buffer: wgpu::Buffer,
+    reflection_bind_group: BindGroup,
+    /// Seconds since start, for animating the water.
+    elapsed: f32,
+    assets: AssetLoader,
+    terrain_diffuse: TextureHandle,
+    mesh_renderer: MeshRenderer,
+    cube: Mesh,
+    cube_instances: InstanceBuffer,
+    marker: Model,
+    marker_instances: Vec<InstanceBuffer>,
+    /// Meshes drawn for entities with a `MeshInstance`, indexed by `MeshId`.
+    entity_meshes: Vec<(Mesh, InstanceBuffer)>,
+    entity_instances: Vec<Vec<Instance>>,
+    /// Drawn under selected units.
+    selection_ring: Mesh,
+    selection_ring_instances: InstanceBuffer,
+    /// Queued orders of the selected units, drawn with the selection ring.
+    waypoint_instances: InstanceBuffer,
+    skinned_renderer: SkinnedMeshRenderer,
+    walker: ModelData,
+    walker_mesh: SkinnedMesh,
+    walker_instances: SkinnedInstanceBuffer,
+    walker_players: Vec<AnimationPlayer>,
 }
 
 impl Autonomy {
-    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, color_format: wgpu::TextureFormat) -> Self {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, color_format: wgpu::TextureFormat, extent: wgpu::Extent3d) -> Self {
         let camera = Camera{
             eye: (0.0, 1.0, 2.0).into(),
             target: (0.0, 0.0, 0.0).into(),
             up: cgmath::Vector3::unit_y(),
-            aspect: 1.0,
+            aspect: extent.width as f32 / extent.height.max(1) as f32,
             fovy: 45.0,
             znear: 0.1,
             zfar: 100.0,
@@ -165,47 +413,787 @@ impl Autonomy {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStage::VERTEX,
+                    visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
                     count: None,
-                }
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: true,
+                        filtering: true,
+                    },
+                    count: None,
+                },
             ],
             label: Some("uniform_bind_group_layout"),
         });
 
-        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &uniform_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: uniform_buffer.as_entire_binding(),
-                }
-            ],
-            label: Some("uniform_bind_group"),
-        });
+        let shadows = ShadowMaps::new(device, ShadowSettings::default(), std::mem::size_of::<Uniforms>());
+        let uniform_bind_group = create_uniform_bind_group(device, &uniform_bind_group_layout, &uniform_buffer, &shadows);
+
+        let post = PostProcess::new(device, queue, color_format, extent);
+        let scene = Arc::new(SceneTargets::new(device, extent, DEFAULT_SAMPLE_COUNT, post.hdr_view()));
+        let sky = Sky::new(device, HDR_FORMAT, DEFAULT_SAMPLE_COUNT, &uniform_bind_group_layout);
+        let time_of_day = TimeOfDay::default();
+        let lighting = time_of_day.lighting(sky.settings());
+        let triangle = Triangle::new(device, HDR_FORMAT, DEFAULT_SAMPLE_COUNT, &uniform_bind_group_layout);
+        let heightmap = TerrainGenerator::default().generate();
+        let water = Water::new(device, queue, HDR_FORMAT, DEFAULT_SAMPLE_COUNT, &uniform_bind_group_layout, shadows.pass_layout(), std::mem::size_of::<Uniforms>(), extent, heightmap.sea_level());
+        let terrain = Terrain::new(device, queue, HDR_FORMAT, DEFAULT_SAMPLE_COUNT, &uniform_bind_group_layout, shadows.pass_layout(), heightmap);
+        let reflection_uniform_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Reflection Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[uniforms]),
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            }
+        );
+        let reflection_bind_group = create_uniform_bind_group(device, &uniform_bind_group_layout, &reflection_uniform_buffer, &shadows);
+        let text = TextRenderer::new(device, queue, HDR_FORMAT, color_format, DEFAULT_SAMPLE_COUNT, &uniform_bind_group_layout);
+        let mut ui = Ui::new(text.font(TextRenderer::DEFAULT_FONT).clone(), Theme::default());
+        ui.set_screen_size([extent.width as f32, extent.height as f32]);
+        let input = match Bindings::load("bindings.cfg") {
+            Ok(bindings) => Input::new(bindings),
+            Err(e) => {
+                log::error!("Failed to load input bindings, using the defaults: {}", e);
+                Input::default()
+            }
+        };
+        #[cfg(feature = "debug_draw")]
+        let debug_renderer = debug_draw::DebugRenderer::new(device, HDR_FORMAT, DEFAULT_SAMPLE_COUNT, &uniform_bind_group_layout);
+
+        let mesh_renderer = MeshRenderer::new(device, queue, HDR_FORMAT, DEFAULT_SAMPLE_COUNT, &uniform_bind_group_layout, shadows.pass_layout());
+        let cube = Mesh::cube(device);
+        // Placeholder scenery until game objects drive the instance buffers.
+        // It never moves, so it is uploaded once here.
+        let mut cube_instances = InstanceBuffer::new(device, 256);
+        let instances: Vec<Instance> = (0..16 * 16)
+            .map(|i| {
+                let (x, z) = ((i % 16) as f32, (i / 16) as f32);
+                let position = cgmath::Vector3::new(-0.9 + x * 0.12, 0.025, -0.9 + z * 0.12);
+                let transform = cgmath::Matrix4::from_translation(position) * cgmath::Matrix4::from_scale(0.05);
+                Instance::new(transform, [0.2 + x / 20.0, 0.5, 0.2 + z / 20.0, 1.0])
+            })
+            .collect();
+        cube_instances.update(device, queue, &instances);
+        let mut assets = AssetLoader::default();
+        let marker = ModelData::load("marker.gltf").expect("failed to load marker model").upload(device, &mesh_renderer, &mut assets);
+        let placements = [
+            cgmath::Matrix4::from_translation(cgmath::Vector3::new(-0.5, 0.0, 0.5)) * cgmath::Matrix4::from_scale(0.2),
+            cgmath::Matrix4::from_translation(cgmath::Vector3::new(0.5, 0.0, 0.5)) * cgmath::Matrix4::from_scale(0.2),
+        ];
+        let marker_instances = marker
+            .instances(&placements)
+            .iter()
+            .map(|instances| {
+                let mut buffer = InstanceBuffer::new(device, instances.len());
+                buffer.update(device, queue, instances);
+                buffer
+            })
+            .collect();
+        let entity_meshes = vec![(Mesh::cube(device), InstanceBuffer::new(device, 16))];
+        let selection_ring = Mesh::ring(device, 0.8);
+        let selection_ring_instances = InstanceBuffer::new(device, 16);
+        let mut world = World::new();
+        world.insert_resource(terrain.heightmap().clone());
+        world.insert_resource(NavGrid::from_heightmap(terrain.heightmap(), &NavSettings::default()));
+        world.insert_resource(FlowFields::default());
+        world.insert_resource(orders::BuildingFootprints(vec![0.075]));
+        world.insert_resource(orders::PendingCommands::default());
+        world.insert_resource(orders::Stockpile::default());
+        spawn_placeholder_units(&mut world, MeshId(0), terrain.heightmap());
+        let mut schedule = Schedule::new();
+        schedule.add_system("snapshot_transforms", scene::snapshot_transforms);
+        schedule.add_system("obstacles", navigation::obstacle_system);
+        schedule.add_system("flow_fields", navigation::flow_field_system);
+        schedule.add_system("orders", orders::order_system);
 
-        let triangle = Triangle::new(device, color_format, &uniform_bind_group_layout);
-        let terrain = Terrain::new(device, queue, color_format, &uniform_bind_group_layout);
+        let skinned_renderer = SkinnedMeshRenderer::new(device, HDR_FORMAT, DEFAULT_SAMPLE_COUNT, &uniform_bind_group_layout, shadows.pass_layout());
+        let walker = ModelData::load("walker.gltf").expect("failed to load walker model");
+        let walker_mesh = SkinnedMesh::from_data(device, &walker.meshes[0]).expect("walker mesh is not skinned");
+        let walker_instances = SkinnedInstanceBuffer::new(device, 4);
+        let walker_players = (0..4)
+            .map(|i| {
+                let clip = if i % 2 == 0 { "idle" } else { "sway" };
+                let mut player = AnimationPlayer::new(walker.clip(clip).unwrap(), true);
+                player.speed = 0.5 + i as f32 * 0.25;
+                player
+            })
+            .collect();
+
+        let terrain_diffuse = assets.load_texture("prototype/Light/texture_07.png", ColorSpace::Srgb);
         Autonomy {
             camera,
             triangle,
             terrain,
+            water,
+            text,
+            ui,
+            input,
+            gamepads: Gamepads::default(),
+            #[cfg(feature = "debug_draw")]
+            debug_renderer,
+            reflection_uniform_buffer,
+            reflection_bind_group,
+            elapsed: 0.0,
             uniforms,
             uniform_buffer,
+            uniform_bind_group_layout,
             uniform_bind_group,
+            shadows,
+            timestep: FixedTimestep::default(),
+            world,
+            schedule,
+            transform_propagation: TransformPropagation::default(),
+            selection: Selection::default(),
+            order_input: OrderInput::default(),
+            time_of_day: Interpolated::new(time_of_day),
+            sun_direction: lighting.direction,
+            sun_color: lighting.color,
+            ambient: lighting.ambient,
+            post,
+            scene,
+            sky,
+            fog: FogSettings::default(),
+            assets,
+            terrain_diffuse,
+            mesh_renderer,
+            cube,
+            cube_instances,
+            marker,
+            marker_instances,
+            entity_meshes,
+            entity_instances: Vec::new(),
+            selection_ring,
+            selection_ring_instances,
+            waypoint_instances: InstanceBuffer::new(device, 64),
+            skinned_renderer,
+            walker,
+            walker_mesh,
+            walker_instances,
+            walker_players,
+        }
+    }
+
+    /// Routes a window event to the UI, then to input mapping unless the UI
+    /// consumed it.
+    pub fn handle_event(&mut self, event: &winit::event::WindowEvent) {
+        let captured = self.ui.handle_event(event);
+        self.input.handle_event(event, captured);
+    }
+
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, delta: f32) {
+        let screen_size = [self.scene.extent.width as f32, self.scene.extent.height as f32];
+        for event in self.gamepads.update(delta, self.input.cursor(), screen_size) {
+            let captured = self.ui.handle_input(&event);
+            self.input.handle(event, captured);
+        }
+        self.input.begin_frame();
+        self.update_camera(delta);
+
+        let speed = self.timestep.speed();
+        if self.input.pressed("game.pause") {
+            self.timestep.set_speed(if speed == GameSpeed::Paused { GameSpeed::Normal } else { GameSpeed::Paused });
+        } else if self.input.pressed("game.faster") {
+            self.timestep.set_speed(speed.faster());
+        } else if self.input.pressed("game.slower") {
+            self.timestep.set_speed(speed.slower());
+        }
+        let ticks = self.timestep.advance(delta);
+        // `advance` already counted these ticks.
+        let first_tick = self.timestep.tick() - ticks as u64;
+        for number in first_tick..self.timestep.tick() {
+            self.tick(number);
+        }
+        let alpha = self.timestep.alpha();
+        self.transform_propagation.run(&mut self.world);
+        // A click that picks an order's target doesn't also select.
+        let targeting = self.order_input.targeting().is_some();
+        if let Some(command) = self.order_input.update(&self.world, &self.input, &self.camera, self.terrain.heightmap(), screen_size) {
+            orders::issue(&mut self.world, command);
+        }
+        if !targeting {
+            self.selection.update(&mut self.world, &self.input, &self.camera, self.terrain.heightmap(), screen_size, delta);
+        }
+        // Presentation-only animation follows the game speed but not the ticks.
+        let game_delta = delta * self.timestep.speed().multiplier() as f32;
+
+        let ready = self.assets.poll(device, queue);
+        for &handle in &ready {
+            if handle == self.terrain_diffuse {
+                let texture = self.assets.texture(handle).unwrap();
+                self.terrain.set_diffuse_texture(device, texture);
+            }
+        }
+        if !ready.is_empty() {
+            self.marker.bind_materials(device, &self.mesh_renderer, &self.assets);
+        }
+
+        show_constructions(&mut self.world, MeshId(0));
+        scene::gather_instances(&self.world, alpha, &mut self.entity_instances);
+        for ((_, buffer), instances) in self.entity_meshes.iter_mut().zip(&self.entity_instances) {
+            buffer.update(device, queue, instances);
+        }
+        let mut rings = Vec::new();
+        let heightmap = self.terrain.heightmap();
+        self.world.query::<(&Selectable, &GlobalTransform, With<Selected>)>(|unit, (selectable, global, _)| {
+            let position = scene::interpolated_matrix(&self.world, unit, alpha).unwrap_or(global.0).w.truncate();
+            // Just above the ground so it doesn't flicker against the terrain.
+            let ground = cgmath::Vector3::new(position.x, heightmap.height_at(position.x, position.z) + 0.005, position.z);
+            let transform = cgmath::Matrix4::from_translation(ground) * cgmath::Matrix4::from_scale(selectable.radius * 1.3);
+            rings.push(Instance::new(transform, [0.3, 1.0, 0.3, 1.0]));
+        });
+        self.selection_ring_instances.update(device, queue, &rings);
+        let waypoints = waypoint_markers(&self.world, heightmap, alpha);
+        self.waypoint_instances.update(device, queue, &waypoints);
+
+        let skeleton = &self.walker.skeletons[0];
+        let mut joint_matrices = Vec::new();
+        let mut walker_instances = Vec::new();
+        for (i, player) in self.walker_players.iter_mut().enumerate() {
+            player.advance(game_delta);
+            let position = cgmath::Vector3::new(-0.3 + i as f32 * 0.2, 0.0, 0.3);
+            let transform = cgmath::Matrix4::from_translation(position) * cgmath::Matrix4::from_scale(0.2);
+            walker_instances.push(SkinnedInstance::new(transform, [0.2, 0.4, 0.9, 1.0], joint_matrices.len() as u32));
+            joint_matrices.extend(player.pose(skeleton, &self.walker.clips).skinning_matrices(skeleton));
+        }
+        self.skinned_renderer.update_joints(device, queue, &joint_matrices);
+        self.walker_instances.update(device, queue, &walker_instances);
+
+        self.elapsed += delta;
+        let lighting = self.time_of_day.get(alpha).lighting(self.sky.settings());
+        self.sun_direction = lighting.direction;
+        self.sun_color = lighting.color;
+        self.ambient = lighting.ambient;
+        self.sky.set_intensity_scale(queue, lighting.sky_scale);
+
+        let cascades = shadow::compute_cascades(&self.camera, self.sun_direction, self.shadows.settings());
+        self.uniforms.update_view_proj(&self.camera);
+        self.uniforms.update_sun(self.sun_direction, self.sun_color, self.ambient, &cascades);
+        let forward = self.camera.target - self.camera.eye;
+        let sky_color = |direction| {
+            let [r, g, b] = sky::sky_color(self.sky.settings(), self.sun_direction, direction);
+            let scale = self.sky.intensity_scale();
+            [r * scale, g * scale, b * scale]
+        };
+        let fog_color = match self.fog.color {
+            FogColor::Fixed(color) => color,
+            FogColor::Sky => sky_color(cgmath::Vector3::new(forward.x, 0.0, forward.z)),
+        };
+        // Stands in for the reflection when it is disabled.
+        let reflected_sky = sky_color(cgmath::Vector3::new(forward.x, forward.y.abs(), forward.z));
+        self.water.update(queue, self.elapsed, reflected_sky);
+        self.uniforms.update_fog(&self.fog, fog_color);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+        queue.write_buffer(self.water.depth_buffer(), 0, bytemuck::cast_slice(&[self.uniforms]));
+        if self.water.reflection_targets().is_some() {
+            let reflected = self.uniforms.reflected(&self.camera, self.water.sea_level());
+            queue.write_buffer(&self.reflection_uniform_buffer, 0, bytemuck::cast_slice(&[reflected]));
+        }
+        if let Some(rect) = self.selection.drag_rect() {
+            let color = [0.3, 1.0, 0.3, 0.8];
+            let (min, max) = (rect.min, rect.max);
+            self.text.queue_screen_rect(min, max, [0.3, 1.0, 0.3, 0.15]);
+            self.text.queue_screen_rect(min, [max[0], min[1] + 1.0], color);
+            self.text.queue_screen_rect([min[0], max[1] - 1.0], max, color);
+            self.text.queue_screen_rect(min, [min[0] + 1.0, max[1]], color);
+            self.text.queue_screen_rect([max[0] - 1.0, min[1]], max, color);
+        }
+        for command in self.ui.take_commands() {
+            match command {
+                DrawCommand::Rect { rect, color } => self.text.queue_screen_rect(rect.min, rect.max, color),
+                DrawCommand::Text { text, position, style } => self.text.queue_screen(&text, position, &style),
+            }
+        }
+        // There's no system pointer to show where the stick put the cursor.
+        if let Some([x, y]) = self.gamepads.virtual_cursor() {
+            self.text.queue_screen_rect([x - 3.0, y - 3.0], [x + 3.0, y + 3.0], [1.0, 1.0, 1.0, 0.9]);
+        }
+        self.text.prepare(device, queue, &self.camera, self.scene.extent);
+        #[cfg(feature = "debug_draw")]
+        self.debug_renderer.prepare(device, queue, &self.camera);
+        for (i, cascade) in cascades.iter().enumerate() {
+            let mut cascade_uniforms = self.uniforms;
+            cascade_uniforms.view_proj = cascade.view_proj.into();
+            queue.write_buffer(self.shadows.cascade_buffer(i), 0, bytemuck::cast_slice(&[cascade_uniforms]));
+        }
+    }
+
+    /// Advances the simulation by one fixed tick. Everything in here must
+    /// depend only on the previous state, `tick_delta` and the input, so
+    /// replays and networked peers stay in step.
+    fn tick(&mut self, number: u64) {
+        let tick_delta = self.timestep.tick_delta();
+        self.time_of_day.update(|time_of_day| time_of_day.advance(tick_delta));
+        self.world.insert_resource(Tick { number, delta: tick_delta });
+        self.schedule.run(&mut self.world);
+    }
+
+    /// Pans across the ground and zooms towards the target from the camera axes.
+    fn update_camera(&mut self, delta: f32) {
+        use cgmath::InnerSpace;
+        let offset = self.camera.eye - self.camera.target;
+        let distance = offset.magnitude();
+        let forward = cgmath::Vector3::new(-offset.x, 0.0, -offset.z);
+        if forward.magnitude2() > 0.0 {
+            let forward = forward.normalize();
+            let right = forward.cross(cgmath::Vector3::unit_y());
+            // Pan faster when zoomed out so the speed looks the same on screen.
+            let speed = CAMERA_PAN_SPEED * distance * delta;
+            let pan = right * self.input.axis("camera.pan_x") * speed - forward * self.input.axis("camera.pan_z") * speed;
+            self.camera.eye += pan;
+            self.camera.target += pan;
+        }
+        let zoom = self.input.axis("camera.zoom");
+        if zoom != 0.0 {
+            let distance = (distance * CAMERA_ZOOM_STEP.powf(-zoom)).clamp(CAMERA_MIN_DISTANCE, CAMERA_MAX_DISTANCE);
+            self.camera.eye = self.camera.target + offset.normalize() * distance;
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, extent: wgpu::Extent3d) {
+        self.camera.aspect = extent.width as f32 / extent.height.max(1) as f32;
+        self.post.resize(device, extent);
+        self.scene = Arc::new(SceneTargets::new(device, extent, self.scene.sample_count, self.post.hdr_view()));
+        self.water.resize(device, extent);
+        self.ui.set_screen_size([extent.width as f32, extent.height as f32]);
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.scene.sample_count
+    }
+
+    /// Switches MSAA, rebuilding the scene targets and every scene pipeline.
+    /// `sample_count` must be one of `SUPPORTED_SAMPLE_COUNTS`.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        assert!(
+            SUPPORTED_SAMPLE_COUNTS.contains(&sample_count),
+            "unsupported MSAA sample count {}",
+            sample_count
+        );
+        if sample_count == self.scene.sample_count {
+            return;
+        }
+        self.scene = Arc::new(SceneTargets::new(device, self.scene.extent, sample_count, self.post.hdr_view()));
+        self.sky.set_sample_count(device, sample_count);
+        self.triangle.set_sample_count(device, sample_count);
+        self.terrain.set_sample_count(device, sample_count);
+        self.mesh_renderer.set_sample_count(device, sample_count);
+        self.skinned_renderer.set_sample_count(device, sample_count);
+        self.water.set_sample_count(device, sample_count);
+        self.text.set_sample_count(device, sample_count);
+        #[cfg(feature = "debug_draw")]
+        self.debug_renderer.set_sample_count(device, sample_count);
+    }
+
+    pub fn post_settings(&self) -> &PostSettings {
+        self.post.settings()
+    }
+
+    pub fn set_post_settings(&mut self, queue: &wgpu::Queue, settings: PostSettings) {
+        self.post.set_settings(queue, settings);
+    }
+
+    /// Replaces the colour grading LUT, see `PostProcess::set_color_grading_lut`.
+    pub fn set_color_grading_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, image: &image::RgbaImage) -> Result<(), post::PostError> {
+        self.post.set_color_grading_lut(device, queue, image)
+    }
+
+    pub fn sky_settings(&self) -> &SkySettings {
+        self.sky.settings()
+    }
+
+    pub fn set_sky_settings(&mut self, queue: &wgpu::Queue, settings: SkySettings) {
+        self.sky.set_settings(queue, settings);
+    }
+
+    /// The clock driving the sun and moon, for pausing, scrubbing or fixing the time.
+    pub fn time_of_day(&self) -> &TimeOfDay {
+        self.time_of_day.current()
+    }
+
+    /// Jumps the clock without blending from the old time.
+    pub fn set_time_of_day(&mut self, time_of_day: TimeOfDay) {
+        *self.time_of_day.current_mut() = time_of_day;
+        self.time_of_day.snap();
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// For spawning and editing game objects between ticks.
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    /// Systems run on every simulation tick, in the order they were added.
+    pub fn schedule_mut(&mut self) -> &mut Schedule {
+        &mut self.schedule
+    }
+
+    pub fn selection(&self) -> &Selection {
+        &self.selection
+    }
+
+    pub fn selection_mut(&mut self) -> &mut Selection {
+        &mut self.selection
+    }
+
+    pub fn order_input(&self) -> &OrderInput {
+        &self.order_input
+    }
+
+    pub fn order_input_mut(&mut self) -> &mut OrderInput {
+        &mut self.order_input
+    }
+
+    /// Queues a command for the next tick, as if the player had given it.
+    pub fn issue(&mut self, command: Command) {
+        orders::issue(&mut self.world, command);
+    }
+
+    /// Registers a mesh for entities to draw through a `MeshInstance`.
+    pub fn add_mesh(&mut self, device: &wgpu::Device, mesh: Mesh) -> MeshId {
+        self.entity_meshes.push((mesh, InstanceBuffer::new(device, 16)));
+        MeshId(self.entity_meshes.len() - 1)
+    }
+
+    /// The fixed simulation clock, for the game speed and tick rate.
+    pub fn timestep(&self) -> &FixedTimestep {
+        &self.timestep
+    }
+
+    pub fn timestep_mut(&mut self) -> &mut FixedTimestep {
+        &mut self.timestep
+    }
+
+    pub fn fog_settings(&self) -> &FogSettings {
+        &self.fog
+    }
+
+    pub fn set_fog_settings(&mut self, settings: FogSettings) {
+        self.fog = settings;
+    }
+
+    pub fn shadow_settings(&self) -> &ShadowSettings {
+        self.shadows.settings()
+    }
+
+    /// Recreates the shadow maps, e.g. to change cascade count or resolution.
+    pub fn set_shadow_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        self.shadows.set_settings(device, settings, std::mem::size_of::<Uniforms>());
+        self.uniform_bind_group = create_uniform_bind_group(device, &self.uniform_bind_group_layout, &self.uniform_buffer, &self.shadows);
+        self.reflection_bind_group = create_uniform_bind_group(device, &self.uniform_bind_group_layout, &self.reflection_uniform_buffer, &self.shadows);
+    }
+
+    pub fn water_settings(&self) -> &WaterSettings {
+        self.water.settings()
+    }
+
+    pub fn set_water_settings(&mut self, device: &wgpu::Device, settings: WaterSettings) {
+        self.water.set_settings(device, settings);
+    }
+
+    pub fn text(&self) -> &TextRenderer {
+        &self.text
+    }
+
+    /// For queueing text, which is drawn by the next `update` and `draw`.
+    pub fn text_mut(&mut self) -> &mut TextRenderer {
+        &mut self.text
+    }
+
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    /// For rebinding actions.
+    pub fn input_mut(&mut self) -> &mut Input {
+        &mut self.input
+    }
+
+    pub fn gamepads(&self) -> &Gamepads {
+        &self.gamepads
+    }
+
+    /// For tuning dead zones and the virtual cursor.
+    pub fn gamepads_mut(&mut self) -> &mut Gamepads {
+        &mut self.gamepads
+    }
+
+    pub fn ui(&self) -> &Ui {
+        &self.ui
+    }
+
+    /// For feeding window events and declaring widgets, which are drawn over
+    /// the scene by the next `update` and `draw`.
+    pub fn ui_mut(&mut self) -> &mut Ui {
+        &mut self.ui
+    }
+
+    pub fn terrain(&self) -> &Terrain {
+        &self.terrain
+    }
+
+    /// Replaces the terrain, moving the water to its sea level.
+    pub fn set_heightmap(&mut self, device: &wgpu::Device, heightmap: heightmap::Heightmap) {
+        self.water.set_sea_level(heightmap.sea_level());
+        self.world.insert_resource(heightmap.clone());
+        self.world.insert_resource(NavGrid::from_heightmap(&heightmap, &NavSettings::default()));
+        // Marked changed so the next tick's `obstacle_system` blocks them again.
+        for building in self.world.matching::<With<Footprint>>() {
+            drop(self.world.get_mut::<Footprint>(building));
         }
+        // The new grid's versions start over, so nothing planned on the old
+        // one would look stale.
+        if let Some(mut fields) = self.world.resource_mut::<FlowFields>() {
+            fields.clear();
+        }
+        for unit in self.world.matching::<With<Path>>() {
+            self.world.remove::<Path>(unit);
+        }
+        self.terrain.set_heightmap(device, heightmap);
+    }
+
+    async fn draw_shadows(&self, device: &wgpu::Device, mesh_batches: &[MeshBatch<'_>], skinned_batches: &[(&SkinnedMesh, &SkinnedInstanceBuffer)]) -> CommandBuffer {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("shadows") });
+        for cascade in 0..self.shadows.cascade_count() {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("shadow cascade"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: self.shadows.layer_view(cascade),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_bind_group(0, self.shadows.cascade_bind_group(cascade), &[]);
+            self.terrain.record_shadow(&mut pass);
+            self.mesh_renderer.record_shadow(&mut pass, mesh_batches);
+            self.skinned_renderer.record_shadow(&mut pass, skinned_batches);
+        }
+        encoder.finish()
+    }
+
+    /// Renders the opaque casters into the single-sampled depth target the
+    /// water shader reads.
+    async fn draw_water_depth(&self, device: &wgpu::Device, mesh_batches: &[MeshBatch<'_>], skinned_batches: &[(&SkinnedMesh, &SkinnedInstanceBuffer)]) -> CommandBuffer {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("water depth") });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("water depth"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: self.water.depth_view(),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_bind_group(0, self.water.depth_bind_group(), &[]);
+            self.terrain.record_shadow(&mut pass);
+            self.mesh_renderer.record_shadow(&mut pass, mesh_batches);
+            self.skinned_renderer.record_shadow(&mut pass, skinned_batches);
+        }
+        encoder.finish()
+    }
+
+    /// The opaque scene seen from below the water surface.
+    async fn draw_reflection(&self, device: &wgpu::Device, targets: Arc<SceneTargets>, mesh_batches: &[MeshBatch<'_>], skinned_batches: &[(&SkinnedMesh, &SkinnedInstanceBuffer)]) -> Vec<CommandBuffer> {
+        let uniforms_bg = &self.reflection_bind_group;
+        let (b0, b1, b2, b3) = futures::join!(
+            self.sky.draw(device, targets.clone(), uniforms_bg),
+            self.terrain.draw(device, targets.clone(), uniforms_bg),
+            self.mesh_renderer.draw(device, targets.clone(), uniforms_bg, mesh_batches),
+            self.skinned_renderer.draw(device, targets.clone(), uniforms_bg, skinned_batches),
+        );
+        vec![b0, b1, b2, b3]
+    }
+
+    pub fn loading_progress(&self) -> LoadProgress {
+        self.assets.progress()
     }
 
     pub async fn draw(&self, device: &wgpu::Device, targets: Arc<ScreenTargets>) -> Vec<CommandBuffer> {
+        let plain = self.mesh_renderer.default_material();
+        let mut mesh_batches = vec![(&self.cube, plain, &self.cube_instances)];
+        mesh_batches.extend(
+            self.marker
+                .meshes
+                .iter()
+                .zip(&self.marker_instances)
+                .enumerate()
+                .map(|(i, (mesh, instances))| (mesh, self.marker.material(i, &self.mesh_renderer), instances)),
+        );
+        mesh_batches.extend(self.entity_meshes.iter().map(|(mesh, instances)| (mesh, plain, instances)));
+        let skinned_batches = [(&self.walker_mesh, &self.walker_instances)];
+
+        let scene = self.scene.clone();
+
         // TODO: should use spawn
-        let f1 = clear_screen(device, targets.clone(), wgpu::Color{ r: 0.2, g: 0.2, b: 0.2, a: 1.0 });
-        let f2 = self.triangle.draw(device, targets.clone(), &self.uniform_bind_group);
-        let f3 = self.terrain.draw(device, targets.clone(), &self.uniform_bind_group);
-        let (b1, b2, b3) = futures::join!(f1,f2, f3);
-        vec![b1, b2, b3]
+        let f0 = self.draw_shadows(device, &mesh_batches, &skinned_batches);
+        let f1 = self.sky.draw(device, scene.clone(), &self.uniform_bind_group);
+        let f2 = self.triangle.draw(device, scene.clone(), &self.uniform_bind_group);
+        let f3 = self.terrain.draw(device, scene.clone(), &self.uniform_bind_group);
+        // Selection circles are markings, so they stay out of shadows and reflections.
+        let mut lit_batches = mesh_batches.clone();
+        lit_batches.push((&self.selection_ring, plain, &self.selection_ring_instances));
+        lit_batches.push((&self.selection_ring, plain, &self.waypoint_instances));
+        let f4 = self.mesh_renderer.draw(device, scene.clone(), &self.uniform_bind_group, &lit_batches);
+        let f5 = self.skinned_renderer.draw(device, scene.clone(), &self.uniform_bind_group, &skinned_batches);
+        let f6 = self.water.draw(device, scene.clone(), &self.uniform_bind_group);
+        let f7 = self.post.draw(device, &targets.color.output.view);
+        let f8 = self.draw_water_depth(device, &mesh_batches, &skinned_batches);
+        let f9 = async {
+            match self.water.reflection_targets() {
+                Some(reflection) => self.draw_reflection(device, reflection, &mesh_batches, &skinned_batches).await,
+                None => Vec::new(),
+            }
+        };
+        let (b0, b1, b2, b3, b4, b5, b6, b7, b8, b9) = futures::join!(f0, f1, f2, f3, f4, f5, f6, f7, f8, f9);
+        let mut buffers = vec![b0];
+        buffers.extend(b9);
+        buffers.extend(vec![b8, b1, b2, b3, b4, b5, b6]);
+        buffers.push(self.text.draw_world(device, scene.clone(), &self.uniform_bind_group).await);
+        #[cfg(feature = "debug_draw")]
+        buffers.push(self.debug_renderer.draw(device, scene.clone(), &self.uniform_bind_group).await);
+        buffers.push(b7);
+        buffers.push(self.text.draw_screen(device, &targets.color.output.view, &self.uniform_bind_group).await);
+        buffers
+    }
+}
+
+/// Placeholder units until the game spawns its own: tanks with a turret
+/// attached, scouts that gather and build, a depot, resource nodes and a few
+/// enemy tanks holding their ground.
+fn spawn_placeholder_units(world: &mut World, cube: MeshId, heightmap: &heightmap::Heightmap) {
+    use self::orders::{Builder, Dropoff, Gatherer, Health, Mover, ResourceNode, Team, Weapon};
+    let ground = |x: f32, z: f32, size: f32| cgmath::Vector3::new(x, heightmap.height_at(x, z) + size / 2.0, z);
+    let spawn_tank = |world: &mut World, x: f32, z: f32, team: u8, color: [f32; 4]| {
+        let tank = world.spawn_with((
+            Transform::from_translation(ground(x, z, 0.08)).with_scale(0.08),
+            MeshInstance { mesh: cube, color },
+            Selectable { radius: 0.08, kind: 0 },
+            Team(team),
+            Health::new(100.0),
+            Mover { speed: 0.4 },
+            Weapon::new(0.5, 10.0, 1.0),
+            Orders::default(),
+        ));
+        let turret = world.spawn_with((
+            Transform::from_translation(cgmath::Vector3::new(0.0, 0.7, 0.0)).with_scale(0.5),
+            MeshInstance { mesh: cube, color: [color[0] * 0.7, color[1] * 0.7, color[2] * 0.7, 1.0] },
+        ));
+        scene::set_parent(world, turret, tank);
+        tank
+    };
+    for i in 0..8 {
+        let x = -0.7 + i as f32 * 0.2;
+        if i % 2 == 0 {
+            spawn_tank(world, x, -1.2, 0, [0.7, 0.6, 0.3, 1.0]);
+        } else {
+            let scout = world.spawn_with((
+                Transform::from_translation(ground(x, -1.2, 0.05)).with_scale(0.05),
+                MeshInstance { mesh: cube, color: [0.4, 0.6, 0.8, 1.0] },
+                Selectable { radius: 0.05, kind: 1 },
+                Team(0),
+                Health::new(40.0),
+                Mover { speed: 0.6 },
+                Gatherer { capacity: 5.0, rate: 2.5, carried: 0.0 },
+                Builder { rate: 0.1 },
+            ));
+            world.insert(scout, Orders::default());
+        }
+    }
+    world.spawn_with((
+        Transform::from_translation(ground(-1.4, -1.8, 0.2)).with_scale(0.2),
+        MeshInstance { mesh: cube, color: [0.5, 0.5, 0.55, 1.0] },
+        Selectable { radius: 0.2, kind: 2 },
+        Team(0),
+        Health::new(500.0),
+        Dropoff,
+        Footprint { half_size: 0.1 },
+    ));
+    for i in 0..3 {
+        let (x, z) = (1.4 + i as f32 * 0.15, -1.6 - i as f32 * 0.1);
+        world.spawn_with((
+            Transform::from_translation(ground(x, z, 0.06)).with_scale(0.06),
+            MeshInstance { mesh: cube, color: [0.9, 0.75, 0.2, 1.0] },
+            Selectable { radius: 0.06, kind: 3 },
+            ResourceNode { remaining: 50.0 },
+        ));
+    }
+    for i in 0..3 {
+        let tank = spawn_tank(world, -0.2 + i as f32 * 0.2, 1.4, 1, [0.7, 0.25, 0.2, 1.0]);
+        world.get_mut::<Orders>(tank).unwrap().give(Order::HoldPosition, false, cgmath::Point3::new(0.0, 0.0, 0.0));
+    }
+}
+
+/// Gives construction sites a placeholder look that grows with progress.
+fn show_constructions(world: &mut World, cube: MeshId) {
+    for site in world.matching::<(With<Construction>, ecs::Without<MeshInstance>)>() {
+        world.insert(site, MeshInstance { mesh: cube, color: [0.6, 0.55, 0.5, 1.0] });
     }
+    world.query::<(&Construction, &mut Transform)>(|_, (construction, transform)| {
+        let scale = 0.15 * (0.2 + 0.8 * construction.progress);
+        transform.scale = cgmath::Vector3::new(scale, scale, scale);
+    });
+}
+
+/// Rings along the paths of the selected units' queued orders.
+fn waypoint_markers(world: &World, heightmap: &heightmap::Heightmap, alpha: f32) -> Vec<Instance> {
+    use cgmath::{EuclideanSpace, InnerSpace};
+    const DOT_SPACING: f32 = 0.06;
+    let on_ground = |point: cgmath::Point3<f32>, scale: f32, color| {
+        let ground = cgmath::Vector3::new(point.x, heightmap.height_at(point.x, point.z) + 0.005, point.z);
+        Instance::new(cgmath::Matrix4::from_translation(ground) * cgmath::Matrix4::from_scale(scale), color)
+    };
+    let mut markers = Vec::new();
+    world.query::<(&Orders, &Transform, With<Selected>)>(|unit, (orders, transform, _)| {
+        let drawn = scene::interpolated_matrix(world, unit, alpha).map_or(transform.translation, |matrix| matrix.w.truncate());
+        let mut from = cgmath::Point3::from_vec(drawn);
+        for order in orders.queue() {
+            let to = match order.position(world) {
+                Some(to) => to,
+                None => continue,
+            };
+            let color = match order {
+                Order::Attack(_) | Order::AttackMove(_) => [1.0, 0.3, 0.2, 1.0],
+                Order::Patrol(_) => [1.0, 0.9, 0.3, 1.0],
+                Order::Gather(_) | Order::Build { .. } => [0.3, 0.7, 1.0, 1.0],
+                _ => [0.3, 1.0, 0.3, 1.0],
+            };
+            let offset = to - from;
+            let dots = (offset.magnitude() / DOT_SPACING) as u32;
+            for i in 1..dots {
+                markers.push(on_ground(from + offset * (i as f32 / dots as f32), 0.008, color));
+            }
+            markers.push(on_ground(to, 0.035, color));
+            from = to;
+        }
+    });
+    markers
 }
@@ -0,0 +1,516 @@
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use wgpu::util::DeviceExt;
+use crate::helpers::{self, ColorSpace};
+use crate::SceneTargets;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MeshVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coords: [f32; 2],
+}
+
+impl MeshVertex {
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<MeshVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+            ]
+        }
+    }
+}
+
+/// Per-instance data, read by the vertex shader at locations 5..=9.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Instance {
+    pub model: [[f32; 4]; 4],
+    pub color: [f32; 4],
+}
+
+impl Instance {
+    pub fn new(transform: cgmath::Matrix4<f32>, color: [f32; 4]) -> Self {
+        Self { model: transform.into(), color }
+    }
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const VEC4: wgpu::BufferAddress = std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress;
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Instance>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: VEC4,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: VEC4 * 2,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: VEC4 * 3,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: VEC4 * 4,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ]
+        }
+    }
+}
+
+pub struct Mesh {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+}
+
+impl Mesh {
+    pub fn new(device: &wgpu::Device, label: &str, vertices: &[MeshVertex], indices: &[u32]) -> Self {
+        let vertex_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(vertices),
+                usage: wgpu::BufferUsage::VERTEX,
+            }
+        );
+        let index_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(indices),
+                usage: wgpu::BufferUsage::INDEX,
+            }
+        );
+        Self { vertex_buffer, index_buffer, num_indices: indices.len() as u32 }
+    }
+
+    /// Unit cube centered on the origin.
+    pub fn cube(device: &wgpu::Device) -> Self {
+        let (vertices, indices) = cube_geometry();
+        Self::new(device, "cube", &vertices, &indices)
+    }
+
+    /// Flat ring facing up, of outer radius 1 and the given inner radius.
+    pub fn ring(device: &wgpu::Device, inner_radius: f32) -> Self {
+        let (vertices, indices) = ring_geometry(inner_radius, 32);
+        Self::new(device, "ring", &vertices, &indices)
+    }
+}
+
+pub fn cube_geometry() -> (Vec<MeshVertex>, Vec<u32>) {
+    const FACES: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        // normal, u axis, v axis
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (n, u, v) in FACES.iter() {
+        let base = vertices.len() as u32;
+        for &(su, sv) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let position = [
+                0.5 * (n[0] + su * u[0] + sv * v[0]),
+                0.5 * (n[1] + su * u[1] + sv * v[1]),
+                0.5 * (n[2] + su * u[2] + sv * v[2]),
+            ];
+            vertices.push(MeshVertex {
+                position,
+                normal: *n,
+                tex_coords: [(su + 1.0) * 0.5, (1.0 - sv) * 0.5],
+            });
+        }
+        indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+    (vertices, indices)
+}
+
+pub fn ring_geometry(inner_radius: f32, segments: u32) -> (Vec<MeshVertex>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity(2 * segments as usize + 2);
+    let mut indices = Vec::with_capacity(6 * segments as usize);
+    for i in 0..=segments {
+        let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+        let (sin, cos) = angle.sin_cos();
+        for &radius in &[inner_radius, 1.0] {
+            vertices.push(MeshVertex {
+                position: [radius * cos, 0.0, -radius * sin],
+                normal: [0.0, 1.0, 0.0],
+                tex_coords: [i as f32 / segments as f32, radius],
+            });
+        }
+    }
+    for i in 0..segments {
+        let (inner, outer) = (2 * i, 2 * i + 1);
+        indices.extend_from_slice(&[inner, outer, outer + 2, inner, outer + 2, inner + 2]);
+    }
+    (vertices, indices)
+}
+
+/// GPU buffer of per-instance vertex data, `Instance`s unless stated
+/// otherwise, rewritten from the CPU whenever `update` is called.
+pub struct InstanceBuffer<T = Instance> {
+    pub(crate) buffer: wgpu::Buffer,
+    capacity: usize,
+    len: u32,
+    instance: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> InstanceBuffer<T> {
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            buffer: Self::create_buffer(device, capacity),
+            capacity,
+            len: 0,
+            instance: PhantomData,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Replaces the contents with `instances`, growing the buffer if needed.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, instances: &[T]) {
+        if instances.len() > self.capacity {
+            self.capacity = instances.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(instances));
+        self.len = instances.len() as u32;
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Metallic and roughness factors of a material, read by the fragment shader.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniforms {
+    // x: metallic, y: roughness.
+    factors: [f32; 4],
+}
+
+/// What a `Material` samples. Missing textures read as white (or a flat normal),
+/// so only the factors and the instance colour apply.
+#[derive(Copy, Clone)]
+pub struct MaterialDesc<'a> {
+    pub base_color: Option<&'a wgpu::Texture>,
+    pub normal: Option<&'a wgpu::Texture>,
+    /// Roughness in green and metalness in blue, as glTF packs them.
+    pub metallic_roughness: Option<&'a wgpu::Texture>,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+impl Default for MaterialDesc<'_> {
+    fn default() -> Self {
+        Self { base_color: None, normal: None, metallic_roughness: None, metallic: 0.0, roughness: 1.0 }
+    }
+}
+
+/// Bind group of a mesh material, created by `MeshRenderer::create_material`.
+pub struct Material {
+    bind_group: wgpu::BindGroup,
+}
+
+/// A mesh drawn with `material` at every instance in the buffer.
+pub type MeshBatch<'a> = (&'a Mesh, &'a Material, &'a InstanceBuffer);
+
+pub struct MeshRenderer {
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    render_pipeline: wgpu::RenderPipeline,
+    shadow_pipeline: wgpu::RenderPipeline,
+    material_layout: wgpu::BindGroupLayout,
+    material_sampler: wgpu::Sampler,
+    white: wgpu::Texture,
+    flat_normal: wgpu::Texture,
+    default_material: Material,
+}
+
+impl MeshRenderer {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, color_format: wgpu::TextureFormat, sample_count: u32, uniforms_bgl: &wgpu::BindGroupLayout, shadow_bgl: &wgpu::BindGroupLayout) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("mesh shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!("../res/shader/globals.wgsl"), include_str!("../res/shader/mesh.wgsl")))),
+            flags: wgpu::ShaderFlags::all(),
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let material_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                texture_entry(2),
+                texture_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("material_bind_group_layout"),
+        });
+        let material_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        // Stand in for the textures a material doesn't have.
+        let white = image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255]));
+        let white = helpers::upload_texture("white", &white, ColorSpace::Srgb, device, queue);
+        let flat_normal = image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255]));
+        let flat_normal = helpers::upload_texture("flat normal", &flat_normal, ColorSpace::Linear, device, queue);
+        let default_material = Self::material(device, &material_layout, &material_sampler, &white, &flat_normal, MaterialDesc::default());
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[uniforms_bgl, &material_layout],
+            push_constant_ranges: &[],
+        });
+
+        let render_pipeline = Self::create_render_pipeline(device, &pipeline_layout, &shader, color_format, sample_count);
+
+        let shadow_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[shadow_bgl],
+            push_constant_ranges: &[],
+        });
+        let shadow_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("mesh shadow pipeline"),
+            layout: Some(&shadow_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_shadow",
+                buffers: &[MeshVertex::desc(), Instance::desc()],
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(crate::shadow::shadow_depth_stencil_state()),
+            multisample: wgpu::MultisampleState::default(),
+        });
+
+        Self {
+            shader,
+            pipeline_layout,
+            color_format,
+            render_pipeline,
+            shadow_pipeline,
+            material_layout,
+            material_sampler,
+            white,
+            flat_normal,
+            default_material,
+        }
+    }
+
+    fn material(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, sampler: &wgpu::Sampler, white: &wgpu::Texture, flat_normal: &wgpu::Texture, desc: MaterialDesc) -> Material {
+        let uniforms = MaterialUniforms { factors: [desc.metallic, desc.roughness, 0.0, 0.0] };
+        let uniform_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Material Uniform Buffer"),
+                contents: bytemuck::bytes_of(&uniforms),
+                usage: wgpu::BufferUsage::UNIFORM,
+            }
+        );
+        let view = |texture: Option<&wgpu::Texture>, fallback| texture.unwrap_or(fallback).create_view(&wgpu::TextureViewDescriptor::default());
+        let base_color = view(desc.base_color, white);
+        let normal = view(desc.normal, flat_normal);
+        // The white fallback is sRGB, but 1.0 decodes to 1.0 either way.
+        let metallic_roughness = view(desc.metallic_roughness, white);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&base_color),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&metallic_roughness),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("material_bind_group"),
+        });
+        Material { bind_group }
+    }
+
+    pub fn create_material(&self, device: &wgpu::Device, desc: MaterialDesc) -> Material {
+        Self::material(device, &self.material_layout, &self.material_sampler, &self.white, &self.flat_normal, desc)
+    }
+
+    /// Untextured, fully rough and not metallic, for meshes coloured only by
+    /// their instances.
+    pub fn default_material(&self) -> &Material {
+        &self.default_material
+    }
+
+    fn create_render_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, color_format: wgpu::TextureFormat, sample_count: u32) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("mesh pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[MeshVertex::desc(), Instance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[color_format.into()],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(crate::depth_stencil_state()),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Rebuilds the colour pipeline for targets with `sample_count` samples.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.render_pipeline = Self::create_render_pipeline(device, &self.pipeline_layout, &self.shader, self.color_format, sample_count);
+    }
+
+    /// Records `batches` into a shadow pass whose cascade bind group is already set.
+    pub fn record_shadow<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, batches: &[MeshBatch<'a>]) {
+        pass.set_pipeline(&self.shadow_pipeline);
+        for (mesh, _, instances) in batches {
+            if instances.is_empty() {
+                continue;
+            }
+            pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            pass.set_vertex_buffer(1, instances.buffer.slice(..));
+            pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..mesh.num_indices, 0, 0..instances.len());
+        }
+    }
+
+    /// Draws every instance of each batch with its material, one draw call per batch.
+    pub async fn draw(&self, device: &wgpu::Device, targets: Arc<SceneTargets>, uniforms_bg: &wgpu::BindGroup, batches: &[MeshBatch<'_>]) -> wgpu::CommandBuffer {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &targets.color,
+                    resolve_target: targets.resolve.as_deref(),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &targets.depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_pipeline(&self.render_pipeline);
+            pass.set_bind_group(0, uniforms_bg, &[]);
+            for (mesh, material, instances) in batches {
+                if instances.is_empty() {
+                    continue;
+                }
+                pass.set_bind_group(1, &material.bind_group, &[]);
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_vertex_buffer(1, instances.buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_indices, 0, 0..instances.len());
+            }
+        }
+        encoder.finish()
+    }
+}
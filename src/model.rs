@@ -0,0 +1,533 @@
+use std::fmt;
+use std::path::Path;
+
+use cgmath::SquareMatrix;
+
+use crate::animation::{Channel, Clip, Interpolation, Joint, JointTransform, Keyframes, Skeleton};
+use crate::assets::{AssetLoader, TextureHandle};
+use crate::helpers::ColorSpace;
+use crate::mesh::{Instance, Material, MaterialDesc, Mesh, MeshRenderer, MeshVertex};
+
+#[derive(Debug)]
+pub enum ModelError {
+    Gltf(gltf::Error),
+    MissingPositions { mesh: String },
+    UnsupportedPrimitive { mesh: String, mode: gltf::mesh::Mode },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::Gltf(e) => write!(f, "{}", e),
+            ModelError::MissingPositions { mesh } => write!(f, "mesh {} has a primitive without positions", mesh),
+            ModelError::UnsupportedPrimitive { mesh, mode } => write!(f, "mesh {} uses unsupported primitive mode {:?}", mesh, mode),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+impl From<gltf::Error> for ModelError {
+    fn from(e: gltf::Error) -> Self {
+        ModelError::Gltf(e)
+    }
+}
+
+/// One glTF primitive, with the attributes the mesh renderer understands.
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+    pub material: Option<usize>,
+    pub skin: Option<SkinWeights>,
+}
+
+/// Per-vertex joint indices and weights of a skinned primitive.
+pub struct SkinWeights {
+    pub joints: Vec<[u32; 4]>,
+    pub weights: Vec<[f32; 4]>,
+}
+
+/// A glTF image decoded to RGBA8, tagged with the colour space implied by the
+/// material slot that references it.
+pub struct ImageData {
+    pub image: image::RgbaImage,
+    pub color_space: ColorSpace,
+}
+
+/// Metallic-roughness material. Texture fields index into `ModelData::images`.
+#[derive(Clone)]
+pub struct MaterialData {
+    pub name: String,
+    pub base_color_factor: [f32; 4],
+    pub base_color_texture: Option<usize>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub metallic_roughness_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+}
+
+impl Default for MaterialData {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            base_color_factor: [1.0; 4],
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+        }
+    }
+}
+
+pub struct NodeData {
+    pub name: String,
+    /// Transform relative to the parent node.
+    pub transform: cgmath::Matrix4<f32>,
+    /// Indices into `ModelData::meshes`, one per primitive of the glTF mesh.
+    pub meshes: Vec<usize>,
+    pub children: Vec<usize>,
+    /// Index into `ModelData::skeletons` for skinned meshes.
+    pub skin: Option<usize>,
+}
+
+/// Everything read from a glTF/GLB file, before anything touches the GPU.
+pub struct ModelData {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+    pub images: Vec<ImageData>,
+    pub nodes: Vec<NodeData>,
+    /// Root nodes of the default scene.
+    pub roots: Vec<usize>,
+    pub skeletons: Vec<Skeleton>,
+    pub clips: Vec<Clip>,
+}
+
+impl ModelData {
+    /// Loads a `.gltf` or `.glb` file from `res/models`.
+    pub fn load(path: &str) -> Result<Self, ModelError> {
+        let (document, buffers, images) = gltf::import(Path::new("./res/models").join(path))?;
+        Self::from_gltf(&document, &buffers, &images)
+    }
+
+    /// Parses an in-memory `.gltf` (with embedded buffers) or `.glb`. Images
+    /// must be stored in buffer views; data URIs are only read by `load`.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, ModelError> {
+        let (document, buffers, images) = gltf::import_slice(bytes)?;
+        Self::from_gltf(&document, &buffers, &images)
+    }
+
+    fn from_gltf(document: &gltf::Document, buffers: &[gltf::buffer::Data], images: &[gltf::image::Data]) -> Result<Self, ModelError> {
+        // glTF does not tag images with a colour space, the material slot decides it.
+        let mut color_spaces = vec![ColorSpace::Linear; images.len()];
+        let materials = document
+            .materials()
+            .map(|material| {
+                let pbr = material.pbr_metallic_roughness();
+                let base_color_texture = pbr.base_color_texture().map(|info| info.texture().source().index());
+                if let Some(image) = base_color_texture {
+                    color_spaces[image] = ColorSpace::Srgb;
+                }
+                MaterialData {
+                    name: material.name().unwrap_or_default().to_string(),
+                    base_color_factor: pbr.base_color_factor(),
+                    base_color_texture,
+                    metallic_factor: pbr.metallic_factor(),
+                    roughness_factor: pbr.roughness_factor(),
+                    metallic_roughness_texture: pbr.metallic_roughness_texture().map(|info| info.texture().source().index()),
+                    normal_texture: material.normal_texture().map(|info| info.texture().source().index()),
+                }
+            })
+            .collect();
+
+        let images = images
+            .iter()
+            .zip(color_spaces)
+            .map(|(data, color_space)| ImageData { image: to_rgba8(data), color_space })
+            .collect();
+
+        let mut meshes = Vec::new();
+        let mut mesh_primitives = Vec::new();
+        for mesh in document.meshes() {
+            let name = mesh.name().unwrap_or_default().to_string();
+            let mut primitives = Vec::new();
+            for primitive in mesh.primitives() {
+                primitives.push(meshes.len());
+                meshes.push(read_primitive(&name, &primitive, buffers)?);
+            }
+            mesh_primitives.push(primitives);
+        }
+
+        let nodes = document
+            .nodes()
+            .map(|node| NodeData {
+                name: node.name().unwrap_or_default().to_string(),
+                transform: node.transform().matrix().into(),
+                meshes: node.mesh().map(|mesh| mesh_primitives[mesh.index()].clone()).unwrap_or_default(),
+                children: node.children().map(|child| child.index()).collect(),
+                skin: node.skin().map(|skin| skin.index()),
+            })
+            .collect::<Vec<_>>();
+
+        let roots = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .map(|scene| scene.nodes().map(|node| node.index()).collect())
+            .unwrap_or_default();
+
+        let mut parents = vec![None; nodes.len()];
+        for (index, node) in nodes.iter().enumerate() {
+            for &child in &node.children {
+                parents[child] = Some(index);
+            }
+        }
+        let global_transform = |mut node: usize| {
+            let mut transform = nodes[node].transform;
+            while let Some(parent) = parents[node] {
+                transform = nodes[parent].transform * transform;
+                node = parent;
+            }
+            transform
+        };
+
+        let skeletons = document
+            .skins()
+            .map(|skin| {
+                let joint_nodes: Vec<usize> = skin.joints().map(|node| node.index()).collect();
+                let inverse_binds: Vec<cgmath::Matrix4<f32>> = skin
+                    .reader(|buffer| Some(&buffers[buffer.index()]))
+                    .read_inverse_bind_matrices()
+                    .map(|matrices| matrices.map(cgmath::Matrix4::from).collect())
+                    .unwrap_or_else(|| vec![cgmath::Matrix4::identity(); joint_nodes.len()]);
+                let joints = skin
+                    .joints()
+                    .zip(inverse_binds)
+                    .map(|(node, inverse_bind)| {
+                        let (translation, rotation, scale) = node.transform().decomposed();
+                        Joint {
+                            name: node.name().unwrap_or_default().to_string(),
+                            parent: parents[node.index()].and_then(|parent| joint_nodes.iter().position(|&n| n == parent)),
+                            rest: JointTransform {
+                                translation: translation.into(),
+                                rotation: cgmath::Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2]),
+                                scale: scale.into(),
+                            },
+                            inverse_bind,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                // Everything above the first root joint is baked into the skeleton.
+                let root_transform = joints
+                    .iter()
+                    .position(|joint| joint.parent.is_none())
+                    .and_then(|root| parents[joint_nodes[root]])
+                    .map_or(cgmath::Matrix4::identity(), global_transform);
+                Skeleton::new(joints, &joint_nodes, root_transform)
+            })
+            .collect();
+
+        let clips = document
+            .animations()
+            .map(|animation| {
+                let channels = animation
+                    .channels()
+                    .filter_map(|channel| read_channel(&channel, buffers))
+                    .collect();
+                Clip::new(animation.name().unwrap_or_default(), channels)
+            })
+            .collect();
+
+        Ok(Self { meshes, materials, images, nodes, roots, skeletons, clips })
+    }
+
+    pub fn clip(&self, name: &str) -> Option<usize> {
+        self.clips.iter().position(|clip| clip.name == name)
+    }
+
+    /// Walks the node hierarchy from the scene roots and returns every mesh
+    /// together with its model-space transform.
+    pub fn mesh_transforms(&self) -> Vec<(usize, cgmath::Matrix4<f32>)> {
+        let mut out = Vec::new();
+        let mut stack: Vec<(usize, cgmath::Matrix4<f32>)> = self.roots.iter().map(|&root| (root, cgmath::Matrix4::identity())).collect();
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            let transform = parent * node.transform;
+            out.extend(node.meshes.iter().map(|&mesh| (mesh, transform)));
+            stack.extend(node.children.iter().map(|&child| (child, transform)));
+        }
+        out
+    }
+
+    /// Uploads the meshes and queues the images on `assets`. Materials are
+    /// bound as soon as their textures are ready, see `Model::bind_materials`.
+    pub fn upload(&self, device: &wgpu::Device, renderer: &MeshRenderer, assets: &mut AssetLoader) -> Model {
+        let meshes = self
+            .meshes
+            .iter()
+            .map(|mesh| Mesh::new(device, &mesh.name, &mesh.vertices, &mesh.indices))
+            .collect();
+        let textures = self
+            .images
+            .iter()
+            .enumerate()
+            .map(|(i, image)| assets.add_image(&format!("model image {}", i), image.image.clone(), image.color_space))
+            .collect();
+        let parts = self
+            .mesh_transforms()
+            .into_iter()
+            .map(|(mesh, transform)| {
+                let color = self.meshes[mesh]
+                    .material
+                    .map_or([1.0; 4], |material| self.materials[material].base_color_factor);
+                ModelPart { mesh, transform, color }
+            })
+            .collect();
+        let mut model = Model {
+            meshes,
+            mesh_materials: self.meshes.iter().map(|mesh| mesh.material).collect(),
+            materials: self.materials.clone(),
+            textures,
+            bound: self.materials.iter().map(|_| None).collect(),
+            parts,
+        };
+        // Untextured materials don't have to wait for anything.
+        model.bind_materials(device, renderer, assets);
+        model
+    }
+}
+
+fn read_primitive(mesh: &str, primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Result<MeshData, ModelError> {
+    if primitive.mode() != gltf::mesh::Mode::Triangles {
+        return Err(ModelError::UnsupportedPrimitive { mesh: mesh.to_string(), mode: primitive.mode() });
+    }
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let positions: Vec<[f32; 3]> = reader
+        .read_positions()
+        .ok_or_else(|| ModelError::MissingPositions { mesh: mesh.to_string() })?
+        .collect();
+    let normals: Vec<[f32; 3]> = reader
+        .read_normals()
+        .map(|normals| normals.collect())
+        .unwrap_or_else(|| vec![[0.0, 1.0, 0.0]; positions.len()]);
+    let tex_coords: Vec<[f32; 2]> = reader
+        .read_tex_coords(0)
+        .map(|coords| coords.into_f32().collect())
+        .unwrap_or_else(|| vec![[0.0; 2]; positions.len()]);
+    let indices = reader
+        .read_indices()
+        .map(|indices| indices.into_u32().collect())
+        .unwrap_or_else(|| (0..positions.len() as u32).collect());
+
+    let vertices = positions
+        .iter()
+        .zip(normals)
+        .zip(tex_coords)
+        .map(|((&position, normal), tex_coords)| MeshVertex { position, normal, tex_coords })
+        .collect();
+
+    let skin = match (reader.read_joints(0), reader.read_weights(0)) {
+        (Some(joints), Some(weights)) => Some(SkinWeights {
+            joints: joints
+                .into_u16()
+                .map(|j| [j[0] as u32, j[1] as u32, j[2] as u32, j[3] as u32])
+                .collect(),
+            weights: weights.into_f32().collect(),
+        }),
+        _ => None,
+    };
+
+    Ok(MeshData {
+        name: mesh.to_string(),
+        vertices,
+        indices,
+        material: primitive.material().index(),
+        skin,
+    })
+}
+
+fn read_channel(channel: &gltf::animation::Channel, buffers: &[gltf::buffer::Data]) -> Option<Channel> {
+    use gltf::animation::util::ReadOutputs;
+
+    let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+    let times: Vec<f32> = reader.read_inputs()?.collect();
+    let interpolation = match channel.sampler().interpolation() {
+        gltf::animation::Interpolation::Step => Interpolation::Step,
+        gltf::animation::Interpolation::Linear | gltf::animation::Interpolation::CubicSpline => Interpolation::Linear,
+    };
+    // Cubic spline outputs are (in tangent, value, out tangent) triples, keep the values.
+    let cubic = channel.sampler().interpolation() == gltf::animation::Interpolation::CubicSpline;
+    fn values<T>(items: impl Iterator<Item = T>, cubic: bool) -> Vec<T> {
+        if cubic {
+            items.skip(1).step_by(3).collect()
+        } else {
+            items.collect()
+        }
+    }
+    let keyframes = match reader.read_outputs()? {
+        ReadOutputs::Translations(items) => Keyframes::Translation(values(items.map(Into::into), cubic)),
+        ReadOutputs::Rotations(items) => Keyframes::Rotation(values(
+            items.into_f32().map(|r| cgmath::Quaternion::new(r[3], r[0], r[1], r[2])),
+            cubic,
+        )),
+        ReadOutputs::Scales(items) => Keyframes::Scale(values(items.map(Into::into), cubic)),
+        ReadOutputs::MorphTargetWeights(_) => return None,
+    };
+    Some(Channel {
+        node: channel.target().node().index(),
+        times,
+        keyframes,
+        interpolation,
+    })
+}
+
+fn to_rgba8(data: &gltf::image::Data) -> image::RgbaImage {
+    use gltf::image::Format;
+    // 16 bit formats are stored little endian, keep the high byte.
+    let (channels, stride, offset) = match data.format {
+        Format::R8 => (1, 1, 0),
+        Format::R8G8 => (2, 1, 0),
+        Format::R8G8B8 | Format::B8G8R8 => (3, 1, 0),
+        Format::R8G8B8A8 | Format::B8G8R8A8 => (4, 1, 0),
+        Format::R16 => (1, 2, 1),
+        Format::R16G16 => (2, 2, 1),
+        Format::R16G16B16 => (3, 2, 1),
+        Format::R16G16B16A16 => (4, 2, 1),
+    };
+    let bgr = matches!(data.format, Format::B8G8R8 | Format::B8G8R8A8);
+    let texel = channels * stride;
+    let rgba = data
+        .pixels
+        .chunks_exact(texel)
+        .flat_map(|px| {
+            let c = |i: usize| px[i * stride + offset];
+            let mut out = match channels {
+                1 => [c(0), c(0), c(0), 255],
+                2 => [c(0), c(1), 0, 255],
+                3 => [c(0), c(1), c(2), 255],
+                _ => [c(0), c(1), c(2), c(3)],
+            };
+            if bgr {
+                out.swap(0, 2);
+            }
+            out
+        })
+        .collect();
+    image::RgbaImage::from_raw(data.width, data.height, rgba).expect("glTF image size does not match its pixel data")
+}
+
+/// A mesh placed by the node hierarchy.
+pub struct ModelPart {
+    pub mesh: usize,
+    pub transform: cgmath::Matrix4<f32>,
+    pub color: [f32; 4],
+}
+
+/// GPU resources of an imported model, ready to be fed to the `MeshRenderer`.
+pub struct Model {
+    pub meshes: Vec<Mesh>,
+    /// Index into `materials` of each mesh.
+    pub mesh_materials: Vec<Option<usize>>,
+    pub materials: Vec<MaterialData>,
+    /// One per `ModelData::images`, uploaded by the `AssetLoader`.
+    pub textures: Vec<TextureHandle>,
+    bound: Vec<Option<Material>>,
+    pub parts: Vec<ModelPart>,
+}
+
+impl Model {
+    /// Per-mesh instance lists for placing the whole model at each of `placements`.
+    pub fn instances(&self, placements: &[cgmath::Matrix4<f32>]) -> Vec<Vec<Instance>> {
+        let mut instances = vec![Vec::new(); self.meshes.len()];
+        for part in &self.parts {
+            instances[part.mesh].extend(
+                placements
+                    .iter()
+                    .map(|placement| Instance::new(placement * part.transform, part.color)),
+            );
+        }
+        instances
+    }
+
+    /// Creates the bind group of every material whose textures finished
+    /// loading. Call after `AssetLoader::poll` returns new handles; textures
+    /// that failed to load are left out of the material.
+    pub fn bind_materials(&mut self, device: &wgpu::Device, renderer: &MeshRenderer, assets: &AssetLoader) {
+        let textures = &self.textures;
+        for (material, bound) in self.materials.iter().zip(&mut self.bound) {
+            if bound.is_some() {
+                continue;
+            }
+            let slots = [material.base_color_texture, material.normal_texture, material.metallic_roughness_texture];
+            let pending = slots
+                .iter()
+                .flatten()
+                .any(|&image| assets.texture(textures[image]).is_none() && !assets.is_failed(textures[image]));
+            if pending {
+                continue;
+            }
+            let texture = |slot: Option<usize>| slot.and_then(|image| assets.texture(textures[image]));
+            *bound = Some(renderer.create_material(device, MaterialDesc {
+                base_color: texture(material.base_color_texture),
+                normal: texture(material.normal_texture),
+                metallic_roughness: texture(material.metallic_roughness_texture),
+                metallic: material.metallic_factor,
+                roughness: material.roughness_factor,
+            }));
+        }
+    }
+
+    /// The material to draw `mesh` with, the renderer's default until its own
+    /// is bound.
+    pub fn material<'a>(&'a self, mesh: usize, renderer: &'a MeshRenderer) -> &'a Material {
+        self.mesh_materials[mesh]
+            .and_then(|material| self.bound[material].as_ref())
+            .unwrap_or_else(|| renderer.default_material())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Matrix4, Vector3};
+
+    const FIXTURE: &[u8] = include_bytes!("../res/models/fixture.gltf");
+
+    #[test]
+    fn mesh_transforms_follow_the_hierarchy() {
+        let model = ModelData::from_slice(FIXTURE).unwrap();
+        assert_eq!(model.roots, vec![0, 2]);
+        assert_eq!(model.nodes[0].children, vec![1]);
+        let mut transforms = model.mesh_transforms();
+        transforms.sort_by(|a, b| a.1.w.y.total_cmp(&b.1.w.y));
+        let loose = Matrix4::from_translation(Vector3::new(0.0, 0.0, -3.0));
+        let child = Matrix4::from_translation(Vector3::new(1.0, 0.0, 0.0))
+            * Matrix4::from_translation(Vector3::new(0.0, 2.0, 0.0))
+            * Matrix4::from_scale(2.0);
+        assert_eq!(transforms, vec![(0, loose), (0, child)]);
+    }
+
+    #[test]
+    fn materials_keep_their_factors() {
+        let model = ModelData::from_slice(FIXTURE).unwrap();
+        assert_eq!(model.meshes[0].material, Some(0));
+        let material = &model.materials[0];
+        assert_eq!(material.name, "painted");
+        assert_eq!(material.base_color_factor, [0.5, 0.25, 1.0, 1.0]);
+        assert_eq!(material.metallic_factor, 0.2);
+        assert_eq!(material.roughness_factor, 0.7);
+        assert_eq!(material.metallic_roughness_texture, None);
+    }
+
+    #[test]
+    fn images_take_the_colour_space_of_their_slot() {
+        let model = ModelData::from_slice(FIXTURE).unwrap();
+        let material = &model.materials[0];
+        let base_color = &model.images[material.base_color_texture.unwrap()];
+        let normal = &model.images[material.normal_texture.unwrap()];
+        assert_eq!(base_color.color_space, ColorSpace::Srgb);
+        assert_eq!(normal.color_space, ColorSpace::Linear);
+        assert_eq!(base_color.image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(normal.image.get_pixel(0, 0).0, [128, 128, 255, 255]);
+    }
+}
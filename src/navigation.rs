@@ -0,0 +1,1068 @@
+//! Ground navigation. `NavGrid` marks which square cells of the ground units
+//! can walk on: terrain too steep or under water is impassable, and so is
+//! the footprint of every building. `PathFinder` runs A* over it and pulls
+//! the result taut into a few straight legs. When many units head for the
+//! same cell they share a `FlowField` towards it instead, kept in the
+//! `FlowFields` resource.
+//!
+//! Positions are in world units; only their x and z matter. Cells are
+//! 8-connected, but a diagonal step never cuts the corner of a blocked cell.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector2};
+
+use crate::ecs::{Changed, Entity, With, World};
+use crate::heightmap::Heightmap;
+use crate::scene::Transform;
+
+const DIAGONAL: f32 = std::f32::consts::SQRT_2;
+/// Cells a unit looks down its flow field for a farther point it can walk
+/// straight to.
+const FLOW_LOOKAHEAD: usize = 8;
+const NO_CELL: u32 = u32::MAX;
+/// Grid changes remembered for deciding which paths they affect. Paths
+/// older than that are replanned.
+const CHANGE_LOG: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NavSettings {
+    /// Side of a cell in world units.
+    pub cell_size: f32,
+    /// Steepest walkable slope, as rise over run.
+    pub max_slope: f32,
+}
+
+impl Default for NavSettings {
+    fn default() -> Self {
+        Self { cell_size: 0.25, max_slope: 1.0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Cell {
+    pub x: u32,
+    pub z: u32,
+}
+
+impl Cell {
+    pub fn new(x: u32, z: u32) -> Self {
+        Self { x, z }
+    }
+}
+
+/// Cells from `min` to `max` inclusive.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CellRect {
+    pub min: Cell,
+    pub max: Cell,
+}
+
+impl CellRect {
+    pub fn cells(self) -> impl Iterator<Item = Cell> {
+        (self.min.z..=self.max.z).flat_map(move |z| (self.min.x..=self.max.x).map(move |x| Cell::new(x, z)))
+    }
+}
+
+/// Square area a building takes out of the `NavGrid`, centred on its
+/// `Transform`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Footprint {
+    pub half_size: f32,
+}
+
+/// Resource of walkable cells.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NavGrid {
+    width: u32,
+    height: u32,
+    cell_size: f32,
+    /// World x and z of the corner of cell (0, 0).
+    origin: [f32; 2],
+    /// Whether the ground itself is walkable.
+    walkable: Vec<bool>,
+    /// Footprints covering each cell; overlapping buildings each count.
+    blockers: Vec<u16>,
+    /// Footprints blocked by `obstacle_system`, to unblock when they go.
+    obstacles: BTreeMap<Entity, CellRect>,
+    version: u64,
+    /// The cells each of the latest versions changed.
+    changes: VecDeque<(u64, CellRect)>,
+}
+
+impl NavGrid {
+    /// An open grid of `width` by `height` cells, e.g. for building synthetic
+    /// maps with `set_walkable`.
+    pub fn new(width: u32, height: u32, cell_size: f32, origin: [f32; 2]) -> Self {
+        assert!(width > 0 && height > 0, "nav grid needs at least one cell");
+        let cells = (width * height) as usize;
+        Self {
+            width,
+            height,
+            cell_size,
+            origin,
+            walkable: vec![true; cells],
+            blockers: vec![0; cells],
+            obstacles: BTreeMap::new(),
+            version: 0,
+            changes: VecDeque::new(),
+        }
+    }
+
+    /// Covers the heightmap, marking cells too steep or partly under water.
+    #[profiling::function]
+    pub fn from_heightmap(heightmap: &Heightmap, settings: &NavSettings) -> Self {
+        let size = heightmap.size();
+        let cells = (size / settings.cell_size).ceil().max(1.0) as u32;
+        let half = size / 2.0;
+        let mut grid = Self::new(cells, cells, settings.cell_size, [-half, -half]);
+        for z in 0..cells {
+            for x in 0..cells {
+                let [x0, z0] = grid.cell_corner(Cell::new(x, z));
+                let (x1, z1) = (x0 + settings.cell_size, z0 + settings.cell_size);
+                let corners = [
+                    heightmap.height_at(x0, z0),
+                    heightmap.height_at(x1, z0),
+                    heightmap.height_at(x1, z1),
+                    heightmap.height_at(x0, z1),
+                ];
+                let under_water = corners.iter().any(|&height| height < heightmap.sea_level());
+                let mut rise = 0.0f32;
+                for i in 0..4 {
+                    rise = rise.max((corners[i] - corners[(i + 1) % 4]).abs());
+                }
+                rise = rise.max((corners[0] - corners[2]).abs() / DIAGONAL).max((corners[1] - corners[3]).abs() / DIAGONAL);
+                let index = grid.index(Cell::new(x, z));
+                grid.walkable[index] = !under_water && rise / settings.cell_size <= settings.max_slope;
+            }
+        }
+        grid
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    /// Bumped whenever a cell changes, so paths and flow fields computed
+    /// before can tell they may be stale.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The cells changed after `version`, or `None` if that is too long ago
+    /// to tell.
+    pub fn changes_since(&self, version: u64) -> Option<impl Iterator<Item = CellRect> + '_> {
+        let oldest = self.changes.front().map_or(self.version + 1, |&(oldest, _)| oldest);
+        if version + 1 < oldest {
+            return None;
+        }
+        Some(self.changes.iter().filter(move |&&(changed, _)| changed > version).map(|&(_, rect)| rect))
+    }
+
+    fn record_change(&mut self, rect: CellRect) {
+        self.version += 1;
+        self.changes.push_back((self.version, rect));
+        if self.changes.len() > CHANGE_LOG {
+            self.changes.pop_front();
+        }
+    }
+
+    pub fn contains(&self, cell: Cell) -> bool {
+        cell.x < self.width && cell.z < self.height
+    }
+
+    pub(crate) fn index(&self, cell: Cell) -> usize {
+        (cell.z * self.width + cell.x) as usize
+    }
+
+    pub(crate) fn cell_of_index(&self, index: usize) -> Cell {
+        Cell::new(index as u32 % self.width, index as u32 / self.width)
+    }
+
+    /// The cell under a world position, clamped to the grid.
+    pub fn cell_at(&self, position: Point3<f32>) -> Cell {
+        let x = ((position.x - self.origin[0]) / self.cell_size).floor();
+        let z = ((position.z - self.origin[1]) / self.cell_size).floor();
+        Cell::new(x.clamp(0.0, (self.width - 1) as f32) as u32, z.clamp(0.0, (self.height - 1) as f32) as u32)
+    }
+
+    fn cell_corner(&self, cell: Cell) -> [f32; 2] {
+        [self.origin[0] + cell.x as f32 * self.cell_size, self.origin[1] + cell.z as f32 * self.cell_size]
+    }
+
+    /// World position of the middle of a cell, at height zero.
+    pub fn cell_center(&self, cell: Cell) -> Point3<f32> {
+        let [x, z] = self.cell_corner(cell);
+        Point3::new(x + self.cell_size / 2.0, 0.0, z + self.cell_size / 2.0)
+    }
+
+    pub fn is_passable(&self, cell: Cell) -> bool {
+        self.contains(cell) && {
+            let index = self.index(cell);
+            self.walkable[index] && self.blockers[index] == 0
+        }
+    }
+
+    /// Changes whether the ground of a cell is walkable, e.g. after editing
+    /// the terrain.
+    pub fn set_walkable(&mut self, cell: Cell, walkable: bool) {
+        let index = self.index(cell);
+        if self.walkable[index] != walkable {
+            self.walkable[index] = walkable;
+            self.record_change(CellRect { min: cell, max: cell });
+        }
+    }
+
+    /// The cells overlapping a square of `half_size` around `center`.
+    pub fn cells_in_square(&self, center: Point3<f32>, half_size: f32) -> CellRect {
+        let min = self.cell_at(Point3::new(center.x - half_size, 0.0, center.z - half_size));
+        let max = self.cell_at(Point3::new(center.x + half_size, 0.0, center.z + half_size));
+        CellRect { min, max }
+    }
+
+    /// Makes the cells impassable until a matching `unblock`.
+    pub fn block(&mut self, rect: CellRect) {
+        for cell in rect.cells() {
+            let index = self.index(cell);
+            self.blockers[index] += 1;
+        }
+        self.record_change(rect);
+    }
+
+    pub fn unblock(&mut self, rect: CellRect) {
+        for cell in rect.cells() {
+            let index = self.index(cell);
+            self.blockers[index] = self.blockers[index].saturating_sub(1);
+        }
+        self.record_change(rect);
+    }
+
+    /// Whether the segment from `from` to `to` passes over or touches any
+    /// cell of `rect`.
+    pub fn segment_touches(&self, from: Point3<f32>, to: Point3<f32>, rect: CellRect) -> bool {
+        let min = self.cell_corner(rect.min);
+        let max = self.cell_corner(Cell::new(rect.max.x + 1, rect.max.z + 1));
+        let (mut enter, mut exit) = (0.0f32, 1.0f32);
+        for (start, delta, low, high) in [(from.x, to.x - from.x, min[0], max[0]), (from.z, to.z - from.z, min[1], max[1])] {
+            if delta == 0.0 {
+                if start < low || start > high {
+                    return false;
+                }
+                continue;
+            }
+            let (a, b) = ((low - start) / delta, (high - start) / delta);
+            enter = enter.max(a.min(b));
+            exit = exit.min(a.max(b));
+        }
+        enter <= exit
+    }
+
+    /// Neighbours reachable in one step, with the step's length in cells.
+    pub(crate) fn neighbours(&self, cell: Cell) -> impl Iterator<Item = (Cell, f32)> + '_ {
+        const STEPS: [(i32, i32); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+        STEPS.iter().filter_map(move |&(dx, dz)| {
+            let (x, z) = (cell.x as i32 + dx, cell.z as i32 + dz);
+            if x < 0 || z < 0 {
+                return None;
+            }
+            let next = Cell::new(x as u32, z as u32);
+            if !self.is_passable(next) {
+                return None;
+            }
+            if dx != 0 && dz != 0 {
+                let side_x = Cell::new(x as u32, cell.z);
+                let side_z = Cell::new(cell.x, z as u32);
+                if !self.is_passable(side_x) || !self.is_passable(side_z) {
+                    return None;
+                }
+                return Some((next, DIAGONAL));
+            }
+            Some((next, 1.0))
+        })
+    }
+
+    /// Whether a unit can walk straight from `from` to `to`: every cell the
+    /// segment touches is passable, and it never squeezes between two
+    /// blocked cells that only meet at a corner.
+    pub fn line_of_sight(&self, from: Point3<f32>, to: Point3<f32>) -> bool {
+        let start = self.cell_at(from);
+        let (mut x, mut z) = (start.x as i32, start.z as i32);
+        let (dx, dz) = (to.x - from.x, to.z - from.z);
+        let (step_x, step_z) = (if dx < 0.0 { -1 } else { 1 }, if dz < 0.0 { -1 } else { 1 });
+        let [corner_x, corner_z] = self.cell_corner(start);
+        // Fraction of the segment at which it crosses the next cell boundary
+        // along each axis, and how much more to cross each one after that.
+        let crossing = |position: f32, corner: f32, step: i32, delta: f32| {
+            if delta == 0.0 {
+                return f32::INFINITY;
+            }
+            let boundary = if step > 0 { corner + self.cell_size } else { corner };
+            (boundary - position) / delta
+        };
+        let mut t_x = crossing(from.x, corner_x, step_x, dx);
+        let mut t_z = crossing(from.z, corner_z, step_z, dz);
+        let t_delta_x = if dx != 0.0 { self.cell_size / dx.abs() } else { f32::INFINITY };
+        let t_delta_z = if dz != 0.0 { self.cell_size / dz.abs() } else { f32::INFINITY };
+        let passable = |x: i32, z: i32| x >= 0 && z >= 0 && self.is_passable(Cell::new(x as u32, z as u32));
+        if !passable(x, z) {
+            return false;
+        }
+        while t_x.min(t_z) <= 1.0 {
+            if (t_x - t_z).abs() < 1e-6 {
+                // Through a corner: both cells beside it must be open.
+                if !passable(x + step_x, z) || !passable(x, z + step_z) {
+                    return false;
+                }
+                x += step_x;
+                z += step_z;
+                t_x += t_delta_x;
+                t_z += t_delta_z;
+            } else if t_x < t_z {
+                x += step_x;
+                t_x += t_delta_x;
+            } else {
+                z += step_z;
+                t_z += t_delta_z;
+            }
+            if !passable(x, z) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Octile distance in cells: straight steps plus diagonal ones.
+fn octile(a: Cell, b: Cell) -> f32 {
+    let dx = (a.x as f32 - b.x as f32).abs();
+    let dz = (a.z as f32 - b.z as f32).abs();
+    dx.max(dz) + (DIAGONAL - 1.0) * dx.min(dz)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct OpenNode {
+    estimate: f32,
+    remaining: f32,
+    index: u32,
+}
+
+impl Eq for OpenNode {}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed for a min-heap; ties prefer nodes nearer the goal, then
+        // the lower index, so equal paths always come out the same.
+        other
+            .estimate
+            .total_cmp(&self.estimate)
+            .then_with(|| other.remaining.total_cmp(&self.remaining))
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A* search with its working memory kept between searches, so repeated
+/// queries don't allocate. Also a resource, shared by every unit.
+#[derive(Clone, Debug, Default)]
+pub struct PathFinder {
+    cost: Vec<f32>,
+    parent: Vec<u32>,
+    /// Search in which each cell was last reached; others hold stale data.
+    visited: Vec<u32>,
+    closed: Vec<u32>,
+    search: u32,
+    open: BinaryHeap<OpenNode>,
+}
+
+impl PathFinder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cells from `start` to `goal`, both included. When the goal can't be
+    /// reached the path ends at the reachable cell nearest to it instead.
+    /// The start cell may be blocked, e.g. for a builder inside its site.
+    #[profiling::function]
+    pub fn find_cells(&mut self, grid: &NavGrid, start: Cell, goal: Cell) -> Vec<Cell> {
+        let cells = (grid.width * grid.height) as usize;
+        if self.visited.len() != cells {
+            self.cost = vec![0.0; cells];
+            self.parent = vec![0; cells];
+            self.visited = vec![0; cells];
+            self.closed = vec![0; cells];
+            self.search = 0;
+        }
+        self.search = self.search.wrapping_add(1);
+        if self.search == 0 {
+            // Wrapped: stale stamps could look current.
+            self.visited.iter_mut().for_each(|stamp| *stamp = 0);
+            self.closed.iter_mut().for_each(|stamp| *stamp = 0);
+            self.search = 1;
+        }
+        self.open.clear();
+
+        let start_index = grid.index(start);
+        self.visited[start_index] = self.search;
+        self.cost[start_index] = 0.0;
+        self.parent[start_index] = start_index as u32;
+        let remaining = octile(start, goal);
+        self.open.push(OpenNode { estimate: remaining, remaining, index: start_index as u32 });
+        let mut nearest = (remaining, start_index);
+
+        while let Some(node) = self.open.pop() {
+            let index = node.index as usize;
+            if self.closed[index] == self.search {
+                continue;
+            }
+            self.closed[index] = self.search;
+            if node.remaining < nearest.0 {
+                nearest = (node.remaining, index);
+            }
+            let cell = grid.cell_of_index(index);
+            if cell == goal {
+                break;
+            }
+            for (next, step) in grid.neighbours(cell) {
+                let next_index = grid.index(next);
+                let cost = self.cost[index] + step;
+                if self.visited[next_index] == self.search && cost >= self.cost[next_index] {
+                    continue;
+                }
+                self.visited[next_index] = self.search;
+                self.cost[next_index] = cost;
+                self.parent[next_index] = index as u32;
+                let remaining = octile(next, goal);
+                self.open.push(OpenNode { estimate: cost + remaining, remaining, index: next_index as u32 });
+            }
+        }
+
+        let mut path = vec![grid.cell_of_index(nearest.1)];
+        let mut index = nearest.1;
+        while index != start_index {
+            index = self.parent[index] as usize;
+            path.push(grid.cell_of_index(index));
+        }
+        path.reverse();
+        path
+    }
+
+    /// Waypoints from `start` towards `goal`, without `start` itself and
+    /// with as few turns as line of sight allows. Ends at `goal` if it is
+    /// reachable, otherwise at the nearest cell that is.
+    pub fn find_path(&mut self, grid: &NavGrid, start: Point3<f32>, goal: Point3<f32>) -> Vec<Point3<f32>> {
+        let goal_cell = grid.cell_at(goal);
+        let cells = self.find_cells(grid, grid.cell_at(start), goal_cell);
+        let mut points: Vec<Point3<f32>> = cells.iter().map(|&cell| grid.cell_center(cell)).collect();
+        points[0] = start;
+        if cells.last() == Some(&goal_cell) {
+            *points.last_mut().unwrap() = goal;
+        }
+        let mut path = string_pull(grid, &points);
+        path.remove(0);
+        for point in &mut path {
+            point.y = goal.y;
+        }
+        path
+    }
+}
+
+/// Drops every point the path can skip by walking straight past it.
+pub fn string_pull(grid: &NavGrid, points: &[Point3<f32>]) -> Vec<Point3<f32>> {
+    let mut pulled = Vec::new();
+    let mut anchor = match points.first() {
+        Some(&first) => first,
+        None => return pulled,
+    };
+    pulled.push(anchor);
+    for i in 1..points.len() - 1 {
+        if !grid.line_of_sight(anchor, points[i + 1]) {
+            anchor = points[i];
+            pulled.push(anchor);
+        }
+    }
+    if points.len() > 1 {
+        pulled.push(points[points.len() - 1]);
+    }
+    pulled
+}
+
+/// Directions from every cell towards one goal cell, shared by all units
+/// going there. The integration field holds each cell's walking distance to
+/// the goal; the direction field points each cell at the neighbour it should
+/// step to. Built for one `NavGrid::version` and stale after it changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlowField {
+    goal: Cell,
+    width: u32,
+    version: u64,
+    /// Distance to the goal in cells; infinite where it can't be reached.
+    cost: Vec<f32>,
+    /// Index of the next cell towards the goal, or `NO_CELL`.
+    next: Vec<u32>,
+}
+
+impl FlowField {
+    /// Runs Dijkstra outwards from `goal` over the whole grid. The goal cell
+    /// may be blocked; units then walk up to its edge.
+    #[profiling::function]
+    pub fn new(grid: &NavGrid, goal: Cell) -> Self {
+        let cells = (grid.width * grid.height) as usize;
+        let mut cost = vec![f32::INFINITY; cells];
+        let mut next = vec![NO_CELL; cells];
+        let mut open = BinaryHeap::new();
+        let goal_index = grid.index(goal);
+        cost[goal_index] = 0.0;
+        open.push(OpenNode { estimate: 0.0, remaining: 0.0, index: goal_index as u32 });
+        while let Some(node) = open.pop() {
+            let index = node.index as usize;
+            if node.estimate > cost[index] {
+                continue;
+            }
+            // Steps are symmetric, so a step out of the cell is also one in.
+            for (neighbour, step) in grid.neighbours(grid.cell_of_index(index)) {
+                let neighbour_index = grid.index(neighbour);
+                let distance = cost[index] + step;
+                if distance < cost[neighbour_index] {
+                    cost[neighbour_index] = distance;
+                    next[neighbour_index] = index as u32;
+                    open.push(OpenNode { estimate: distance, remaining: 0.0, index: neighbour_index as u32 });
+                }
+            }
+        }
+        Self { goal, width: grid.width, version: grid.version(), cost, next }
+    }
+
+    pub fn goal(&self) -> Cell {
+        self.goal
+    }
+
+    /// The `NavGrid::version` the field was built for.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn index(&self, cell: Cell) -> usize {
+        (cell.z * self.width + cell.x) as usize
+    }
+
+    /// Walking distance from `cell` to the goal in cells, if it can get there.
+    pub fn cost(&self, cell: Cell) -> Option<f32> {
+        Some(self.cost[self.index(cell)]).filter(|cost| cost.is_finite())
+    }
+
+    /// The neighbour to step to from `cell`; `None` at the goal and where it
+    /// can't be reached.
+    pub fn next_cell(&self, cell: Cell) -> Option<Cell> {
+        match self.next[self.index(cell)] {
+            NO_CELL => None,
+            index => Some(Cell::new(index % self.width, index / self.width)),
+        }
+    }
+
+    /// Unit vector in x and z from `cell` towards the cell to step to.
+    pub fn direction(&self, cell: Cell) -> Option<Vector2<f32>> {
+        let next = self.next_cell(cell)?;
+        Some(Vector2::new(next.x as f32 - cell.x as f32, next.z as f32 - cell.z as f32).normalize())
+    }
+}
+
+/// Resource caching a `FlowField` per goal cell that enough units are
+/// heading for. Fields rebuild when the grid changes and are dropped once
+/// nobody steers by them.
+#[derive(Clone, Debug)]
+pub struct FlowFields {
+    /// Units that must share a goal cell in one tick before they switch from
+    /// their own A* paths to a flow field.
+    min_units: u32,
+    fields: HashMap<Cell, FlowField>,
+    /// Units steering towards each goal cell this tick and the last.
+    demand: HashMap<Cell, u32>,
+    last_demand: HashMap<Cell, u32>,
+}
+
+impl Default for FlowFields {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+impl FlowFields {
+    pub fn new(min_units: u32) -> Self {
+        Self { min_units, fields: HashMap::new(), demand: HashMap::new(), last_demand: HashMap::new() }
+    }
+
+    pub fn min_units(&self) -> u32 {
+        self.min_units
+    }
+
+    pub fn set_min_units(&mut self, min_units: u32) {
+        self.min_units = min_units;
+    }
+
+    /// Counts a unit heading for `goal` and returns whether enough are that
+    /// it should use the shared field.
+    pub fn want(&mut self, goal: Cell) -> bool {
+        let demand = self.demand.entry(goal).or_insert(0);
+        *demand += 1;
+        let last = self.last_demand.get(&goal).copied().unwrap_or(0);
+        self.fields.contains_key(&goal) || *demand >= self.min_units || last >= self.min_units
+    }
+
+    /// The field towards `goal`, built or rebuilt if it isn't current.
+    pub fn field(&mut self, grid: &NavGrid, goal: Cell) -> &FlowField {
+        let field = self.fields.entry(goal).or_insert_with(|| FlowField::new(grid, goal));
+        if field.version != grid.version() {
+            *field = FlowField::new(grid, goal);
+        }
+        field
+    }
+
+    /// Fields currently cached, e.g. for drawing.
+    pub fn fields(&self) -> impl Iterator<Item = &FlowField> {
+        self.fields.values()
+    }
+
+    /// Starts a new tick of demand, dropping fields nobody used in the last.
+    pub fn end_tick(&mut self) {
+        self.last_demand = std::mem::take(&mut self.demand);
+        let last_demand = &self.last_demand;
+        self.fields.retain(|goal, _| last_demand.contains_key(goal));
+    }
+
+    /// Forgets every field, e.g. after the grid was replaced outright.
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+}
+
+/// Where a unit at `here` should head next by the shared flow field towards
+/// `goal`: straight for the goal if nothing is in the way, otherwise the
+/// farthest cell a few steps down the field it can walk straight to. `None`
+/// when too few units share the goal or the field can't reach the unit.
+fn follow_flow(world: &World, here: Point3<f32>, goal: Point3<f32>) -> Option<Point3<f32>> {
+    let grid = world.resource::<NavGrid>()?;
+    let mut fields = world.resource_mut::<FlowFields>()?;
+    let goal_cell = grid.cell_at(goal);
+    if !fields.want(goal_cell) {
+        return None;
+    }
+    let field = fields.field(&grid, goal_cell);
+    let start = grid.cell_at(here);
+    if start == goal_cell || grid.line_of_sight(here, goal) {
+        return Some(goal);
+    }
+    let mut cell = field.next_cell(start)?;
+    let mut waypoint = grid.cell_center(cell);
+    for _ in 0..FLOW_LOOKAHEAD {
+        let ahead = match field.next_cell(cell) {
+            Some(ahead) => ahead,
+            None => break,
+        };
+        let center = grid.cell_center(ahead);
+        if !grid.line_of_sight(here, center) {
+            break;
+        }
+        cell = ahead;
+        waypoint = center;
+    }
+    waypoint.y = goal.y;
+    Some(waypoint)
+}
+
+/// Rolls `FlowFields` demand over to the next tick. Add it to the schedule
+/// before anything that steers.
+pub fn flow_field_system(world: &mut World) {
+    if let Some(mut fields) = world.resource_mut::<FlowFields>() {
+        fields.end_tick();
+    }
+}
+
+/// Path a unit is following, kept between ticks and replanned when the goal
+/// moves by more than a cell or the grid changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Path {
+    goal: Point3<f32>,
+    waypoints: VecDeque<Point3<f32>>,
+    /// Whether the path gets all the way to `goal`.
+    complete: bool,
+    version: u64,
+}
+
+impl Path {
+    pub fn waypoints(&self) -> &VecDeque<Point3<f32>> {
+        &self.waypoints
+    }
+
+    /// Whether grid changes since the path was planned could matter to a
+    /// unit at `here`: they touch what is left of the route, or the path
+    /// stops short and anything might have opened a way through.
+    fn is_affected(&self, grid: &NavGrid, here: Point3<f32>) -> bool {
+        let mut changes = match grid.changes_since(self.version) {
+            Some(changes) => changes,
+            None => return true,
+        };
+        if !self.complete {
+            return changes.next().is_some();
+        }
+        let legs: Vec<(Point3<f32>, Point3<f32>)> = std::iter::once(here)
+            .chain(self.waypoints.iter().copied())
+            .zip(self.waypoints.iter().copied())
+            .collect();
+        changes.any(|rect| legs.iter().any(|&(from, to)| grid.segment_touches(from, to, rect)))
+    }
+}
+
+/// The point `unit` at `here` should head for next on its way to `goal`:
+/// where the shared `FlowField` leads if enough units are going there, else
+/// the next waypoint of its own `Path`, or `goal` itself once past them or
+/// when there is no `NavGrid`. Waypoints within `reached` are dropped. Grid
+/// changes only replan paths they affect.
+pub fn steer(world: &mut World, unit: Entity, here: Point3<f32>, goal: Point3<f32>, reached: f32) -> Point3<f32> {
+    if let Some(waypoint) = follow_flow(world, here, goal) {
+        world.remove::<Path>(unit);
+        return waypoint;
+    }
+    let replan = {
+        let grid = match world.resource::<NavGrid>() {
+            Some(grid) => grid,
+            None => return goal,
+        };
+        match world.get_mut::<Path>(unit) {
+            Some(mut path) => {
+                if flat_distance(path.goal, goal) > grid.cell_size() || path.is_affected(&grid, here) {
+                    true
+                } else {
+                    path.version = grid.version();
+                    false
+                }
+            }
+            None => true,
+        }
+    };
+    if replan {
+        if world.resource::<PathFinder>().is_none() {
+            world.insert_resource(PathFinder::new());
+        }
+        let grid = world.resource::<NavGrid>().unwrap();
+        let waypoints = world.resource_mut::<PathFinder>().unwrap().find_path(&grid, here, goal);
+        let complete = waypoints.last() == Some(&goal);
+        let path = Path { goal, waypoints: waypoints.into(), complete, version: grid.version() };
+        drop(grid);
+        world.insert(unit, path);
+    }
+    let mut path = world.get_mut::<Path>(unit).unwrap();
+    while path.waypoints.front().is_some_and(|&waypoint| flat_distance(waypoint, here) <= reached) {
+        path.waypoints.pop_front();
+    }
+    // Once past the last waypoint, head straight for the goal even if the
+    // path stopped short of it.
+    path.waypoints.front().copied().unwrap_or(goal)
+}
+
+/// Blocks the footprint of every new `Footprint`, moves those whose
+/// `Footprint` or `Transform` changed and unblocks those that were removed
+/// or despawned. Add it to the schedule before anything that finds paths.
+pub fn obstacle_system(world: &mut World) {
+    let mut grid = match world.resource_mut::<NavGrid>() {
+        Some(grid) => grid,
+        None => return,
+    };
+    let gone: Vec<Entity> = grid.obstacles.keys().copied().filter(|&entity| !world.has::<Footprint>(entity)).collect();
+    for entity in gone {
+        let rect = grid.obstacles.remove(&entity).unwrap();
+        grid.unblock(rect);
+    }
+    let mut changed = world.matching::<(Changed<Footprint>, With<Transform>)>();
+    changed.extend(world.matching::<(With<Footprint>, Changed<Transform>)>());
+    changed.sort_unstable();
+    changed.dedup();
+    for entity in changed {
+        let footprint = *world.get::<Footprint>(entity).unwrap();
+        let center = Point3::from_vec(world.get::<Transform>(entity).unwrap().translation);
+        let rect = grid.cells_in_square(center, footprint.half_size);
+        match grid.obstacles.insert(entity, rect) {
+            Some(previous) if previous == rect => continue,
+            Some(previous) => grid.unblock(previous),
+            None => {}
+        }
+        grid.block(rect);
+    }
+}
+
+fn flat_distance(a: Point3<f32>, b: Point3<f32>) -> f32 {
+    (a.x - b.x).hypot(a.z - b.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::Schedule;
+    use cgmath::Vector3;
+
+    /// A grid with one cell per character, rows in increasing z, where `#`
+    /// is blocked.
+    fn grid_from(rows: &[&str]) -> NavGrid {
+        let mut grid = NavGrid::new(rows[0].len() as u32, rows.len() as u32, 1.0, [0.0, 0.0]);
+        for (z, row) in rows.iter().enumerate() {
+            for (x, tile) in row.chars().enumerate() {
+                if tile == '#' {
+                    grid.set_walkable(Cell::new(x as u32, z as u32), false);
+                }
+            }
+        }
+        grid
+    }
+
+    fn cost(cells: &[Cell]) -> f32 {
+        cells.windows(2).map(|step| octile(step[0], step[1])).sum()
+    }
+
+    fn center(x: u32, z: u32) -> Point3<f32> {
+        Point3::new(x as f32 + 0.5, 0.0, z as f32 + 0.5)
+    }
+
+    #[test]
+    fn open_paths_cost_the_octile_distance() {
+        let grid = NavGrid::new(8, 8, 1.0, [0.0, 0.0]);
+        let mut finder = PathFinder::new();
+        let straight = finder.find_cells(&grid, Cell::new(0, 0), Cell::new(5, 0));
+        assert_eq!(straight.len(), 6);
+        assert_eq!(cost(&straight), 5.0);
+        let diagonal = finder.find_cells(&grid, Cell::new(0, 0), Cell::new(3, 3));
+        assert_eq!(diagonal, vec![Cell::new(0, 0), Cell::new(1, 1), Cell::new(2, 2), Cell::new(3, 3)]);
+        let mixed = finder.find_cells(&grid, Cell::new(0, 0), Cell::new(4, 2));
+        assert!((cost(&mixed) - (2.0 + 2.0 * DIAGONAL)).abs() < 1e-5);
+        assert!((cost(&mixed) - octile(Cell::new(0, 0), Cell::new(4, 2))).abs() < 1e-5);
+    }
+
+    #[test]
+    fn diagonals_never_cut_corners() {
+        let grid = grid_from(&[".#.", "...", "..."]);
+        assert!(!grid.neighbours(Cell::new(0, 0)).any(|(cell, _)| cell == Cell::new(1, 1)));
+        let path = PathFinder::new().find_cells(&grid, Cell::new(0, 0), Cell::new(1, 1));
+        assert_eq!(path, vec![Cell::new(0, 0), Cell::new(0, 1), Cell::new(1, 1)]);
+    }
+
+    #[test]
+    fn unreachable_goals_end_at_the_nearest_reachable_cell() {
+        let grid = grid_from(&["..#..", "..#..", "..#.."]);
+        let path = PathFinder::new().find_cells(&grid, Cell::new(0, 1), Cell::new(4, 1));
+        assert_eq!(path, vec![Cell::new(0, 1), Cell::new(1, 1)]);
+    }
+
+    #[test]
+    fn paths_can_start_in_a_blocked_cell() {
+        let mut grid = NavGrid::new(6, 5, 1.0, [0.0, 0.0]);
+        grid.block(CellRect { min: Cell::new(2, 2), max: Cell::new(2, 2) });
+        let path = PathFinder::new().find_cells(&grid, Cell::new(2, 2), Cell::new(5, 2));
+        assert_eq!(path.first(), Some(&Cell::new(2, 2)));
+        assert_eq!(path.last(), Some(&Cell::new(5, 2)));
+        assert!(path[1..].iter().all(|&cell| grid.is_passable(cell)));
+    }
+
+    #[test]
+    fn line_of_sight_needs_both_sides_of_a_corner() {
+        let open = NavGrid::new(4, 4, 1.0, [0.0, 0.0]);
+        assert!(open.line_of_sight(center(0, 0), center(1, 1)));
+        assert!(open.line_of_sight(center(0, 0), center(3, 1)));
+        let one_side = grid_from(&[".#..", "....", "....", "...."]);
+        assert!(!one_side.line_of_sight(center(0, 0), center(1, 1)));
+        assert!(!one_side.line_of_sight(center(1, 1), center(0, 0)));
+        assert!(!one_side.line_of_sight(center(0, 0), center(3, 0)));
+        assert!(one_side.line_of_sight(center(0, 1), center(3, 1)));
+    }
+
+    #[test]
+    fn string_pulling_an_l_leaves_its_corner() {
+        let grid = grid_from(&[".....", "####.", "####.", "####.", "####."]);
+        let cells = PathFinder::new().find_cells(&grid, Cell::new(0, 0), Cell::new(4, 4));
+        assert_eq!(cells.len(), 9);
+        let points: Vec<Point3<f32>> = cells.iter().map(|&cell| grid.cell_center(cell)).collect();
+        assert_eq!(string_pull(&grid, &points), vec![center(0, 0), center(4, 0), center(4, 4)]);
+        assert_eq!(PathFinder::new().find_path(&grid, center(0, 0), center(4, 4)), vec![center(4, 0), center(4, 4)]);
+    }
+
+    #[test]
+    fn heightmaps_block_steep_and_flooded_cells() {
+        let mut heights = vec![1.0; 25];
+        heights[0] = -1.0;
+        heights[24] = 10.0;
+        let heightmap = Heightmap::new(5, 1.0, 0.0, heights);
+        let grid = NavGrid::from_heightmap(&heightmap, &NavSettings { cell_size: 1.0, max_slope: 1.0 });
+        assert_eq!((grid.width(), grid.height()), (4, 4));
+        assert!(!grid.is_passable(Cell::new(0, 0)), "under the sea");
+        assert!(!grid.is_passable(Cell::new(3, 3)), "too steep");
+        let open = CellRect { min: Cell::new(0, 0), max: Cell::new(3, 3) }.cells().filter(|&cell| grid.is_passable(cell)).count();
+        assert_eq!(open, 14);
+        assert_eq!(grid.cell_at(Point3::new(-1.5, 0.0, 1.5)), Cell::new(0, 3));
+    }
+
+    #[test]
+    fn overlapping_footprints_block_until_both_go() {
+        let mut grid = NavGrid::new(4, 4, 1.0, [0.0, 0.0]);
+        let a = CellRect { min: Cell::new(0, 0), max: Cell::new(2, 2) };
+        let b = CellRect { min: Cell::new(1, 1), max: Cell::new(3, 3) };
+        grid.block(a);
+        grid.block(b);
+        grid.unblock(a);
+        assert!(grid.is_passable(Cell::new(0, 0)));
+        assert!(!grid.is_passable(Cell::new(1, 1)));
+        assert!(!grid.is_passable(Cell::new(3, 3)));
+        grid.unblock(b);
+        assert!(CellRect { min: Cell::new(0, 0), max: Cell::new(3, 3) }.cells().all(|cell| grid.is_passable(cell)));
+        assert_eq!(grid.version(), 4);
+        assert_eq!(grid.changes_since(2).unwrap().collect::<Vec<_>>(), vec![a, b]);
+    }
+
+    #[test]
+    fn the_change_log_forgets_old_versions() {
+        let mut grid = NavGrid::new(4, 4, 1.0, [0.0, 0.0]);
+        for _ in 0..CHANGE_LOG + 1 {
+            let cell = Cell::new(0, 0);
+            grid.set_walkable(cell, !grid.is_passable(cell));
+        }
+        assert!(grid.changes_since(0).is_none());
+        assert_eq!(grid.changes_since(1).unwrap().count(), CHANGE_LOG);
+        assert_eq!(grid.changes_since(grid.version()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn obstacles_follow_footprints() {
+        let mut world = World::new();
+        world.insert_resource(NavGrid::new(8, 8, 1.0, [0.0, 0.0]));
+        let mut schedule = Schedule::new();
+        schedule.add_system("obstacles", obstacle_system);
+        let passable = |world: &World, x: u32, z: u32| world.resource::<NavGrid>().unwrap().is_passable(Cell::new(x, z));
+        let version = |world: &World| world.resource::<NavGrid>().unwrap().version();
+
+        let building = world.spawn_with((Footprint { half_size: 0.5 }, Transform::from_translation(Vector3::new(2.0, 0.0, 2.0))));
+        schedule.run(&mut world);
+        assert!(!passable(&world, 1, 1) && !passable(&world, 2, 2));
+        let blocked = version(&world);
+        schedule.run(&mut world);
+        assert_eq!(version(&world), blocked, "nothing changed");
+
+        world.get_mut::<Transform>(building).unwrap().translation.x = 5.0;
+        schedule.run(&mut world);
+        assert!(passable(&world, 1, 1) && !passable(&world, 4, 1) && !passable(&world, 5, 2));
+
+        world.despawn(building);
+        schedule.run(&mut world);
+        assert!((0..8).all(|x| (0..8).all(|z| passable(&world, x, z))));
+    }
+
+    #[test]
+    fn only_paths_crossing_a_change_are_replanned() {
+        let mut world = World::new();
+        world.insert_resource(NavGrid::new(32, 32, 1.0, [0.0, 0.0]));
+        let unit = world.spawn();
+        let (here, goal) = (center(0, 0), center(20, 0));
+        assert_eq!(steer(&mut world, unit, here, goal, 0.01), goal);
+
+        world.resource_mut::<NavGrid>().unwrap().block(CellRect { min: Cell::new(5, 10), max: Cell::new(8, 12) });
+        let before = world.get::<Path>(unit).unwrap().clone();
+        assert_eq!(steer(&mut world, unit, here, goal, 0.01), goal);
+        let after = world.get::<Path>(unit).unwrap().clone();
+        assert_eq!(after.waypoints, before.waypoints);
+        assert_eq!(after.version, world.resource::<NavGrid>().unwrap().version());
+
+        world.resource_mut::<NavGrid>().unwrap().block(CellRect { min: Cell::new(10, 0), max: Cell::new(10, 3) });
+        let waypoint = steer(&mut world, unit, here, goal, 0.01);
+        assert_ne!(waypoint, goal, "walks around the new wall");
+        assert!(world.get::<Path>(unit).unwrap().waypoints().len() > 1);
+    }
+
+    #[test]
+    fn flow_field_costs_match_a_star() {
+        let grid = grid_from(&["......", ".####.", "....#.", "###.#.", "......"]);
+        let goal = Cell::new(0, 2);
+        let field = FlowField::new(&grid, goal);
+        let mut finder = PathFinder::new();
+        for start in [Cell::new(5, 0), Cell::new(0, 4), Cell::new(5, 4), Cell::new(3, 3)].iter().copied() {
+            let path = finder.find_cells(&grid, start, goal);
+            assert_eq!(path.last(), Some(&goal));
+            assert!((field.cost(start).unwrap() - cost(&path)).abs() < 1e-4, "from {:?}", start);
+        }
+    }
+
+    #[test]
+    fn flow_fields_lead_to_the_goal_and_stop_there() {
+        let grid = grid_from(&["...#.", "...#.", "####.", "....."]);
+        let goal = Cell::new(0, 0);
+        let field = FlowField::new(&grid, goal);
+        assert_eq!(field.next_cell(goal), None);
+        assert_eq!(field.cost(goal), Some(0.0));
+        let walled_off = Cell::new(4, 0);
+        assert_eq!(field.next_cell(walled_off), None);
+        assert_eq!(field.cost(walled_off), None);
+        assert_eq!(field.direction(walled_off), None);
+
+        let mut cell = Cell::new(2, 1);
+        let mut steps = 0;
+        while let Some(next) = field.next_cell(cell) {
+            assert!(field.cost(next) < field.cost(cell));
+            assert!((field.direction(cell).unwrap().magnitude() - 1.0).abs() < 1e-5);
+            cell = next;
+            steps += 1;
+        }
+        assert_eq!((cell, steps), (goal, 2));
+    }
+
+    #[test]
+    fn flow_fields_rebuild_when_the_grid_changes() {
+        let mut grid = NavGrid::new(5, 5, 1.0, [0.0, 0.0]);
+        let mut fields = FlowFields::new(1);
+        let goal = Cell::new(0, 2);
+        assert_eq!(fields.field(&grid, goal).cost(Cell::new(4, 2)), Some(4.0));
+        grid.block(CellRect { min: Cell::new(2, 1), max: Cell::new(2, 3) });
+        let field = fields.field(&grid, goal);
+        assert_eq!(field.version(), grid.version());
+        assert!(field.cost(Cell::new(4, 2)).unwrap() > 4.0);
+        assert_eq!(field, &FlowField::new(&grid, goal));
+    }
+
+    #[test]
+    fn flow_fields_switch_on_with_demand_and_hold_for_a_tick() {
+        let grid = NavGrid::new(4, 4, 1.0, [0.0, 0.0]);
+        let goal = Cell::new(3, 3);
+        let mut fields = FlowFields::new(3);
+        assert!(!fields.want(goal));
+        assert!(!fields.want(goal));
+        assert!(fields.want(goal), "the third unit reaches min_units");
+        fields.end_tick();
+        assert!(fields.want(goal), "last tick's demand carries over");
+        fields.end_tick();
+        assert!(!fields.want(goal), "one unit isn't enough on its own");
+
+        fields.field(&grid, goal);
+        assert!(fields.want(goal), "a built field stays in use");
+    }
+
+    #[test]
+    fn end_tick_drops_fields_nobody_used() {
+        let grid = NavGrid::new(4, 4, 1.0, [0.0, 0.0]);
+        let (used, unused) = (Cell::new(0, 0), Cell::new(3, 3));
+        let mut fields = FlowFields::new(1);
+        fields.want(used);
+        fields.field(&grid, used);
+        fields.field(&grid, unused);
+        fields.end_tick();
+        assert_eq!(fields.fields().map(FlowField::goal).collect::<Vec<_>>(), vec![used]);
+        fields.end_tick();
+        assert_eq!(fields.fields().count(), 0);
+    }
+}
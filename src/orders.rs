@@ -0,0 +1,1028 @@
+//! Orders for units. An order is plain data that can be written out and
+//! parsed back, e.g. for replays or the network. Players issue `Command`s,
+//! which wait in `PendingCommands` until the next tick; `order_system` then
+//! hands them to the units and runs each unit's current order.
+//!
+//! ```text
+//! move 1.5 0 -2 @ 3v0 4v0
+//! queue attack 12v1 @ 3v0
+//! ```
+//!
+//! Units are expected to be roots of the scene graph, so their `Transform`
+//! is their world position. The simulation never reads `GlobalTransform`,
+//! which is only updated between frames.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Quaternion, Rad, Rotation3, Vector3};
+
+use crate::camera::Camera;
+use crate::ecs::{Entity, With, World};
+use crate::heightmap::Heightmap;
+use crate::input::Input;
+use crate::navigation::{self, Footprint};
+use crate::scene::{self, Transform};
+use crate::selection;
+use crate::timestep::Tick;
+
+/// How close a unit gets to a point before a move counts as done.
+const ARRIVE_DISTANCE: f32 = 0.02;
+/// How close gatherers and builders get to what they work on.
+const WORK_DISTANCE: f32 = 0.15;
+/// Attack-moving and patrolling units engage enemies within this many weapon
+/// ranges.
+const ACQUIRE_RANGES: f32 = 2.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OrderError {
+    UnknownOrder(String),
+    BadArgument(String),
+    MissingUnits,
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderError::UnknownOrder(name) => write!(f, "unknown order `{}`", name),
+            OrderError::BadArgument(message) => write!(f, "{}", message),
+            OrderError::MissingUnits => write!(f, "expected `@` followed by the units"),
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Order {
+    Move(Point3<f32>),
+    /// Follows and shoots the unit until it dies.
+    Attack(Entity),
+    /// Moves, engaging enemies met on the way.
+    AttackMove(Point3<f32>),
+    /// Attack-moves back and forth between this point and the previous one,
+    /// or between all the patrol points queued in a row.
+    Patrol(Point3<f32>),
+    /// Drops every order.
+    Stop,
+    /// Stays put, only shooting at enemies in range.
+    HoldPosition,
+    /// Carries from the resource node to the nearest dropoff until it runs out.
+    Gather(Entity),
+    /// Puts up a `Construction` of `kind` at `position` and works on it until
+    /// it is finished.
+    Build { kind: u32, position: Point3<f32> },
+}
+
+impl Order {
+    /// Parses one order in the format `Display` writes.
+    pub fn parse(source: &str) -> Result<Self, OrderError> {
+        let mut words = source.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let expect = |count: usize| {
+            if args.len() == count {
+                Ok(())
+            } else {
+                Err(OrderError::BadArgument(format!("`{}` takes {} arguments, not {}", name, count, args.len())))
+            }
+        };
+        let order = match name {
+            "move" => {
+                expect(3)?;
+                Order::Move(parse_point(&args)?)
+            }
+            "attack" => {
+                expect(1)?;
+                Order::Attack(parse_entity(args[0])?)
+            }
+            "attack_move" => {
+                expect(3)?;
+                Order::AttackMove(parse_point(&args)?)
+            }
+            "patrol" => {
+                expect(3)?;
+                Order::Patrol(parse_point(&args)?)
+            }
+            "stop" => {
+                expect(0)?;
+                Order::Stop
+            }
+            "hold" => {
+                expect(0)?;
+                Order::HoldPosition
+            }
+            "gather" => {
+                expect(1)?;
+                Order::Gather(parse_entity(args[0])?)
+            }
+            "build" => {
+                expect(4)?;
+                let kind = args[0].parse().map_err(|_| OrderError::BadArgument(format!("invalid building kind `{}`", args[0])))?;
+                Order::Build { kind, position: parse_point(&args[1..])? }
+            }
+            _ => return Err(OrderError::UnknownOrder(name.to_owned())),
+        };
+        Ok(order)
+    }
+
+    /// Where the unit is headed for this order, if anywhere.
+    pub fn position(&self, world: &World) -> Option<Point3<f32>> {
+        match *self {
+            Order::Move(position) | Order::AttackMove(position) | Order::Patrol(position) | Order::Build { position, .. } => Some(position),
+            Order::Attack(target) | Order::Gather(target) => position(world, target),
+            Order::Stop | Order::HoldPosition => None,
+        }
+    }
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let point = |p: &Point3<f32>| format!("{} {} {}", p.x, p.y, p.z);
+        match self {
+            Order::Move(position) => write!(f, "move {}", point(position)),
+            Order::Attack(target) => write!(f, "attack {}", target),
+            Order::AttackMove(position) => write!(f, "attack_move {}", point(position)),
+            Order::Patrol(position) => write!(f, "patrol {}", point(position)),
+            Order::Stop => write!(f, "stop"),
+            Order::HoldPosition => write!(f, "hold"),
+            Order::Gather(node) => write!(f, "gather {}", node),
+            Order::Build { kind, position } => write!(f, "build {} {}", kind, point(position)),
+        }
+    }
+}
+
+fn parse_point(args: &[&str]) -> Result<Point3<f32>, OrderError> {
+    let mut coordinates = [0.0; 3];
+    for (coordinate, arg) in coordinates.iter_mut().zip(args) {
+        *coordinate = arg.parse().map_err(|_| OrderError::BadArgument(format!("invalid coordinate `{}`", arg)))?;
+    }
+    Ok(coordinates.into())
+}
+
+fn parse_entity(source: &str) -> Result<Entity, OrderError> {
+    let error = || OrderError::BadArgument(format!("invalid entity `{}`", source));
+    let (index, generation) = source.split_once('v').ok_or_else(error)?;
+    Ok(Entity::from_raw(index.parse().map_err(|_| error())?, generation.parse().map_err(|_| error())?))
+}
+
+/// An order for a group of units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Command {
+    pub units: Vec<Entity>,
+    pub order: Order,
+    /// Whether the order goes after the units' current orders instead of
+    /// replacing them.
+    pub queued: bool,
+}
+
+impl Command {
+    /// Parses one command in the format `Display` writes.
+    pub fn parse(source: &str) -> Result<Self, OrderError> {
+        let (order, units) = source.split_once('@').ok_or(OrderError::MissingUnits)?;
+        let order = order.trim();
+        let (queued, order) = match order.strip_prefix("queue ") {
+            Some(order) => (true, order),
+            None => (false, order),
+        };
+        let units = units.split_whitespace().map(parse_entity).collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { units, order: Order::parse(order)?, queued })
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.queued {
+            write!(f, "queue ")?;
+        }
+        write!(f, "{} @", self.order)?;
+        for unit in &self.units {
+            write!(f, " {}", unit)?;
+        }
+        Ok(())
+    }
+}
+
+/// Resource of commands issued since the last tick.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PendingCommands(pub Vec<Command>);
+
+/// Queues `command` for the next tick.
+pub fn issue(world: &mut World, command: Command) {
+    if world.resource::<PendingCommands>().is_none() {
+        world.insert_resource(PendingCommands::default());
+    }
+    world.resource_mut::<PendingCommands>().unwrap().0.push(command);
+}
+
+/// Side a unit fights for. Units of different teams attack each other.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Team(pub u8);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+}
+
+/// Lets a unit follow orders that move it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mover {
+    /// World units per second.
+    pub speed: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Weapon {
+    pub range: f32,
+    pub damage: f32,
+    /// Seconds between shots.
+    pub cooldown: f32,
+    /// Seconds until the next shot.
+    pub ready_in: f32,
+}
+
+impl Weapon {
+    pub fn new(range: f32, damage: f32, cooldown: f32) -> Self {
+        Self { range, damage, cooldown, ready_in: 0.0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Gatherer {
+    pub capacity: f32,
+    /// Resources picked up per second.
+    pub rate: f32,
+    pub carried: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ResourceNode {
+    /// Despawned once it reaches zero.
+    pub remaining: f32,
+}
+
+/// Where gatherers of the same team deliver what they carry.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Dropoff;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Builder {
+    /// Construction progress per second, where 1 is finished.
+    pub rate: f32,
+}
+
+/// A building going up. Game code decides what a `kind` turns into once
+/// `progress` reaches 1.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Construction {
+    pub kind: u32,
+    pub progress: f32,
+}
+
+impl Construction {
+    pub fn is_finished(&self) -> bool {
+        self.progress >= 1.0
+    }
+}
+
+/// Resource of the `Footprint` half size of each building kind, indexed by
+/// kind. Construction sites of kinds beyond it don't block the `NavGrid`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BuildingFootprints(pub Vec<f32>);
+
+/// Resource of what each team's gatherers have delivered.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stockpile {
+    amounts: Vec<f32>,
+}
+
+impl Stockpile {
+    pub fn get(&self, team: Team) -> f32 {
+        self.amounts.get(team.0 as usize).copied().unwrap_or(0.0)
+    }
+
+    pub fn add(&mut self, team: Team, amount: f32) {
+        let index = team.0 as usize;
+        if self.amounts.len() <= index {
+            self.amounts.resize(index + 1, 0.0);
+        }
+        self.amounts[index] += amount;
+    }
+}
+
+/// What a unit is doing about its current order.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum UnitState {
+    #[default]
+    Idle,
+    Moving,
+    Attacking(Entity),
+    Holding,
+    Gathering,
+    /// Carrying resources to a dropoff.
+    Returning,
+    Building(Entity),
+}
+
+/// A unit's order queue; the front order is the one being carried out.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Orders {
+    queue: VecDeque<Order>,
+    state: UnitState,
+}
+
+impl Orders {
+    pub fn current(&self) -> Option<&Order> {
+        self.queue.front()
+    }
+
+    /// The current order followed by the queued ones.
+    pub fn queue(&self) -> &VecDeque<Order> {
+        &self.queue
+    }
+
+    pub fn state(&self) -> UnitState {
+        self.state
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Takes an order, either replacing the queue or going after it. The
+    /// unit's `position` is where a first patrol leg starts from.
+    pub fn give(&mut self, order: Order, queued: bool, position: Point3<f32>) {
+        if !queued {
+            self.queue.clear();
+            self.state = UnitState::Idle;
+        }
+        match order {
+            Order::Stop if !queued => {}
+            Order::Patrol(point) => {
+                if let Some(Order::Patrol(_)) = self.queue.back() {
+                    // Extends the loop, keeping the leg back to its start last.
+                    self.queue.insert(self.queue.len() - 1, Order::Patrol(point));
+                } else {
+                    let start = self.queue.iter().rev().find_map(|order| match *order {
+                        Order::Move(position) | Order::AttackMove(position) | Order::Build { position, .. } => Some(position),
+                        _ => None,
+                    });
+                    self.queue.push_back(Order::Patrol(point));
+                    self.queue.push_back(Order::Patrol(start.unwrap_or(position)));
+                }
+            }
+            order => self.queue.push_back(order),
+        }
+    }
+}
+
+/// Hands out the pending commands and advances every unit's current order by
+/// one tick. Add it to the schedule; it reads `Tick` for the time step and
+/// follows the ground of a `Heightmap` resource when there is one.
+pub fn order_system(world: &mut World) {
+    let delta = world.resource::<Tick>().map_or(0.0, |tick| tick.delta);
+    world.query::<&mut Weapon>(|_, weapon| weapon.ready_in = (weapon.ready_in - delta).max(0.0));
+
+    let commands = world.resource_mut::<PendingCommands>().map(|mut pending| std::mem::take(&mut pending.0)).unwrap_or_default();
+    for command in &commands {
+        for &unit in &command.units {
+            let here = match position(world, unit) {
+                Some(here) => here,
+                None => continue,
+            };
+            if let Some(mut orders) = world.get_mut::<Orders>(unit) {
+                orders.give(command.order.clone(), command.queued, here);
+            }
+        }
+    }
+
+    for unit in world.matching::<With<Orders>>() {
+        // Earlier units may have killed this one.
+        if world.is_alive(unit) {
+            step(world, unit, delta);
+        }
+    }
+}
+
+fn step(world: &mut World, unit: Entity, delta: f32) {
+    let (order, state) = match world.get::<Orders>(unit) {
+        Some(orders) => (orders.queue.front().cloned(), orders.state),
+        None => return,
+    };
+    let order = match order {
+        Some(order) => order,
+        None => return set_state(world, unit, UnitState::Idle),
+    };
+    match order {
+        Order::Move(target) => {
+            set_state(world, unit, UnitState::Moving);
+            if move_towards(world, unit, target, ARRIVE_DISTANCE, delta) {
+                finish(world, unit);
+            }
+        }
+        Order::AttackMove(target) | Order::Patrol(target) => {
+            if let Some(enemy) = acquire(world, unit, ACQUIRE_RANGES) {
+                return engage(world, unit, enemy, true, delta);
+            }
+            set_state(world, unit, UnitState::Moving);
+            if move_towards(world, unit, target, ARRIVE_DISTANCE, delta) {
+                if let Order::Patrol(_) = order {
+                    // Only the loop turns; orders queued after it stay behind it.
+                    let mut orders = world.get_mut::<Orders>(unit).unwrap();
+                    let patrol = orders.queue.iter().take_while(|order| matches!(order, Order::Patrol(_))).count();
+                    orders.queue.make_contiguous()[..patrol].rotate_left(1);
+                } else {
+                    finish(world, unit);
+                }
+            }
+        }
+        Order::Attack(target) => {
+            if !world.has::<Health>(target) || !world.has::<Weapon>(unit) {
+                return finish(world, unit);
+            }
+            engage(world, unit, target, true, delta);
+        }
+        Order::Stop => {
+            let mut orders = world.get_mut::<Orders>(unit).unwrap();
+            orders.queue.clear();
+            orders.state = UnitState::Idle;
+        }
+        Order::HoldPosition => match acquire(world, unit, 1.0) {
+            Some(enemy) => engage(world, unit, enemy, false, delta),
+            None => set_state(world, unit, UnitState::Holding),
+        },
+        Order::Gather(node) => gather(world, unit, node, delta),
+        Order::Build { kind, position } => build(world, unit, state, kind, position, delta),
+    }
+}
+
+/// Moves into range of `target` if `chase` allows, and shoots when ready.
+fn engage(world: &mut World, unit: Entity, target: Entity, chase: bool, delta: f32) {
+    set_state(world, unit, UnitState::Attacking(target));
+    let (range, damage, cooldown, ready) = match world.get::<Weapon>(unit) {
+        Some(weapon) => (weapon.range, weapon.damage, weapon.cooldown, weapon.ready_in <= 0.0),
+        None => return,
+    };
+    let (here, there) = match (position(world, unit), position(world, target)) {
+        (Some(here), Some(there)) => (here, there),
+        _ => return,
+    };
+    if flat_distance(here, there) > range {
+        if chase {
+            move_towards(world, unit, there, range, delta);
+        }
+        return;
+    }
+    face(world, unit, there);
+    if !ready {
+        return;
+    }
+    world.get_mut::<Weapon>(unit).unwrap().ready_in = cooldown;
+    let dead = match world.get_mut::<Health>(target) {
+        Some(mut health) => {
+            health.current -= damage;
+            health.current <= 0.0
+        }
+        None => false,
+    };
+    if dead {
+        scene::despawn_recursive(world, target);
+    }
+}
+
+fn gather(world: &mut World, unit: Entity, node: Entity, delta: f32) {
+    let (capacity, rate, carried) = match world.get::<Gatherer>(unit) {
+        Some(gatherer) => (gatherer.capacity, gatherer.rate, gatherer.carried),
+        None => return finish(world, unit),
+    };
+    let node_left = world.get::<ResourceNode>(node).is_some_and(|node| node.remaining > 0.0);
+    if carried >= capacity || (!node_left && carried > 0.0) {
+        let own = team(world, unit);
+        let dropoff = nearest(world, unit, |world, entity| world.has::<Dropoff>(entity) && team(world, entity) == own);
+        let dropoff = match dropoff {
+            Some(dropoff) => dropoff,
+            // Nowhere to deliver to, so wait with a full load.
+            None => return set_state(world, unit, UnitState::Idle),
+        };
+        set_state(world, unit, UnitState::Returning);
+        let target = position(world, dropoff).unwrap();
+        if move_towards(world, unit, target, WORK_DISTANCE, delta) {
+            world.get_mut::<Gatherer>(unit).unwrap().carried = 0.0;
+            if let Some(team) = own {
+                if world.resource::<Stockpile>().is_none() {
+                    world.insert_resource(Stockpile::default());
+                }
+                world.resource_mut::<Stockpile>().unwrap().add(team, carried);
+            }
+            if !node_left {
+                finish(world, unit);
+            }
+        }
+        return;
+    }
+    if !node_left {
+        return finish(world, unit);
+    }
+    set_state(world, unit, UnitState::Gathering);
+    let target = position(world, node).unwrap();
+    if !move_towards(world, unit, target, WORK_DISTANCE, delta) {
+        return;
+    }
+    let depleted = {
+        let mut resource = world.get_mut::<ResourceNode>(node).unwrap();
+        let amount = (rate * delta).min(capacity - carried).min(resource.remaining);
+        resource.remaining -= amount;
+        world.get_mut::<Gatherer>(unit).unwrap().carried += amount;
+        resource.remaining <= 0.0
+    };
+    if depleted {
+        scene::despawn_recursive(world, node);
+    }
+}
+
+fn build(world: &mut World, unit: Entity, state: UnitState, kind: u32, position: Point3<f32>, delta: f32) {
+    let rate = match world.get::<Builder>(unit) {
+        Some(builder) => builder.rate,
+        None => return finish(world, unit),
+    };
+    if !move_towards(world, unit, position, WORK_DISTANCE, delta) {
+        return set_state(world, unit, UnitState::Moving);
+    }
+    let site = match state {
+        UnitState::Building(site) if world.has::<Construction>(site) => site,
+        _ => find_site(world, kind, position).unwrap_or_else(|| {
+            let team = team(world, unit);
+            let site = world.spawn_with((Transform::from_translation(position.to_vec()), Construction { kind, progress: 0.0 }));
+            if let Some(team) = team {
+                world.insert(site, team);
+            }
+            let half_size = world.resource::<BuildingFootprints>().and_then(|footprints| footprints.0.get(kind as usize).copied());
+            if let Some(half_size) = half_size {
+                world.insert(site, Footprint { half_size });
+            }
+            site
+        }),
+    };
+    set_state(world, unit, UnitState::Building(site));
+    let finished = {
+        let mut construction = world.get_mut::<Construction>(site).unwrap();
+        construction.progress = (construction.progress + rate * delta).min(1.0);
+        construction.is_finished()
+    };
+    if finished {
+        finish(world, unit);
+    }
+}
+
+/// An unfinished construction of `kind` at `position`, so builders sent to
+/// the same spot work together.
+fn find_site(world: &World, kind: u32, position: Point3<f32>) -> Option<Entity> {
+    let mut site = None;
+    world.query::<(&Construction, &Transform)>(|entity, (construction, transform)| {
+        let at = Point3::from_vec(transform.translation);
+        if site.is_none() && construction.kind == kind && !construction.is_finished() && flat_distance(at, position) < ARRIVE_DISTANCE {
+            site = Some(entity);
+        }
+    });
+    site
+}
+
+/// The nearest enemy within `ranges` weapon ranges.
+fn acquire(world: &World, unit: Entity, ranges: f32) -> Option<Entity> {
+    let range = world.get::<Weapon>(unit)?.range * ranges;
+    let team = team(world, unit);
+    let here = position(world, unit)?;
+    let mut nearest: Option<(Entity, f32)> = None;
+    world.query::<(&Health, &Transform)>(|entity, (_, transform)| {
+        if entity == unit || self::team(world, entity) == team {
+            return;
+        }
+        let distance = flat_distance(here, Point3::from_vec(transform.translation));
+        if distance <= range && nearest.is_none_or(|(_, nearest)| distance < nearest) {
+            nearest = Some((entity, distance));
+        }
+    });
+    nearest.map(|(entity, _)| entity)
+}
+
+/// The nearest entity passing `filter`.
+fn nearest(world: &World, unit: Entity, filter: impl Fn(&World, Entity) -> bool) -> Option<Entity> {
+    let here = position(world, unit)?;
+    let mut nearest: Option<(Entity, f32)> = None;
+    world.query::<&Transform>(|entity, transform| {
+        if entity == unit || !filter(world, entity) {
+            return;
+        }
+        let distance = flat_distance(here, Point3::from_vec(transform.translation));
+        if nearest.is_none_or(|(_, nearest)| distance < nearest) {
+            nearest = Some((entity, distance));
+        }
+    });
+    nearest.map(|(entity, _)| entity)
+}
+
+/// Moves the unit over the ground until it is within `stop_distance` of
+/// `target`, returning whether it is. With a `NavGrid` the unit follows a
+/// path around obstacles. Units without a `Mover` never get closer.
+fn move_towards(world: &mut World, unit: Entity, target: Point3<f32>, stop_distance: f32, delta: f32) -> bool {
+    let here = match position(world, unit) {
+        Some(here) => here,
+        None => return false,
+    };
+    if flat_distance(here, target) <= stop_distance {
+        return true;
+    }
+    let speed = match world.get::<Mover>(unit) {
+        Some(mover) => mover.speed,
+        None => return false,
+    };
+    let waypoint = navigation::steer(world, unit, here, target, ARRIVE_DISTANCE);
+    let offset = Vector3::new(waypoint.x - here.x, 0.0, waypoint.z - here.z);
+    let distance = offset.magnitude();
+    if distance <= 0.0 {
+        return false;
+    }
+    // Only the last leg stops short.
+    let wanted = if waypoint == target { distance - stop_distance } else { distance };
+    let travel = (speed * delta).min(wanted);
+    let mut next = here + offset / distance * travel;
+    if let Some(heightmap) = world.resource::<Heightmap>() {
+        // Keeps the unit's height above the ground.
+        next.y += heightmap.height_at(next.x, next.z) - heightmap.height_at(here.x, here.z);
+    }
+    world.get_mut::<Transform>(unit).unwrap().translation = next.to_vec();
+    face(world, unit, waypoint);
+    flat_distance(next, target) <= stop_distance + 1e-4
+}
+
+/// Turns the unit about the vertical axis so its +Z side faces `target`.
+fn face(world: &World, unit: Entity, target: Point3<f32>) {
+    if let Some(mut transform) = world.get_mut::<Transform>(unit) {
+        let offset = target - Point3::from_vec(transform.translation);
+        if offset.x != 0.0 || offset.z != 0.0 {
+            transform.rotation = Quaternion::from_angle_y(Rad(offset.x.atan2(offset.z)));
+        }
+    }
+}
+
+fn finish(world: &World, unit: Entity) {
+    if let Some(mut orders) = world.get_mut::<Orders>(unit) {
+        orders.queue.pop_front();
+        orders.state = UnitState::Idle;
+    }
+}
+
+fn set_state(world: &World, unit: Entity, state: UnitState) {
+    if let Some(mut orders) = world.get_mut::<Orders>(unit) {
+        orders.state = state;
+    }
+}
+
+fn position(world: &World, entity: Entity) -> Option<Point3<f32>> {
+    world.get::<Transform>(entity).map(|transform| Point3::from_vec(transform.translation))
+}
+
+fn team(world: &World, entity: Entity) -> Option<Team> {
+    world.get::<Team>(entity).map(|team| *team)
+}
+
+fn flat_distance(a: Point3<f32>, b: Point3<f32>) -> f32 {
+    (a.x - b.x).hypot(a.z - b.z)
+}
+
+/// An order waiting for a click to say where or whom.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OrderMode {
+    Attack,
+    Patrol,
+    Gather,
+    Build(u32),
+}
+
+/// Turns the order actions into commands for the selected units of `team`.
+/// `command` gives the order that suits what is under the cursor; the order
+/// actions pick one and wait for a `select` click on the target, or for
+/// `command` to cancel. Holding `queue_modifier` queues the order and keeps
+/// waiting for more targets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderInput {
+    pub team: Team,
+    /// What `order.build` puts up.
+    pub build_kind: u32,
+    targeting: Option<OrderMode>,
+}
+
+impl OrderInput {
+    pub fn new(team: Team) -> Self {
+        Self { team, build_kind: 0, targeting: None }
+    }
+
+    pub fn targeting(&self) -> Option<OrderMode> {
+        self.targeting
+    }
+
+    /// Waits for a target as if the order's action was pressed, e.g. from a
+    /// UI button.
+    pub fn set_targeting(&mut self, mode: Option<OrderMode>) {
+        self.targeting = mode;
+    }
+
+    /// Reads this frame's input. Returns the command to issue, if any.
+    pub fn update(&mut self, world: &World, input: &Input, camera: &Camera, heightmap: &Heightmap, screen_size: [f32; 2]) -> Option<Command> {
+        let units: Vec<Entity> = selection::selected(world)
+            .into_iter()
+            .filter(|&unit| world.has::<Orders>(unit) && team(world, unit) == Some(self.team))
+            .collect();
+        if units.is_empty() {
+            self.targeting = None;
+            return None;
+        }
+        let queued = input.held("queue_modifier");
+        let command = |order| Some(Command { units: units.clone(), order, queued });
+        if input.pressed("order.stop") {
+            self.targeting = None;
+            return command(Order::Stop);
+        }
+        if input.pressed("order.hold") {
+            self.targeting = None;
+            return command(Order::HoldPosition);
+        }
+        for (action, mode) in [
+            ("order.attack", OrderMode::Attack),
+            ("order.patrol", OrderMode::Patrol),
+            ("order.gather", OrderMode::Gather),
+            ("order.build", OrderMode::Build(self.build_kind)),
+        ] {
+            if input.pressed(action) {
+                self.targeting = Some(mode);
+            }
+        }
+
+        let cursor = input.cursor()?;
+        let ray = camera.screen_ray(cursor, screen_size);
+        let ground = || heightmap.raycast(&ray).map(|distance| ray.at(distance));
+        let unit = || selection::pick(world, camera, heightmap, screen_size, cursor);
+        let own = Some(self.team);
+        let is_enemy = |target: Entity| world.has::<Health>(target) && team(world, target) != own;
+        let mode = match self.targeting {
+            Some(_) if input.pressed("command") => {
+                self.targeting = None;
+                return None;
+            }
+            Some(mode) if input.pressed("select") => {
+                if !queued {
+                    self.targeting = None;
+                }
+                mode
+            }
+            Some(_) => return None,
+            None if input.pressed("command") => {
+                let order = match unit() {
+                    Some(target) if is_enemy(target) => Order::Attack(target),
+                    Some(target) if world.has::<ResourceNode>(target) => Order::Gather(target),
+                    Some(target) => Order::Move(position(world, target)?),
+                    None => Order::Move(ground()?),
+                };
+                return command(order);
+            }
+            None => return None,
+        };
+        let order = match mode {
+            OrderMode::Attack => match unit() {
+                Some(target) if is_enemy(target) => Order::Attack(target),
+                _ => Order::AttackMove(ground()?),
+            },
+            OrderMode::Patrol => Order::Patrol(ground()?),
+            OrderMode::Gather => match unit() {
+                Some(target) if world.has::<ResourceNode>(target) => Order::Gather(target),
+                _ => return None,
+            },
+            OrderMode::Build(kind) => Order::Build { kind, position: ground()? },
+        };
+        command(order)
+    }
+}
+
+impl Default for OrderInput {
+    fn default() -> Self {
+        Self::new(Team(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: f32 = 0.1;
+
+    fn point(x: f32, z: f32) -> Point3<f32> {
+        Point3::new(x, 0.0, z)
+    }
+
+    fn at(x: f32, z: f32) -> Transform {
+        Transform::from_translation(Vector3::new(x, 0.0, z))
+    }
+
+    /// Runs `order_system` for `ticks` ticks of `TICK` seconds.
+    fn run(world: &mut World, ticks: u64) {
+        for number in 0..ticks {
+            world.insert_resource(Tick { number, delta: TICK });
+            order_system(world);
+        }
+    }
+
+    /// Runs ticks until `unit` has no orders left, returning how many it took.
+    fn run_until_idle(world: &mut World, unit: Entity) -> u64 {
+        for ticks in 1..=1000 {
+            run(world, 1);
+            if world.get::<Orders>(unit).unwrap().is_idle() {
+                return ticks;
+            }
+        }
+        panic!("{} never finished its orders", unit);
+    }
+
+    fn command(order: Order, units: &[Entity]) -> Command {
+        Command { units: units.to_vec(), order, queued: false }
+    }
+
+    fn here(world: &World, unit: Entity) -> Point3<f32> {
+        position(world, unit).unwrap()
+    }
+
+    #[test]
+    fn orders_round_trip_through_display() {
+        let target = Entity::from_raw(12, 1);
+        let orders = [
+            Order::Move(Point3::new(1.5, 0.0, -2.0)),
+            Order::Attack(target),
+            Order::AttackMove(point(0.25, 3.0)),
+            Order::Patrol(point(-1.0, 1e-3)),
+            Order::Stop,
+            Order::HoldPosition,
+            Order::Gather(target),
+            Order::Build { kind: 2, position: point(4.0, 5.0) },
+        ];
+        for order in &orders {
+            assert_eq!(Order::parse(&order.to_string()).as_ref(), Ok(order));
+        }
+
+        let source = "move 1.5 0 -2 @ 3v0 4v0";
+        let command = Command::parse(source).unwrap();
+        assert_eq!(command.units, vec![Entity::from_raw(3, 0), Entity::from_raw(4, 0)]);
+        assert!(!command.queued);
+        assert_eq!(command.to_string(), source);
+        let queued = Command::parse("queue attack 12v1 @ 3v0").unwrap();
+        assert_eq!(queued, Command { units: vec![Entity::from_raw(3, 0)], order: Order::Attack(target), queued: true });
+        assert_eq!(Command::parse(&queued.to_string()), Ok(queued));
+    }
+
+    #[test]
+    fn bad_orders_are_rejected() {
+        assert_eq!(Order::parse("fly 1 2 3"), Err(OrderError::UnknownOrder("fly".into())));
+        assert_eq!(Order::parse(""), Err(OrderError::UnknownOrder(String::new())));
+        let bad_argument = |source| matches!(Order::parse(source), Err(OrderError::BadArgument(_)));
+        assert!(bad_argument("move 1 2"));
+        assert!(bad_argument("move 1 up 3"));
+        assert!(bad_argument("stop now"));
+        assert!(bad_argument("attack 12"));
+        assert!(bad_argument("gather 1vx"));
+        assert!(bad_argument("build house 1 2 3"));
+        assert_eq!(Command::parse("stop"), Err(OrderError::MissingUnits));
+        assert!(matches!(Command::parse("stop @ 3"), Err(OrderError::BadArgument(_))));
+        assert!(matches!(Command::parse("queue dance @ 3v0"), Err(OrderError::UnknownOrder(name)) if name == "dance"));
+    }
+
+    #[test]
+    fn giving_orders_replaces_or_queues() {
+        let here = point(0.0, 0.0);
+        let mut orders = Orders::default();
+        orders.give(Order::Move(point(1.0, 0.0)), false, here);
+        orders.give(Order::HoldPosition, true, here);
+        assert_eq!(orders.queue(), &[Order::Move(point(1.0, 0.0)), Order::HoldPosition]);
+        orders.give(Order::Move(point(2.0, 0.0)), false, here);
+        assert_eq!(orders.queue(), &[Order::Move(point(2.0, 0.0))]);
+        orders.give(Order::Stop, true, here);
+        assert_eq!(orders.queue(), &[Order::Move(point(2.0, 0.0)), Order::Stop]);
+        orders.give(Order::Stop, false, here);
+        assert!(orders.is_idle());
+        assert_eq!(orders.state(), UnitState::Idle);
+    }
+
+    #[test]
+    fn patrols_loop_back_to_where_they_started() {
+        let (a, b, c) = (point(1.0, 0.0), point(1.0, 1.0), point(0.0, 1.0));
+        let mut orders = Orders::default();
+        orders.give(Order::Patrol(a), false, point(0.0, 0.0));
+        assert_eq!(orders.queue(), &[Order::Patrol(a), Order::Patrol(point(0.0, 0.0))]);
+        orders.give(Order::Patrol(b), true, point(5.0, 5.0));
+        assert_eq!(orders.queue(), &[Order::Patrol(a), Order::Patrol(b), Order::Patrol(point(0.0, 0.0))]);
+
+        // A patrol queued after a move starts where the move ends.
+        orders.give(Order::Move(c), false, point(0.0, 0.0));
+        orders.give(Order::Patrol(a), true, point(0.0, 0.0));
+        assert_eq!(orders.queue(), &[Order::Move(c), Order::Patrol(a), Order::Patrol(c)]);
+    }
+
+    #[test]
+    fn patrolling_turns_only_the_loop() {
+        let mut world = World::new();
+        let unit = world.spawn_with((at(0.0, 0.0), Mover { speed: 1.0 }, Orders::default()));
+        let (a, later) = (point(0.5, 0.0), point(3.0, 3.0));
+        issue(&mut world, command(Order::Patrol(a), &[unit]));
+        issue(&mut world, Command { units: vec![unit], order: Order::Move(later), queued: true });
+        run(&mut world, 1);
+        assert_eq!(world.get::<Orders>(unit).unwrap().queue(), &[Order::Patrol(a), Order::Patrol(point(0.0, 0.0)), Order::Move(later)]);
+        run(&mut world, 5);
+        assert_eq!(world.get::<Orders>(unit).unwrap().queue(), &[Order::Patrol(point(0.0, 0.0)), Order::Patrol(a), Order::Move(later)]);
+        run(&mut world, 4);
+        assert_eq!(world.get::<Orders>(unit).unwrap().queue()[0], Order::Patrol(a));
+        assert!(flat_distance(here(&world, unit), point(0.0, 0.0)) < 0.05);
+    }
+
+    #[test]
+    fn units_move_at_their_speed_and_then_idle() {
+        let mut world = World::new();
+        let unit = world.spawn_with((at(0.0, 0.0), Mover { speed: 1.0 }, Orders::default()));
+        let fixed = world.spawn_with((at(0.0, 0.0), Orders::default()));
+        issue(&mut world, command(Order::Move(point(0.0, 0.45)), &[unit, fixed]));
+        run(&mut world, 2);
+        assert_eq!(world.get::<Orders>(unit).unwrap().state(), UnitState::Moving);
+        assert!((here(&world, unit).z - 0.2).abs() < 1e-5);
+        assert_eq!(here(&world, fixed), point(0.0, 0.0), "no Mover, no movement");
+        assert_eq!(run_until_idle(&mut world, unit), 3);
+        assert!(flat_distance(here(&world, unit), point(0.0, 0.45)) <= ARRIVE_DISTANCE + 1e-4);
+        assert_eq!(world.get::<Orders>(unit).unwrap().state(), UnitState::Idle);
+    }
+
+    #[test]
+    fn attackers_chase_and_shoot_until_the_target_dies() {
+        let mut world = World::new();
+        let attacker = world.spawn_with((at(0.0, 0.0), Mover { speed: 1.0 }, Weapon::new(0.5, 4.0, 0.3), Team(0), Orders::default()));
+        let target = world.spawn_with((at(1.0, 0.0), Health::new(10.0), Team(1)));
+        issue(&mut world, command(Order::Attack(target), &[attacker]));
+        run(&mut world, 1);
+        assert_eq!(world.get::<Orders>(attacker).unwrap().state(), UnitState::Attacking(target));
+        run_until_idle(&mut world, attacker);
+        assert!(!world.is_alive(target));
+        assert!((here(&world, attacker).x - 0.5).abs() < 1e-4, "stopped at weapon range");
+        assert_eq!(world.get::<Orders>(attacker).unwrap().state(), UnitState::Idle);
+    }
+
+    #[test]
+    fn gatherers_carry_to_the_dropoff_until_the_node_runs_out() {
+        let mut world = World::new();
+        let gatherer = Gatherer { capacity: 2.0, rate: 10.0, carried: 0.0 };
+        let unit = world.spawn_with((at(0.0, 0.0), Mover { speed: 5.0 }, gatherer, Team(2), Orders::default()));
+        let node = world.spawn_with((at(1.0, 0.0), ResourceNode { remaining: 3.0 }));
+        world.spawn_with((at(-1.0, 0.0), Dropoff, Team(2)));
+        world.spawn_with((at(-0.5, 0.0), Dropoff, Team(1)));
+        issue(&mut world, command(Order::Gather(node), &[unit]));
+        let mut states = Vec::new();
+        for _ in 0..100 {
+            run(&mut world, 1);
+            let state = world.get::<Orders>(unit).unwrap().state();
+            if states.last() != Some(&state) {
+                states.push(state);
+            }
+        }
+        use UnitState::*;
+        assert_eq!(states, vec![Gathering, Returning, Gathering, Returning, Idle]);
+        assert!(!world.is_alive(node));
+        let stockpile = world.resource::<Stockpile>().unwrap();
+        assert!((stockpile.get(Team(2)) - 3.0).abs() < 1e-4);
+        assert_eq!(stockpile.get(Team(1)), 0.0);
+        assert_eq!(world.get::<Gatherer>(unit).unwrap().carried, 0.0);
+    }
+
+    #[test]
+    fn builders_share_a_site_until_it_is_finished() {
+        let mut world = World::new();
+        world.insert_resource(BuildingFootprints(vec![0.2]));
+        let builders: Vec<Entity> = (0..2)
+            .map(|i| world.spawn_with((at(i as f32 * 0.1, 0.0), Mover { speed: 1.0 }, Builder { rate: 0.5 }, Team(3), Orders::default())))
+            .collect();
+        let site_at = point(0.5, 0.0);
+        issue(&mut world, command(Order::Build { kind: 0, position: site_at }, &builders));
+        run(&mut world, 5);
+        let sites = world.matching::<With<Construction>>();
+        assert_eq!(sites.len(), 1);
+        let site = sites[0];
+        assert_eq!(world.get::<Orders>(builders[0]).unwrap().state(), UnitState::Building(site));
+        assert_eq!(world.get::<Footprint>(site).unwrap().half_size, 0.2);
+        assert_eq!(*world.get::<Team>(site).unwrap(), Team(3));
+
+        let ticks = run_until_idle(&mut world, builders[0]);
+        assert!(world.get::<Construction>(site).unwrap().is_finished());
+        // Two builders at 0.5 per second finish in about a second of work.
+        assert!(ticks < 10, "took {} more ticks", ticks);
+        assert!(world.get::<Orders>(builders[1]).unwrap().is_idle());
+    }
+}
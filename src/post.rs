@@ -0,0 +1,519 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+/// Format of the intermediate colour target the scene is rendered into.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const DEFAULT_LUT_SIZE: u32 = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PostSettings {
+    pub bloom: bool,
+    /// Exposed luminance above which pixels start to bloom.
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub exposure: f32,
+    /// ACES filmic tonemapping, otherwise colours are clamped.
+    pub tonemapping: bool,
+    pub color_grading: bool,
+    pub fxaa: bool,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.5,
+            exposure: 1.0,
+            tonemapping: true,
+            color_grading: true,
+            fxaa: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PostError {
+    /// LUTs are `size * size` by `size` strips of blue slices.
+    InvalidLut { width: u32, height: u32 },
+}
+
+impl std::fmt::Display for PostError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PostError::InvalidLut { width, height } => write!(
+                f,
+                "colour grading LUT must be N*N by N pixels, got {}x{}",
+                width, height
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PostError {}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct PostUniforms {
+    exposure: f32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    tonemapping: f32,
+    color_grading: f32,
+    output_srgb: f32,
+    lut_size: f32,
+    padding: f32,
+}
+
+unsafe impl Zeroable for PostUniforms{}
+unsafe impl Pod for PostUniforms{}
+
+impl PostUniforms {
+    fn new(settings: &PostSettings, output_format: wgpu::TextureFormat, lut_size: u32) -> Self {
+        let flag = |enabled: bool| if enabled { 1.0 } else { 0.0 };
+        Self {
+            exposure: settings.exposure,
+            bloom_threshold: settings.bloom_threshold,
+            bloom_intensity: if settings.bloom { settings.bloom_intensity } else { 0.0 },
+            tonemapping: flag(settings.tonemapping),
+            color_grading: flag(settings.color_grading),
+            output_srgb: flag(output_format.describe().srgb),
+            lut_size: lut_size as f32,
+            padding: 0.0,
+        }
+    }
+}
+
+/// Builds the neutral LUT strip, a starting point for authoring grades.
+pub fn identity_lut(size: u32) -> image::RgbaImage {
+    let scale = 255.0 / (size - 1) as f32;
+    image::RgbaImage::from_fn(size * size, size, |x, y| {
+        let channel = |v: u32| (v as f32 * scale).round() as u8;
+        image::Rgba([channel(x % size), channel(y), channel(x / size), 255])
+    })
+}
+
+/// Render targets and bind groups that depend on the screen size.
+struct PostTargets {
+    extent: wgpu::Extent3d,
+    hdr: Arc<wgpu::TextureView>,
+    bloom: [wgpu::TextureView; 2],
+    ldr: wgpu::TextureView,
+    bright_bind_group: wgpu::BindGroup,
+    blur_horizontal_bind_group: wgpu::BindGroup,
+    blur_vertical_bind_group: wgpu::BindGroup,
+    tonemap_bind_group: wgpu::BindGroup,
+    fxaa_bind_group: wgpu::BindGroup,
+}
+
+/// Resolves the HDR scene into the output target: bloom, exposure and
+/// tonemapping, colour grading, then FXAA.
+pub struct PostProcess {
+    settings: PostSettings,
+    output_format: wgpu::TextureFormat,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    source_layout: wgpu::BindGroupLayout,
+    tonemap_layout: wgpu::BindGroupLayout,
+    bright_pipeline: wgpu::RenderPipeline,
+    blur_horizontal_pipeline: wgpu::RenderPipeline,
+    blur_vertical_pipeline: wgpu::RenderPipeline,
+    tonemap_pipeline: wgpu::RenderPipeline,
+    fxaa_pipeline: wgpu::RenderPipeline,
+    lut: wgpu::TextureView,
+    lut_size: u32,
+    targets: PostTargets,
+}
+
+fn texture_entry(binding: u32, view_dimension: wgpu::TextureViewDimension) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStage::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(entry_point),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_fullscreen",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[format.into()],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+    })
+}
+
+fn create_target(device: &wgpu::Device, label: &str, width: u32, height: u32, format: wgpu::TextureFormat) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn upload_lut(device: &wgpu::Device, queue: &wgpu::Queue, image: &image::RgbaImage) -> Result<(wgpu::TextureView, u32), PostError> {
+    let (width, height) = image.dimensions();
+    if height < 2 || width != height * height {
+        return Err(PostError::InvalidLut { width, height });
+    }
+    let size = height;
+
+    // Rearrange the strip of blue slices into a 3D texture.
+    let mut texels = Vec::with_capacity((4 * size * size * size) as usize);
+    for blue in 0..size {
+        for green in 0..size {
+            for red in 0..size {
+                texels.extend_from_slice(&image.get_pixel(blue * size + red, green).0);
+            }
+        }
+    }
+
+    let extent = wgpu::Extent3d {
+        width: size,
+        height: size,
+        depth_or_array_layers: size,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Colour Grading LUT"),
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+    });
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        &texels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: std::num::NonZeroU32::new(4 * size),
+            rows_per_image: std::num::NonZeroU32::new(size),
+        },
+        extent,
+    );
+    Ok((texture.create_view(&wgpu::TextureViewDescriptor::default()), size))
+}
+
+impl PostProcess {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, output_format: wgpu::TextureFormat, extent: wgpu::Extent3d) -> Self {
+        let settings = PostSettings::default();
+        let (lut, lut_size) = upload_lut(device, queue, &identity_lut(DEFAULT_LUT_SIZE)).unwrap();
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[PostUniforms::new(&settings, output_format, lut_size)]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let sampler_entry = wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Sampler {
+                comparison: false,
+                filtering: true,
+            },
+            count: None,
+        };
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let source_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, wgpu::TextureViewDimension::D2),
+                sampler_entry,
+                uniform_entry,
+            ],
+            label: Some("post_source_bind_group_layout"),
+        });
+        let tonemap_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, wgpu::TextureViewDimension::D2),
+                sampler_entry,
+                uniform_entry,
+                texture_entry(3, wgpu::TextureViewDimension::D2),
+                texture_entry(4, wgpu::TextureViewDimension::D3),
+            ],
+            label: Some("tonemap_bind_group_layout"),
+        });
+
+        let bloom_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("bloom"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!("../res/shader/post.wgsl"), include_str!("../res/shader/bloom.wgsl")))),
+            flags: wgpu::ShaderFlags::all(),
+        });
+        let tonemap_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("tonemap"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!("../res/shader/post.wgsl"), include_str!("../res/shader/tonemap.wgsl")))),
+            flags: wgpu::ShaderFlags::all(),
+        });
+        let fxaa_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("fxaa"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!("../res/shader/post.wgsl"), include_str!("../res/shader/fxaa.wgsl")))),
+            flags: wgpu::ShaderFlags::all(),
+        });
+
+        let bright_pipeline = create_pipeline(device, &source_layout, &bloom_shader, "fs_bright", HDR_FORMAT);
+        let blur_horizontal_pipeline = create_pipeline(device, &source_layout, &bloom_shader, "fs_blur_horizontal", HDR_FORMAT);
+        let blur_vertical_pipeline = create_pipeline(device, &source_layout, &bloom_shader, "fs_blur_vertical", HDR_FORMAT);
+        // Tonemapping writes either straight to the output or to the FXAA
+        // input, which shares the output format.
+        let tonemap_pipeline = create_pipeline(device, &tonemap_layout, &tonemap_shader, "fs_main", output_format);
+        let fxaa_pipeline = create_pipeline(device, &source_layout, &fxaa_shader, "fs_main", output_format);
+
+        let targets = Self::create_targets(device, &source_layout, &tonemap_layout, &sampler, &uniform_buffer, &lut, output_format, extent);
+        Self {
+            settings,
+            output_format,
+            sampler,
+            uniform_buffer,
+            source_layout,
+            tonemap_layout,
+            bright_pipeline,
+            blur_horizontal_pipeline,
+            blur_vertical_pipeline,
+            tonemap_pipeline,
+            fxaa_pipeline,
+            lut,
+            lut_size,
+            targets,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_targets(
+        device: &wgpu::Device,
+        source_layout: &wgpu::BindGroupLayout,
+        tonemap_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+        lut: &wgpu::TextureView,
+        output_format: wgpu::TextureFormat,
+        extent: wgpu::Extent3d,
+    ) -> PostTargets {
+        let hdr = Arc::new(create_target(device, "HDR", extent.width, extent.height, HDR_FORMAT));
+        // Bloom runs at half resolution, which also widens the blur for free.
+        let bloom = [
+            create_target(device, "Bloom", extent.width / 2, extent.height / 2, HDR_FORMAT),
+            create_target(device, "Bloom", extent.width / 2, extent.height / 2, HDR_FORMAT),
+        ];
+        let ldr = create_target(device, "LDR", extent.width, extent.height, output_format);
+
+        let source_bind_group = |source: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: source_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("post_source_bind_group"),
+            })
+        };
+        let bright_bind_group = source_bind_group(&hdr);
+        let blur_horizontal_bind_group = source_bind_group(&bloom[0]);
+        let blur_vertical_bind_group = source_bind_group(&bloom[1]);
+        let fxaa_bind_group = source_bind_group(&ldr);
+        let tonemap_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: tonemap_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&bloom[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(lut),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        });
+
+        PostTargets {
+            extent,
+            hdr,
+            bloom,
+            ldr,
+            bright_bind_group,
+            blur_horizontal_bind_group,
+            blur_vertical_bind_group,
+            tonemap_bind_group,
+            fxaa_bind_group,
+        }
+    }
+
+    fn rebuild_targets(&mut self, device: &wgpu::Device, extent: wgpu::Extent3d) {
+        self.targets = Self::create_targets(
+            device,
+            &self.source_layout,
+            &self.tonemap_layout,
+            &self.sampler,
+            &self.uniform_buffer,
+            &self.lut,
+            self.output_format,
+            extent,
+        );
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, extent: wgpu::Extent3d) {
+        self.rebuild_targets(device, extent);
+    }
+
+    /// The HDR colour target scene passes render into.
+    pub fn hdr_view(&self) -> Arc<wgpu::TextureView> {
+        self.targets.hdr.clone()
+    }
+
+    pub fn settings(&self) -> &PostSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: PostSettings) {
+        self.settings = settings;
+        self.write_uniforms(queue);
+    }
+
+    /// Replaces the colour grading LUT, a strip of `size` blue slices each
+    /// `size` by `size` pixels (red across, green down), e.g. 256x16.
+    pub fn set_color_grading_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, image: &image::RgbaImage) -> Result<(), PostError> {
+        let (lut, lut_size) = upload_lut(device, queue, image)?;
+        self.lut = lut;
+        self.lut_size = lut_size;
+        self.write_uniforms(queue);
+        let extent = self.extent();
+        self.rebuild_targets(device, extent);
+        Ok(())
+    }
+
+    fn extent(&self) -> wgpu::Extent3d {
+        self.targets.extent
+    }
+
+    fn write_uniforms(&self, queue: &wgpu::Queue) {
+        let uniforms = PostUniforms::new(&self.settings, self.output_format, self.lut_size);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
+
+    fn fullscreen_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        target: &wgpu::TextureView,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    pub async fn draw(&self, device: &wgpu::Device, output: &wgpu::TextureView) -> wgpu::CommandBuffer {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("post") });
+        let targets = &self.targets;
+        if self.settings.bloom {
+            Self::fullscreen_pass(&mut encoder, "bloom bright", &targets.bloom[0], &self.bright_pipeline, &targets.bright_bind_group);
+            Self::fullscreen_pass(&mut encoder, "bloom blur horizontal", &targets.bloom[1], &self.blur_horizontal_pipeline, &targets.blur_horizontal_bind_group);
+            Self::fullscreen_pass(&mut encoder, "bloom blur vertical", &targets.bloom[0], &self.blur_vertical_pipeline, &targets.blur_vertical_bind_group);
+        }
+        if self.settings.fxaa {
+            Self::fullscreen_pass(&mut encoder, "tonemap", &targets.ldr, &self.tonemap_pipeline, &targets.tonemap_bind_group);
+            Self::fullscreen_pass(&mut encoder, "fxaa", output, &self.fxaa_pipeline, &targets.fxaa_bind_group);
+        } else {
+            Self::fullscreen_pass(&mut encoder, "tonemap", output, &self.tonemap_pipeline, &targets.tonemap_bind_group);
+        }
+        encoder.finish()
+    }
+}
@@ -0,0 +1,352 @@
+//! Parent/child transforms for entities. Game code edits the local
+//! `Transform`; `TransformPropagation` recomputes the world-space
+//! `GlobalTransform` of whatever changed and everything below it. Drawing
+//! between two ticks blends each `Transform` from its `PreviousTransform`.
+
+use cgmath::{Matrix4, Quaternion, SquareMatrix, Vector3, VectorSpace};
+
+use crate::ecs::{Added, ChangeTracker, Changed, Entity, With, Without, World};
+use crate::mesh::Instance;
+use crate::timestep::Lerp;
+
+/// Position, rotation and scale relative to the parent, or to the world for
+/// entities without a `Parent`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vector3::new(0.0, 0.0, 0.0),
+        rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        scale: Vector3::new(1.0, 1.0, 1.0),
+    };
+
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self { translation, ..Self::IDENTITY }
+    }
+
+    pub fn with_rotation(self, rotation: Quaternion<f32>) -> Self {
+        Self { rotation, ..self }
+    }
+
+    pub fn with_scale(self, scale: f32) -> Self {
+        Self { scale: Vector3::new(scale, scale, scale), ..self }
+    }
+
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Lerp for Transform {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: Lerp::lerp(&self.rotation, &other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+}
+
+/// `Transform` as of the start of the current tick, written by
+/// `snapshot_transforms`. Drawing between ticks blends from it to the
+/// current `Transform`; entities without one are drawn where they are.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PreviousTransform(pub Transform);
+
+/// Remembers every `Transform` before the tick changes it. Add it to the
+/// schedule ahead of anything that moves entities.
+pub fn snapshot_transforms(world: &mut World) {
+    world.query::<(&Transform, &mut PreviousTransform)>(|_, (transform, previous)| previous.0 = *transform);
+    for entity in world.matching::<(With<Transform>, Without<PreviousTransform>)>() {
+        let transform = *world.get::<Transform>(entity).unwrap();
+        world.insert(entity, PreviousTransform(transform));
+    }
+}
+
+/// World-space transform, written by `TransformPropagation`. Inserted
+/// automatically on entities with a `Transform`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GlobalTransform(pub Matrix4<f32>);
+
+impl GlobalTransform {
+    pub fn translation(&self) -> Vector3<f32> {
+        self.0.w.truncate()
+    }
+}
+
+impl Default for GlobalTransform {
+    fn default() -> Self {
+        Self(Matrix4::identity())
+    }
+}
+
+/// The entity this one is attached to. Change it with `set_parent` and
+/// `remove_parent` so `Children` stays in sync.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Parent(pub Entity);
+
+/// Entities attached to this one, in the order they were attached.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Children(pub Vec<Entity>);
+
+/// Attaches `child` to `parent`, detaching it from any previous parent. Its
+/// `Transform` becomes relative to the parent.
+pub fn set_parent(world: &mut World, child: Entity, parent: Entity) {
+    assert_ne!(child, parent, "an entity can't be its own parent");
+    remove_parent(world, child);
+    // The old local transform was relative to something else.
+    world.remove::<PreviousTransform>(child);
+    world.insert(child, Parent(parent));
+    if let Some(mut children) = world.get_mut::<Children>(parent) {
+        children.0.push(child);
+        return;
+    }
+    world.insert(parent, Children(vec![child]));
+}
+
+/// Detaches `child`, leaving its `Transform` relative to the world.
+pub fn remove_parent(world: &mut World, child: Entity) {
+    if let Some(Parent(parent)) = world.remove::<Parent>(child) {
+        if let Some(mut children) = world.get_mut::<Children>(parent) {
+            children.0.retain(|&entity| entity != child);
+        }
+        // Nothing else marks a removed parent as a change.
+        drop(world.get_mut::<Transform>(child));
+        world.remove::<PreviousTransform>(child);
+    }
+}
+
+/// Despawns `entity` and everything attached below it.
+pub fn despawn_recursive(world: &mut World, entity: Entity) {
+    remove_parent(world, entity);
+    let mut stack = vec![entity];
+    while let Some(entity) = stack.pop() {
+        if let Some(children) = world.remove::<Children>(entity) {
+            stack.extend(children.0);
+        }
+        world.despawn(entity);
+    }
+}
+
+/// Keeps `GlobalTransform`s up to date, only touching subtrees whose
+/// `Transform` or `Parent` changed since the last run. Entities whose parent
+/// was despawned without `despawn_recursive` are detached and become roots.
+#[derive(Clone, Debug, Default)]
+pub struct TransformPropagation {
+    tracker: ChangeTracker,
+}
+
+impl TransformPropagation {
+    pub fn run(&mut self, world: &mut World) {
+        self.tracker.run(world, |world| {
+            for entity in world.matching::<(With<Transform>, Without<GlobalTransform>)>() {
+                world.insert(entity, GlobalTransform::default());
+            }
+            let orphans: Vec<Entity> = world
+                .matching::<With<Parent>>()
+                .into_iter()
+                .filter(|&entity| world.get::<Parent>(entity).is_some_and(|parent| !world.is_alive(parent.0)))
+                .collect();
+            for &orphan in &orphans {
+                world.remove::<Parent>(orphan);
+            }
+            let mut dirty = orphans;
+            dirty.extend(world.matching::<Changed<Transform>>());
+            dirty.extend(world.matching::<Changed<Parent>>());
+            dirty.extend(world.matching::<Added<GlobalTransform>>());
+            dirty.sort_unstable();
+            dirty.dedup();
+
+            let mut stack: Vec<(Entity, Matrix4<f32>, bool)> = world
+                .matching::<(With<Transform>, Without<Parent>)>()
+                .into_iter()
+                .map(|root| (root, Matrix4::identity(), false))
+                .collect();
+            while let Some((entity, parent, parent_dirty)) = stack.pop() {
+                let is_dirty = parent_dirty || dirty.binary_search(&entity).is_ok();
+                let global = if is_dirty {
+                    let global = match world.get::<Transform>(entity) {
+                        Some(transform) => parent * transform.to_matrix(),
+                        None => continue,
+                    };
+                    if let Some(mut current) = world.get_mut::<GlobalTransform>(entity) {
+                        current.0 = global;
+                    }
+                    global
+                } else {
+                    match world.get::<GlobalTransform>(entity) {
+                        Some(global) => global.0,
+                        None => continue,
+                    }
+                };
+                if let Some(children) = world.get::<Children>(entity) {
+                    let alive = children.0.iter().rev().filter(|&&child| world.is_alive(child));
+                    stack.extend(alive.map(|&child| (child, global, is_dirty)));
+                }
+            }
+        });
+    }
+}
+
+/// Index of a mesh registered with `Autonomy::add_mesh`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MeshId(pub usize);
+
+/// Draws the entity at its `GlobalTransform` as an instance of a registered mesh.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MeshInstance {
+    pub mesh: MeshId,
+    pub color: [f32; 4],
+}
+
+/// World-space matrix of `entity` `alpha` of the way from the previous tick
+/// to the current one, blending every `Transform` up its parent chain.
+pub fn interpolated_matrix(world: &World, entity: Entity, alpha: f32) -> Option<Matrix4<f32>> {
+    let mut matrix = Matrix4::identity();
+    let mut next = Some(entity);
+    while let Some(entity) = next {
+        let transform = *world.get::<Transform>(entity)?;
+        let blended = match world.get::<PreviousTransform>(entity) {
+            Some(previous) => Lerp::lerp(&previous.0, &transform, alpha),
+            None => transform,
+        };
+        matrix = blended.to_matrix() * matrix;
+        next = world.get::<Parent>(entity).map(|parent| parent.0).filter(|&parent| world.is_alive(parent));
+    }
+    Some(matrix)
+}
+
+/// Collects the per-instance data of every visible entity into one list per
+/// mesh, indexed by `MeshId`, placed `alpha` of the way between the last two
+/// ticks. Lists are cleared first and grown as needed.
+pub fn gather_instances(world: &World, alpha: f32, batches: &mut Vec<Vec<Instance>>) {
+    for batch in batches.iter_mut() {
+        batch.clear();
+    }
+    world.query::<(&MeshInstance, &GlobalTransform)>(|entity, (instance, global)| {
+        let index = instance.mesh.0;
+        if batches.len() <= index {
+            batches.resize_with(index + 1, Vec::new);
+        }
+        let matrix = interpolated_matrix(world, entity, alpha).unwrap_or(global.0);
+        batches[index].push(Instance::new(matrix, instance.color));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(world: &World, entity: Entity) -> Vector3<f32> {
+        world.get::<GlobalTransform>(entity).unwrap().translation()
+    }
+
+    fn at(x: f32) -> Transform {
+        Transform::from_translation(Vector3::new(x, 0.0, 0.0))
+    }
+
+    #[test]
+    fn children_follow_their_parents() {
+        let mut world = World::new();
+        let mut propagation = TransformPropagation::default();
+        let root = world.spawn_with((at(1.0),));
+        let child = world.spawn_with((at(2.0),));
+        let grandchild = world.spawn_with((at(4.0),));
+        set_parent(&mut world, child, root);
+        set_parent(&mut world, grandchild, child);
+        propagation.run(&mut world);
+        assert_eq!(translation(&world, grandchild), Vector3::new(7.0, 0.0, 0.0));
+
+        world.get_mut::<Transform>(root).unwrap().translation.x = 11.0;
+        propagation.run(&mut world);
+        assert_eq!(translation(&world, grandchild), Vector3::new(17.0, 0.0, 0.0));
+
+        remove_parent(&mut world, child);
+        propagation.run(&mut world);
+        assert_eq!(translation(&world, child), Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(translation(&world, grandchild), Vector3::new(6.0, 0.0, 0.0));
+        assert_eq!(world.get::<Children>(root).unwrap().0, Vec::<Entity>::new());
+    }
+
+    #[test]
+    fn despawning_a_parent_directly_leaves_its_children_as_roots() {
+        let mut world = World::new();
+        let mut propagation = TransformPropagation::default();
+        let parent = world.spawn_with((at(1.0),));
+        let child = world.spawn_with((at(2.0),));
+        set_parent(&mut world, child, parent);
+        propagation.run(&mut world);
+        assert_eq!(translation(&world, child), Vector3::new(3.0, 0.0, 0.0));
+
+        world.despawn(parent);
+        propagation.run(&mut world);
+        assert!(!world.has::<Parent>(child));
+        assert_eq!(translation(&world, child), Vector3::new(2.0, 0.0, 0.0));
+
+        world.get_mut::<Transform>(child).unwrap().translation.x = 5.0;
+        propagation.run(&mut world);
+        assert_eq!(translation(&world, child), Vector3::new(5.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn despawn_recursive_takes_the_subtree() {
+        let mut world = World::new();
+        let root = world.spawn_with((at(0.0),));
+        let child = world.spawn_with((at(0.0),));
+        let grandchild = world.spawn_with((at(0.0),));
+        let other = world.spawn_with((at(0.0),));
+        set_parent(&mut world, child, root);
+        set_parent(&mut world, grandchild, child);
+        set_parent(&mut world, other, root);
+        despawn_recursive(&mut world, child);
+        assert!(!world.is_alive(child) && !world.is_alive(grandchild));
+        assert_eq!(world.get::<Children>(root).unwrap().0, vec![other]);
+    }
+
+    #[test]
+    fn drawing_blends_from_the_previous_tick() {
+        let mut world = World::new();
+        let mut propagation = TransformPropagation::default();
+        let parent = world.spawn_with((at(0.0),));
+        let child = world.spawn_with((at(1.0), MeshInstance { mesh: MeshId(1), color: [1.0; 4] }));
+        set_parent(&mut world, child, parent);
+        let drawn = |world: &World, entity, alpha| interpolated_matrix(world, entity, alpha).unwrap().w.truncate();
+        assert_eq!(drawn(&world, child, 0.5), Vector3::new(1.0, 0.0, 0.0), "nothing to blend from yet");
+
+        snapshot_transforms(&mut world);
+        world.get_mut::<Transform>(parent).unwrap().translation.x = 10.0;
+        world.get_mut::<Transform>(child).unwrap().translation.x = 3.0;
+        propagation.run(&mut world);
+        assert_eq!(drawn(&world, parent, 0.5), Vector3::new(5.0, 0.0, 0.0));
+        assert_eq!(drawn(&world, child, 0.5), Vector3::new(7.0, 0.0, 0.0));
+        assert_eq!(drawn(&world, child, 1.0), translation(&world, child));
+
+        let mut batches = Vec::new();
+        gather_instances(&world, 0.25, &mut batches);
+        assert_eq!((batches.len(), batches[0].len()), (2, 0));
+        let expected = Instance::new(interpolated_matrix(&world, child, 0.25).unwrap(), [1.0; 4]);
+        assert_eq!(bytemuck::cast_slice::<_, u8>(&batches[1]), bytemuck::bytes_of(&expected));
+
+        // A tick that doesn't move anything leaves nothing to blend.
+        snapshot_transforms(&mut world);
+        assert_eq!(drawn(&world, child, 0.5), Vector3::new(13.0, 0.0, 0.0));
+
+        // Reparenting changes what the local transform is relative to.
+        world.get_mut::<Transform>(child).unwrap().translation.x = 4.0;
+        remove_parent(&mut world, child);
+        assert_eq!(drawn(&world, child, 0.5), Vector3::new(4.0, 0.0, 0.0));
+    }
+}
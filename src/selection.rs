@@ -0,0 +1,433 @@
+//! Picking and selecting units with the mouse: clicks, drag rectangles,
+//! double-clicks and numbered control groups. Selected units carry the
+//! `Selected` marker so other code can query them.
+
+use cgmath::{EuclideanSpace, Point3};
+
+use crate::camera::Camera;
+use crate::ecs::{Entity, With, World};
+use crate::heightmap::Heightmap;
+use crate::input::Input;
+use crate::scene::GlobalTransform;
+use crate::ui::Rect;
+
+/// Control groups, numbered like the keys they are bound to.
+pub const CONTROL_GROUPS: usize = 10;
+
+/// A unit that can be clicked on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Selectable {
+    /// Radius of the pick sphere around the unit's `GlobalTransform`, and of
+    /// its selection circle.
+    pub radius: f32,
+    /// Units of the same kind are selected together by a double-click.
+    pub kind: u32,
+}
+
+/// Marks a selected unit.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Selected;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SelectionSettings {
+    /// Pixels the cursor must move with the button down to start a rectangle.
+    pub drag_threshold: f32,
+    /// Seconds between two clicks for them to count as a double-click.
+    pub double_click_time: f32,
+    /// Pixels the cursor may move between the clicks of a double-click.
+    pub double_click_distance: f32,
+}
+
+impl Default for SelectionSettings {
+    fn default() -> Self {
+        Self { drag_threshold: 4.0, double_click_time: 0.3, double_click_distance: 6.0 }
+    }
+}
+
+/// How a click or rectangle combines with the current selection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SelectMode {
+    Replace,
+    /// Shift: adds the units.
+    Add,
+    /// Ctrl: flips each unit in or out of the selection.
+    Toggle,
+}
+
+struct Click {
+    time: f32,
+    position: [f32; 2],
+    kind: Option<u32>,
+}
+
+/// Turns the `select` action and control group actions into changes to the
+/// `Selected` markers.
+pub struct Selection {
+    settings: SelectionSettings,
+    /// Seconds since creation, for timing double-clicks.
+    time: f32,
+    /// Where the `select` button went down, while it is held.
+    drag_start: Option<[f32; 2]>,
+    cursor: Option<[f32; 2]>,
+    last_click: Option<Click>,
+    groups: [Vec<Entity>; CONTROL_GROUPS],
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Self::new(SelectionSettings::default())
+    }
+}
+
+impl Selection {
+    pub fn new(settings: SelectionSettings) -> Self {
+        Self { settings, time: 0.0, drag_start: None, cursor: None, last_click: None, groups: Default::default() }
+    }
+
+    pub fn settings(&self) -> &SelectionSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: SelectionSettings) {
+        self.settings = settings;
+    }
+
+    /// Reads this frame's input and updates the selection. The camera and
+    /// heightmap are only used for picking.
+    pub fn update(&mut self, world: &mut World, input: &Input, camera: &Camera, heightmap: &Heightmap, screen_size: [f32; 2], delta: f32) {
+        self.time += delta;
+        self.cursor = input.cursor();
+        for group in &mut self.groups {
+            group.retain(|&entity| world.is_alive(entity));
+        }
+
+        let mode = if input.held("selection.toggle") {
+            SelectMode::Toggle
+        } else if input.held("selection.add") {
+            SelectMode::Add
+        } else {
+            SelectMode::Replace
+        };
+        if input.pressed("select") {
+            self.drag_start = self.cursor;
+        }
+        if input.released("select") {
+            if let (Some(start), Some(end)) = (self.drag_start.take(), self.cursor) {
+                if distance(start, end) >= self.settings.drag_threshold {
+                    let units = units_in_rect(world, camera, screen_size, Rect::from_corners(start, end));
+                    select(world, &units, mode);
+                    self.last_click = None;
+                } else {
+                    self.click(world, camera, heightmap, screen_size, end, mode);
+                }
+            }
+        }
+
+        for group in 0..CONTROL_GROUPS {
+            if input.pressed(&format!("group.set.{}", group)) {
+                self.groups[group] = selected(world);
+            } else if input.pressed(&format!("group.add.{}", group)) {
+                for entity in selected(world) {
+                    if !self.groups[group].contains(&entity) {
+                        self.groups[group].push(entity);
+                    }
+                }
+            } else if input.pressed(&format!("group.select.{}", group)) {
+                let units = self.groups[group].clone();
+                select(world, &units, SelectMode::Replace);
+            }
+        }
+    }
+
+    fn click(&mut self, world: &mut World, camera: &Camera, heightmap: &Heightmap, screen_size: [f32; 2], position: [f32; 2], mode: SelectMode) {
+        let unit = pick(world, camera, heightmap, screen_size, position);
+        let kind = unit.and_then(|unit| world.get::<Selectable>(unit).map(|selectable| selectable.kind));
+        let double = self.last_click.as_ref().is_some_and(|last| {
+            self.time - last.time <= self.settings.double_click_time
+                && distance(last.position, position) <= self.settings.double_click_distance
+                && last.kind.is_some()
+                && last.kind == kind
+        });
+        if double {
+            let kind = kind.unwrap();
+            let screen = Rect::new([0.0, 0.0], screen_size);
+            let mut units = units_in_rect(world, camera, screen_size, screen);
+            units.retain(|&unit| world.get::<Selectable>(unit).is_some_and(|selectable| selectable.kind == kind));
+            // The first click already toggled the unit under the cursor.
+            select(world, &units, if mode == SelectMode::Toggle { SelectMode::Add } else { mode });
+            self.last_click = None;
+            return;
+        }
+        match unit {
+            Some(unit) => select(world, &[unit], mode),
+            None if mode == SelectMode::Replace => select(world, &[], mode),
+            None => {}
+        }
+        self.last_click = Some(Click { time: self.time, position, kind });
+    }
+
+    /// The rectangle being dragged out, for drawing.
+    pub fn drag_rect(&self) -> Option<Rect> {
+        let (start, end) = (self.drag_start?, self.cursor?);
+        (distance(start, end) >= self.settings.drag_threshold).then(|| Rect::from_corners(start, end))
+    }
+
+    /// Units in control group `group`, `0..CONTROL_GROUPS`, minus any that
+    /// died since the last `update`.
+    pub fn group(&self, group: usize) -> &[Entity] {
+        &self.groups[group]
+    }
+
+    pub fn set_group(&mut self, group: usize, units: Vec<Entity>) {
+        self.groups[group] = units;
+    }
+}
+
+/// Selected units, in entity order.
+pub fn selected(world: &World) -> Vec<Entity> {
+    world.matching::<With<Selected>>()
+}
+
+/// Applies a selection change to `units`.
+pub fn select(world: &mut World, units: &[Entity], mode: SelectMode) {
+    if mode == SelectMode::Replace {
+        for entity in selected(world) {
+            if !units.contains(&entity) {
+                world.remove::<Selected>(entity);
+            }
+        }
+    }
+    for &unit in units {
+        if !world.has::<Selectable>(unit) {
+            continue;
+        }
+        if mode == SelectMode::Toggle && world.has::<Selected>(unit) {
+            world.remove::<Selected>(unit);
+        } else if !world.has::<Selected>(unit) {
+            world.insert(unit, Selected);
+        }
+    }
+}
+
+/// The nearest unit under a screen position that the ground doesn't hide.
+pub fn pick(world: &World, camera: &Camera, heightmap: &Heightmap, screen_size: [f32; 2], position: [f32; 2]) -> Option<Entity> {
+    let ray = camera.screen_ray(position, screen_size);
+    let ground = heightmap.raycast(&ray).unwrap_or(f32::INFINITY);
+    let mut nearest: Option<(Entity, f32)> = None;
+    world.query::<(&Selectable, &GlobalTransform)>(|entity, (selectable, global)| {
+        let center = Point3::from_vec(global.translation());
+        if let Some(distance) = ray.sphere_distance(center, selectable.radius) {
+            if distance <= ground && nearest.is_none_or(|(_, nearest)| distance < nearest) {
+                nearest = Some((entity, distance));
+            }
+        }
+    });
+    nearest.map(|(entity, _)| entity)
+}
+
+/// Units whose position projects inside a screen rectangle.
+pub fn units_in_rect(world: &World, camera: &Camera, screen_size: [f32; 2], rect: Rect) -> Vec<Entity> {
+    let mut units = Vec::new();
+    world.query::<(&Selectable, &GlobalTransform)>(|entity, (_, global)| {
+        let center = Point3::from_vec(global.translation());
+        if camera.world_to_screen(center, screen_size).is_some_and(|position| rect.contains(position)) {
+            units.push(entity);
+        }
+    });
+    units
+}
+
+fn distance(a: [f32; 2], b: [f32; 2]) -> f32 {
+    (a[0] - b[0]).hypot(a[1] - b[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Button, InputEvent};
+    use cgmath::{Matrix4, Vector3};
+    use winit::event::{ModifiersState, MouseButton, VirtualKeyCode};
+
+    const SCREEN: [f32; 2] = [200.0, 200.0];
+
+    /// A camera looking down at flat ground at height 0, and the input and
+    /// selection state that `Selection::update` runs on.
+    struct Fixture {
+        world: World,
+        input: Input,
+        camera: Camera,
+        heightmap: Heightmap,
+        selection: Selection,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let camera = Camera {
+                eye: Point3::new(0.0, 10.0, 5.0),
+                target: Point3::new(0.0, 0.0, 0.0),
+                up: Vector3::unit_y(),
+                aspect: 1.0,
+                fovy: 60.0,
+                znear: 0.1,
+                zfar: 100.0,
+            };
+            let heightmap = Heightmap::new(21, 1.0, -1.0, vec![0.0; 21 * 21]);
+            Self { world: World::new(), input: Input::default(), camera, heightmap, selection: Selection::default() }
+        }
+
+        fn unit(&mut self, x: f32, y: f32, z: f32, kind: u32) -> Entity {
+            let global = GlobalTransform(Matrix4::from_translation(Vector3::new(x, y, z)));
+            self.world.spawn_with((Selectable { radius: 0.4, kind }, global))
+        }
+
+        fn screen(&self, unit: Entity) -> [f32; 2] {
+            let center = Point3::from_vec(self.world.get::<GlobalTransform>(unit).unwrap().translation());
+            self.camera.world_to_screen(center, SCREEN).unwrap()
+        }
+
+        fn frame(&mut self, events: &[InputEvent], delta: f32) {
+            for &event in events {
+                self.input.handle(event, false);
+            }
+            self.input.begin_frame();
+            self.selection.update(&mut self.world, &self.input, &self.camera, &self.heightmap, SCREEN, delta);
+        }
+
+        fn drag(&mut self, from: [f32; 2], to: [f32; 2]) {
+            self.frame(&[InputEvent::CursorMoved(from), mouse(true)], 0.0);
+            self.frame(&[InputEvent::CursorMoved(to), mouse(false)], 0.0);
+        }
+
+        fn click(&mut self, position: [f32; 2], delta: f32) {
+            self.frame(&[InputEvent::CursorMoved(position), mouse(true)], delta);
+            self.frame(&[mouse(false)], 0.0);
+        }
+
+        fn keys(&mut self, modifiers: ModifiersState, key: VirtualKeyCode) {
+            let button = Button::Key(key);
+            self.frame(&[InputEvent::Modifiers(modifiers), InputEvent::Button { button, pressed: true }], 0.0);
+            self.frame(&[InputEvent::Button { button, pressed: false }, InputEvent::Modifiers(ModifiersState::empty())], 0.0);
+        }
+    }
+
+    fn mouse(pressed: bool) -> InputEvent {
+        InputEvent::Button { button: Button::Mouse(MouseButton::Left), pressed }
+    }
+
+    #[test]
+    fn select_modes_replace_add_and_toggle() {
+        let mut fixture = Fixture::new();
+        let [a, b, c] = [fixture.unit(0.0, 0.0, 0.0, 0), fixture.unit(1.0, 0.0, 0.0, 0), fixture.unit(2.0, 0.0, 0.0, 0)];
+        let scenery = fixture.world.spawn();
+        let world = &mut fixture.world;
+        select(world, &[a, b], SelectMode::Replace);
+        assert_eq!(selected(world), vec![a, b]);
+        select(world, &[c], SelectMode::Add);
+        assert_eq!(selected(world), vec![a, b, c]);
+        select(world, &[b, scenery], SelectMode::Toggle);
+        assert_eq!(selected(world), vec![a, c]);
+        select(world, &[c], SelectMode::Replace);
+        assert_eq!(selected(world), vec![c]);
+        select(world, &[], SelectMode::Replace);
+        assert!(selected(world).is_empty());
+    }
+
+    #[test]
+    fn rectangles_take_the_units_projected_inside() {
+        let mut fixture = Fixture::new();
+        let left = fixture.unit(-2.0, 0.0, 0.0, 0);
+        let right = fixture.unit(2.0, 0.0, 0.0, 0);
+        // Behind the camera.
+        fixture.unit(0.0, 0.0, 20.0, 0);
+        let [left_x, right_x] = [fixture.screen(left)[0], fixture.screen(right)[0]];
+        let everything = Rect::new([0.0, 0.0], SCREEN);
+        let left_half = Rect::new([0.0, 0.0], [(left_x + right_x) / 2.0, SCREEN[1]]);
+        assert_eq!(units_in_rect(&fixture.world, &fixture.camera, SCREEN, everything), vec![left, right]);
+        assert_eq!(units_in_rect(&fixture.world, &fixture.camera, SCREEN, left_half), vec![left]);
+    }
+
+    #[test]
+    fn picking_takes_the_nearest_unit_the_ground_doesnt_hide() {
+        let mut fixture = Fixture::new();
+        let far = fixture.unit(0.0, 0.5, 0.0, 0);
+        let position = fixture.screen(far);
+        let pick_at = |fixture: &Fixture, position| pick(&fixture.world, &fixture.camera, &fixture.heightmap, SCREEN, position);
+        assert_eq!(pick_at(&fixture, position), Some(far));
+        // Halfway along the same ray, so it covers `far` from the camera.
+        let near = fixture.unit(0.0, 5.25, 2.5, 0);
+        assert_eq!(pick_at(&fixture, position), Some(near));
+        fixture.world.despawn(near);
+
+        let buried = fixture.unit(-3.0, -2.0, 0.0, 0);
+        assert_eq!(pick_at(&fixture, fixture.screen(buried)), None);
+        assert_eq!(pick_at(&fixture, [5.0, 5.0]), None);
+    }
+
+    #[test]
+    fn clicks_and_drags_select_with_the_held_modifiers() {
+        let mut fixture = Fixture::new();
+        let a = fixture.unit(-2.0, 0.0, 0.0, 0);
+        let b = fixture.unit(2.0, 0.0, 0.0, 1);
+        fixture.click(fixture.screen(a), 1.0);
+        assert_eq!(selected(&fixture.world), vec![a]);
+
+        fixture.input.handle(InputEvent::Button { button: Button::Key(VirtualKeyCode::LShift), pressed: true }, false);
+        fixture.click(fixture.screen(b), 1.0);
+        assert_eq!(selected(&fixture.world), vec![a, b]);
+        fixture.input.handle(InputEvent::Button { button: Button::Key(VirtualKeyCode::LShift), pressed: false }, false);
+
+        fixture.input.handle(InputEvent::Button { button: Button::Key(VirtualKeyCode::LControl), pressed: true }, false);
+        fixture.click(fixture.screen(a), 1.0);
+        assert_eq!(selected(&fixture.world), vec![b]);
+        fixture.input.handle(InputEvent::Button { button: Button::Key(VirtualKeyCode::LControl), pressed: false }, false);
+
+        fixture.click([1.0, 1.0], 1.0);
+        assert!(selected(&fixture.world).is_empty(), "clicking the ground clears");
+
+        fixture.drag([0.0, 0.0], SCREEN);
+        assert_eq!(selected(&fixture.world), vec![a, b]);
+        fixture.frame(&[InputEvent::CursorMoved([0.0, 0.0]), mouse(true)], 0.0);
+        fixture.frame(&[InputEvent::CursorMoved([2.0, 2.0])], 0.0);
+        assert!(fixture.selection.drag_rect().is_none(), "below the drag threshold");
+        fixture.frame(&[InputEvent::CursorMoved([50.0, 60.0])], 0.0);
+        assert_eq!(fixture.selection.drag_rect(), Some(Rect::from_corners([0.0, 0.0], [50.0, 60.0])));
+    }
+
+    #[test]
+    fn double_clicks_select_every_visible_unit_of_the_kind() {
+        let mut fixture = Fixture::new();
+        let a = fixture.unit(-2.0, 0.0, 0.0, 7);
+        let b = fixture.unit(2.0, 0.0, 0.0, 7);
+        let other = fixture.unit(0.0, 0.0, 0.0, 8);
+        let offscreen = fixture.unit(0.0, 0.0, 30.0, 7);
+        fixture.click(fixture.screen(a), 1.0);
+        fixture.click(fixture.screen(a), 0.1);
+        assert_eq!(selected(&fixture.world), vec![a, b]);
+
+        // Too slow for a double-click.
+        fixture.click(fixture.screen(other), 1.0);
+        fixture.click(fixture.screen(other), 1.0);
+        assert_eq!(selected(&fixture.world), vec![other]);
+        assert!(!fixture.world.has::<Selected>(offscreen));
+    }
+
+    #[test]
+    fn control_groups_remember_units_until_they_die() {
+        let mut fixture = Fixture::new();
+        let [a, b, c] = [fixture.unit(-2.0, 0.0, 0.0, 0), fixture.unit(0.0, 0.0, 0.0, 0), fixture.unit(2.0, 0.0, 0.0, 0)];
+        select(&mut fixture.world, &[a, b], SelectMode::Replace);
+        fixture.keys(ModifiersState::CTRL, VirtualKeyCode::Key1);
+        assert_eq!(fixture.selection.group(1), &[a, b]);
+
+        select(&mut fixture.world, &[b, c], SelectMode::Replace);
+        fixture.keys(ModifiersState::SHIFT, VirtualKeyCode::Key1);
+        assert_eq!(fixture.selection.group(1), &[a, b, c]);
+
+        select(&mut fixture.world, &[], SelectMode::Replace);
+        fixture.world.despawn(b);
+        fixture.keys(ModifiersState::empty(), VirtualKeyCode::Key1);
+        assert_eq!(fixture.selection.group(1), &[a, c]);
+        assert_eq!(selected(&fixture.world), vec![a, c]);
+        assert!(fixture.selection.group(2).is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+
+use crate::camera::{Camera, OPENGL_TO_WGPU_MATRIX};
+
+/// Size of the cascade arrays in the global uniform block.
+pub const MAX_CASCADES: usize = 4;
+pub const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShadowSettings {
+    /// Number of cascades, `1..=MAX_CASCADES`.
+    pub cascade_count: u32,
+    /// Width and height of each cascade's shadow map.
+    pub resolution: u32,
+    /// View distance past which nothing receives shadows.
+    pub max_distance: f32,
+    /// Blend between uniform (0.0) and logarithmic (1.0) cascade splits.
+    pub split_lambda: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            cascade_count: 3,
+            resolution: 2048,
+            max_distance: 50.0,
+            split_lambda: 0.6,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Cascade {
+    pub view_proj: Matrix4<f32>,
+    /// View-space distance where this cascade ends.
+    pub split_depth: f32,
+}
+
+/// Far distance of each cascade using the practical split scheme.
+pub fn cascade_splits(near: f32, far: f32, count: u32, lambda: f32) -> Vec<f32> {
+    (1..=count)
+        .map(|i| {
+            let f = i as f32 / count as f32;
+            let log = near * (far / near).powf(f);
+            let uniform = near + (far - near) * f;
+            lambda * log + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
+
+/// Fits one orthographic sun projection around each slice of the camera frustum.
+pub fn compute_cascades(camera: &Camera, sun_direction: Vector3<f32>, settings: &ShadowSettings) -> Vec<Cascade> {
+    let count = settings.cascade_count.clamp(1, MAX_CASCADES as u32);
+    let far = camera.zfar.min(settings.max_distance);
+    let sun_direction = sun_direction.normalize();
+    let up = if sun_direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+
+    let mut near = camera.znear;
+    cascade_splits(camera.znear, far, count, settings.split_lambda)
+        .into_iter()
+        .map(|split_depth| {
+            let corners = camera.frustum_corners(near, split_depth);
+            near = split_depth;
+
+            // A bounding sphere keeps the projection size constant as the camera
+            // rotates, which stops shadow edges from swimming.
+            let center = Point3::centroid(&corners);
+            let radius = corners
+                .iter()
+                .map(|corner| (corner - center).magnitude())
+                .fold(0.0, f32::max);
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            // Pull the near plane back so casters outside the slice still land in the map.
+            let depth_range = radius * 4.0;
+            let eye = center + sun_direction * (depth_range - radius);
+            let view = Matrix4::look_at_rh(eye, center, up);
+            let proj = cgmath::ortho(-radius, radius, -radius, radius, 0.0, depth_range);
+            let mut view_proj = OPENGL_TO_WGPU_MATRIX * proj * view;
+
+            // Snap the projection to whole texels.
+            let half_resolution = settings.resolution as f32 / 2.0;
+            let origin = view_proj * Vector4::new(0.0, 0.0, 0.0, 1.0) * half_resolution;
+            let offset = Vector4::new(origin.x.round() - origin.x, origin.y.round() - origin.y, 0.0, 0.0) / half_resolution;
+            view_proj = Matrix4::from_translation(offset.truncate()) * view_proj;
+
+            Cascade { view_proj, split_depth }
+        })
+        .collect()
+}
+
+/// Depth texture array with one layer per cascade, plus the per-cascade
+/// uniform buffers the shadow casters render with.
+pub struct ShadowMaps {
+    settings: ShadowSettings,
+    pass_layout: wgpu::BindGroupLayout,
+    view: wgpu::TextureView,
+    layer_views: Vec<wgpu::TextureView>,
+    sampler: wgpu::Sampler,
+    cascade_buffers: Vec<wgpu::Buffer>,
+    cascade_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl ShadowMaps {
+    /// `uniforms_size` is the size of the global uniform block, which the
+    /// cascade buffers mirror so shadow passes can reuse the scene shaders.
+    pub fn new(device: &wgpu::Device, settings: ShadowSettings, uniforms_size: usize) -> Self {
+        let pass_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("shadow_pass_bind_group_layout"),
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let (view, layer_views, cascade_buffers, cascade_bind_groups) = Self::create_targets(device, &pass_layout, &settings, uniforms_size);
+        Self { settings, pass_layout, view, layer_views, sampler, cascade_buffers, cascade_bind_groups }
+    }
+
+    fn create_targets(device: &wgpu::Device, pass_layout: &wgpu::BindGroupLayout, settings: &ShadowSettings, uniforms_size: usize) -> (wgpu::TextureView, Vec<wgpu::TextureView>, Vec<wgpu::Buffer>, Vec<wgpu::BindGroup>) {
+        let count = settings.cascade_count.clamp(1, MAX_CASCADES as u32);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Maps"),
+            size: wgpu::Extent3d {
+                width: settings.resolution,
+                height: settings.resolution,
+                depth_or_array_layers: count,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let layer_views = (0..count)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let cascade_buffers: Vec<wgpu::Buffer> = (0..count)
+            .map(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Cascade Uniform Buffer"),
+                    size: uniforms_size as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+        let cascade_bind_groups = cascade_buffers
+            .iter()
+            .map(|buffer| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: pass_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: buffer.as_entire_binding(),
+                        }
+                    ],
+                    label: Some("cascade_bind_group"),
+                })
+            })
+            .collect();
+        (view, layer_views, cascade_buffers, cascade_bind_groups)
+    }
+
+    /// Recreates the maps. The caller must rebuild bind groups using `view`.
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings, uniforms_size: usize) {
+        let (view, layer_views, cascade_buffers, cascade_bind_groups) = Self::create_targets(device, &self.pass_layout, &settings, uniforms_size);
+        self.settings = settings;
+        self.view = view;
+        self.layer_views = layer_views;
+        self.cascade_buffers = cascade_buffers;
+        self.cascade_bind_groups = cascade_bind_groups;
+    }
+
+    pub fn settings(&self) -> &ShadowSettings {
+        &self.settings
+    }
+
+    pub fn cascade_count(&self) -> usize {
+        self.layer_views.len()
+    }
+
+    /// Layout of bind group 0 while rendering shadow casters.
+    pub fn pass_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.pass_layout
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+
+    pub(crate) fn cascade_buffer(&self, cascade: usize) -> &wgpu::Buffer {
+        &self.cascade_buffers[cascade]
+    }
+
+    pub(crate) fn cascade_bind_group(&self, cascade: usize) -> &wgpu::BindGroup {
+        &self.cascade_bind_groups[cascade]
+    }
+
+    pub(crate) fn layer_view(&self, cascade: usize) -> &wgpu::TextureView {
+        &self.layer_views[cascade]
+    }
+}
+
+/// Depth state for shadow caster pipelines, biased against shadow acne.
+pub(crate) fn shadow_depth_stencil_state() -> wgpu::DepthStencilState {
+    wgpu::DepthStencilState {
+        format: SHADOW_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::LessEqual,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState {
+            constant: 2,
+            slope_scale: 2.0,
+            clamp: 0.0,
+        },
+    }
+}
+
+pub(crate) fn identity_cascades() -> [[[f32; 4]; 4]; MAX_CASCADES] {
+    [Matrix4::identity().into(); MAX_CASCADES]
+}
@@ -0,0 +1,342 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use wgpu::util::DeviceExt;
+use crate::SceneTargets;
+use crate::mesh::InstanceBuffer;
+use crate::model::MeshData;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinnedVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+impl SkinnedVertex {
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<SkinnedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ]
+        }
+    }
+}
+
+/// Like `mesh::Instance`, plus where this instance's joints start in the `JointBuffer`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinnedInstance {
+    pub model: [[f32; 4]; 4],
+    pub color: [f32; 4],
+    pub joint_offset: u32,
+}
+
+impl SkinnedInstance {
+    pub fn new(transform: cgmath::Matrix4<f32>, color: [f32; 4], joint_offset: u32) -> Self {
+        Self { model: transform.into(), color, joint_offset }
+    }
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const VEC4: wgpu::BufferAddress = std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress;
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<SkinnedInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: VEC4,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: VEC4 * 2,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: VEC4 * 3,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: VEC4 * 4,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: VEC4 * 5,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ]
+        }
+    }
+}
+
+pub struct SkinnedMesh {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+}
+
+impl SkinnedMesh {
+    /// Returns `None` for meshes without joint weights.
+    pub fn from_data(device: &wgpu::Device, mesh: &MeshData) -> Option<Self> {
+        let skin = mesh.skin.as_ref()?;
+        let vertices: Vec<SkinnedVertex> = mesh
+            .vertices
+            .iter()
+            .zip(skin.joints.iter().zip(&skin.weights))
+            .map(|(vertex, (&joints, &weights))| SkinnedVertex {
+                position: vertex.position,
+                normal: vertex.normal,
+                tex_coords: vertex.tex_coords,
+                joints,
+                weights,
+            })
+            .collect();
+        let vertex_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&mesh.name),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsage::VERTEX,
+            }
+        );
+        let index_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&mesh.name),
+                contents: bytemuck::cast_slice(&mesh.indices),
+                usage: wgpu::BufferUsage::INDEX,
+            }
+        );
+        Some(Self { vertex_buffer, index_buffer, num_indices: mesh.indices.len() as u32 })
+    }
+}
+
+/// Per-instance data of skinned meshes, rewritten from the CPU each frame.
+pub type SkinnedInstanceBuffer = InstanceBuffer<SkinnedInstance>;
+
+/// Storage buffer holding the skinning matrices of every animated instance.
+struct JointBuffer {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    capacity: usize,
+}
+
+impl JointBuffer {
+    fn create(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, capacity: usize) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Joint Buffer"),
+            size: (capacity * std::mem::size_of::<[[f32; 4]; 4]>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("joint_bind_group"),
+        });
+        Self { buffer, bind_group, capacity }
+    }
+}
+
+pub struct SkinnedMeshRenderer {
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    render_pipeline: wgpu::RenderPipeline,
+    shadow_pipeline: wgpu::RenderPipeline,
+    joint_bind_group_layout: wgpu::BindGroupLayout,
+    joints: JointBuffer,
+}
+
+impl SkinnedMeshRenderer {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat, sample_count: u32, uniforms_bgl: &wgpu::BindGroupLayout, shadow_bgl: &wgpu::BindGroupLayout) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("skinned shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!("../res/shader/globals.wgsl"), include_str!("../res/shader/skinned.wgsl")))),
+            flags: wgpu::ShaderFlags::all(),
+        });
+
+        let joint_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("joint_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[uniforms_bgl, &joint_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let render_pipeline = Self::create_render_pipeline(device, &pipeline_layout, &shader, color_format, sample_count);
+
+        let shadow_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[shadow_bgl, &joint_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shadow_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("skinned shadow pipeline"),
+            layout: Some(&shadow_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_shadow",
+                buffers: &[SkinnedVertex::desc(), SkinnedInstance::desc()],
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(crate::shadow::shadow_depth_stencil_state()),
+            multisample: wgpu::MultisampleState::default(),
+        });
+
+        let joints = JointBuffer::create(device, &joint_bind_group_layout, 64);
+        Self { shader, pipeline_layout, color_format, render_pipeline, shadow_pipeline, joint_bind_group_layout, joints }
+    }
+
+    fn create_render_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, color_format: wgpu::TextureFormat, sample_count: u32) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("skinned pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[SkinnedVertex::desc(), SkinnedInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[color_format.into()],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(crate::depth_stencil_state()),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Rebuilds the colour pipeline for targets with `sample_count` samples.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.render_pipeline = Self::create_render_pipeline(device, &self.pipeline_layout, &self.shader, self.color_format, sample_count);
+    }
+
+    /// Records `batches` into a shadow pass whose cascade bind group is already set.
+    pub fn record_shadow<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, batches: &[(&'a SkinnedMesh, &'a SkinnedInstanceBuffer)]) {
+        pass.set_pipeline(&self.shadow_pipeline);
+        pass.set_bind_group(1, &self.joints.bind_group, &[]);
+        for (mesh, instances) in batches {
+            if instances.is_empty() {
+                continue;
+            }
+            pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            pass.set_vertex_buffer(1, instances.buffer.slice(..));
+            pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..mesh.num_indices, 0, 0..instances.len());
+        }
+    }
+
+    /// Uploads the skinning matrices of all instances, concatenated. Instances
+    /// address their slice through `SkinnedInstance::joint_offset`.
+    pub fn update_joints(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, matrices: &[cgmath::Matrix4<f32>]) {
+        if matrices.len() > self.joints.capacity {
+            let capacity = matrices.len().next_power_of_two();
+            self.joints = JointBuffer::create(device, &self.joint_bind_group_layout, capacity);
+        }
+        let raw: Vec<[[f32; 4]; 4]> = matrices.iter().map(|&m| m.into()).collect();
+        queue.write_buffer(&self.joints.buffer, 0, bytemuck::cast_slice(&raw));
+    }
+
+    pub async fn draw(&self, device: &wgpu::Device, targets: Arc<SceneTargets>, uniforms_bg: &wgpu::BindGroup, batches: &[(&SkinnedMesh, &SkinnedInstanceBuffer)]) -> wgpu::CommandBuffer {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &targets.color,
+                    resolve_target: targets.resolve.as_deref(),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &targets.depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_pipeline(&self.render_pipeline);
+            pass.set_bind_group(0, uniforms_bg, &[]);
+            pass.set_bind_group(1, &self.joints.bind_group, &[]);
+            for (mesh, instances) in batches {
+                if instances.is_empty() {
+                    continue;
+                }
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_vertex_buffer(1, instances.buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_indices, 0, 0..instances.len());
+            }
+        }
+        encoder.finish()
+    }
+}
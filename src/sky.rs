@@ -0,0 +1,287 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{ElementWise, InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+use crate::SceneTargets;
+
+/// Parameters of the procedural atmosphere. Distances are in metres.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SkySettings {
+    pub rayleigh_coefficients: [f32; 3],
+    pub rayleigh_scale_height: f32,
+    pub mie_coefficient: f32,
+    pub mie_scale_height: f32,
+    /// Forward scattering of the Mie phase function, `0.0..1.0`.
+    pub mie_anisotropy: f32,
+    pub sun_intensity: f32,
+    /// Angular diameter of the sun disc, in degrees.
+    pub sun_disc_size: f32,
+    /// Brightness of the sun disc relative to the scattered light.
+    pub sun_disc_intensity: f32,
+    /// Albedo the sky fades to below the horizon.
+    pub ground_color: [f32; 3],
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        // Earth-like values.
+        Self {
+            rayleigh_coefficients: [5.5e-6, 13.0e-6, 22.4e-6],
+            rayleigh_scale_height: 8e3,
+            mie_coefficient: 21e-6,
+            mie_scale_height: 1.2e3,
+            mie_anisotropy: 0.758,
+            sun_intensity: 22.0,
+            sun_disc_size: 0.53,
+            sun_disc_intensity: 20.0,
+            ground_color: [0.3, 0.28, 0.25],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct SkyUniforms {
+    rayleigh: [f32; 4],
+    mie: [f32; 4],
+    sun: [f32; 4],
+    ground: [f32; 4],
+}
+
+unsafe impl Zeroable for SkyUniforms{}
+unsafe impl Pod for SkyUniforms{}
+
+impl SkyUniforms {
+    fn new(settings: &SkySettings, intensity_scale: f32) -> Self {
+        let [r, g, b] = settings.rayleigh_coefficients;
+        let disc_radius = (settings.sun_disc_size * 0.5).to_radians();
+        Self {
+            rayleigh: [r, g, b, settings.rayleigh_scale_height],
+            mie: [settings.mie_coefficient, settings.mie_scale_height, settings.mie_anisotropy, 0.0],
+            sun: [settings.sun_intensity * intensity_scale, disc_radius.cos(), settings.sun_disc_intensity, 0.0],
+            ground: [settings.ground_color[0], settings.ground_color[1], settings.ground_color[2], 1.0],
+        }
+    }
+}
+
+/// Atmospheric scattering sky lit by the sun in the global uniforms. Drawing
+/// it also clears the scene targets, so it must be the first scene pass.
+pub struct Sky {
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    render_pipeline: wgpu::RenderPipeline,
+    settings: SkySettings,
+    intensity_scale: f32,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Sky {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat, sample_count: u32, uniforms_bgl: &wgpu::BindGroupLayout) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("sky shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!("../res/shader/globals.wgsl"), include_str!("../res/shader/sky.wgsl")))),
+            flags: wgpu::ShaderFlags::all(),
+        });
+
+        let settings = SkySettings::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sky Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[SkyUniforms::new(&settings, 1.0)]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("sky_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("sky_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[uniforms_bgl, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = Self::create_render_pipeline(device, &pipeline_layout, &shader, color_format, sample_count);
+
+        Self { shader, pipeline_layout, color_format, render_pipeline, settings, intensity_scale: 1.0, uniform_buffer, bind_group }
+    }
+
+    fn create_render_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, color_format: wgpu::TextureFormat, sample_count: u32) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("sky pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[color_format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Sits exactly on the cleared far plane without writing depth.
+            depth_stencil: Some(wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                ..crate::depth_stencil_state()
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Rebuilds the pipeline for targets with `sample_count` samples.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.render_pipeline = Self::create_render_pipeline(device, &self.pipeline_layout, &self.shader, self.color_format, sample_count);
+    }
+
+    pub fn settings(&self) -> &SkySettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: SkySettings) {
+        self.settings = settings;
+        self.write_uniforms(queue);
+    }
+
+    pub fn intensity_scale(&self) -> f32 {
+        self.intensity_scale
+    }
+
+    /// Dims the sky on top of `SkySettings::sun_intensity`, e.g. at night.
+    pub fn set_intensity_scale(&mut self, queue: &wgpu::Queue, scale: f32) {
+        if scale != self.intensity_scale {
+            self.intensity_scale = scale;
+            self.write_uniforms(queue);
+        }
+    }
+
+    fn write_uniforms(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[SkyUniforms::new(&self.settings, self.intensity_scale)]));
+    }
+
+    /// Clears colour and depth, then fills the background with the sky.
+    pub async fn draw(&self, device: &wgpu::Device, targets: Arc<SceneTargets>, uniforms_bg: &wgpu::BindGroup) -> wgpu::CommandBuffer {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("sky") });
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("sky"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &targets.color,
+                    resolve_target: targets.resolve.as_deref(),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &targets.depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            rpass.set_pipeline(&self.render_pipeline);
+            rpass.set_bind_group(0, uniforms_bg, &[]);
+            rpass.set_bind_group(1, &self.bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+        encoder.finish()
+    }
+}
+
+fn ray_sphere_exit(origin: Vector3<f32>, direction: Vector3<f32>, radius: f32) -> f32 {
+    let b = direction.dot(origin);
+    let c = origin.dot(origin) - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return 0.0;
+    }
+    -b + discriminant.sqrt()
+}
+
+/// CPU evaluation of the sky shader's scattering, without the sun disc, for
+/// colouring things that should blend into the sky.
+pub fn sky_color(settings: &SkySettings, sun_direction: Vector3<f32>, direction: Vector3<f32>) -> [f32; 3] {
+    const PLANET_RADIUS: f32 = 6_371_000.0;
+    const ATMOSPHERE_RADIUS: f32 = 6_471_000.0;
+    const PRIMARY_STEPS: usize = 16;
+    const LIGHT_STEPS: usize = 8;
+
+    let sun_direction = sun_direction.normalize();
+    // Like the shader, rays below the horizon are marched along the horizon.
+    let direction = Vector3::new(direction.x, direction.y.max(0.0), direction.z);
+    let direction = if direction.magnitude2() > 1e-8 { direction.normalize() } else { Vector3::unit_z() };
+    let origin = Vector3::new(0.0, PLANET_RADIUS + 1000.0, 0.0);
+    let rayleigh = Vector3::from(settings.rayleigh_coefficients);
+    let mie = Vector3::new(1.0, 1.0, 1.0) * settings.mie_coefficient;
+
+    let step_size = ray_sphere_exit(origin, direction, ATMOSPHERE_RADIUS) / PRIMARY_STEPS as f32;
+    let mut optical_rayleigh = 0.0;
+    let mut optical_mie = 0.0;
+    let mut total_rayleigh = Vector3::new(0.0, 0.0, 0.0);
+    let mut total_mie = Vector3::new(0.0, 0.0, 0.0);
+    for i in 0..PRIMARY_STEPS {
+        let position = origin + direction * (step_size * (i as f32 + 0.5));
+        let height = position.magnitude() - PLANET_RADIUS;
+        let density_rayleigh = (-height / settings.rayleigh_scale_height).exp() * step_size;
+        let density_mie = (-height / settings.mie_scale_height).exp() * step_size;
+        optical_rayleigh += density_rayleigh;
+        optical_mie += density_mie;
+
+        let light_step_size = ray_sphere_exit(position, sun_direction, ATMOSPHERE_RADIUS) / LIGHT_STEPS as f32;
+        let mut light_rayleigh = 0.0;
+        let mut light_mie = 0.0;
+        for j in 0..LIGHT_STEPS {
+            let light_position = position + sun_direction * (light_step_size * (j as f32 + 0.5));
+            let light_height = light_position.magnitude() - PLANET_RADIUS;
+            light_rayleigh += (-light_height / settings.rayleigh_scale_height).exp() * light_step_size;
+            light_mie += (-light_height / settings.mie_scale_height).exp() * light_step_size;
+        }
+
+        let depth = mie * (optical_mie + light_mie) + rayleigh * (optical_rayleigh + light_rayleigh);
+        let attenuation = Vector3::new((-depth.x).exp(), (-depth.y).exp(), (-depth.z).exp());
+        total_rayleigh += attenuation * density_rayleigh;
+        total_mie += attenuation * density_mie;
+    }
+
+    let mu = direction.dot(sun_direction);
+    let g = settings.mie_anisotropy;
+    let phase_rayleigh = 3.0 / (16.0 * std::f32::consts::PI) * (1.0 + mu * mu);
+    let phase_mie = 3.0 / (8.0 * std::f32::consts::PI) * ((1.0 - g * g) * (1.0 + mu * mu))
+        / ((1.0 + g * g - 2.0 * mu * g).powf(1.5) * (2.0 + g * g));
+    let color = (total_rayleigh.mul_element_wise(rayleigh) * phase_rayleigh
+        + total_mie.mul_element_wise(mie) * phase_mie)
+        * settings.sun_intensity;
+    color.into()
+}
@@ -2,13 +2,15 @@ use std::borrow::Cow;
 use std::sync::Arc;
 
 use wgpu::util::DeviceExt;
-use crate::ScreenTargets;
+use crate::SceneTargets;
+use crate::heightmap::Heightmap;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
+    normal: [f32; 3],
 }
 
 impl Vertex {
@@ -26,44 +28,71 @@ impl Vertex {
                     offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
                 }
             ]
         }
     }
 }
 
-const VERTICES: &[Vertex] = &[
-    Vertex { position: [-1.0, 0.0, -1.0], tex_coords: [0.0, 0.0] },
-    Vertex { position: [1.0, 0.0, -1.0], tex_coords: [8.0, 0.0] },
-    Vertex { position: [1.0, 0.0, 1.0], tex_coords: [8.0, 8.0] },
-    Vertex { position: [-1.0, 0.0, 1.0], tex_coords: [0.0, 8.0] },
-];
+/// World units covered by one repeat of the diffuse texture.
+const TEXTURE_SCALE: f32 = 2.0;
 
-const INDICES: &[u16] = &[
-    0, 1, 2,
-    0, 2, 3,
-];
+/// One vertex per heightmap sample, two triangles per cell.
+fn build_mesh(heightmap: &Heightmap) -> (Vec<Vertex>, Vec<u32>) {
+    let resolution = heightmap.resolution();
+    let mut vertices = Vec::with_capacity((resolution * resolution) as usize);
+    for z in 0..resolution {
+        for x in 0..resolution {
+            let position = heightmap.sample_position(x, z);
+            vertices.push(Vertex {
+                position: position.into(),
+                tex_coords: [position.x / TEXTURE_SCALE, position.z / TEXTURE_SCALE],
+                normal: heightmap.sample_normal(x, z).into(),
+            });
+        }
+    }
+    let mut indices = Vec::with_capacity(((resolution - 1) * (resolution - 1) * 6) as usize);
+    for z in 0..resolution - 1 {
+        for x in 0..resolution - 1 {
+            let i = z * resolution + x;
+            indices.extend_from_slice(&[i, i + resolution, i + 1, i + 1, i + resolution, i + resolution + 1]);
+        }
+    }
+    (vertices, indices)
+}
 
 pub struct Terrain {
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
     render_pipeline: wgpu::RenderPipeline,
+    shadow_pipeline: wgpu::RenderPipeline,
     vertex_buffer: wgpu::Buffer,
     num_indices: u32,
     index_buffer: wgpu::Buffer,
-    diffuse_texture: wgpu::Texture,
+    heightmap: Heightmap,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    diffuse_sampler: wgpu::Sampler,
     diffuse_bind_group: wgpu::BindGroup,
 }
 
 impl Terrain {
-    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, color_format: wgpu::TextureFormat, uniforms_bgl: &wgpu::BindGroupLayout) -> Self {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, color_format: wgpu::TextureFormat, sample_count: u32, uniforms_bgl: &wgpu::BindGroupLayout, shadow_bgl: &wgpu::BindGroupLayout, heightmap: Heightmap) -> Self {
         let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
             label: Some("terrain shader"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../res/shader/terrain.wgsl"))),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!("../res/shader/globals.wgsl"), include_str!("../res/shader/terrain.wgsl")))),
             flags: wgpu::ShaderFlags::all(),
         });
 
-        let diffuse_texture = crate::helpers::load_texture("prototype/Light/texture_07.png".to_string(), device, queue);
+        // Drawn until the real diffuse texture finishes streaming in.
+        let placeholder = image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 128, 255]));
+        let diffuse_texture = crate::helpers::upload_texture("terrain placeholder", &placeholder, crate::helpers::ColorSpace::Srgb, device, queue);
 
-        let diffuse_texture_view = diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default());
         let diffuse_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::Repeat,
             address_mode_v: wgpu::AddressMode::Repeat,
@@ -100,23 +129,7 @@ impl Terrain {
             }
         );
 
-        let diffuse_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture_view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_sampler),
-                    }
-                ],
-                label: Some("diffuse_bind_group"),
-            }
-        );
-
+        let diffuse_bind_group = Self::create_diffuse_bind_group(device, &texture_bind_group_layout, &diffuse_texture, &diffuse_sampler);
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: None,
@@ -124,65 +137,152 @@ impl Terrain {
         push_constant_ranges: &[],
         });
 
-    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: None,
-        layout: Some(&pipeline_layout),
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vs_main",
-            buffers: &[Vertex::desc()],
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
-            targets: &[color_format.into()],
-        }),
-        primitive: wgpu::PrimitiveState::default(),
-        depth_stencil: None,
-        multisample: wgpu::MultisampleState::default(),
+        let render_pipeline = Self::create_render_pipeline(device, &pipeline_layout, &shader, color_format, sample_count);
+
+        let shadow_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[shadow_bgl],
+            push_constant_ranges: &[],
         });
+        let shadow_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("terrain shadow pipeline"),
+            layout: Some(&shadow_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_shadow",
+                buffers: &[Vertex::desc()],
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(crate::shadow::shadow_depth_stencil_state()),
+            multisample: wgpu::MultisampleState::default(),
+        });
+
+        let (vertex_buffer, index_buffer, num_indices) = Self::create_buffers(device, &heightmap);
 
+        Self { shader, pipeline_layout, color_format, render_pipeline, shadow_pipeline, vertex_buffer, index_buffer, num_indices, heightmap, texture_bind_group_layout, diffuse_sampler, diffuse_bind_group }
+    }
+
+    fn create_buffers(device: &wgpu::Device, heightmap: &Heightmap) -> (wgpu::Buffer, wgpu::Buffer, u32) {
+        let (vertices, indices) = build_mesh(heightmap);
         let vertex_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(VERTICES),
+                label: Some("Terrain Vertex Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
                 usage: wgpu::BufferUsage::VERTEX,
             }
         );
         let index_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(INDICES),
+                label: Some("Terrain Index Buffer"),
+                contents: bytemuck::cast_slice(&indices),
                 usage: wgpu::BufferUsage::INDEX,
             }
         );
-        let num_indices = INDICES.len() as u32;
+        (vertex_buffer, index_buffer, indices.len() as u32)
+    }
+
+    pub fn heightmap(&self) -> &Heightmap {
+        &self.heightmap
+    }
+
+    /// Replaces the terrain shape, e.g. after editing or regenerating it.
+    pub fn set_heightmap(&mut self, device: &wgpu::Device, heightmap: Heightmap) {
+        let (vertex_buffer, index_buffer, num_indices) = Self::create_buffers(device, &heightmap);
+        self.vertex_buffer = vertex_buffer;
+        self.index_buffer = index_buffer;
+        self.num_indices = num_indices;
+        self.heightmap = heightmap;
+    }
+
+    fn create_render_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, color_format: wgpu::TextureFormat, sample_count: u32) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[color_format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(crate::depth_stencil_state()),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Rebuilds the colour pipeline for targets with `sample_count` samples.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.render_pipeline = Self::create_render_pipeline(device, &self.pipeline_layout, &self.shader, self.color_format, sample_count);
+    }
+
+    fn create_diffuse_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, texture: &wgpu::Texture, sampler: &wgpu::Sampler) -> wgpu::BindGroup {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    }
+                ],
+                label: Some("diffuse_bind_group"),
+            }
+        )
+    }
 
+    pub fn set_diffuse_texture(&mut self, device: &wgpu::Device, texture: &wgpu::Texture) {
+        self.diffuse_bind_group = Self::create_diffuse_bind_group(device, &self.texture_bind_group_layout, texture, &self.diffuse_sampler);
+    }
 
-        Self { render_pipeline, vertex_buffer, index_buffer, num_indices, diffuse_texture, diffuse_bind_group }
+    /// Records the terrain into a shadow pass whose cascade bind group is already set.
+    pub fn record_shadow<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_pipeline(&self.shadow_pipeline);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        pass.draw_indexed(0..self.num_indices, 0, 0..1);
     }
 
-    pub async fn draw(&self, device: &wgpu::Device, targets: Arc<ScreenTargets>, uniforms_bg: &wgpu::BindGroup) -> wgpu::CommandBuffer {
+    pub async fn draw(&self, device: &wgpu::Device, targets: Arc<SceneTargets>, uniforms_bg: &wgpu::BindGroup) -> wgpu::CommandBuffer {
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
         {
             let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[wgpu::RenderPassColorAttachment {
-                    view: &targets.color.output.view,
-                    resolve_target: None,
+                    view: &targets.color,
+                    resolve_target: targets.resolve.as_deref(),
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Load,
                         store: true,
                     },
                 }],
-                depth_stencil_attachment: None,
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &targets.depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
             });
             pass.set_pipeline(&self.render_pipeline);
             pass.set_bind_group(0, uniforms_bg, &[]);
             pass.set_bind_group(1, &self.diffuse_bind_group, &[]);
             pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
 
             pass.draw_indexed(0..self.num_indices, 0, 0..1);
         }
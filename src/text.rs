@@ -0,0 +1,710 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use ab_glyph::{Font as _, FontArc, GlyphId, ScaleFont};
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Point3};
+use wgpu::util::DeviceExt;
+use crate::SceneTargets;
+use crate::camera::Camera;
+
+const ATLAS_SIZE: u32 = 1024;
+/// Empty texels around each glyph so linear filtering doesn't bleed.
+const ATLAS_PADDING: u32 = 1;
+/// Side of the opaque block in the atlas corner that solid quads sample.
+const SOLID_BLOCK: u32 = 4;
+/// World-space text is rasterized at this pixel size and then scaled.
+const WORLD_RASTER_SIZE: f32 = 48.0;
+
+#[derive(Debug)]
+pub enum TextError {
+    Io(std::io::Error),
+    InvalidFont,
+}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextError::Io(e) => write!(f, "{}", e),
+            TextError::InvalidFont => write!(f, "not a TrueType or OpenType font"),
+        }
+    }
+}
+
+impl std::error::Error for TextError {}
+
+impl From<std::io::Error> for TextError {
+    fn from(e: std::io::Error) -> Self {
+        TextError::Io(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct Font {
+    inner: FontArc,
+}
+
+impl Font {
+    /// Loads a `.ttf` or `.otf` file from `res/fonts`.
+    pub fn load(path: &str) -> Result<Self, TextError> {
+        Self::from_vec(std::fs::read(Path::new("./res/fonts").join(path))?)
+    }
+
+    pub fn from_vec(data: Vec<u8>) -> Result<Self, TextError> {
+        let inner = FontArc::try_from_vec(data).map_err(|_| TextError::InvalidFont)?;
+        Ok(Self { inner })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FontId(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LaidOutGlyph {
+    pub id: GlyphId,
+    /// Pen position on the baseline, relative to the top left of the text.
+    pub position: [f32; 2],
+}
+
+/// Glyph positions for a string, in pixels at the requested size.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextLayout {
+    pub glyphs: Vec<LaidOutGlyph>,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Width of `text` on a single line, including trailing whitespace, e.g. for
+/// placing a caret after it.
+pub fn advance_width(font: &Font, text: &str, size: f32) -> f32 {
+    let font = font.inner.as_scaled(size);
+    let mut previous = None;
+    let mut width = 0.0;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, id);
+        }
+        width += font.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+/// Lays out `text` at `size` pixels per em, with kerning. Lines break at
+/// `\n` and, given `max_width`, after the last space that fits, or mid-word
+/// if a word is wider than a whole line.
+pub fn layout(font: &Font, text: &str, size: f32, max_width: Option<f32>) -> TextLayout {
+    let font = font.inner.as_scaled(size);
+    let line_height = font.height() + font.line_gap();
+    // (glyph, advance, whitespace)
+    let mut glyphs: Vec<(LaidOutGlyph, f32, bool)> = Vec::new();
+    let mut line_start = 0;
+    let mut last_break = None;
+    let mut caret = 0.0;
+    let mut baseline = font.ascent();
+    let mut width: f32 = 0.0;
+    let mut previous = None;
+
+    // Width of a run of glyphs on one line, ignoring trailing whitespace.
+    let line_width = |glyphs: &[(LaidOutGlyph, f32, bool)]| {
+        glyphs
+            .iter()
+            .rev()
+            .find(|(_, _, whitespace)| !whitespace)
+            .map_or(0.0, |(glyph, advance, _)| glyph.position[0] + advance)
+    };
+
+    for c in text.chars() {
+        if c == '\n' {
+            width = width.max(line_width(&glyphs[line_start..]));
+            line_start = glyphs.len();
+            last_break = None;
+            caret = 0.0;
+            baseline += line_height;
+            previous = None;
+            continue;
+        }
+        if c.is_control() {
+            continue;
+        }
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += font.kern(previous, id);
+        }
+        let advance = font.h_advance(id);
+        let whitespace = c.is_whitespace();
+
+        let overflows = max_width.is_some_and(|max_width| caret + advance > max_width);
+        if overflows && !whitespace && glyphs.len() > line_start {
+            let start = last_break.unwrap_or(glyphs.len());
+            width = width.max(line_width(&glyphs[line_start..start]));
+            let shift = if start < glyphs.len() { glyphs[start].0.position[0] } else { caret };
+            baseline += line_height;
+            for (glyph, _, _) in &mut glyphs[start..] {
+                glyph.position = [glyph.position[0] - shift, baseline];
+            }
+            caret -= shift;
+            line_start = start;
+            last_break = None;
+        }
+
+        glyphs.push((LaidOutGlyph { id, position: [caret, baseline] }, advance, whitespace));
+        caret += advance;
+        if whitespace {
+            last_break = Some(glyphs.len());
+        }
+        previous = Some(id);
+    }
+    width = width.max(line_width(&glyphs[line_start..]));
+
+    TextLayout {
+        glyphs: glyphs.into_iter().map(|(glyph, _, _)| glyph).collect(),
+        width,
+        height: baseline - font.descent(),
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextStyle {
+    pub font: FontId,
+    /// Pixels per em on screen, or world units per em in the world.
+    pub size: f32,
+    pub color: [f32; 4],
+    pub max_width: Option<f32>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            font: TextRenderer::DEFAULT_FONT,
+            size: 16.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            max_width: None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TextVertex {
+    position: [f32; 3],
+    tex_coords: [f32; 2],
+    color: [f32; 4],
+}
+
+impl TextVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Float32x4];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TextVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct TextUniforms {
+    screen: [f32; 4],
+}
+
+unsafe impl Zeroable for TextUniforms{}
+unsafe impl Pod for TextUniforms{}
+
+/// Where a rasterized glyph sits in the atlas, relative to its pen position.
+#[derive(Copy, Clone, Debug)]
+struct AtlasEntry {
+    offset: [f32; 2],
+    size: [f32; 2],
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+}
+
+/// Glyph coverage packed into rows of a single texture.
+struct GlyphAtlas {
+    texture: wgpu::Texture,
+    entries: HashMap<(FontId, GlyphId, u32), Option<AtlasEntry>>,
+    cursor: [u32; 2],
+    row_height: u32,
+    full: bool,
+}
+
+impl GlyphAtlas {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Glyph Atlas"),
+            size: wgpu::Extent3d { width: ATLAS_SIZE, height: ATLAS_SIZE, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &[255; (SOLID_BLOCK * SOLID_BLOCK) as usize],
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(SOLID_BLOCK),
+                rows_per_image: std::num::NonZeroU32::new(SOLID_BLOCK),
+            },
+            wgpu::Extent3d { width: SOLID_BLOCK, height: SOLID_BLOCK, depth_or_array_layers: 1 },
+        );
+        let mut atlas = Self { texture, entries: HashMap::new(), cursor: [0, 0], row_height: 0, full: false };
+        atlas.clear();
+        atlas
+    }
+
+    /// Texture coordinates inside the opaque block, away from its filtered edges.
+    fn solid_uv() -> [f32; 2] {
+        let center = SOLID_BLOCK as f32 / 2.0 / ATLAS_SIZE as f32;
+        [center, center]
+    }
+
+    /// Forgets every glyph so the space can be reused. Stale texels are
+    /// simply overwritten.
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.cursor = [SOLID_BLOCK + ATLAS_PADDING, 0];
+        self.row_height = SOLID_BLOCK;
+        self.full = false;
+    }
+
+    /// The atlas entry for a glyph at `size` pixels, rasterizing it on first
+    /// use. `None` for glyphs without an outline, like spaces, or when the
+    /// atlas is full.
+    fn entry(&mut self, queue: &wgpu::Queue, fonts: &[Font], font_id: FontId, id: GlyphId, size: f32) -> Option<AtlasEntry> {
+        let key = (font_id, id, size.to_bits());
+        if let Some(entry) = self.entries.get(&key) {
+            return *entry;
+        }
+        let outlined = fonts[font_id.0]
+            .inner
+            .outline_glyph(id.with_scale(size))
+            .filter(|outlined| outlined.px_bounds().width() >= 1.0 && outlined.px_bounds().height() >= 1.0);
+        let outlined = match outlined {
+            Some(outlined) => outlined,
+            None => {
+                self.entries.insert(key, None);
+                return None;
+            }
+        };
+        let bounds = outlined.px_bounds();
+        let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+
+        if self.cursor[0] + width + ATLAS_PADDING > ATLAS_SIZE {
+            self.cursor = [0, self.cursor[1] + self.row_height + ATLAS_PADDING];
+            self.row_height = 0;
+        }
+        if self.cursor[1] + height + ATLAS_PADDING > ATLAS_SIZE || width + ATLAS_PADDING > ATLAS_SIZE {
+            self.full = true;
+            return None;
+        }
+        let origin = self.cursor;
+        self.cursor[0] += width + ATLAS_PADDING;
+        self.row_height = self.row_height.max(height);
+
+        let mut pixels = vec![0u8; (width * height) as usize];
+        outlined.draw(|x, y, coverage| {
+            if x < width && y < height {
+                pixels[(y * width + x) as usize] = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: origin[0], y: origin[1], z: 0 },
+            },
+            &pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(width),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+            wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        );
+
+        let atlas_size = ATLAS_SIZE as f32;
+        let entry = AtlasEntry {
+            offset: [bounds.min.x, bounds.min.y],
+            size: [width as f32, height as f32],
+            uv_min: [origin[0] as f32 / atlas_size, origin[1] as f32 / atlas_size],
+            uv_max: [(origin[0] + width) as f32 / atlas_size, (origin[1] + height) as f32 / atlas_size],
+        };
+        self.entries.insert(key, Some(entry));
+        Some(entry)
+    }
+}
+
+enum Anchor {
+    /// Top left corner in screen pixels.
+    Screen([f32; 2]),
+    /// Bottom centre in world space.
+    World(Point3<f32>),
+}
+
+enum Queued {
+    Text { text: String, anchor: Anchor, style: TextStyle },
+    /// Solid screen-space rectangle, for backgrounds behind text.
+    Rect { min: [f32; 2], max: [f32; 2], color: [f32; 4] },
+}
+
+/// Draws text queued during the frame: world-space labels into the scene,
+/// with depth testing, and screen-space text over the final image.
+pub struct TextRenderer {
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    world_pipeline: wgpu::RenderPipeline,
+    screen_pipeline: wgpu::RenderPipeline,
+    fonts: Vec<Font>,
+    atlas: GlyphAtlas,
+    uniform_buffer: wgpu::Buffer,
+    output_srgb: bool,
+    bind_group: wgpu::BindGroup,
+    queued: Vec<Queued>,
+    vertex_buffer: wgpu::Buffer,
+    capacity: usize,
+    world_count: u32,
+    screen_count: u32,
+}
+
+impl TextRenderer {
+    /// DejaVu Sans, always loaded.
+    pub const DEFAULT_FONT: FontId = FontId(0);
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, scene_format: wgpu::TextureFormat, output_format: wgpu::TextureFormat, sample_count: u32, uniforms_bgl: &wgpu::BindGroupLayout) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("text shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!("../res/shader/globals.wgsl"), include_str!("../res/shader/text.wgsl")))),
+            flags: wgpu::ShaderFlags::all(),
+        });
+        let default_font = Font::from_vec(include_bytes!("../res/fonts/DejaVuSans.ttf").to_vec()).expect("bundled font is valid");
+
+        let atlas = GlyphAtlas::new(device, queue);
+        let atlas_view = atlas.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("glyph sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let output_srgb = output_format.describe().srgb;
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Text Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[TextUniforms { screen: [1.0, 1.0, if output_srgb { 1.0 } else { 0.0 }, 0.0] }]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("text_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("text_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[uniforms_bgl, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let world_pipeline = Self::create_world_pipeline(device, &pipeline_layout, &shader, scene_format, sample_count);
+        let screen_pipeline = Self::create_pipeline(device, &pipeline_layout, &shader, "screen", output_format, None, 1);
+
+        let capacity = 1024;
+        Self {
+            shader,
+            pipeline_layout,
+            world_pipeline,
+            screen_pipeline,
+            fonts: vec![default_font],
+            atlas,
+            uniform_buffer,
+            output_srgb,
+            bind_group,
+            queued: Vec::new(),
+            vertex_buffer: Self::create_buffer(device, capacity),
+            capacity,
+            world_count: 0,
+            screen_count: 0,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Text Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<TextVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, space: &str, color_format: wgpu::TextureFormat, depth_stencil: Option<wgpu::DepthStencilState>, sample_count: u32) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("text pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: &format!("vs_{}", space),
+                buffers: &[TextVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: &format!("fs_{}", space),
+                targets: &[wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+        })
+    }
+
+    fn create_world_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, color_format: wgpu::TextureFormat, sample_count: u32) -> wgpu::RenderPipeline {
+        let depth_stencil = wgpu::DepthStencilState {
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            ..crate::depth_stencil_state()
+        };
+        Self::create_pipeline(device, layout, shader, "world", color_format, Some(depth_stencil), sample_count)
+    }
+
+    /// Rebuilds the world-space pipeline for scene targets with `sample_count` samples.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.world_pipeline = Self::create_world_pipeline(device, &self.pipeline_layout, &self.shader, crate::post::HDR_FORMAT, sample_count);
+    }
+
+    pub fn add_font(&mut self, font: Font) -> FontId {
+        self.fonts.push(font);
+        FontId(self.fonts.len() - 1)
+    }
+
+    pub fn font(&self, id: FontId) -> &Font {
+        &self.fonts[id.0]
+    }
+
+    /// Size of `text` on screen, for placing it before drawing.
+    pub fn measure(&self, text: &str, style: &TextStyle) -> TextLayout {
+        layout(self.font(style.font), text, style.size, style.max_width)
+    }
+
+    /// Queues text for this frame with its top left corner at `position`
+    /// pixels from the top left of the screen.
+    pub fn queue_screen(&mut self, text: &str, position: [f32; 2], style: &TextStyle) {
+        self.queued.push(Queued::Text { text: text.to_owned(), anchor: Anchor::Screen(position), style: *style });
+    }
+
+    /// Queues a filled rectangle between the screen pixels `min` and `max`.
+    /// Screen-space rectangles and text are drawn in the order they were queued.
+    pub fn queue_screen_rect(&mut self, min: [f32; 2], max: [f32; 2], color: [f32; 4]) {
+        self.queued.push(Queued::Rect { min, max, color });
+    }
+
+    /// Queues a label for this frame facing the camera, centred above
+    /// `position`. `style.size` and `style.max_width` are in world units.
+    pub fn queue_world(&mut self, text: &str, position: Point3<f32>, style: &TextStyle) {
+        self.queued.push(Queued::Text { text: text.to_owned(), anchor: Anchor::World(position), style: *style });
+    }
+
+    /// Lays out everything queued since the last call and uploads the quads.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, camera: &Camera, extent: wgpu::Extent3d) {
+        let uniforms = TextUniforms {
+            screen: [extent.width.max(1) as f32, extent.height.max(1) as f32, if self.output_srgb { 1.0 } else { 0.0 }, 0.0],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+
+        if self.atlas.full {
+            self.atlas.clear();
+        }
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+        let up = right.cross(forward);
+
+        let mut world = Vec::new();
+        let mut screen = Vec::new();
+        for queued in std::mem::take(&mut self.queued) {
+            let (text, anchor, style) = match queued {
+                Queued::Text { text, anchor, style } => (text, anchor, style),
+                Queued::Rect { min, max, color } => {
+                    let tex_coords = GlyphAtlas::solid_uv();
+                    let corners = [[min[0], min[1]], [min[0], max[1]], [max[0], min[1]], [max[0], max[1]]];
+                    screen.extend([0, 1, 2, 2, 1, 3].iter().map(|&i| TextVertex {
+                        position: [corners[i][0], corners[i][1], 0.0],
+                        tex_coords,
+                        color,
+                    }));
+                    continue;
+                }
+            };
+            let font = &self.fonts[style.font.0];
+            // World text is laid out in raster pixels, then scaled to world units.
+            let (raster_size, scale) = match anchor {
+                Anchor::Screen(_) => (style.size, 1.0),
+                Anchor::World(_) => (WORLD_RASTER_SIZE, style.size / WORLD_RASTER_SIZE),
+            };
+            let text_layout = layout(font, &text, raster_size, style.max_width.map(|width| width / scale));
+            for glyph in &text_layout.glyphs {
+                let entry = match self.atlas.entry(queue, &self.fonts, style.font, glyph.id, raster_size) {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+                let x0 = glyph.position[0] + entry.offset[0];
+                let y0 = glyph.position[1] + entry.offset[1];
+                let (x1, y1) = (x0 + entry.size[0], y0 + entry.size[1]);
+                let corners = [
+                    (x0, y0, entry.uv_min[0], entry.uv_min[1]),
+                    (x0, y1, entry.uv_min[0], entry.uv_max[1]),
+                    (x1, y0, entry.uv_max[0], entry.uv_min[1]),
+                    (x1, y1, entry.uv_max[0], entry.uv_max[1]),
+                ];
+                let vertex = |(x, y, u, v): (f32, f32, f32, f32)| {
+                    let position = match anchor {
+                        Anchor::Screen([left, top]) => [left + x, top + y, 0.0],
+                        Anchor::World(anchor) => {
+                            let x = (x - text_layout.width / 2.0) * scale;
+                            let y = (text_layout.height - y) * scale;
+                            (anchor + right * x + up * y).into()
+                        }
+                    };
+                    TextVertex { position, tex_coords: [u, v], color: style.color }
+                };
+                let target = match anchor {
+                    Anchor::Screen(_) => &mut screen,
+                    Anchor::World(_) => &mut world,
+                };
+                target.extend([0, 1, 2, 2, 1, 3].iter().map(|&i| vertex(corners[i])));
+            }
+        }
+
+        self.world_count = world.len() as u32;
+        self.screen_count = screen.len() as u32;
+        world.append(&mut screen);
+        if world.len() > self.capacity {
+            self.capacity = world.len().next_power_of_two();
+            self.vertex_buffer = Self::create_buffer(device, self.capacity);
+        }
+        if !world.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&world));
+        }
+    }
+
+    /// Draws the world-space labels into the scene.
+    pub async fn draw_world(&self, device: &wgpu::Device, targets: Arc<SceneTargets>, uniforms_bg: &wgpu::BindGroup) -> wgpu::CommandBuffer {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("world text") });
+        if self.world_count > 0 {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("world text"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &targets.color,
+                    resolve_target: targets.resolve.as_deref(),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &targets.depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_pipeline(&self.world_pipeline);
+            pass.set_bind_group(0, uniforms_bg, &[]);
+            pass.set_bind_group(1, &self.bind_group, &[]);
+            pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            pass.draw(0..self.world_count, 0..1);
+        }
+        encoder.finish()
+    }
+
+    /// Draws the screen-space text over the final image in `output_view`.
+    pub async fn draw_screen(&self, device: &wgpu::Device, output_view: &wgpu::TextureView, uniforms_bg: &wgpu::BindGroup) -> wgpu::CommandBuffer {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("screen text") });
+        if self.screen_count > 0 {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("screen text"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: output_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(&self.screen_pipeline);
+            pass.set_bind_group(0, uniforms_bg, &[]);
+            pass.set_bind_group(1, &self.bind_group, &[]);
+            pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            pass.draw(self.world_count..self.world_count + self.screen_count, 0..1);
+        }
+        encoder.finish()
+    }
+}
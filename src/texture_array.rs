@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::helpers::{self, ColorSpace};
+
+#[derive(Debug)]
+pub enum TextureArrayError {
+    Io(std::io::Error),
+    Image(String, image::ImageError),
+    SizeMismatch {
+        name: String,
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+    DuplicateName(String),
+    Empty,
+}
+
+impl fmt::Display for TextureArrayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureArrayError::Io(e) => write!(f, "{}", e),
+            TextureArrayError::Image(name, e) => write!(f, "failed to decode {}: {}", name, e),
+            TextureArrayError::SizeMismatch { name, expected, found } => write!(
+                f,
+                "{} is {}x{} but the array layers are {}x{}",
+                name, found.0, found.1, expected.0, expected.1
+            ),
+            TextureArrayError::DuplicateName(name) => write!(f, "{} was added twice", name),
+            TextureArrayError::Empty => write!(f, "texture array has no layers"),
+        }
+    }
+}
+
+impl std::error::Error for TextureArrayError {}
+
+impl From<std::io::Error> for TextureArrayError {
+    fn from(e: std::io::Error) -> Self {
+        TextureArrayError::Io(e)
+    }
+}
+
+/// Collects same-sized images into the layers of a `texture_2d_array`.
+///
+/// Everything up to `build` happens on the CPU, so the builder can be filled
+/// on a worker thread and handed to the render thread for the upload.
+pub struct TextureArrayBuilder {
+    color_space: ColorSpace,
+    size: Option<(u32, u32)>,
+    layers: Vec<image::RgbaImage>,
+    names: HashMap<String, u32>,
+}
+
+impl TextureArrayBuilder {
+    pub fn new(color_space: ColorSpace) -> Self {
+        Self {
+            color_space,
+            size: None,
+            layers: Vec::new(),
+            names: HashMap::new(),
+        }
+    }
+
+    /// Appends `image` as a new layer and returns its index.
+    pub fn add(&mut self, name: &str, image: image::RgbaImage) -> Result<u32, TextureArrayError> {
+        let found = image.dimensions();
+        let expected = *self.size.get_or_insert(found);
+        if found != expected {
+            return Err(TextureArrayError::SizeMismatch { name: name.to_string(), expected, found });
+        }
+        if self.names.contains_key(name) {
+            return Err(TextureArrayError::DuplicateName(name.to_string()));
+        }
+        let layer = self.layers.len() as u32;
+        self.layers.push(image);
+        self.names.insert(name.to_string(), layer);
+        Ok(layer)
+    }
+
+    /// Adds every PNG below `dir` (relative to `res/textures`). Layers are named
+    /// by their path relative to `dir` without the extension, e.g. `Light/texture_07`.
+    pub fn add_directory(&mut self, dir: &str) -> Result<(), TextureArrayError> {
+        let root = Path::new("./res/textures").join(dir);
+        let mut files = Vec::new();
+        collect_pngs(&root, &mut files)?;
+        files.sort();
+        for file in files {
+            let relative = file.strip_prefix(&root).unwrap();
+            let name = relative.with_extension("").to_string_lossy().replace('\\', "/");
+            let path = Path::new(dir).join(relative).to_string_lossy().replace('\\', "/");
+            let image = helpers::decode_image(&path).map_err(|e| TextureArrayError::Image(path, e))?;
+            self.add(&name, image)?;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn build(self, label: &str, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<TextureArray, TextureArrayError> {
+        let (width, height) = self.size.ok_or(TextureArrayError::Empty)?;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: self.layers.len() as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.color_space.texture_format(),
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+            label: Some(label),
+        });
+
+        for (layer, image) in self.layers.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                },
+                image,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(4 * width),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        Ok(TextureArray {
+            texture,
+            view,
+            size: (width, height),
+            names: self.names,
+        })
+    }
+}
+
+fn collect_pngs(dir: &Path, files: &mut Vec<std::path::PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_pngs(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+pub struct TextureArray {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub size: (u32, u32),
+    names: HashMap<String, u32>,
+}
+
+impl TextureArray {
+    /// Layer index of the texture added under `name`.
+    pub fn layer(&self, name: &str) -> Option<u32> {
+        self.names.get(name).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn bind_group_layout_entry(binding: u32, visibility: wgpu::ShaderStage) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32) -> image::RgbaImage {
+        image::RgbaImage::from_pixel(width, height, image::Rgba([255, 0, 255, 255]))
+    }
+
+    #[test]
+    fn layers_follow_the_order_they_were_added_in() {
+        let mut builder = TextureArrayBuilder::new(ColorSpace::Srgb);
+        assert!(builder.is_empty());
+        assert_eq!(builder.add("grass", solid(4, 4)).unwrap(), 0);
+        assert_eq!(builder.add("rock", solid(4, 4)).unwrap(), 1);
+        assert_eq!(builder.add("sand", solid(4, 4)).unwrap(), 2);
+        assert_eq!(builder.len(), 3);
+        assert_eq!(builder.names["rock"], 1);
+    }
+
+    #[test]
+    fn layers_must_share_a_size_and_a_name_is_only_used_once() {
+        let mut builder = TextureArrayBuilder::new(ColorSpace::Linear);
+        builder.add("grass", solid(4, 4)).unwrap();
+        match builder.add("wide", solid(8, 4)) {
+            Err(TextureArrayError::SizeMismatch { name, expected, found }) => {
+                assert_eq!((name.as_str(), expected, found), ("wide", (4, 4), (8, 4)));
+            }
+            other => panic!("expected a size mismatch, got {:?}", other),
+        }
+        let error = builder.add("grass", solid(4, 4)).unwrap_err();
+        assert!(matches!(error, TextureArrayError::DuplicateName(ref name) if name == "grass"));
+        assert_eq!(builder.len(), 1);
+        assert_eq!(
+            TextureArrayError::SizeMismatch { name: "wide".into(), expected: (4, 4), found: (8, 4) }.to_string(),
+            "wide is 8x4 but the array layers are 4x4"
+        );
+    }
+
+    #[test]
+    fn directories_are_added_sorted_by_path() {
+        let mut builder = TextureArrayBuilder::new(ColorSpace::Srgb);
+        builder.add_directory("prototype/Dark").unwrap();
+        assert_eq!(builder.len(), 13);
+        assert_eq!(builder.size, Some((1024, 1024)));
+        for layer in 0..13 {
+            assert_eq!(builder.names[&format!("texture_{:02}", layer + 1)], layer);
+        }
+        assert!(matches!(builder.add_directory("missing"), Err(TextureArrayError::Io(_))));
+    }
+}
@@ -0,0 +1,129 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::sky::SkySettings;
+use crate::timestep::Lerp;
+
+/// The sun is swapped for the moon as the scene light once it is this far
+/// below the horizon (sine of the elevation), by which point it is dark.
+const MOON_SWITCH: f32 = -0.07;
+const MOON_COLOR: [f32; 3] = [0.1, 0.12, 0.18];
+/// Moonlit sky brightness relative to daylight.
+const MOON_SKY_SCALE: f32 = 0.03;
+const DAY_AMBIENT: f32 = 0.3;
+const NIGHT_AMBIENT: f32 = 0.04;
+
+/// Light, ambient and sky brightness for one moment of the day.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Lighting {
+    /// Towards the sun by day and the moon by night.
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub ambient: f32,
+    /// Scales `SkySettings::sun_intensity`.
+    pub sky_scale: f32,
+}
+
+/// Game clock for the sun and moon. One in-game day lasts `day_length` seconds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimeOfDay {
+    /// Hours since midnight, `0.0..24.0`.
+    hours: f32,
+    pub day_length: f32,
+    /// Tilts the sun's path towards the south, in degrees.
+    pub latitude: f32,
+    pub paused: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self::new(9.0, 600.0)
+    }
+}
+
+impl TimeOfDay {
+    pub fn new(hours: f32, day_length: f32) -> Self {
+        Self { hours: hours.rem_euclid(24.0), day_length, latitude: 30.0, paused: false }
+    }
+
+    /// A clock stopped at `hours`, e.g. for screenshots.
+    pub fn fixed(hours: f32) -> Self {
+        Self { paused: true, ..Self::new(hours, 600.0) }
+    }
+
+    pub fn advance(&mut self, delta: f32) {
+        if !self.paused && self.day_length > 0.0 {
+            self.scrub(delta / self.day_length * 24.0);
+        }
+    }
+
+    pub fn hours(&self) -> f32 {
+        self.hours
+    }
+
+    pub fn set_hours(&mut self, hours: f32) {
+        self.hours = hours.rem_euclid(24.0);
+    }
+
+    /// Moves the clock by `hours`, which may be negative, paused or not.
+    pub fn scrub(&mut self, hours: f32) {
+        self.set_hours(self.hours + hours);
+    }
+
+    /// Unit vector towards the sun. It rises in the east (+x) at 6:00, peaks
+    /// at noon and sets in the west at 18:00.
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        let angle = (self.hours - 6.0) / 24.0 * std::f32::consts::TAU;
+        let latitude = self.latitude.to_radians();
+        Vector3::new(angle.cos(), angle.sin() * latitude.cos(), angle.sin() * latitude.sin()).normalize()
+    }
+
+    /// The moon is kept opposite the sun.
+    pub fn moon_direction(&self) -> Vector3<f32> {
+        -self.sun_direction()
+    }
+
+    pub fn lighting(&self, sky: &SkySettings) -> Lighting {
+        let sun = self.sun_direction();
+        if sun.y > MOON_SWITCH {
+            let daylight = ((sun.y + 0.1) / 0.3).clamp(0.0, 1.0);
+            Lighting {
+                direction: sun,
+                color: sun_transmittance(sky, sun.y),
+                ambient: NIGHT_AMBIENT + (DAY_AMBIENT - NIGHT_AMBIENT) * daylight,
+                sky_scale: 1.0,
+            }
+        } else {
+            // Fade the moon in as it rises so the switch isn't visible.
+            let moon = self.moon_direction();
+            let rise = ((moon.y + MOON_SWITCH) / 0.2).clamp(0.0, 1.0);
+            Lighting {
+                direction: moon,
+                color: [MOON_COLOR[0] * rise, MOON_COLOR[1] * rise, MOON_COLOR[2] * rise],
+                ambient: NIGHT_AMBIENT,
+                sky_scale: MOON_SKY_SCALE * rise,
+            }
+        }
+    }
+}
+
+/// Fraction of sunlight reaching the ground through the atmosphere, with the
+/// Kasten-Young air mass for a sun at elevation `asin(sin_elevation)`.
+fn sun_transmittance(sky: &SkySettings, sin_elevation: f32) -> [f32; 3] {
+    let elevation = sin_elevation.clamp(-1.0, 1.0).asin().to_degrees().max(-4.0);
+    let air_mass = 1.0 / (elevation.to_radians().sin() + 0.50572 * (elevation + 6.07995).powf(-1.6364));
+    let mie = sky.mie_coefficient * sky.mie_scale_height * 1.1;
+    let channel = |rayleigh: f32| (-(rayleigh * sky.rayleigh_scale_height + mie) * air_mass).exp();
+    let [r, g, b] = sky.rayleigh_coefficients;
+    [channel(r), channel(g), channel(b)]
+}
+
+/// Blends the hours the short way round the clock, so 23:59 to 0:01 doesn't
+/// replay the whole day.
+impl Lerp for TimeOfDay {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let difference = (other.hours - self.hours + 12.0).rem_euclid(24.0) - 12.0;
+        let mut blended = *other;
+        blended.set_hours(self.hours + difference * t);
+        blended
+    }
+}
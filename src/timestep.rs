@@ -0,0 +1,335 @@
+//! Fixed-rate simulation clock. Frames take however long they take; the
+//! simulation only ever advances in whole ticks of `tick_delta` seconds, so
+//! the same inputs always produce the same states, whatever the frame rate.
+//! Rendering blends the last two tick states with `alpha`.
+
+use std::fmt;
+
+use cgmath::{Point3, Quaternion, Vector3, VectorSpace};
+
+/// Frame time beyond this is dropped rather than simulated, e.g. after a
+/// breakpoint or while the window was being dragged.
+const MAX_FRAME_TIME: f64 = 0.25;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GameSpeed {
+    Paused,
+    Normal,
+    Double,
+    Quadruple,
+}
+
+impl GameSpeed {
+    pub const ALL: [GameSpeed; 4] = [GameSpeed::Paused, GameSpeed::Normal, GameSpeed::Double, GameSpeed::Quadruple];
+
+    /// Simulated seconds per real second.
+    pub fn multiplier(self) -> u32 {
+        match self {
+            GameSpeed::Paused => 0,
+            GameSpeed::Normal => 1,
+            GameSpeed::Double => 2,
+            GameSpeed::Quadruple => 4,
+        }
+    }
+
+    /// The next speed up, staying at the fastest.
+    pub fn faster(self) -> Self {
+        match self {
+            GameSpeed::Paused => GameSpeed::Normal,
+            GameSpeed::Normal => GameSpeed::Double,
+            GameSpeed::Double | GameSpeed::Quadruple => GameSpeed::Quadruple,
+        }
+    }
+
+    /// The next speed down, stopping at normal speed rather than pausing.
+    pub fn slower(self) -> Self {
+        match self {
+            GameSpeed::Quadruple => GameSpeed::Double,
+            GameSpeed::Double | GameSpeed::Normal => GameSpeed::Normal,
+            GameSpeed::Paused => GameSpeed::Paused,
+        }
+    }
+}
+
+impl fmt::Display for GameSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameSpeed::Paused => write!(f, "Pause"),
+            speed => write!(f, "{}x", speed.multiplier()),
+        }
+    }
+}
+
+/// Resource telling systems which tick they are simulating.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tick {
+    /// Ticks simulated before this one.
+    pub number: u64,
+    /// Simulated seconds this tick covers.
+    pub delta: f32,
+}
+
+/// Turns frame times into a number of fixed simulation ticks to run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixedTimestep {
+    tick_rate: u32,
+    speed: GameSpeed,
+    /// Most ticks run in one frame at 1x; scaled by the speed multiplier.
+    /// When the simulation can't keep up, time is dropped instead of letting
+    /// the backlog grow every frame.
+    pub max_ticks_per_frame: u32,
+    /// Simulated seconds owed to the simulation, always below `tick_delta`
+    /// between frames.
+    accumulator: f64,
+    tick: u64,
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(30)
+    }
+}
+
+impl FixedTimestep {
+    /// A clock ticking `tick_rate` times per simulated second.
+    pub fn new(tick_rate: u32) -> Self {
+        assert!(tick_rate > 0, "tick rate must be positive");
+        Self { tick_rate, speed: GameSpeed::Normal, max_ticks_per_frame: 8, accumulator: 0.0, tick: 0 }
+    }
+
+    pub fn tick_rate(&self) -> u32 {
+        self.tick_rate
+    }
+
+    /// Changes the rate, keeping how far the clock is between two ticks.
+    pub fn set_tick_rate(&mut self, tick_rate: u32) {
+        assert!(tick_rate > 0, "tick rate must be positive");
+        let alpha = self.alpha() as f64;
+        self.tick_rate = tick_rate;
+        self.accumulator = alpha * self.tick_delta_f64();
+    }
+
+    /// Simulated seconds per tick, the only time step the simulation sees.
+    pub fn tick_delta(&self) -> f32 {
+        self.tick_delta_f64() as f32
+    }
+
+    fn tick_delta_f64(&self) -> f64 {
+        1.0 / self.tick_rate as f64
+    }
+
+    pub fn speed(&self) -> GameSpeed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: GameSpeed) {
+        self.speed = speed;
+    }
+
+    /// Ticks simulated since the start, e.g. for stamping replays.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Adds a frame's real time and returns how many ticks to simulate now.
+    pub fn advance(&mut self, delta: f32) -> u32 {
+        let multiplier = self.speed.multiplier();
+        self.accumulator += (delta as f64).clamp(0.0, MAX_FRAME_TIME) * multiplier as f64;
+        let tick_delta = self.tick_delta_f64();
+        let mut ticks = (self.accumulator / tick_delta) as u32;
+        let max_ticks = self.max_ticks_per_frame * multiplier;
+        if ticks > max_ticks {
+            ticks = max_ticks;
+            self.accumulator = 0.0;
+        } else {
+            self.accumulator -= ticks as f64 * tick_delta;
+        }
+        self.tick += ticks as u64;
+        ticks
+    }
+
+    /// How far the clock is from the last tick towards the next, `0.0..1.0`,
+    /// for blending the last two simulation states.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.tick_delta_f64()).clamp(0.0, 1.0) as f32
+    }
+}
+
+/// Linear blend between two states.
+pub trait Lerp {
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Vector3<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        VectorSpace::lerp(*self, *other, t)
+    }
+}
+
+impl Lerp for Point3<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Quaternion<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        crate::animation::slerp(*self, *other, t)
+    }
+}
+
+/// A simulation value as of the last two ticks.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Interpolated<T> {
+    previous: T,
+    current: T,
+}
+
+impl<T: Clone> Interpolated<T> {
+    pub fn new(value: T) -> Self {
+        Self { previous: value.clone(), current: value }
+    }
+
+    /// Runs one tick's change on the current state, keeping the old one.
+    pub fn update(&mut self, tick: impl FnOnce(&mut T)) {
+        self.previous = self.current.clone();
+        tick(&mut self.current);
+    }
+
+    pub fn current(&self) -> &T {
+        &self.current
+    }
+
+    /// Changes the current state without a tick, e.g. from the UI. It blends
+    /// in over the rest of the tick unless followed by `snap`.
+    pub fn current_mut(&mut self) -> &mut T {
+        &mut self.current
+    }
+
+    /// Drops the previous state so a jump doesn't blend.
+    pub fn snap(&mut self) {
+        self.previous = self.current.clone();
+    }
+
+    pub fn previous(&self) -> &T {
+        &self.previous
+    }
+}
+
+impl<T: Clone + Lerp> Interpolated<T> {
+    /// The state `alpha` of the way from the previous tick to the current one.
+    pub fn get(&self, alpha: f32) -> T {
+        self.previous.lerp(&self.current, alpha)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, InnerSpace, Rotation, Rotation3};
+
+    /// Runs `frames` through a 32 Hz clock, whose tick is exact in binary,
+    /// and returns the clock and a value integrated once per tick.
+    fn simulate(frames: &[f32]) -> (FixedTimestep, f32) {
+        let mut clock = FixedTimestep::new(32);
+        let mut position = 1.0f32;
+        for &frame in frames {
+            for _ in 0..clock.advance(frame) {
+                position = position * 1.01 + clock.tick_delta();
+            }
+        }
+        (clock, position)
+    }
+
+    #[test]
+    fn uneven_frames_run_the_same_ticks() {
+        let steady = vec![1.0 / 32.0; 64];
+        let uneven: Vec<f32> = [64.0, 4.0, 12.0, 48.0, 1.0, 127.0].iter().map(|parts| parts / 512.0).cycle().take(24).collect();
+        assert_eq!(uneven.iter().sum::<f32>(), 2.0);
+        let (steady_clock, steady_position) = simulate(&steady);
+        let (uneven_clock, uneven_position) = simulate(&uneven);
+        assert_eq!(steady_clock.tick(), 64);
+        assert_eq!(uneven_clock.tick(), 64);
+        assert_eq!(steady_position.to_bits(), uneven_position.to_bits());
+        assert_eq!(uneven_clock.alpha(), 0.0);
+    }
+
+    #[test]
+    fn long_frames_are_clamped_instead_of_spiralling() {
+        let mut clock = FixedTimestep::new(32);
+        assert_eq!(clock.advance(10.0), 8, "clamped to MAX_FRAME_TIME");
+        assert_eq!(clock.advance(-1.0), 0);
+        assert_eq!(clock.tick(), 8);
+
+        clock.max_ticks_per_frame = 4;
+        assert_eq!(clock.advance(0.25), 4);
+        assert_eq!(clock.alpha(), 0.0, "the backlog is dropped");
+        clock.set_speed(GameSpeed::Quadruple);
+        assert_eq!(clock.advance(0.25), 16);
+        assert_eq!(clock.tick(), 28);
+    }
+
+    #[test]
+    fn game_speed_scales_simulated_time() {
+        let mut clock = FixedTimestep::new(32);
+        clock.set_speed(GameSpeed::Paused);
+        assert_eq!(clock.advance(0.125), 0);
+        assert_eq!(clock.alpha(), 0.0);
+        clock.set_speed(GameSpeed::Normal);
+        assert_eq!(clock.advance(0.125), 4);
+        clock.set_speed(GameSpeed::Double);
+        assert_eq!(clock.advance(0.125), 8);
+        clock.set_speed(GameSpeed::Quadruple);
+        assert_eq!(clock.advance(0.125), 16);
+
+        assert_eq!(GameSpeed::Paused.faster(), GameSpeed::Normal);
+        assert_eq!(GameSpeed::Quadruple.faster(), GameSpeed::Quadruple);
+        assert_eq!(GameSpeed::Normal.slower(), GameSpeed::Normal);
+        assert_eq!(GameSpeed::ALL.iter().map(ToString::to_string).collect::<Vec<_>>(), ["Pause", "1x", "2x", "4x"]);
+    }
+
+    #[test]
+    fn alpha_tracks_the_time_between_ticks() {
+        let mut clock = FixedTimestep::new(32);
+        for frame in 0..100 {
+            clock.advance(0.001 * (frame % 7) as f32);
+            assert!((0.0..1.0).contains(&clock.alpha()), "alpha {}", clock.alpha());
+        }
+
+        let mut clock = FixedTimestep::new(32);
+        assert_eq!(clock.advance(1.25 / 32.0), 1);
+        assert_eq!(clock.alpha(), 0.25);
+        clock.set_tick_rate(64);
+        assert_eq!(clock.alpha(), 0.25);
+        assert_eq!(clock.tick_delta(), 1.0 / 64.0);
+        assert_eq!(clock.advance(0.75 / 64.0), 1);
+        assert_eq!(clock.alpha(), 0.0);
+    }
+
+    #[test]
+    fn interpolated_values_blend_the_last_two_ticks() {
+        let mut value = Interpolated::new(0.0f32);
+        value.update(|value| *value = 10.0);
+        assert_eq!((value.get(0.0), value.get(0.5), value.get(1.0)), (0.0, 5.0, 10.0));
+        *value.current_mut() = 20.0;
+        assert_eq!(value.get(0.5), 10.0);
+        value.snap();
+        assert_eq!((*value.previous(), value.get(0.0)), (20.0, 20.0));
+
+        let mut position = Interpolated::new(Point3::new(0.0f32, 0.0, 0.0));
+        position.update(|position| position.x = 4.0);
+        assert_eq!(position.get(0.25), Point3::new(1.0, 0.0, 0.0));
+
+        let mut rotation = Interpolated::new(Quaternion::from_angle_y(Deg(0.0f32)));
+        rotation.update(|rotation| *rotation = -Quaternion::from_angle_y(Deg(90.0)));
+        let forward = rotation.get(0.5).rotate_vector(Vector3::unit_z());
+        let expected = Quaternion::from_angle_y(Deg(45.0)).rotate_vector(Vector3::unit_z());
+        assert!((forward - expected).magnitude() < 1e-5, "{:?}", forward);
+    }
+}
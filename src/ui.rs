@@ -0,0 +1,560 @@
+//! Immediate-mode UI. Widgets are declared every frame between
+//! `Ui::begin_frame` and `Autonomy::update`, which turns them into
+//! screen-space quads and text drawn over the final image.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ops::RangeInclusive;
+
+use winit::event::{ElementState, MouseButton, VirtualKeyCode, WindowEvent};
+
+use crate::input::{Button, InputEvent};
+use crate::text::{self, Font, FontId, TextStyle};
+
+/// Pixels scrolled per wheel line.
+const SCROLL_LINE: f32 = 20.0;
+
+/// Screen-space rectangle in pixels from the top left.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl Rect {
+    pub fn new(position: [f32; 2], size: [f32; 2]) -> Self {
+        Self { min: position, max: [position[0] + size[0], position[1] + size[1]] }
+    }
+
+    /// The rectangle spanned by two opposite corners in either order.
+    pub fn from_corners(a: [f32; 2], b: [f32; 2]) -> Self {
+        Self { min: [a[0].min(b[0]), a[1].min(b[1])], max: [a[0].max(b[0]), a[1].max(b[1])] }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.max[0] - self.min[0]
+    }
+
+    pub fn height(&self) -> f32 {
+        self.max[1] - self.min[1]
+    }
+
+    pub fn contains(&self, point: [f32; 2]) -> bool {
+        point[0] >= self.min[0] && point[0] < self.max[0] && point[1] >= self.min[1] && point[1] < self.max[1]
+    }
+
+    fn shrink(&self, amount: f32) -> Self {
+        Self {
+            min: [self.min[0] + amount, self.min[1] + amount],
+            max: [self.max[0] - amount, self.max[1] - amount],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub font: FontId,
+    pub text_size: f32,
+    pub text_color: [f32; 4],
+    pub panel_color: [f32; 4],
+    pub title_color: [f32; 4],
+    pub widget_color: [f32; 4],
+    pub hover_color: [f32; 4],
+    pub active_color: [f32; 4],
+    /// Slider fill, list selection and the text caret.
+    pub accent_color: [f32; 4],
+    pub padding: f32,
+    /// Gap between consecutive widgets.
+    pub spacing: f32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            font: crate::text::TextRenderer::DEFAULT_FONT,
+            text_size: 16.0,
+            text_color: [0.9, 0.9, 0.9, 1.0],
+            panel_color: [0.02, 0.02, 0.03, 0.85],
+            title_color: [0.05, 0.08, 0.15, 0.95],
+            widget_color: [0.08, 0.08, 0.1, 1.0],
+            hover_color: [0.15, 0.15, 0.2, 1.0],
+            active_color: [0.25, 0.25, 0.35, 1.0],
+            accent_color: [0.2, 0.45, 0.9, 1.0],
+            padding: 6.0,
+            spacing: 4.0,
+        }
+    }
+}
+
+/// What the UI asks the renderer to draw, in order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCommand {
+    Rect { rect: Rect, color: [f32; 4] },
+    /// Text with its top left corner at `position`.
+    Text { text: String, position: [f32; 2], style: TextStyle },
+}
+
+/// Input edges collected between two frames.
+#[derive(Clone, Debug, Default)]
+struct FrameInput {
+    pressed: bool,
+    released: bool,
+    scroll: f32,
+    characters: String,
+    keys: Vec<VirtualKeyCode>,
+}
+
+struct Layout {
+    cursor: [f32; 2],
+    /// Right edge widgets stretch to in a vertical layout.
+    right: f32,
+    horizontal: bool,
+    /// Extent of everything placed so far.
+    max: [f32; 2],
+}
+
+pub struct Ui {
+    theme: Theme,
+    font: Font,
+    screen_size: [f32; 2],
+    cursor: [f32; 2],
+    mouse_down: bool,
+    pending: FrameInput,
+    input: FrameInput,
+    /// Widget under a held mouse button, e.g. a dragged slider.
+    active: Option<u64>,
+    /// Text input receiving keyboard input.
+    focused: Option<u64>,
+    /// Byte offset of the caret in the focused text input.
+    caret: usize,
+    scroll: HashMap<u64, f32>,
+    commands: Vec<DrawCommand>,
+    layouts: Vec<Layout>,
+    ids: Vec<u64>,
+    panels: Vec<Rect>,
+    previous_panels: Vec<Rect>,
+    current_panel: Option<usize>,
+    /// Topmost panel under the cursor last frame, the only one that reacts.
+    hovered_panel: Option<usize>,
+}
+
+impl Ui {
+    /// `font` must be the font `theme.font` refers to, for measuring text.
+    pub fn new(font: Font, theme: Theme) -> Self {
+        Self {
+            theme,
+            font,
+            screen_size: [1.0, 1.0],
+            cursor: [-1.0, -1.0],
+            mouse_down: false,
+            pending: FrameInput::default(),
+            input: FrameInput::default(),
+            active: None,
+            focused: None,
+            caret: 0,
+            scroll: HashMap::new(),
+            commands: Vec::new(),
+            layouts: Vec::new(),
+            ids: Vec::new(),
+            panels: Vec::new(),
+            previous_panels: Vec::new(),
+            current_panel: None,
+            hovered_panel: None,
+        }
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    pub fn screen_size(&self) -> [f32; 2] {
+        self.screen_size
+    }
+
+    pub fn set_screen_size(&mut self, size: [f32; 2]) {
+        self.screen_size = size;
+    }
+
+    /// True while the cursor is over the UI or dragging one of its widgets,
+    /// so mouse input should not reach the world.
+    pub fn wants_pointer(&self) -> bool {
+        self.active.is_some() || self.previous_panels.iter().any(|panel| panel.contains(self.cursor))
+    }
+
+    /// True while a text input has focus.
+    pub fn wants_keyboard(&self) -> bool {
+        self.focused.is_some()
+    }
+
+    /// Feeds a window event to the UI. Returns true if the UI consumed it and
+    /// the game should ignore it.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::ReceivedCharacter(c) => {
+                if self.focused.is_some() && !c.is_control() {
+                    self.pending.characters.push(*c);
+                }
+                self.wants_keyboard()
+            }
+            WindowEvent::KeyboardInput { input, .. } => {
+                if let (Some(key), ElementState::Pressed, true) = (input.virtual_keycode, input.state, self.focused.is_some()) {
+                    self.pending.keys.push(key);
+                }
+                self.wants_keyboard()
+            }
+            _ => InputEvent::from_window_event(event).is_some_and(|event| self.handle_input(&event)),
+        }
+    }
+
+    /// Feeds pointer input that doesn't come from the window, e.g. a
+    /// gamepad's virtual cursor. Returns true if the UI consumed it.
+    pub fn handle_input(&mut self, event: &InputEvent) -> bool {
+        match *event {
+            InputEvent::CursorMoved(position) => {
+                self.cursor = position;
+                self.active.is_some() && self.mouse_down
+            }
+            InputEvent::Button { button: Button::Mouse(MouseButton::Left), pressed } => {
+                let captured = self.wants_pointer();
+                if pressed {
+                    self.pending.pressed = true;
+                } else {
+                    self.pending.released = true;
+                }
+                self.mouse_down = pressed;
+                captured
+            }
+            InputEvent::Button { button: Button::Mouse(_), .. } => self.wants_pointer(),
+            InputEvent::Wheel(lines) => {
+                self.pending.scroll += lines * SCROLL_LINE;
+                self.wants_pointer()
+            }
+            _ => false,
+        }
+    }
+
+    /// Starts declaring this frame's widgets, discarding anything declared
+    /// but not drawn since the last frame.
+    pub fn begin_frame(&mut self) {
+        if self.input.released {
+            self.active = None;
+        }
+        self.input = std::mem::take(&mut self.pending);
+        self.previous_panels = std::mem::take(&mut self.panels);
+        self.hovered_panel = self.previous_panels.iter().rposition(|panel| panel.contains(self.cursor));
+        self.current_panel = None;
+        self.commands.clear();
+        self.ids.clear();
+        let padding = self.theme.padding;
+        self.layouts = vec![Layout {
+            cursor: [padding, padding],
+            right: self.screen_size[0] - padding,
+            horizontal: false,
+            max: [padding, padding],
+        }];
+    }
+
+    /// Everything declared this frame, in drawing order.
+    pub fn take_commands(&mut self) -> Vec<DrawCommand> {
+        std::mem::take(&mut self.commands)
+    }
+
+    fn id(&self, label: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.ids.last().hash(&mut hasher);
+        label.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn text_style(&self, color: [f32; 4], max_width: Option<f32>) -> TextStyle {
+        TextStyle { font: self.theme.font, size: self.theme.text_size, color, max_width }
+    }
+
+    fn rect(&mut self, rect: Rect, color: [f32; 4]) {
+        self.commands.push(DrawCommand::Rect { rect, color });
+    }
+
+    fn text(&mut self, text: &str, position: [f32; 2], color: [f32; 4]) {
+        let style = self.text_style(color, None);
+        self.commands.push(DrawCommand::Text { text: text.to_owned(), position, style });
+    }
+
+    fn line_height(&self) -> f32 {
+        text::layout(&self.font, "", self.theme.text_size, None).height
+    }
+
+    /// Reserves space for a widget of `size` in the current layout. Widgets
+    /// in a vertical layout stretch to its full width.
+    fn allocate(&mut self, size: [f32; 2]) -> Rect {
+        let spacing = self.theme.spacing;
+        let layout = self.layouts.last_mut().expect("layout stack is never empty");
+        let rect = if layout.horizontal {
+            let rect = Rect::new(layout.cursor, size);
+            layout.cursor[0] += size[0] + spacing;
+            rect
+        } else {
+            let width = (layout.right - layout.cursor[0]).max(size[0]);
+            let rect = Rect::new(layout.cursor, [width, size[1]]);
+            layout.cursor[1] += size[1] + spacing;
+            rect
+        };
+        layout.max = [layout.max[0].max(rect.max[0]), layout.max[1].max(rect.max[1])];
+        rect
+    }
+
+    fn available_width(&self) -> f32 {
+        let layout = self.layouts.last().expect("layout stack is never empty");
+        (layout.right - layout.cursor[0]).max(0.0)
+    }
+
+    /// Whether the cursor is over `rect` and not covered by another panel.
+    fn hovered(&self, rect: Rect) -> bool {
+        rect.contains(self.cursor) && self.hovered_panel == self.current_panel
+    }
+
+    /// Press and release handling shared by clickable widgets. Returns
+    /// (hovered, held, clicked).
+    fn interact(&mut self, id: u64, rect: Rect) -> (bool, bool, bool) {
+        let hovered = self.hovered(rect);
+        if hovered && self.input.pressed {
+            self.active = Some(id);
+        }
+        let held = self.active == Some(id);
+        let clicked = held && hovered && self.input.released;
+        (hovered, held, clicked)
+    }
+
+    fn widget_color(&self, hovered: bool, held: bool) -> [f32; 4] {
+        match (hovered, held) {
+            (_, true) => self.theme.active_color,
+            (true, false) => self.theme.hover_color,
+            (false, false) => self.theme.widget_color,
+        }
+    }
+
+    /// A window with an optional title bar at `position`, `width` pixels wide
+    /// and as tall as its contents.
+    pub fn panel(&mut self, title: &str, position: [f32; 2], width: f32, contents: impl FnOnce(&mut Ui)) {
+        let padding = self.theme.padding;
+        let index = self.panels.len();
+        self.panels.push(Rect::new(position, [width, 0.0]));
+        let parent_panel = self.current_panel.replace(index);
+        self.ids.push(self.id(title));
+
+        let background = self.commands.len();
+        let mut top = position[1];
+        if !title.is_empty() {
+            let height = self.line_height() + padding * 2.0;
+            self.rect(Rect::new(position, [width, height]), self.theme.title_color);
+            self.text(title, [position[0] + padding, position[1] + padding], self.theme.text_color);
+            top += height;
+        }
+        self.layouts.push(Layout {
+            cursor: [position[0] + padding, top + padding],
+            right: position[0] + width - padding,
+            horizontal: false,
+            max: [position[0] + padding, top],
+        });
+        contents(self);
+        let layout = self.layouts.pop().expect("panel layout was pushed");
+
+        let rect = Rect { min: position, max: [position[0] + width, layout.max[1] + padding] };
+        self.panels[index] = rect;
+        self.commands.insert(background, DrawCommand::Rect { rect, color: self.theme.panel_color });
+        self.ids.pop();
+        self.current_panel = parent_panel;
+    }
+
+    /// Lays out the widgets in `contents` left to right.
+    pub fn horizontal(&mut self, contents: impl FnOnce(&mut Ui)) {
+        let cursor = self.layouts.last().expect("layout stack is never empty").cursor;
+        let right = self.layouts.last().expect("layout stack is never empty").right;
+        self.layouts.push(Layout { cursor, right, horizontal: true, max: cursor });
+        contents(self);
+        let layout = self.layouts.pop().expect("row layout was pushed");
+        let size = [layout.max[0] - cursor[0], layout.max[1] - cursor[1]];
+        // Claim the row's space in the parent without stretching it.
+        let parent = self.layouts.last_mut().expect("layout stack is never empty");
+        if parent.horizontal {
+            parent.cursor[0] += size[0] + self.theme.spacing;
+        } else {
+            parent.cursor[1] += size[1] + self.theme.spacing;
+        }
+        parent.max = [parent.max[0].max(layout.max[0]), parent.max[1].max(layout.max[1])];
+    }
+
+    pub fn space(&mut self, height: f32) {
+        self.allocate([0.0, height - self.theme.spacing]);
+    }
+
+    pub fn separator(&mut self) {
+        let rect = self.allocate([0.0, 1.0]);
+        self.rect(rect, self.theme.widget_color);
+    }
+
+    /// Text wrapped to the available width.
+    pub fn label(&mut self, text: &str) {
+        let max_width = self.available_width();
+        let size = text::layout(&self.font, text, self.theme.text_size, Some(max_width));
+        let rect = self.allocate([size.width, size.height]);
+        let style = self.text_style(self.theme.text_color, Some(max_width));
+        self.commands.push(DrawCommand::Text { text: text.to_owned(), position: rect.min, style });
+    }
+
+    /// Returns true on the frame the button is clicked.
+    pub fn button(&mut self, text: &str) -> bool {
+        let padding = self.theme.padding;
+        let size = [text::advance_width(&self.font, text, self.theme.text_size) + padding * 2.0, self.line_height() + padding * 2.0];
+        let rect = self.allocate(size);
+        let (hovered, held, clicked) = self.interact(self.id(text), rect);
+        self.rect(rect, self.widget_color(hovered, held));
+        self.text(text, [rect.min[0] + padding, rect.min[1] + padding], self.theme.text_color);
+        clicked
+    }
+
+    /// Returns true when `value` was toggled.
+    pub fn checkbox(&mut self, text: &str, value: &mut bool) -> bool {
+        let line_height = self.line_height();
+        let size = [line_height + self.theme.padding + text::advance_width(&self.font, text, self.theme.text_size), line_height];
+        let rect = self.allocate(size);
+        let (hovered, held, clicked) = self.interact(self.id(text), rect);
+        let check = Rect::new(rect.min, [line_height, line_height]);
+        self.rect(check, self.widget_color(hovered, held));
+        if *value {
+            self.rect(check.shrink(line_height * 0.25), self.theme.accent_color);
+        }
+        self.text(text, [rect.min[0] + line_height + self.theme.padding, rect.min[1]], self.theme.text_color);
+        if clicked {
+            *value = !*value;
+        }
+        clicked
+    }
+
+    /// A labelled horizontal slider. Returns true when `value` changed.
+    pub fn slider(&mut self, text: &str, value: &mut f32, range: RangeInclusive<f32>) -> bool {
+        let line_height = self.line_height();
+        let label = format!("{}: {:.2}", text, value);
+        let rect = self.allocate([120.0, line_height * 2.0]);
+        self.text(&label, rect.min, self.theme.text_color);
+        let track = Rect { min: [rect.min[0], rect.min[1] + line_height], max: rect.max };
+        let (hovered, held, _) = self.interact(self.id(text), track);
+
+        let (start, end) = (*range.start(), *range.end());
+        let previous = *value;
+        if held && self.mouse_down && track.width() > 0.0 {
+            let t = ((self.cursor[0] - track.min[0]) / track.width()).clamp(0.0, 1.0);
+            *value = start + (end - start) * t;
+        }
+        let t = if end > start { ((*value - start) / (end - start)).clamp(0.0, 1.0) } else { 0.0 };
+        self.rect(track, self.widget_color(hovered, held));
+        let fill = Rect { min: track.min, max: [track.min[0] + track.width() * t, track.max[1]] };
+        self.rect(fill, self.theme.accent_color);
+        *value != previous
+    }
+
+    /// A single line text field, focused by clicking it and unfocused by
+    /// Enter, Escape or clicking elsewhere. Returns true when `value` changed.
+    pub fn text_input(&mut self, label: &str, value: &mut String) -> bool {
+        let padding = self.theme.padding;
+        let line_height = self.line_height();
+        let id = self.id(label);
+        let rect = self.allocate([120.0, line_height + padding * 2.0]);
+        let (hovered, held, clicked) = self.interact(id, rect);
+        if clicked {
+            self.focused = Some(id);
+            self.caret = value.len();
+        } else if self.input.pressed && !hovered && self.focused == Some(id) {
+            self.focused = None;
+        }
+
+        let focused = self.focused == Some(id);
+        let mut changed = false;
+        if focused {
+            self.caret = self.caret.min(value.len());
+            for c in self.input.characters.chars() {
+                value.insert(self.caret, c);
+                self.caret += c.len_utf8();
+                changed = true;
+            }
+            for key in self.input.keys.clone() {
+                let previous_char = value[..self.caret].chars().next_back().map_or(0, char::len_utf8);
+                let next_char = value[self.caret..].chars().next().map_or(0, char::len_utf8);
+                match key {
+                    VirtualKeyCode::Back if previous_char > 0 => {
+                        self.caret -= previous_char;
+                        value.remove(self.caret);
+                        changed = true;
+                    }
+                    VirtualKeyCode::Delete if next_char > 0 => {
+                        value.remove(self.caret);
+                        changed = true;
+                    }
+                    VirtualKeyCode::Left => self.caret -= previous_char,
+                    VirtualKeyCode::Right => self.caret += next_char,
+                    VirtualKeyCode::Home => self.caret = 0,
+                    VirtualKeyCode::End => self.caret = value.len(),
+                    VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter | VirtualKeyCode::Escape => self.focused = None,
+                    _ => {}
+                }
+            }
+        }
+
+        let color = if focused { self.theme.active_color } else { self.widget_color(hovered, held) };
+        self.rect(rect, color);
+        let text_position = [rect.min[0] + padding, rect.min[1] + padding];
+        if value.is_empty() && !focused {
+            let mut placeholder = self.theme.text_color;
+            placeholder[3] *= 0.5;
+            self.text(label, text_position, placeholder);
+        } else {
+            self.text(value, text_position, self.theme.text_color);
+        }
+        if self.focused == Some(id) {
+            let x = text_position[0] + text::advance_width(&self.font, &value[..self.caret], self.theme.text_size);
+            self.rect(Rect::new([x, text_position[1]], [1.0, line_height]), self.theme.accent_color);
+        }
+        changed
+    }
+
+    /// A scrollable list showing `rows` items at a time. Clicking an item
+    /// selects it; returns true when the selection changed.
+    pub fn list<T: AsRef<str>>(&mut self, label: &str, items: &[T], selected: &mut Option<usize>, rows: usize) -> bool {
+        let padding = self.theme.padding;
+        let row_height = self.line_height() + padding;
+        let id = self.id(label);
+        let rect = self.allocate([120.0, row_height * rows as f32]);
+        self.rect(rect, self.theme.widget_color);
+
+        let max_scroll = items.len().saturating_sub(rows) as f32 * row_height;
+        let mut scroll = self.scroll.get(&id).copied().unwrap_or(0.0);
+        if self.hovered(rect) {
+            scroll -= self.input.scroll;
+        }
+        // Scroll in whole rows so nothing needs clipping.
+        let scroll = (scroll.clamp(0.0, max_scroll) / row_height).round() * row_height;
+        self.scroll.insert(id, scroll);
+
+        let first = (scroll / row_height) as usize;
+        let mut changed = false;
+        for (row, (index, item)) in items.iter().enumerate().skip(first).take(rows).enumerate() {
+            let row_rect = Rect::new([rect.min[0], rect.min[1] + row as f32 * row_height], [rect.width(), row_height]);
+            let (hovered, held, clicked) = self.interact(id.wrapping_add(index as u64 + 1), row_rect);
+            if clicked && *selected != Some(index) {
+                *selected = Some(index);
+                changed = true;
+            }
+            if *selected == Some(index) {
+                self.rect(row_rect, self.theme.accent_color);
+            } else if hovered || held {
+                self.rect(row_rect, self.widget_color(hovered, held));
+            }
+            self.text(item.as_ref(), [row_rect.min[0] + padding, row_rect.min[1] + padding / 2.0], self.theme.text_color);
+        }
+        changed
+    }
+}
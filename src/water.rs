@@ -0,0 +1,487 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Matrix4, Vector3};
+use wgpu::util::DeviceExt;
+use crate::SceneTargets;
+
+const NORMAL_MAP_SIZE: u32 = 128;
+/// Half the side length of the water quad. The sea reaches past the far plane
+/// so its edge is never visible.
+const SURFACE_EXTENT: f32 = 200.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WaterSettings {
+    /// Render the scene mirrored about the surface each frame. Without this
+    /// the water reflects a flat sky colour.
+    pub reflections: bool,
+    /// Resolution of the reflection relative to the screen.
+    pub reflection_scale: f32,
+    pub shallow_color: [f32; 3],
+    pub deep_color: [f32; 3],
+    /// How much water it takes to reach the deep colour, in world units.
+    pub absorption_distance: f32,
+    /// Depth up to which the shore foams.
+    pub foam_depth: f32,
+    /// Normal map repeats per world unit.
+    pub wave_scale: f32,
+    pub wave_speed: f32,
+    pub wave_strength: f32,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            reflections: false,
+            reflection_scale: 0.5,
+            shallow_color: [0.1, 0.45, 0.45],
+            deep_color: [0.01, 0.08, 0.15],
+            absorption_distance: 1.5,
+            foam_depth: 0.15,
+            wave_scale: 0.25,
+            wave_speed: 0.02,
+            wave_strength: 0.6,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct WaterUniforms {
+    shallow_color: [f32; 4],
+    deep_color: [f32; 4],
+    waves: [f32; 4],
+    sky_color: [f32; 4],
+    surface: [f32; 4],
+}
+
+unsafe impl Zeroable for WaterUniforms{}
+unsafe impl Pod for WaterUniforms{}
+
+impl WaterUniforms {
+    fn new(settings: &WaterSettings, sea_level: f32, time: f32, sky_color: [f32; 3]) -> Self {
+        let [sr, sg, sb] = settings.shallow_color;
+        let [dr, dg, db] = settings.deep_color;
+        let reflections = if settings.reflections { 1.0 } else { 0.0 };
+        Self {
+            shallow_color: [sr, sg, sb, settings.absorption_distance.max(1e-3)],
+            deep_color: [dr, dg, db, settings.foam_depth.max(1e-3)],
+            waves: [settings.wave_scale, settings.wave_speed, settings.wave_strength, time],
+            sky_color: [sky_color[0], sky_color[1], sky_color[2], reflections],
+            surface: [sea_level, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+/// Mirrors world space about the plane `y = sea_level`.
+pub fn reflection_matrix(sea_level: f32) -> Matrix4<f32> {
+    Matrix4::from_translation(Vector3::new(0.0, sea_level, 0.0))
+        * Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0)
+        * Matrix4::from_translation(Vector3::new(0.0, -sea_level, 0.0))
+}
+
+/// Tileable wave normals from a sum of sines with whole periods per tile,
+/// tangent space in rgb, plus a foam pattern in alpha.
+fn generate_normal_map(size: u32) -> image::RgbaImage {
+    // (periods along u, periods along v, amplitude, phase)
+    const WAVES: [(f32, f32, f32, f32); 6] = [
+        (1.0, 2.0, 0.30, 0.0),
+        (-2.0, 1.0, 0.25, 1.3),
+        (3.0, -2.0, 0.12, 2.1),
+        (-4.0, -5.0, 0.06, 0.7),
+        (7.0, 3.0, 0.04, 4.2),
+        (-6.0, 9.0, 0.03, 5.5),
+    ];
+    const FOAM: [(f32, f32, f32); 3] = [(5.0, 3.0, 0.4), (-3.0, 7.0, 2.9), (8.0, -6.0, 5.1)];
+    let tau = std::f32::consts::TAU;
+    image::RgbaImage::from_fn(size, size, |x, y| {
+        let (u, v) = (x as f32 / size as f32, y as f32 / size as f32);
+        let (mut du, mut dv) = (0.0, 0.0);
+        for &(ku, kv, amplitude, phase) in WAVES.iter() {
+            let slope = (tau * (ku * u + kv * v) + phase).cos() * amplitude;
+            du += slope * ku;
+            dv += slope * kv;
+        }
+        let (nx, ny, nz) = (-du * 0.25, -dv * 0.25, 1.0);
+        let length = (nx * nx + ny * ny + nz * nz).sqrt();
+        let foam = FOAM
+            .iter()
+            .map(|&(ku, kv, phase)| (tau * (ku * u + kv * v) + phase).sin())
+            .sum::<f32>() / 3.0;
+        let encode = |value: f32| ((value * 0.5 + 0.5).clamp(0.0, 1.0) * 255.0).round() as u8;
+        image::Rgba([encode(nx / length), encode(ny / length), encode(nz / length), encode(foam)])
+    })
+}
+
+/// Sea surface at `sea_level`, drawn after the opaque scene. Shading needs the
+/// scene depth without multisampling, so the opaque casters are rendered into
+/// a separate depth pre-pass first.
+pub struct Water {
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    render_pipeline: wgpu::RenderPipeline,
+    settings: WaterSettings,
+    sea_level: f32,
+    extent: wgpu::Extent3d,
+    sample_count: u32,
+    uniform_buffer: wgpu::Buffer,
+    vertex_buffer: wgpu::Buffer,
+    normal_map: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    depth: wgpu::TextureView,
+    depth_buffer: wgpu::Buffer,
+    depth_bind_group: wgpu::BindGroup,
+    reflection: Option<Arc<SceneTargets>>,
+    /// Resolved reflection, or a placeholder while reflections are off.
+    reflection_view: Arc<wgpu::TextureView>,
+}
+
+impl Water {
+    /// `depth_layout` is the shadow pass layout and `uniforms_size` the size of
+    /// the global uniform block, so the depth pre-pass can reuse the shadow
+    /// caster pipelines.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, color_format: wgpu::TextureFormat, sample_count: u32, uniforms_bgl: &wgpu::BindGroupLayout, depth_layout: &wgpu::BindGroupLayout, uniforms_size: usize, extent: wgpu::Extent3d, sea_level: f32) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("water shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!("../res/shader/globals.wgsl"), include_str!("../res/shader/water.wgsl")))),
+            flags: wgpu::ShaderFlags::all(),
+        });
+
+        let settings = WaterSettings::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[WaterUniforms::new(&settings, sea_level, 0.0, [0.0; 3])]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let corners: [[f32; 2]; 6] = [
+            [-SURFACE_EXTENT, -SURFACE_EXTENT],
+            [-SURFACE_EXTENT, SURFACE_EXTENT],
+            [SURFACE_EXTENT, -SURFACE_EXTENT],
+            [SURFACE_EXTENT, -SURFACE_EXTENT],
+            [-SURFACE_EXTENT, SURFACE_EXTENT],
+            [SURFACE_EXTENT, SURFACE_EXTENT],
+        ];
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Vertex Buffer"),
+            contents: bytemuck::cast_slice(&corners),
+            usage: wgpu::BufferUsage::VERTEX,
+        });
+
+        let normal_map = crate::helpers::upload_texture("water normals", &generate_normal_map(NORMAL_MAP_SIZE), crate::helpers::ColorSpace::Linear, device, queue)
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("water sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1, wgpu::TextureSampleType::Float { filterable: true }),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+                texture_entry(3, wgpu::TextureSampleType::Depth),
+                texture_entry(4, wgpu::TextureSampleType::Float { filterable: true }),
+            ],
+            label: Some("water_bind_group_layout"),
+        });
+
+        let depth_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Water Depth Uniform Buffer"),
+            size: uniforms_size as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let depth_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: depth_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: depth_buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("water_depth_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[uniforms_bgl, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = Self::create_render_pipeline(device, &pipeline_layout, &shader, color_format, sample_count);
+
+        let depth = Self::create_depth(device, extent);
+        let (reflection, reflection_view) = Self::create_reflection(device, &settings, extent, sample_count);
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, &normal_map, &sampler, &depth, &reflection_view);
+
+        Self {
+            shader,
+            pipeline_layout,
+            color_format,
+            render_pipeline,
+            settings,
+            sea_level,
+            extent,
+            sample_count,
+            uniform_buffer,
+            vertex_buffer,
+            normal_map,
+            sampler,
+            bind_group_layout,
+            bind_group,
+            depth,
+            depth_buffer,
+            depth_bind_group,
+            reflection,
+            reflection_view,
+        }
+    }
+
+    fn create_render_pipeline(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, color_format: wgpu::TextureFormat, sample_count: u32) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("water pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Translucent, so it is tested against the opaque scene but doesn't occlude.
+            depth_stencil: Some(wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                ..crate::depth_stencil_state()
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+        })
+    }
+
+    fn create_depth(device: &wgpu::Device, extent: wgpu::Extent3d) -> wgpu::TextureView {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Water Depth"),
+                size: wgpu::Extent3d {
+                    width: extent.width.max(1),
+                    height: extent.height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: crate::shadow::SHADOW_FORMAT,
+                usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    fn create_reflection(device: &wgpu::Device, settings: &WaterSettings, extent: wgpu::Extent3d, sample_count: u32) -> (Option<Arc<SceneTargets>>, Arc<wgpu::TextureView>) {
+        let size = if settings.reflections {
+            wgpu::Extent3d {
+                width: ((extent.width as f32 * settings.reflection_scale) as u32).max(1),
+                height: ((extent.height as f32 * settings.reflection_scale) as u32).max(1),
+                depth_or_array_layers: 1,
+            }
+        } else {
+            wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 }
+        };
+        let view = Arc::new(device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Water Reflection"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: crate::post::HDR_FORMAT,
+                usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default()));
+        let targets = if settings.reflections {
+            Some(Arc::new(SceneTargets::new(device, size, sample_count, view.clone())))
+        } else {
+            None
+        };
+        (targets, view)
+    }
+
+    fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, uniform_buffer: &wgpu::Buffer, normal_map: &wgpu::TextureView, sampler: &wgpu::Sampler, depth: &wgpu::TextureView, reflection: &wgpu::TextureView) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(normal_map),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(reflection),
+                },
+            ],
+            label: Some("water_bind_group"),
+        })
+    }
+
+    fn recreate_targets(&mut self, device: &wgpu::Device) {
+        self.depth = Self::create_depth(device, self.extent);
+        let (reflection, reflection_view) = Self::create_reflection(device, &self.settings, self.extent, self.sample_count);
+        self.reflection = reflection;
+        self.reflection_view = reflection_view;
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, &self.normal_map, &self.sampler, &self.depth, &self.reflection_view);
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, extent: wgpu::Extent3d) {
+        self.extent = extent;
+        self.recreate_targets(device);
+    }
+
+    /// Rebuilds the pipeline and reflection targets for `sample_count` samples.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.render_pipeline = Self::create_render_pipeline(device, &self.pipeline_layout, &self.shader, self.color_format, sample_count);
+        self.recreate_targets(device);
+    }
+
+    pub fn settings(&self) -> &WaterSettings {
+        &self.settings
+    }
+
+    /// Uniforms are written on the next `update`.
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: WaterSettings) {
+        let targets_changed = settings.reflections != self.settings.reflections
+            || settings.reflection_scale != self.settings.reflection_scale;
+        self.settings = settings;
+        if targets_changed {
+            self.recreate_targets(device);
+        }
+    }
+
+    pub fn sea_level(&self) -> f32 {
+        self.sea_level
+    }
+
+    pub fn set_sea_level(&mut self, sea_level: f32) {
+        self.sea_level = sea_level;
+    }
+
+    /// Animates the waves to `time` seconds and sets the colour reflected
+    /// when there is no reflection pass.
+    pub fn update(&self, queue: &wgpu::Queue, time: f32, sky_color: [f32; 3]) {
+        let uniforms = WaterUniforms::new(&self.settings, self.sea_level, time, sky_color);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
+
+    /// Scene targets for the mirrored scene, if reflections are enabled.
+    pub fn reflection_targets(&self) -> Option<Arc<SceneTargets>> {
+        self.reflection.clone()
+    }
+
+    /// Global uniforms with the camera's view projection for the depth pre-pass.
+    pub(crate) fn depth_buffer(&self) -> &wgpu::Buffer {
+        &self.depth_buffer
+    }
+
+    pub(crate) fn depth_bind_group(&self) -> &wgpu::BindGroup {
+        &self.depth_bind_group
+    }
+
+    pub(crate) fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth
+    }
+
+    pub async fn draw(&self, device: &wgpu::Device, targets: Arc<SceneTargets>, uniforms_bg: &wgpu::BindGroup) -> wgpu::CommandBuffer {
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("water") });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("water"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &targets.color,
+                    resolve_target: targets.resolve.as_deref(),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &targets.depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_pipeline(&self.render_pipeline);
+            pass.set_bind_group(0, uniforms_bg, &[]);
+            pass.set_bind_group(1, &self.bind_group, &[]);
+            pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            pass.draw(0..6, 0..1);
+        }
+        encoder.finish()
+    }
+}